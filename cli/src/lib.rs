@@ -1,4 +1,5 @@
 use std::io::{self};
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 
@@ -9,8 +10,11 @@ use bitcoinzwalletlib::lightclient::lightclient_config::{LightClientConfig, DEFA
 use bitcoinzwalletlib::{commands, lightclient::LightClient};
 use bitcoinzwalletlib::{MainNetwork, Parameters, BitcoinZMainNetwork, BITCOINZ_MAINNET};
 
+pub mod shell;
 pub mod version;
 
+use shell::{keep_in_history, Completions, ShellHelper, MAX_RECENT_TXIDS, WALLET_CHANGING_COMMANDS};
+
 #[macro_export]
 macro_rules! configure_clapapp {
     ( $freshapp: expr ) => {
@@ -84,12 +88,17 @@ pub fn startup(
     data_dir: Option<String>,
     first_sync: bool,
     print_updates: bool,
-) -> io::Result<(Sender<(String, Vec<String>)>, Receiver<String>)> {
+) -> io::Result<(
+    Sender<(String, Vec<String>)>,
+    Receiver<String>,
+    PathBuf,
+    Arc<LightClient<BitcoinZMainNetwork>>,
+)> {
     // Try to get the configuration
     let (config, latest_block_height) = LightClientConfig::create(BITCOINZ_MAINNET, server.clone(), data_dir)?;
     
     let lightclient = match seed {
-        Some(phrase) => Arc::new(LightClient::new_from_phrase(phrase, &config, birthday, false)?),
+        Some(phrase) => Arc::new(LightClient::new_from_phrase(&phrase, &config, birthday, false)?),
         None => {
             if config.wallet_exists() {
                 Arc::new(LightClient::read_from_disk(&config)?)
@@ -124,12 +133,27 @@ pub fn startup(
     // Start the command loop
     let (command_tx, resp_rx) = command_loop(lightclient.clone());
 
-    Ok((command_tx, resp_rx))
+    Ok((command_tx, resp_rx, config.get_history_path().into_path_buf(), lightclient))
 }
 
-pub fn start_interactive(command_tx: Sender<(String, Vec<String>)>, resp_rx: Receiver<String>) {
-    // `()` can be used when no completer is required
-    let mut rl = rustyline::Editor::<()>::new();
+pub fn start_interactive(
+    command_tx: Sender<(String, Vec<String>)>,
+    resp_rx: Receiver<String>,
+    history_path: PathBuf,
+    lightclient: Arc<LightClient<BitcoinZMainNetwork>>,
+) {
+    let commands = commands::get_commands::<BitcoinZMainNetwork>().keys().cloned().collect();
+    let mut rl = rustyline::Editor::<ShellHelper>::new();
+    rl.set_helper(Some(ShellHelper {
+        completions: Completions::new(commands),
+    }));
+
+    // There's no history file the first time around
+    if history_path.exists() {
+        if let Err(e) = rl.load_history(&history_path) {
+            error!("Couldn't load history from {:?}: {}", history_path, e);
+        }
+    }
 
     println!("Ready!");
 
@@ -156,16 +180,49 @@ pub fn start_interactive(command_tx: Sender<(String, Vec<String>)>, resp_rx: Rec
         }
     };
 
+    // Refresh the completion candidates from the wallet
+    let refresh_completions = |rl: &mut rustyline::Editor<ShellHelper>| {
+        let addresses = lightclient.get_all_addresses_sync();
+        let txids = lightclient
+            .get_recent_txids_sync(MAX_RECENT_TXIDS)
+            .iter()
+            .map(|txid| txid.to_string())
+            .collect();
+
+        if let Some(helper) = rl.helper_mut() {
+            helper.completions.update_wallet(addresses, txids);
+        }
+    };
+
+    let mut completions_height = -1;
+    let mut wallet_changed = true;
+
     loop {
         // Read the height first
         let height = json::parse(&send_command("height".to_string(), vec!["false".to_string()])).unwrap()["height"]
             .as_i64()
             .unwrap();
 
+        if wallet_changed || height != completions_height {
+            refresh_completions(&mut rl);
+            completions_height = height;
+            wallet_changed = false;
+        }
+
         let readline = rl.readline(&format!("({}) Block:{} (type 'help') >> ", chain_name, height));
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str());
+                let keep = rl
+                    .helper()
+                    .map(|h| keep_in_history(&line, h.completions.commands()))
+                    .unwrap_or(false);
+                if keep {
+                    rl.add_history_entry(line.as_str());
+                    if let Err(e) = rl.save_history(&history_path) {
+                        error!("Couldn't save history to {:?}: {}", history_path, e);
+                    }
+                }
+
                 // Parse command line arguments
                 let mut cmd_args = match shellwords::split(&line) {
                     Ok(args) => args,
@@ -182,6 +239,7 @@ pub fn start_interactive(command_tx: Sender<(String, Vec<String>)>, resp_rx: Rec
                 let cmd = cmd_args.remove(0);
                 let args: Vec<String> = cmd_args;
 
+                wallet_changed = WALLET_CHANGING_COMMANDS.contains(&cmd.to_ascii_lowercase().as_str());
                 println!("{}", send_command(cmd, args));
 
                 // Special check for Quit command.
@@ -190,10 +248,9 @@ pub fn start_interactive(command_tx: Sender<(String, Vec<String>)>, resp_rx: Rec
                 }
             }
             Err(rustyline::error::ReadlineError::Interrupted) => {
+                // Only cancel the line being edited. Use 'quit' or CTRL-D to exit.
                 println!("CTRL-C");
-                info!("CTRL-C");
-                println!("{}", send_command("save".to_string(), vec![]));
-                break;
+                continue;
             }
             Err(rustyline::error::ReadlineError::Eof) => {
                 println!("CTRL-D");
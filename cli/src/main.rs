@@ -63,7 +63,7 @@ pub fn main() {
     let nosync = matches.is_present("nosync");
 
     let startup_chan = startup(server, seed, birthday, maybe_data_dir ,!nosync, command.is_none());
-    let (command_tx, resp_rx) = match startup_chan {
+    let (command_tx, resp_rx, history_path, lightclient) = match startup_chan {
         Ok(c) => c,
         Err(e) => {
             let emsg = format!("Error during startup: {}\nIf you repeatedly run into this issue, you might have to restore your wallet from your seed phrase.", e);
@@ -80,7 +80,7 @@ pub fn main() {
     };
 
     if command.is_none() {
-        start_interactive(command_tx, resp_rx);
+        start_interactive(command_tx, resp_rx, history_path, lightclient);
     } else {
         command_tx
             .send((
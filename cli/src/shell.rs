@@ -0,0 +1,169 @@
+/// Line editing support for the interactive prompt
+///
+/// Decides which lines are kept in the persistent history (nothing that carries a seed,
+/// key or password) and offers tab completion for command names and, for arguments,
+/// the wallet's own addresses and recent txids, read from the light client.
+/// The wallet has no address labels, so there is nothing to complete for those yet.
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{Context, Helper};
+
+/// Commands whose arguments (or output) are secret. They are never written to the history.
+pub const SENSITIVE_COMMANDS: &[&str] = &["seed", "export", "import", "encrypt", "decrypt", "unlock"];
+
+/// Commands after which the wallet's addresses or transactions may have changed
+pub const WALLET_CHANGING_COMMANDS: &[&str] = &["new", "sync", "rescan", "send", "shield", "sweep", "import", "removekey", "clear"];
+
+/// How many txids to offer, most recent first
+pub const MAX_RECENT_TXIDS: usize = 20;
+
+/// Whether a line may be stored in the history file. Unknown commands are dropped too,
+/// since the most likely reason for one is a seed phrase or key pasted at the prompt.
+pub fn keep_in_history(line: &str, commands: &[String]) -> bool {
+    let cmd = match line.split_whitespace().next() {
+        Some(cmd) => cmd.to_ascii_lowercase(),
+        None => return false,
+    };
+
+    commands.contains(&cmd) && !SENSITIVE_COMMANDS.contains(&cmd.as_str())
+}
+
+pub struct Completions {
+    commands: Vec<String>,
+    addresses: Vec<String>,
+    txids: Vec<String>,
+}
+
+impl Completions {
+    pub fn new(mut commands: Vec<String>) -> Self {
+        commands.sort();
+
+        Self {
+            commands,
+            addresses: vec![],
+            txids: vec![],
+        }
+    }
+
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// Refresh the wallet candidates. `txids` are the most recent first.
+    pub fn update_wallet(&mut self, addresses: Vec<String>, txids: Vec<String>) {
+        self.addresses = addresses;
+        self.txids = txids;
+    }
+
+    /// Where the word under the cursor starts, and what it could be completed to
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &before[start..];
+
+        let pool: Vec<&String> = if before[..start].trim().is_empty() {
+            self.commands.iter().collect()
+        } else {
+            self.addresses.iter().chain(self.txids.iter()).collect()
+        };
+
+        let candidates = pool.into_iter().filter(|c| c.starts_with(word)).cloned().collect();
+        (start, candidates)
+    }
+}
+
+pub struct ShellHelper {
+    pub completions: Completions,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = self.completions.candidates(line, pos);
+
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .map(|c| Pair {
+                    display: c.clone(),
+                    replacement: c,
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for ShellHelper {}
+
+impl Highlighter for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<String> {
+        ["addresses", "balance", "export", "help", "list", "seed", "send", "shield", "unlock"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    fn synthetic_wallet() -> Completions {
+        let mut completions = Completions::new(commands());
+        completions.update_wallet(
+            ["zs1first", "zs1second", "t1abc"].iter().map(|s| s.to_string()).collect(),
+            ["ab22", "aa11", "ff33"].iter().map(|s| s.to_string()).collect(),
+        );
+
+        completions
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let commands = commands();
+
+        assert!(keep_in_history("balance", &commands));
+        assert!(keep_in_history("  send zs1first 1000", &commands));
+        assert!(keep_in_history("LIST", &commands));
+
+        assert!(!keep_in_history("seed", &commands));
+        assert!(!keep_in_history("export zs1first", &commands));
+        assert!(!keep_in_history("Unlock hunter2", &commands));
+        assert!(!keep_in_history("", &commands));
+
+        // A pasted seed phrase isn't a command
+        assert!(!keep_in_history("abandon abandon abandon art", &commands));
+    }
+
+    #[test]
+    fn test_command_completion() {
+        let completions = synthetic_wallet();
+
+        assert_eq!(completions.candidates("s", 1), (0, vec!["seed".to_string(), "send".to_string(), "shield".to_string()]));
+        assert_eq!(completions.candidates("ba", 2), (0, vec!["balance".to_string()]));
+        assert_eq!(completions.candidates("xyz", 3), (0, vec![]));
+    }
+
+    #[test]
+    fn test_argument_completion() {
+        let completions = synthetic_wallet();
+
+        assert_eq!(
+            completions.candidates("send zs1", 8),
+            (5, vec!["zs1first".to_string(), "zs1second".to_string()])
+        );
+        assert_eq!(completions.candidates("send t", 6), (5, vec!["t1abc".to_string()]));
+
+        // Txids keep the order they were given in
+        assert_eq!(completions.candidates("list a", 6), (5, vec!["ab22".to_string(), "aa11".to_string()]));
+        assert_eq!(completions.candidates("list ", 5).1.len(), 6);
+
+        // Only the text before the cursor counts
+        assert_eq!(completions.candidates("send zs1s extra", 9), (5, vec!["zs1second".to_string()]));
+    }
+}
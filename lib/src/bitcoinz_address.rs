@@ -0,0 +1,392 @@
+/// BitcoinZ Address Decoding
+///
+/// zcash_client_backend's `decode_payment_address` reports every failure the same way,
+/// and treats an address for another network like any other bad input. Here a
+/// checksum failure (usually a typo) and a valid address with the wrong HRP
+/// (usually the wrong network) get their own errors, so the user can be told which.
+/// Transparent addresses are base58check with a two byte prefix: t1 for a pubkey hash
+/// and t3 for a script hash on mainnet. Secret keys are WIF: base58check with 0x80, the
+/// key, and 0x01 for a compressed public key. Both go through `bitcoinz_base58`.
+
+use bech32::{FromBase32, Variant};
+use secp256k1::SecretKey;
+use std::convert::TryInto;
+use std::fmt;
+use zcash_primitives::{consensus::Parameters, legacy::TransparentAddress, sapling::PaymentAddress};
+
+use crate::bitcoinz_base58::{decode_check, encode_check, Base58Error};
+
+/// Errors from decoding an address
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddrError {
+    /// Not a bech32 string at all (no separator, bad characters, mixed case...)
+    InvalidEncoding(String),
+    /// Well-formed bech32, but the checksum doesn't match
+    BadChecksum,
+    /// The checksum is valid, but the address is for a different HRP
+    WrongHrp { expected: String, found: String },
+    /// Decodes cleanly, but isn't a valid Sapling payment address
+    InvalidPaymentAddress,
+    /// A base58check address whose prefix is neither this network's pubkey hash nor its
+    /// script hash prefix
+    UnknownPrefix([u8; 2]),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrError::InvalidEncoding(e) => write!(f, "Not a valid bech32 address: {}", e),
+            AddrError::BadChecksum => write!(f, "Address checksum is invalid, check for typos"),
+            AddrError::WrongHrp { expected, found } => write!(
+                f,
+                "Address is for a different network (prefix \"{}\", expected \"{}\")",
+                found, expected
+            ),
+            AddrError::InvalidPaymentAddress => write!(f, "Not a valid shielded payment address"),
+            AddrError::UnknownPrefix(prefix) => write!(
+                f,
+                "Not a transparent address for this network (prefix {:02x}{:02x})",
+                prefix[0], prefix[1]
+            ),
+        }
+    }
+}
+
+impl From<AddrError> for String {
+    fn from(e: AddrError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<Base58Error> for AddrError {
+    fn from(e: Base58Error) -> Self {
+        match e {
+            Base58Error::InvalidEncoding(e) => AddrError::InvalidEncoding(e),
+            Base58Error::BadChecksum => AddrError::BadChecksum,
+        }
+    }
+}
+
+/// Decode a Sapling payment address for the network described by `params`
+pub fn decode_shielded_address<P: Parameters>(s: &str, params: &P) -> Result<PaymentAddress, AddrError> {
+    let (hrp, data, variant) = bech32::decode(s).map_err(|e| match e {
+        bech32::Error::InvalidChecksum => AddrError::BadChecksum,
+        e => AddrError::InvalidEncoding(e.to_string()),
+    })?;
+
+    // Sapling addresses use the original bech32 checksum, not bech32m
+    if variant != Variant::Bech32 {
+        return Err(AddrError::BadChecksum);
+    }
+
+    let expected = params.hrp_sapling_payment_address();
+    if hrp != expected {
+        return Err(AddrError::WrongHrp {
+            expected: expected.to_string(),
+            found: hrp,
+        });
+    }
+
+    let bytes: [u8; 43] = Vec::<u8>::from_base32(&data)
+        .map_err(|e| AddrError::InvalidEncoding(e.to_string()))?
+        .try_into()
+        .map_err(|_| AddrError::InvalidPaymentAddress)?;
+
+    PaymentAddress::from_bytes(&bytes).ok_or(AddrError::InvalidPaymentAddress)
+}
+
+/// Decode a transparent address for the network described by `params`, either a
+/// pubkey hash (P2PKH) or a script hash (P2SH) one
+pub fn decode_transparent_address<P: Parameters>(s: &str, params: &P) -> Result<TransparentAddress, AddrError> {
+    let bytes = decode_check(s)?;
+
+    // Prefix (2) and hash (20)
+    if bytes.len() != 22 {
+        return Err(AddrError::InvalidEncoding(format!("expected 22 bytes, found {}", bytes.len())));
+    }
+
+    let prefix = [bytes[0], bytes[1]];
+    let hash: [u8; 20] = bytes[2..22].try_into().expect("20 bytes");
+    if prefix == params.b58_pubkey_address_prefix() {
+        Ok(TransparentAddress::PublicKey(hash))
+    } else if prefix == params.b58_script_address_prefix() {
+        Ok(TransparentAddress::Script(hash))
+    } else {
+        Err(AddrError::UnknownPrefix(prefix))
+    }
+}
+
+/// Encode a transparent address for the network described by `params`
+pub fn encode_transparent_address<P: Parameters>(addr: &TransparentAddress, params: &P) -> String {
+    let (prefix, hash) = match addr {
+        TransparentAddress::PublicKey(hash) => (params.b58_pubkey_address_prefix(), hash),
+        TransparentAddress::Script(hash) => (params.b58_script_address_prefix(), hash),
+    };
+
+    let mut payload = prefix.to_vec();
+    payload.extend_from_slice(hash);
+    encode_check(&payload)
+}
+
+/// What a user-supplied address turned out to be
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressKind {
+    /// t1 or t3
+    Transparent(TransparentAddress),
+    /// zs
+    Sapling(PaymentAddress),
+}
+
+/// Decode an address of either kind for the network described by `params`. BitcoinZ
+/// mainnet shares t1, t3 and zs with Zcash, so only addresses for another network's
+/// prefix or HRP (a testnet address on mainnet, say) can be told apart and refused.
+pub fn classify_address<P: Parameters>(s: &str, params: &P) -> Result<AddressKind, AddrError> {
+    let transparent = match decode_transparent_address(s, params) {
+        Ok(addr) => return Ok(AddressKind::Transparent(addr)),
+        Err(e) => e,
+    };
+    match decode_shielded_address(s, params) {
+        Ok(addr) => Ok(AddressKind::Sapling(addr)),
+        // Base58 that isn't bech32: the transparent error says what's wrong with it
+        Err(AddrError::InvalidEncoding(_)) if !matches!(transparent, AddrError::InvalidEncoding(_)) => Err(transparent),
+        Err(e) => Err(e),
+    }
+}
+
+/// Version byte of a mainnet WIF secret key
+pub const WIF_PREFIX: u8 = 0x80;
+/// Version byte of a testnet (and regtest) WIF secret key
+pub const TESTNET_WIF_PREFIX: u8 = 0xEF;
+
+/// Encode a secret key as mainnet WIF, flagged for a compressed public key
+pub fn secret_key_to_wif(sk: &SecretKey) -> String {
+    let mut payload = vec![WIF_PREFIX];
+    payload.extend_from_slice(&sk[..]);
+    payload.push(0x01);
+    encode_check(&payload)
+}
+
+/// Decode a mainnet WIF secret key, as `bitcoinz-cli dumpprivkey` prints it; the inverse
+/// of `secret_key_to_wif`. Also returns whether the key is for a compressed public key,
+/// which changes the address it spends from.
+pub fn wif_to_secret_key(wif: &str) -> Result<(SecretKey, bool), String> {
+    let bytes = decode_check(wif).map_err(|e| match e {
+        Base58Error::InvalidEncoding(e) => format!("Invalid WIF key: {}", e),
+        Base58Error::BadChecksum => "WIF key checksum is invalid, check for typos".to_string(),
+    })?;
+
+    // Prefix (1), key (32) and the compressed flag if there is one (1)
+    let compressed = match bytes.len() {
+        34 => true,
+        33 => false,
+        len => return Err(format!("Invalid WIF key: expected 33 or 34 bytes, found {}", len)),
+    };
+
+    match bytes[0] {
+        WIF_PREFIX => {}
+        TESTNET_WIF_PREFIX => return Err("WIF key is for testnet, not mainnet".to_string()),
+        prefix => return Err(format!("Not a WIF key (prefix {:02x})", prefix)),
+    }
+    if compressed && bytes[33] != 0x01 {
+        return Err(format!("Invalid WIF key: compressed flag is {:02x}", bytes[33]));
+    }
+
+    let sk = SecretKey::from_slice(&bytes[1..33]).map_err(|e| format!("Invalid WIF key: {}", e))?;
+    Ok((sk, compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BITCOINZ_MAINNET, BitcoinZTestNetwork};
+    use bech32::ToBase32;
+    use zcash_client_backend::encoding::encode_payment_address;
+    use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+    fn address() -> PaymentAddress {
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        ExtendedFullViewingKey::from(&extsk).default_address().1
+    }
+
+    #[test]
+    fn test_good_address() {
+        let encoded = encode_payment_address("zs", &address());
+        assert_eq!(decode_shielded_address(&encoded, &BITCOINZ_MAINNET), Ok(address()));
+
+        let encoded = encode_payment_address("ztestsapling", &address());
+        assert_eq!(decode_shielded_address(&encoded, &BitcoinZTestNetwork), Ok(address()));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let encoded = encode_payment_address("zs", &address());
+
+        // Change one character of the data part
+        let mut typo: Vec<char> = encoded.chars().collect();
+        let i = typo.len() / 2;
+        typo[i] = if typo[i] == 'q' { 'p' } else { 'q' };
+        let typo: String = typo.into_iter().collect();
+
+        assert_eq!(decode_shielded_address(&typo, &BITCOINZ_MAINNET), Err(AddrError::BadChecksum));
+    }
+
+    #[test]
+    fn test_wrong_hrp() {
+        // A perfectly good testnet address, given to mainnet
+        let encoded = encode_payment_address("ztestsapling", &address());
+        assert_eq!(
+            decode_shielded_address(&encoded, &BITCOINZ_MAINNET),
+            Err(AddrError::WrongHrp {
+                expected: "zs".to_string(),
+                found: "ztestsapling".to_string()
+            })
+        );
+
+        let encoded = encode_payment_address("zs", &address());
+        assert!(matches!(
+            decode_shielded_address(&encoded, &BitcoinZTestNetwork),
+            Err(AddrError::WrongHrp { .. })
+        ));
+    }
+
+    #[test]
+    fn test_not_an_address() {
+        assert!(matches!(
+            decode_shielded_address("t1Hsc1LR8yKnbbe3twRp88p6vFfC5t7DLbs", &BITCOINZ_MAINNET),
+            Err(AddrError::InvalidEncoding(_))
+        ));
+
+        // Valid bech32 with the right HRP, but too short to be an address
+        let short = bech32::encode("zs", [0u8; 20].to_base32(), Variant::Bech32).unwrap();
+        assert_eq!(
+            decode_shielded_address(&short, &BITCOINZ_MAINNET),
+            Err(AddrError::InvalidPaymentAddress)
+        );
+
+        // Bech32m checksums aren't accepted for Sapling addresses
+        let bech32m = bech32::encode("zs", address().to_bytes().to_base32(), Variant::Bech32m).unwrap();
+        assert_eq!(decode_shielded_address(&bech32m, &BITCOINZ_MAINNET), Err(AddrError::BadChecksum));
+    }
+
+    #[test]
+    fn test_transparent_addresses() {
+        use zcash_client_backend::encoding::AddressCodec;
+
+        let p2pkh = TransparentAddress::PublicKey([0x42u8; 20]);
+        let p2sh = TransparentAddress::Script([0x42u8; 20]);
+
+        let encoded = encode_transparent_address(&p2pkh, &BITCOINZ_MAINNET);
+        assert!(encoded.starts_with("t1"), "{}", encoded);
+        assert_eq!(decode_transparent_address(&encoded, &BITCOINZ_MAINNET), Ok(p2pkh.clone()));
+
+        let encoded = encode_transparent_address(&p2sh, &BITCOINZ_MAINNET);
+        assert!(encoded.starts_with("t3"), "{}", encoded);
+        assert_eq!(decode_transparent_address(&encoded, &BITCOINZ_MAINNET), Ok(p2sh.clone()));
+
+        // Same encoding as zcash_client_backend's, for both kinds
+        assert_eq!(encoded, p2sh.encode(&BITCOINZ_MAINNET));
+        assert_eq!(encode_transparent_address(&p2pkh, &BITCOINZ_MAINNET), p2pkh.encode(&BITCOINZ_MAINNET));
+
+        // A script hash pays to OP_HASH160 <hash> OP_EQUAL
+        let mut script = vec![0xa9, 0x14];
+        script.extend_from_slice(&[0x42u8; 20]);
+        script.push(0x87);
+        assert_eq!(p2sh.script().0, script);
+
+        // A testnet script address isn't one on mainnet
+        let testnet = encode_transparent_address(&p2sh, &BitcoinZTestNetwork);
+        assert!(testnet.starts_with("t2"), "{}", testnet);
+        assert_eq!(
+            decode_transparent_address(&testnet, &BITCOINZ_MAINNET),
+            Err(AddrError::UnknownPrefix([0x1c, 0xba]))
+        );
+    }
+
+    #[test]
+    fn test_bad_transparent_address() {
+        let encoded = encode_transparent_address(&TransparentAddress::Script([0x42u8; 20]), &BITCOINZ_MAINNET);
+
+        // Change one character after the prefix
+        let mut typo: Vec<char> = encoded.chars().collect();
+        typo[10] = if typo[10] == 'a' { 'b' } else { 'a' };
+        let typo: String = typo.into_iter().collect();
+        assert_eq!(decode_transparent_address(&typo, &BITCOINZ_MAINNET), Err(AddrError::BadChecksum));
+
+        assert!(matches!(
+            decode_transparent_address("t3-not-base58", &BITCOINZ_MAINNET),
+            Err(AddrError::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            decode_transparent_address(&encode_payment_address("zs", &address()), &BITCOINZ_MAINNET),
+            Err(AddrError::InvalidEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_classify_address() {
+        let t1 = encode_transparent_address(&TransparentAddress::PublicKey([0x42u8; 20]), &BITCOINZ_MAINNET);
+        assert_eq!(
+            classify_address(&t1, &BITCOINZ_MAINNET),
+            Ok(AddressKind::Transparent(TransparentAddress::PublicKey([0x42u8; 20])))
+        );
+        let zs = encode_payment_address("zs", &address());
+        assert_eq!(classify_address(&zs, &BITCOINZ_MAINNET), Ok(AddressKind::Sapling(address())));
+
+        // Zcash testnet addresses, tm and ztestsapling, aren't BitcoinZ mainnet ones
+        let tm = encode_transparent_address(&TransparentAddress::PublicKey([0x42u8; 20]), &BitcoinZTestNetwork);
+        assert!(tm.starts_with("tm"), "{}", tm);
+        assert_eq!(classify_address(&tm, &BITCOINZ_MAINNET), Err(AddrError::UnknownPrefix([0x1d, 0x25])));
+        let ztestsapling = encode_payment_address("ztestsapling", &address());
+        assert!(matches!(
+            classify_address(&ztestsapling, &BITCOINZ_MAINNET),
+            Err(AddrError::WrongHrp { .. })
+        ));
+
+        // A typo in a t-address is reported as one, not as bad bech32
+        let mut typo: Vec<char> = t1.chars().collect();
+        typo[10] = if typo[10] == 'a' { 'b' } else { 'a' };
+        let typo: String = typo.into_iter().collect();
+        assert_eq!(classify_address(&typo, &BITCOINZ_MAINNET), Err(AddrError::BadChecksum));
+        assert!(matches!(classify_address("hello", &BITCOINZ_MAINNET), Err(AddrError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_wif_keys() {
+        // The key 1, in the WIF every Bitcoin-derived wallet gives it
+        let mut bytes = [0u8; 32];
+        bytes[31] = 1;
+        let one = SecretKey::from_slice(&bytes).unwrap();
+        assert_eq!(
+            wif_to_secret_key("KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn"),
+            Ok((one, true))
+        );
+        assert_eq!(secret_key_to_wif(&one), "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn");
+
+        // The same key, for its uncompressed public key
+        assert_eq!(
+            wif_to_secret_key("5HpHagT65TZzG1PH3CSu63k8DbpvD8s5ip4nEB3kEsreAnchuDf"),
+            Ok((one, false))
+        );
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let wif = "KwFfNUhSDaASSAwtG7ssQM1uVX8RgX5GHWnnLfhfiQDigjioWXHH";
+        assert_eq!(wif_to_secret_key(wif).unwrap().0[..], [0x01u8; 32]);
+        assert_eq!(secret_key_to_wif(&sk), wif);
+    }
+
+    #[test]
+    fn test_bad_wif_keys() {
+        // Testnet
+        let err = wif_to_secret_key("cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9").unwrap_err();
+        assert!(err.contains("testnet"), "{}", err);
+
+        // A typo
+        let err = wif_to_secret_key("KwFfNUhSDaASSAwtG7ssQM1uVX8RgX5GHWnnLfhfiQDigjioWXHh").unwrap_err();
+        assert!(err.contains("checksum"), "{}", err);
+        let err = wif_to_secret_key("5HpHagT65TZzG1PH3CSu63k8DbpvD8s5ip4nEB3kEsreAnchuDe").unwrap_err();
+        assert!(err.contains("checksum"), "{}", err);
+
+        assert!(wif_to_secret_key("not a key").is_err());
+        assert!(wif_to_secret_key(&secret_key_to_wif(&SecretKey::from_slice(&[0x01u8; 32]).unwrap())[1..]).is_err());
+    }
+}
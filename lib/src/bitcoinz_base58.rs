@@ -0,0 +1,122 @@
+/// BitcoinZ Base58Check
+///
+/// Transparent addresses and WIF secret keys are base58check: a payload followed by the
+/// first four bytes of its double SHA-256, written in base58. The `base58` crate only
+/// does the alphabet; the checksum is added and checked here, for every caller.
+
+use base58::{FromBase58, ToBase58};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Why a string isn't base58check
+#[derive(Debug, Clone, PartialEq)]
+pub enum Base58Error {
+    /// Not base58 at all, or too short to end in a checksum
+    InvalidEncoding(String),
+    /// Base58, but the last four bytes aren't the checksum of the rest
+    BadChecksum,
+}
+
+impl fmt::Display for Base58Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Base58Error::InvalidEncoding(e) => write!(f, "Not valid base58check: {}", e),
+            Base58Error::BadChecksum => write!(f, "Base58check checksum is invalid, check for typos"),
+        }
+    }
+}
+
+impl From<Base58Error> for String {
+    fn from(e: Base58Error) -> Self {
+        e.to_string()
+    }
+}
+
+/// The first four bytes of the double SHA-256 of `payload`
+pub fn checksum(payload: &[u8]) -> [u8; 4] {
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&Sha256::digest(&Sha256::digest(payload))[..4]);
+    checksum
+}
+
+/// `payload` and its checksum, in base58
+pub fn encode_check(payload: &[u8]) -> String {
+    let mut bytes = payload.to_vec();
+    bytes.extend_from_slice(&checksum(payload));
+    bytes.to_base58()
+}
+
+/// The payload of the base58check string `s`, once its checksum has been checked
+pub fn decode_check(s: &str) -> Result<Vec<u8>, Base58Error> {
+    let mut bytes = s
+        .from_base58()
+        .map_err(|e| Base58Error::InvalidEncoding(format!("{:?}", e)))?;
+    if bytes.len() < 4 {
+        return Err(Base58Error::InvalidEncoding(format!(
+            "{} bytes is too short for a checksum",
+            bytes.len()
+        )));
+    }
+
+    let found = bytes.split_off(bytes.len() - 4);
+    if found != checksum(&bytes) {
+        return Err(Base58Error::BadChecksum);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The compressed public key of the secret key 1
+    const HASH160_OF_KEY_ONE: &str = "751e76e8199196d454941c45d1b3a323f1433bd6";
+
+    fn payload(prefix: &str) -> Vec<u8> {
+        hex::decode(format!("{}{}", prefix, HASH160_OF_KEY_ONE)).unwrap()
+    }
+
+    #[test]
+    fn test_address_vectors() {
+        // Bitcoin's address for the key, which every base58check implementation agrees on
+        assert_eq!(encode_check(&payload("00")), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+
+        // BitcoinZ mainnet's t1 and t3 prefixes
+        for (prefix, address) in [
+            ("1cb8", "t1UYsZVJkLPeMjxEtACvSxfWuNmddpWfxzs"),
+            ("1cbd", "t3VEtV2oBtHxjq7wKHJb3PHsqXHvMRgUmVw"),
+        ] {
+            assert_eq!(encode_check(&payload(prefix)), address);
+            assert_eq!(decode_check(address), Ok(payload(prefix)));
+        }
+    }
+
+    #[test]
+    fn test_wif_vectors() {
+        let mut one = vec![0x80u8];
+        one.extend_from_slice(&[0u8; 31]);
+        one.push(1);
+
+        assert_eq!(encode_check(&one), "5HpHagT65TZzG1PH3CSu63k8DbpvD8s5ip4nEB3kEsreAnchuDf");
+        one.push(0x01);
+        assert_eq!(encode_check(&one), "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn");
+        assert_eq!(decode_check("KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn"), Ok(one));
+    }
+
+    #[test]
+    fn test_leading_zeros_and_empty_payload() {
+        // Each leading zero byte is a '1'
+        assert_eq!(encode_check(&[]), "3QJmnh");
+        assert_eq!(encode_check(&[0]), "1Wh4bh");
+        assert_eq!(decode_check("1Wh4bh"), Ok(vec![0]));
+        assert_eq!(decode_check("3QJmnh"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_bad_strings() {
+        assert_eq!(decode_check("t1UYsZVJkLPeMjxEtACvSxfWuNmddpWfxzt"), Err(Base58Error::BadChecksum));
+        assert!(matches!(decode_check("t1-not-base58"), Err(Base58Error::InvalidEncoding(_))));
+        assert!(matches!(decode_check("111"), Err(Base58Error::InvalidEncoding(_))));
+        assert!(matches!(decode_check(""), Err(Base58Error::InvalidEncoding(_))));
+    }
+}
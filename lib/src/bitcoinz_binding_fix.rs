@@ -7,8 +7,54 @@
 ///
 /// This differs from standard Zcash which may compute it differently.
 
+use crate::bitcoinz_encoding::read_compact_size;
+use crate::bitcoinz_parse::{parse_bitcoinz_v4, V4_TX_HEADER};
 use zcash_primitives::transaction::Transaction;
 use byteorder::{LittleEndian, WriteBytesExt};
+use ff::PrimeField;
+use std::convert::TryInto;
+
+/// State of the binding signature in a serialized v4 transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindingSigStatus {
+    /// No spends or outputs, so the format omits the binding signature
+    Absent,
+    /// 64 bytes are present but all zero
+    AllZero,
+    /// R is a valid Jubjub point encoding and S a canonical scalar
+    WellFormed,
+    /// Non-zero bytes that can't be a RedJubjub signature
+    Malformed,
+}
+
+/// Classify a binding signature as returned by `locate_binding_signature`
+pub fn binding_sig_status(binding_sig: Option<&[u8; 64]>) -> BindingSigStatus {
+    let sig = match binding_sig {
+        Some(sig) => sig,
+        None => return BindingSigStatus::Absent,
+    };
+    
+    if sig.iter().all(|&b| b == 0) {
+        return BindingSigStatus::AllZero;
+    }
+    
+    let r_bytes: [u8; 32] = sig[..32].try_into().unwrap();
+    let s_bytes: [u8; 32] = sig[32..].try_into().unwrap();
+    let r_ok = bool::from(jubjub::AffinePoint::from_bytes(r_bytes).is_some());
+    let s_ok = bool::from(jubjub::Fr::from_repr(s_bytes).is_some());
+    
+    if r_ok && s_ok {
+        BindingSigStatus::WellFormed
+    } else {
+        BindingSigStatus::Malformed
+    }
+}
+
+/// Parse a serialized v4 transaction and return its binding signature, if the
+/// format carries one (only when there are Sapling spends or outputs)
+pub fn locate_binding_signature(tx_bytes: &[u8]) -> Result<Option<[u8; 64]>, String> {
+    Ok(parse_bitcoinz_v4(tx_bytes)?.binding_sig)
+}
 
 /// The core issue with BitcoinZ binding signatures
 pub fn explain_bitcoinz_binding_signature() -> String {
@@ -35,28 +81,26 @@ pub fn explain_bitcoinz_binding_signature() -> String {
 
 /// Analyze the binding signature in a transaction
 pub fn analyze_binding_signature_bytes(tx_bytes: &[u8]) -> Result<String, String> {
-    if tx_bytes.len() < 64 {
-        return Err("Transaction too short".to_string());
-    }
-    
-    // Binding signature is the last 64 bytes of a Sapling transaction
-    let sig_start = tx_bytes.len() - 64;
-    let binding_sig = &tx_bytes[sig_start..];
+    let binding_sig = locate_binding_signature(tx_bytes)?;
     
     let mut analysis = String::new();
     analysis.push_str("Binding Signature Analysis:\n");
-    analysis.push_str(&format!("Position: bytes {}-{}\n", sig_start, tx_bytes.len()));
     
-    // Check if it's all zeros
-    let all_zeros = binding_sig.iter().all(|&b| b == 0);
-    if all_zeros {
-        analysis.push_str("Status: All zeros (empty signature)\n");
-        analysis.push_str("This suggests no shielded value flow\n");
-    } else {
-        analysis.push_str("Status: Non-zero signature present\n");
-        analysis.push_str(&format!("First 8 bytes: {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x}\n",
-            binding_sig[0], binding_sig[1], binding_sig[2], binding_sig[3],
-            binding_sig[4], binding_sig[5], binding_sig[6], binding_sig[7]));
+    match binding_sig_status(binding_sig.as_ref()) {
+        BindingSigStatus::Absent => {
+            analysis.push_str("Status: Absent (no shielded spends or outputs)\n");
+        }
+        BindingSigStatus::AllZero => {
+            analysis.push_str(&format!("Position: bytes {}-{}\n", tx_bytes.len() - 64, tx_bytes.len()));
+            analysis.push_str("Status: All zeros (empty signature)\n");
+        }
+        status => {
+            let sig = binding_sig.unwrap();
+            analysis.push_str(&format!("Position: bytes {}-{}\n", tx_bytes.len() - 64, tx_bytes.len()));
+            analysis.push_str(&format!("Status: {:?}\n", status));
+            analysis.push_str(&format!("First 8 bytes: {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x}\n",
+                sig[0], sig[1], sig[2], sig[3], sig[4], sig[5], sig[6], sig[7]));
+        }
     }
     
     Ok(analysis)
@@ -79,20 +123,170 @@ pub fn zero_binding_signature(tx_bytes: &mut Vec<u8>) -> Result<(), String> {
     Ok(())
 }
 
-/// Check if the transaction has zero value balance
+/// Skip `len` bytes at `pos`, naming `field` if the transaction ends first
+fn skip(tx_bytes: &[u8], pos: usize, len: usize, field: &str) -> Result<usize, String> {
+    match pos.checked_add(len) {
+        Some(end) if end <= tx_bytes.len() => Ok(end),
+        _ => Err(format!("Transaction too short reading {}", field)),
+    }
+}
+
+/// Skip a compact size length prefix and the bytes it counts
+fn skip_script(tx_bytes: &[u8], pos: usize, field: &str) -> Result<usize, String> {
+    let (len, size) = read_compact_size(&tx_bytes[pos..])?
+        .ok_or_else(|| format!("Transaction too short reading {} length", field))?;
+    skip(tx_bytes, pos + size, len as usize, field)
+}
+
+/// Read the Sapling value balance of a serialized v4 transaction.
 /// For transparent-only transactions, value balance should be 0
 pub fn check_value_balance(tx_bytes: &[u8]) -> Result<i64, String> {
-    // In a Sapling transaction, value balance is an 8-byte signed integer
-    // It's located after lock_time (4 bytes) and expiry_height (4 bytes)
-    // but before the binding signature
+    // Header and version group ID
+    let mut pos = skip(tx_bytes, 0, 8, "header")?;
+    let header = u32::from_le_bytes(tx_bytes[0..4].try_into().unwrap());
+    if header != V4_TX_HEADER {
+        return Err(format!("Not a v4 transaction (header {:#010x})", header));
+    }
+    
+    let (input_count, size) = read_compact_size(&tx_bytes[pos..])?
+        .ok_or_else(|| "Transaction too short reading input count".to_string())?;
+    pos += size;
+    for _ in 0..input_count {
+        // Prevout, then the script sig, then the sequence
+        pos = skip(tx_bytes, pos, 36, "prevout")?;
+        pos = skip_script(tx_bytes, pos, "script sig")?;
+        pos = skip(tx_bytes, pos, 4, "sequence")?;
+    }
+    
+    let (output_count, size) = read_compact_size(&tx_bytes[pos..])?
+        .ok_or_else(|| "Transaction too short reading output count".to_string())?;
+    pos += size;
+    for _ in 0..output_count {
+        pos = skip(tx_bytes, pos, 8, "output value")?;
+        pos = skip_script(tx_bytes, pos, "script pubkey")?;
+    }
+    
+    // Lock time and expiry height, then the value balance itself
+    pos = skip(tx_bytes, pos, 8, "lock time and expiry height")?;
+    let end = skip(tx_bytes, pos, 8, "value balance")?;
+    
+    Ok(i64::from_le_bytes(tx_bytes[pos..end].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+    use crate::bitcoinz_v4_shielded::BitcoinZShieldedBuilder;
+    use crate::blaze::test_utils::FakeTxProver;
+    use crate::BITCOINZ_MAINNET;
+    use secp256k1::SecretKey;
+    use zcash_primitives::{
+        consensus::BlockHeight,
+        legacy::TransparentAddress,
+        memo::MemoBytes,
+        transaction::components::{Amount, OutPoint, TxOut},
+        zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
+    };
+    
+    fn transparent_input(value: u64) -> (OutPoint, TxOut, SecretKey) {
+        (
+            OutPoint::new([0x11u8; 32], 0),
+            TxOut {
+                value: Amount::from_u64(value).unwrap(),
+                script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+            },
+            SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+        )
+    }
     
-    // This is approximate - exact position depends on transaction structure
-    if tx_bytes.len() < 72 {
-        return Err("Transaction too short for value balance".to_string());
+    fn shielded_tx() -> Vec<u8> {
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let (outpoint, coin, sk) = transparent_input(100_000);
+        
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder.add_transparent_input(outpoint, coin, sk).unwrap();
+        builder.add_sapling_output(
+            Some(extfvk.fvk.ovk),
+            extfvk.default_address().1,
+            Amount::from_u64(90_000).unwrap(),
+            MemoBytes::empty(),
+        ).unwrap();
+        builder.build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap()).unwrap().raw
     }
     
-    // Try to find value balance (this is simplified)
-    // In reality, we'd need to parse the full transaction structure
+    #[test]
+    fn test_binding_sig_present() {
+        let tx = shielded_tx();
+        let sig = locate_binding_signature(&tx).unwrap();
+        assert!(sig.is_some());
+        assert_eq!(&sig.unwrap()[..], &tx[tx.len() - 64..]);
+        assert_eq!(binding_sig_status(sig.as_ref()), BindingSigStatus::WellFormed);
+    }
+    
+    #[test]
+    fn test_binding_sig_absent() {
+        let tx = build_bitcoinz_v4_no_sig(
+            &BITCOINZ_MAINNET,
+            vec![transparent_input(100_000)],
+            vec![(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap())],
+            BlockHeight::from(500_000),
+            None,
+            None,
+        ).unwrap().raw;
+        let sig = locate_binding_signature(&tx).unwrap();
+        assert!(sig.is_none());
+        assert_eq!(binding_sig_status(sig.as_ref()), BindingSigStatus::Absent);
+    }
     
-    Ok(0) // Placeholder
-}
\ No newline at end of file
+    #[test]
+    fn test_binding_sig_all_zero() {
+        let mut tx = shielded_tx();
+        zero_binding_signature(&mut tx).unwrap();
+        let sig = locate_binding_signature(&tx).unwrap();
+        assert_eq!(binding_sig_status(sig.as_ref()), BindingSigStatus::AllZero);
+    }
+    
+    #[test]
+    fn test_binding_sig_malformed() {
+        // S = 0xff..ff is not a canonical scalar
+        let sig = [0xffu8; 64];
+        assert_eq!(binding_sig_status(Some(&sig)), BindingSigStatus::Malformed);
+    }
+    
+    #[test]
+    fn test_value_balance() {
+        // t→t moves no value in or out of the Sapling pool
+        let t_to_t = build_bitcoinz_v4_no_sig(
+            &BITCOINZ_MAINNET,
+            vec![transparent_input(100_000)],
+            vec![(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap())],
+            BlockHeight::from(500_000),
+            None,
+            None,
+        ).unwrap().raw;
+        assert_eq!(check_value_balance(&t_to_t), Ok(0));
+        
+        // t→z moves the shielded output's value into the pool
+        let t_to_z = shielded_tx();
+        assert_eq!(check_value_balance(&t_to_z), Ok(-90_000));
+        assert_eq!(
+            check_value_balance(&t_to_z),
+            Ok(i64::from(parse_bitcoinz_v4(&t_to_z).unwrap().value_balance))
+        );
+        
+        // Only the empty shielded counts follow the value balance, so the rest isn't needed;
+        // cut off inside it, or not a v4 transaction at all, is an error
+        assert_eq!(check_value_balance(&t_to_t[..t_to_t.len() - 3]), Ok(0));
+        assert!(check_value_balance(&t_to_t[..t_to_t.len() - 4]).is_err());
+        assert!(check_value_balance(&t_to_t[..20]).is_err());
+        assert!(check_value_balance(&[0x01, 0x00, 0x00, 0x00, 0, 0, 0, 0]).is_err());
+    }
+    
+    #[test]
+    fn test_truncated_tx_rejected() {
+        let tx = shielded_tx();
+        assert!(locate_binding_signature(&tx[..tx.len() - 1]).is_err());
+    }
+}
@@ -12,9 +12,13 @@
 /// However, due to API limitations, the actual fix must be implemented
 /// in a custom transaction builder.
 
+use group::Group;
 use zcash_primitives::{
     consensus::BranchId,
+    constants::VALUE_COMMITMENT_VALUE_GENERATOR,
+    sapling::redjubjub::PublicKey,
     transaction::{
+        components::sapling::{Authorization, Bundle as SaplingBundle},
         sighash::SignableInput,
         Transaction,
     },
@@ -62,6 +66,45 @@ pub fn compute_bitcoinz_binding_message(
     message
 }
 
+/// The binding verification key as a verifier derives it from the transaction:
+/// the sum of the spend value commitments, minus the output value commitments,
+/// minus value_balance·G
+pub fn binding_verification_key(
+    spend_cvs: &[jubjub::ExtendedPoint],
+    output_cvs: &[jubjub::ExtendedPoint],
+    value_balance: i64,
+) -> PublicKey {
+    let mut bvk = jubjub::ExtendedPoint::identity();
+    for cv in spend_cvs {
+        bvk += cv;
+    }
+    for cv in output_cvs {
+        bvk -= cv;
+    }
+    
+    let balance = VALUE_COMMITMENT_VALUE_GENERATOR * jubjub::Fr::from(value_balance.unsigned_abs());
+    if value_balance < 0 {
+        bvk += jubjub::ExtendedPoint::from(balance);
+    } else {
+        bvk -= jubjub::ExtendedPoint::from(balance);
+    }
+    
+    PublicKey(bvk)
+}
+
+/// Derive the binding verification key of a built transaction's Sapling bundle, to
+/// check its binding signature against independently of whoever built it
+pub fn compute_bvk_from_bundle<A: Authorization>(bundle: &SaplingBundle<A>) -> Result<PublicKey, String> {
+    if bundle.shielded_spends.is_empty() && bundle.shielded_outputs.is_empty() {
+        return Err("Sapling bundle has no spends or outputs, so no binding signature".to_string());
+    }
+    
+    let spend_cvs: Vec<_> = bundle.shielded_spends.iter().map(|spend| spend.cv).collect();
+    let output_cvs: Vec<_> = bundle.shielded_outputs.iter().map(|output| output.cv).collect();
+    
+    Ok(binding_verification_key(&spend_cvs, &output_cvs, i64::from(bundle.value_balance)))
+}
+
 /// Note about implementation:
 /// 
 /// The proper way to implement BitcoinZ binding signatures would be to:
@@ -1,17 +1,56 @@
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
 use zcash_primitives::consensus::{BlockHeight, BranchId, NetworkUpgrade, Parameters};
-use crate::BitcoinZMainNetwork;
 
 // BitcoinZ-specific branch IDs
 // These need to be confirmed with BitcoinZ developers
+// Flip this once they are
+pub const BRANCH_IDS_CONFIRMED: bool = false;
+
 pub const BITCOINZ_OVERWINTER_BRANCH_ID: u32 = 0x5ba8_1b19; // Same as Zcash for now
 pub const BITCOINZ_SAPLING_BRANCH_ID: u32 = 0x76b8_09bb;    // Same as Zcash for now
 pub const BITCOINZ_BLOSSOM_BRANCH_ID: u32 = 0x2bb4_0e60;    // Same as Zcash for now
 pub const BITCOINZ_HEARTWOOD_BRANCH_ID: u32 = 0xf5b9_230b;  // Same as Zcash for now
 pub const BITCOINZ_CANOPY_BRANCH_ID: u32 = 0xe9ff_75a6;     // Same as Zcash for now
 
+static BRANCH_ID_WARNING_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Log a warning at startup, once per process, while the branch IDs are unconfirmed.
+/// Returns true if this call logged it.
+pub fn warn_if_branch_ids_unconfirmed() -> bool {
+    warn_once(BRANCH_IDS_CONFIRMED, &BRANCH_ID_WARNING_SHOWN)
+}
+
+fn warn_once(confirmed: bool, shown: &AtomicBool) -> bool {
+    if confirmed || shown.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    warn!(
+        "BitcoinZ consensus branch IDs are unconfirmed placeholders copied from Zcash. \
+         Shielded transactions may be rejected by the network."
+    );
+    true
+}
+
+/// Shielded transactions are probably rejected while the branch IDs (and the binding
+/// signature that depends on them) are unconfirmed, so they're only built when the caller
+/// forces it
+pub fn check_shielded_send_allowed(confirmed: bool, force_experimental: bool) -> Result<(), String> {
+    if confirmed || force_experimental {
+        return Ok(());
+    }
+
+    Err("Refusing to build a shielded transaction: BitcoinZ's consensus branch IDs and binding \
+         signature are still unconfirmed, so the network will most likely reject it. Sends between \
+         transparent addresses aren't affected, so send from a t-address to a t-address instead, or \
+         add --force-experimental to try the shielded send anyway."
+        .to_string())
+}
+
 /// Get the BitcoinZ branch ID for a given height
 /// This function maps BitcoinZ network upgrade heights to their corresponding branch IDs
-pub fn bitcoinz_branch_id_for_height(network: &BitcoinZMainNetwork, height: BlockHeight) -> BranchId {
+pub fn bitcoinz_branch_id_for_height<P: Parameters>(network: &P, height: BlockHeight) -> BranchId {
     // Check which network upgrade is active at this height
     if let Some(canopy_height) = network.activation_height(NetworkUpgrade::Canopy) {
         if height >= canopy_height {
@@ -47,6 +86,12 @@ pub fn bitcoinz_branch_id_for_height(network: &BitcoinZMainNetwork, height: Bloc
     BranchId::Sprout
 }
 
+/// The consensus branch ID a transaction signed for `height` commits to in its sighash
+/// personalization
+pub fn bitcoinz_consensus_branch_id<P: Parameters>(network: &P, height: BlockHeight) -> u32 {
+    bitcoinz_branch_id_to_u32(bitcoinz_branch_id_for_height(network, height))
+}
+
 /// Convert a BitcoinZ branch ID value to BranchId enum
 /// This is needed because BitcoinZ might use different numeric values than Zcash
 pub fn bitcoinz_branch_id_from_u32(value: u32) -> Result<BranchId, &'static str> {
@@ -74,4 +119,114 @@ pub fn bitcoinz_branch_id_to_u32(branch_id: BranchId) -> u32 {
         #[cfg(feature = "zfuture")]
         BranchId::ZFuture => panic!("BitcoinZ does not support ZFuture"),
     }
-}
\ No newline at end of file
+}
+
+/// A network that activates every upgrade BitcoinZ knows about, one after another, so
+/// tests can sign on either side of each activation
+#[cfg(test)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StagedUpgradesNetwork;
+
+#[cfg(test)]
+impl StagedUpgradesNetwork {
+    pub(crate) const OVERWINTER: u32 = 100;
+    pub(crate) const SAPLING: u32 = 200;
+    pub(crate) const BLOSSOM: u32 = 300;
+    pub(crate) const HEARTWOOD: u32 = 400;
+    pub(crate) const CANOPY: u32 = 500;
+}
+
+#[cfg(test)]
+impl Parameters for StagedUpgradesNetwork {
+    fn activation_height(&self, nu: NetworkUpgrade) -> Option<BlockHeight> {
+        match nu {
+            NetworkUpgrade::Overwinter => Some(BlockHeight::from(Self::OVERWINTER)),
+            NetworkUpgrade::Sapling => Some(BlockHeight::from(Self::SAPLING)),
+            NetworkUpgrade::Blossom => Some(BlockHeight::from(Self::BLOSSOM)),
+            NetworkUpgrade::Heartwood => Some(BlockHeight::from(Self::HEARTWOOD)),
+            NetworkUpgrade::Canopy => Some(BlockHeight::from(Self::CANOPY)),
+            _ => None,
+        }
+    }
+
+    fn coin_type(&self) -> u32 {
+        crate::BITCOINZ_MAINNET.coin_type()
+    }
+
+    fn address_network(&self) -> Option<zcash_address::Network> {
+        crate::BITCOINZ_MAINNET.address_network()
+    }
+
+    fn hrp_sapling_extended_spending_key(&self) -> &str {
+        crate::BITCOINZ_MAINNET.hrp_sapling_extended_spending_key()
+    }
+
+    fn hrp_sapling_extended_full_viewing_key(&self) -> &str {
+        crate::BITCOINZ_MAINNET.hrp_sapling_extended_full_viewing_key()
+    }
+
+    fn hrp_sapling_payment_address(&self) -> &str {
+        crate::BITCOINZ_MAINNET.hrp_sapling_payment_address()
+    }
+
+    fn b58_pubkey_address_prefix(&self) -> [u8; 2] {
+        crate::BITCOINZ_MAINNET.b58_pubkey_address_prefix()
+    }
+
+    fn b58_script_address_prefix(&self) -> [u8; 2] {
+        crate::BITCOINZ_MAINNET.b58_script_address_prefix()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BITCOINZ_MAINNET, BITCOINZ_TESTNET};
+
+    #[test]
+    fn test_mainnet_branch_ids() {
+        // Overwinter and Sapling activate together at 328500, and nothing after that
+        let branch_id = |height: u32| bitcoinz_consensus_branch_id(&BITCOINZ_MAINNET, BlockHeight::from(height));
+        assert_eq!(branch_id(328_499), 0);
+        assert_eq!(branch_id(328_500), BITCOINZ_SAPLING_BRANCH_ID);
+        assert_eq!(branch_id(500_000), BITCOINZ_SAPLING_BRANCH_ID);
+        assert_eq!(branch_id(u32::MAX), BITCOINZ_SAPLING_BRANCH_ID);
+
+        let branch_id = |height: u32| bitcoinz_consensus_branch_id(&BITCOINZ_TESTNET, BlockHeight::from(height));
+        assert_eq!(branch_id(0), 0);
+        assert_eq!(branch_id(1), BITCOINZ_SAPLING_BRANCH_ID);
+    }
+
+    #[test]
+    fn test_branch_id_at_each_activation() {
+        let network = StagedUpgradesNetwork;
+        for (activation, before, after) in [
+            (StagedUpgradesNetwork::OVERWINTER, 0, BITCOINZ_OVERWINTER_BRANCH_ID),
+            (StagedUpgradesNetwork::SAPLING, BITCOINZ_OVERWINTER_BRANCH_ID, BITCOINZ_SAPLING_BRANCH_ID),
+            (StagedUpgradesNetwork::BLOSSOM, BITCOINZ_SAPLING_BRANCH_ID, BITCOINZ_BLOSSOM_BRANCH_ID),
+            (StagedUpgradesNetwork::HEARTWOOD, BITCOINZ_BLOSSOM_BRANCH_ID, BITCOINZ_HEARTWOOD_BRANCH_ID),
+            (StagedUpgradesNetwork::CANOPY, BITCOINZ_HEARTWOOD_BRANCH_ID, BITCOINZ_CANOPY_BRANCH_ID),
+        ] {
+            assert_eq!(bitcoinz_consensus_branch_id(&network, BlockHeight::from(activation - 1)), before);
+            assert_eq!(bitcoinz_consensus_branch_id(&network, BlockHeight::from(activation)), after);
+        }
+    }
+
+    #[test]
+    fn test_unconfirmed_warning_once() {
+        let shown = AtomicBool::new(false);
+        let warnings = (0..3).filter(|_| warn_once(false, &shown)).count();
+        assert_eq!(warnings, 1);
+
+        let shown = AtomicBool::new(false);
+        assert!(!warn_once(true, &shown));
+    }
+
+    #[test]
+    fn test_shielded_send_guard() {
+        let err = check_shielded_send_allowed(false, false).unwrap_err();
+        assert!(err.contains("--force-experimental"), "{}", err);
+        assert!(check_shielded_send_allowed(false, true).is_ok());
+        assert!(check_shielded_send_allowed(true, false).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+/// BitcoinZ Transaction Builders
+///
+/// There have been many attempts at getting bitcoinzd to accept our transactions, and
+/// most of them are still here. `list_builders` says which one makes which kind of
+/// transaction and whether it should be used, so that lives in code next to the
+/// builders rather than in comments scattered across them.
+
+use std::fmt;
+
+use crate::bitcoinz_transaction::BitcoinZTxType;
+
+/// Whether a builder should be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuilderStatus {
+    /// Makes transactions the node accepts, and is what new code should call
+    Supported,
+    /// Works, but for comparing against other implementations rather than sending
+    Experimental,
+    /// Works, but only wraps a supported builder for callers that haven't moved over
+    Deprecated,
+    /// Doesn't make a transaction the node accepts
+    Stub,
+}
+
+impl fmt::Display for BuilderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuilderStatus::Supported => write!(f, "supported"),
+            BuilderStatus::Experimental => write!(f, "experimental"),
+            BuilderStatus::Deprecated => write!(f, "deprecated"),
+            BuilderStatus::Stub => write!(f, "stub"),
+        }
+    }
+}
+
+/// One of the transaction builders
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuilderInfo {
+    /// The function or type to call, with its module
+    pub name: &'static str,
+    /// The kinds of transaction it can make
+    pub tx_types: Vec<BitcoinZTxType>,
+    pub status: BuilderStatus,
+    pub notes: &'static str,
+}
+
+/// Every transaction builder in the crate
+pub fn list_builders() -> Vec<BuilderInfo> {
+    use BitcoinZTxType::*;
+
+    vec![
+        BuilderInfo {
+            name: "bitcoinz_transparent_builder::transparent_builder",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Supported,
+            notes: "v1, v3 or v4 as the node accepts at the height (see select_format). The wallet's t→t sends.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_legacy_builder::build_legacy_tx",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Deprecated,
+            notes: "v1 through TransparentTxBuilder. The simple v1 and legacy tx builders were folded into it.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_overwinter_builder::build_overwinter_tx",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Deprecated,
+            notes: "v3 through TransparentTxBuilder, with variants taking a sighash type or sequences.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Deprecated,
+            notes: "v4 with no shielded parts, so no binding signature, through TransparentTxBuilder.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_rpc_builder::build_bitcoinz_rpc_tx",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Experimental,
+            notes: "v4 the way createrawtransaction and signrawtransactionwithkey make it, to check against the node.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_js_bridge::build_bitcoinz_js_tx",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Experimental,
+            notes: "bitcore-lib-btcz through Node.js, behind the js-bridge feature. One input and at most two outputs.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_overwinter::build_overwinter_tx",
+            tx_types: vec![TransparentToTransparent],
+            status: BuilderStatus::Stub,
+            notes: "Always fails. Use bitcoinz_overwinter_builder.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_v4_shielded::BitcoinZShieldedBuilder",
+            tx_types: vec![TransparentToShielded, ShieldedToTransparent, ShieldedToShielded, Mixed],
+            status: BuilderStatus::Supported,
+            notes: "v4 with the binding signature over bvk || sighash. The wallet's shielded sends.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_shielded_tx",
+            tx_types: vec![TransparentToShielded, ShieldedToTransparent, ShieldedToShielded],
+            status: BuilderStatus::Supported,
+            notes: "One function per kind of transfer over BitcoinZShieldedBuilder, checking the funds first.",
+        },
+        BuilderInfo {
+            name: "bitcoinz_shielded_builder_simple::build_shielded_transaction",
+            tx_types: vec![TransparentToShielded],
+            status: BuilderStatus::Stub,
+            notes: "Zeroed output descriptions and binding signature. Use BitcoinZShieldedBuilder.",
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BitcoinZTxType::*;
+
+    #[test]
+    fn test_list_builders() {
+        let builders = list_builders();
+        let names = builders.iter().map(|b| b.name).collect::<Vec<_>>();
+        for name in [
+            "bitcoinz_transparent_builder::transparent_builder",
+            "bitcoinz_legacy_builder::build_legacy_tx",
+            "bitcoinz_overwinter_builder::build_overwinter_tx",
+            "bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig",
+            "bitcoinz_rpc_builder::build_bitcoinz_rpc_tx",
+            "bitcoinz_v4_shielded::BitcoinZShieldedBuilder",
+        ] {
+            assert_eq!(names.iter().filter(|n| **n == name).count(), 1, "{}", name);
+        }
+
+        for builder in &builders {
+            assert!(!builder.status.to_string().is_empty(), "{}", builder.name);
+            assert!(!builder.tx_types.is_empty(), "{}", builder.name);
+            assert!(!builder.notes.is_empty(), "{}", builder.name);
+        }
+
+        // Something supported for each kind of transaction
+        for tx_type in [TransparentToTransparent, TransparentToShielded, ShieldedToTransparent, ShieldedToShielded] {
+            assert!(
+                builders
+                    .iter()
+                    .any(|b| b.status == BuilderStatus::Supported && b.tx_types.contains(&tx_type)),
+                "{:?}",
+                tx_type
+            );
+        }
+    }
+}
@@ -0,0 +1,406 @@
+/// BitcoinZ Coin Selection
+///
+/// Picks transparent UTXOs to fund a transaction, so callers of the builders
+/// don't have to choose inputs by hand. `select_coins_with` takes a `CoinSelection`
+/// strategy and charges the fee a `FeePolicy` asks for the transaction's estimated
+/// size; `select_coins` is it with the defaults, largest first.
+
+use std::fmt;
+use std::str::FromStr;
+
+use zcash_primitives::transaction::components::Amount;
+
+use crate::bitcoinz_transaction::{estimate_tx_size, FeePolicy, DUST_THRESHOLD, FEE_SIZE_UNIT};
+use crate::bitcoinz_transparent_builder::TransparentTxFormat;
+use crate::lightwallet::data::Utxo;
+
+/// Branch and bound gives up, and falls back to largest first, after this many steps
+pub const BNB_MAX_TRIES: usize = 100_000;
+
+/// Why no coins could be selected
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoinSelectError {
+    /// The target or the change is outside the valid monetary range
+    InvalidAmount(&'static str),
+    /// The available coins don't cover the amount plus the fee
+    InsufficientFunds { available: u64, required: u64 },
+}
+
+impl fmt::Display for CoinSelectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinSelectError::InvalidAmount(field) => write!(f, "Invalid amount in {}", field),
+            CoinSelectError::InsufficientFunds { available, required } => {
+                write!(f, "Insufficient funds: have {} zatoshis, need {}", available, required)
+            }
+        }
+    }
+}
+
+impl From<CoinSelectError> for String {
+    fn from(e: CoinSelectError) -> Self {
+        e.to_string()
+    }
+}
+
+/// How `select_coins_with` picks UTXOs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSelection {
+    /// Biggest coins first: the fewest inputs, so the smallest fee
+    LargestFirst = 0,
+    /// Smallest coins first, consolidating them into the change
+    SmallestFirst = 1,
+    /// Look for coins that pay the target and fee exactly, with no change output, and
+    /// go largest first if there aren't any
+    BranchAndBound = 2,
+}
+
+impl Default for CoinSelection {
+    fn default() -> Self {
+        CoinSelection::LargestFirst
+    }
+}
+
+impl CoinSelection {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CoinSelection::LargestFirst),
+            1 => Some(CoinSelection::SmallestFirst),
+            2 => Some(CoinSelection::BranchAndBound),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoinSelection::LargestFirst => "largest_first",
+            CoinSelection::SmallestFirst => "smallest_first",
+            CoinSelection::BranchAndBound => "exact",
+        }
+    }
+}
+
+impl FromStr for CoinSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "largest_first" => Ok(CoinSelection::LargestFirst),
+            "smallest_first" => Ok(CoinSelection::SmallestFirst),
+            "exact" => Ok(CoinSelection::BranchAndBound),
+            _ => Err(format!("Unknown coin selection {} (largest_first, smallest_first or exact)", s)),
+        }
+    }
+}
+
+/// Marginal size of a signed P2PKH input: outpoint (36), script length (1),
+/// script sig of up to 107 bytes and sequence (4)
+pub const P2PKH_INPUT_SIZE: u64 = 148;
+
+/// Select UTXOs, largest first, to pay `target` to one output at the default fee for the
+/// transaction's size, as a v4 transaction would be. Returns the selection and the change
+/// left over. `utxos` should only contain coins that are spendable now.
+pub fn select_coins(utxos: &[Utxo], target: Amount) -> Result<(Vec<Utxo>, Amount), CoinSelectError> {
+    select_coins_with(
+        utxos,
+        target,
+        1,
+        CoinSelection::default(),
+        &FeePolicy::default(),
+        TransparentTxFormat::Sapling,
+    )
+}
+
+/// What one more P2PKH input adds to the fee at `policy`'s per-kB rate. A coin worth no
+/// more than this costs more to spend than it brings in.
+pub fn input_cost(policy: &FeePolicy) -> u64 {
+    let per_kb = u64::from(policy.per_kb);
+    (per_kb * P2PKH_INPUT_SIZE + FEE_SIZE_UNIT as u64 - 1) / FEE_SIZE_UNIT as u64
+}
+
+fn fee_with(policy: &FeePolicy, format: TransparentTxFormat, inputs: usize, outputs: usize) -> u64 {
+    u64::from(policy.fee_for_size(estimate_tx_size(inputs, outputs, 0, 0, format)))
+}
+
+/// The change when `inputs` coins worth `total` pay `target` to `outputs` outputs, or None
+/// if they don't cover it and the fee. Change that would be dust is left to the fee, and
+/// comes back as 0.
+fn change_for(
+    total: u64,
+    target: u64,
+    inputs: usize,
+    outputs: usize,
+    policy: &FeePolicy,
+    format: TransparentTxFormat,
+) -> Option<u64> {
+    let with_change = target.saturating_add(fee_with(policy, format, inputs, outputs + 1));
+    if total >= with_change.saturating_add(DUST_THRESHOLD) {
+        return Some(total - with_change);
+    }
+
+    let without_change = target.saturating_add(fee_with(policy, format, inputs, outputs));
+    if total >= without_change {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// Select UTXOs with `strategy` to pay `target` to `outputs` P2PKH outputs in a `format`
+/// transaction, with the fee `policy` asks for. Coins worth no more than `input_cost`
+/// are never picked. Returns the selection and the change, which is 0 when what's left
+/// over would be dust and goes to the fee.
+pub fn select_coins_with(
+    utxos: &[Utxo],
+    target: Amount,
+    outputs: usize,
+    strategy: CoinSelection,
+    policy: &FeePolicy,
+    format: TransparentTxFormat,
+) -> Result<(Vec<Utxo>, Amount), CoinSelectError> {
+    if target.is_negative() {
+        return Err(CoinSelectError::InvalidAmount("target"));
+    }
+    let target = u64::from(target);
+
+    let cost = input_cost(policy);
+    let mut candidates = utxos.iter().filter(|utxo| utxo.value > cost).collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.value.cmp(&a.value));
+
+    match strategy {
+        CoinSelection::LargestFirst => {}
+        CoinSelection::SmallestFirst => candidates.reverse(),
+        CoinSelection::BranchAndBound => {
+            if let Some(selected) = branch_and_bound(&candidates, target, outputs, policy, format) {
+                return Ok((selected.into_iter().cloned().collect(), Amount::zero()));
+            }
+        }
+    }
+
+    let mut selected = vec![];
+    let mut total = 0u64;
+    for utxo in candidates {
+        selected.push(utxo.clone());
+        total = total.saturating_add(utxo.value);
+
+        if let Some(change) = change_for(total, target, selected.len(), outputs, policy, format) {
+            let change = Amount::from_u64(change).map_err(|_| CoinSelectError::InvalidAmount("change"))?;
+            return Ok((selected, change));
+        }
+    }
+
+    Err(CoinSelectError::InsufficientFunds {
+        available: total,
+        required: target.saturating_add(fee_with(policy, format, selected.len().max(1), outputs)),
+    })
+}
+
+/// Coins from `candidates` (largest first) that pay `target` and the fee, with less than
+/// the dust threshold left over, so there's no change output
+fn branch_and_bound<'a>(
+    candidates: &[&'a Utxo],
+    target: u64,
+    outputs: usize,
+    policy: &FeePolicy,
+    format: TransparentTxFormat,
+) -> Option<Vec<&'a Utxo>> {
+    // What candidates[i..] add up to, to stop early when they can't reach the target
+    let mut remaining = vec![0u64; candidates.len() + 1];
+    for i in (0..candidates.len()).rev() {
+        remaining[i] = remaining[i + 1].saturating_add(candidates[i].value);
+    }
+
+    let mut search = BnbSearch { candidates, remaining, target, outputs, policy, format, tries: 0 };
+    let mut chosen = vec![];
+    if search.step(0, 0, &mut chosen) {
+        Some(chosen)
+    } else {
+        None
+    }
+}
+
+struct BnbSearch<'a, 'b> {
+    candidates: &'b [&'a Utxo],
+    remaining: Vec<u64>,
+    target: u64,
+    outputs: usize,
+    policy: &'b FeePolicy,
+    format: TransparentTxFormat,
+    tries: usize,
+}
+
+impl<'a, 'b> BnbSearch<'a, 'b> {
+    /// Decide on candidates[i..], with `chosen` worth `total` taken so far
+    fn step(&mut self, i: usize, total: u64, chosen: &mut Vec<&'a Utxo>) -> bool {
+        self.tries += 1;
+        if self.tries > BNB_MAX_TRIES {
+            return false;
+        }
+
+        if !chosen.is_empty() {
+            let required = self.target.saturating_add(fee_with(self.policy, self.format, chosen.len(), self.outputs));
+            if total >= required {
+                // Any more coins only leave more over, since each is worth more than it costs
+                return total - required < DUST_THRESHOLD;
+            }
+        }
+
+        // Even with everything that's left, and only the fee for one more input, it's short
+        let least_required = self.target.saturating_add(fee_with(self.policy, self.format, chosen.len() + 1, self.outputs));
+        if i == self.candidates.len() || total.saturating_add(self.remaining[i]) < least_required {
+            return false;
+        }
+
+        chosen.push(self.candidates[i]);
+        if self.step(i + 1, total.saturating_add(self.candidates[i].value), chosen) {
+            return true;
+        }
+        chosen.pop();
+
+        self.step(i + 1, total, chosen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zcash_primitives::transaction::TxId;
+
+    fn utxo(n: u8, value: u64) -> Utxo {
+        Utxo {
+            address: "t1test".to_string(),
+            txid: TxId::from_bytes([n; 32]),
+            output_index: 0,
+            script: vec![],
+            value,
+            height: 100,
+            spent_at_height: None,
+            spent: None,
+            unconfirmed_spent: None,
+        }
+    }
+
+    /// The default fee for `inputs` P2PKH inputs paying `outputs` outputs
+    fn fee(inputs: usize, outputs: usize) -> u64 {
+        fee_with(&FeePolicy::default(), TransparentTxFormat::Sapling, inputs, outputs)
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let utxos = vec![utxo(1, 1_000), utxo(2, 50_000 + fee(1, 1))];
+
+        let (selected, change) = select_coins(&utxos, Amount::from_u64(50_000).unwrap()).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].value, 50_000 + fee(1, 1));
+        assert_eq!(change, Amount::zero());
+    }
+
+    #[test]
+    fn test_change() {
+        let utxos = vec![utxo(1, 30_000), utxo(2, 10_000), utxo(3, 40_000)];
+
+        // Largest first: 40,000 isn't enough, 40,000 + 30,000 is
+        let (selected, change) = select_coins(&utxos, Amount::from_u64(50_000).unwrap()).unwrap();
+        assert_eq!(selected.iter().map(|u| u.value).collect::<Vec<_>>(), vec![40_000, 30_000]);
+        assert_eq!(u64::from(change), 70_000 - 50_000 - fee(2, 2));
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let utxos = vec![utxo(1, 30_000), utxo(2, 20_000)];
+
+        // Covers the target, but not the fee for both inputs
+        let err = select_coins(&utxos, Amount::from_u64(50_000).unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            CoinSelectError::InsufficientFunds {
+                available: 50_000,
+                required: 50_000 + fee(2, 1),
+            }
+        );
+
+        assert!(select_coins(&[], Amount::from_u64(1).unwrap()).is_err());
+    }
+
+    fn select(utxos: &[Utxo], target: u64, strategy: CoinSelection) -> Result<(Vec<u64>, u64), CoinSelectError> {
+        let (selected, change) = select_coins_with(
+            utxos,
+            Amount::from_u64(target).unwrap(),
+            1,
+            strategy,
+            &FeePolicy::default(),
+            TransparentTxFormat::Sapling,
+        )?;
+        Ok((selected.iter().map(|u| u.value).collect(), u64::from(change)))
+    }
+
+    #[test]
+    fn test_strategies() {
+        // Small transactions pay the minimum fee
+        let fee = 10_000;
+        let utxos = vec![utxo(1, 30_000), utxo(2, 50_000), utxo(3, 15_000), utxo(4, 20_000)];
+
+        assert_eq!(select(&utxos, 60_000, CoinSelection::LargestFirst), Ok((vec![50_000, 30_000], 80_000 - 60_000 - fee)));
+        assert_eq!(
+            select(&utxos, 60_000, CoinSelection::SmallestFirst),
+            Ok((vec![15_000, 20_000, 30_000, 50_000], 115_000 - 60_000 - fee))
+        );
+
+        // 50,000 + 20,000 pays 60,000 and the fee exactly
+        assert_eq!(select(&utxos, 60_000, CoinSelection::BranchAndBound), Ok((vec![50_000, 20_000], 0)));
+        assert_eq!(CoinSelection::default(), CoinSelection::LargestFirst);
+    }
+
+    #[test]
+    fn test_exact_matches() {
+        let utxos = (1..=12).map(|i| utxo(i, i as u64 * 7_000)).collect::<Vec<_>>();
+        let fee = 10_000;
+
+        // Every total a few of the coins make can be paid with no change
+        for target in [11_000, 25_000, 42_000, 60_000, 111_000] {
+            let (selected, change) = select(&utxos, target, CoinSelection::BranchAndBound).unwrap();
+            assert_eq!(change, 0);
+            assert_eq!(selected.iter().sum::<u64>(), target + fee, "target {}", target);
+        }
+
+        // Less than the dust threshold over is close enough; the rest goes to the fee
+        let (selected, change) = select(&utxos, 60_000 - DUST_THRESHOLD + 1, CoinSelection::BranchAndBound).unwrap();
+        assert_eq!((selected.iter().sum::<u64>(), change), (70_000, 0));
+
+        // No set of coins lands on 60,500 + fee, so it's largest first after all
+        let (selected, change) = select(&utxos, 60_500, CoinSelection::BranchAndBound).unwrap();
+        assert_eq!(selected, vec![84_000]);
+        assert_eq!(change, 84_000 - 60_500 - fee);
+    }
+
+    #[test]
+    fn test_uneconomic_coins_skipped() {
+        let policy = FeePolicy::default();
+        assert_eq!(input_cost(&policy), 1_480);
+
+        // Hundreds of coins worth less than they cost to spend, and one that pays for the send
+        let mut utxos = (0..200).map(|i| utxo(i as u8, 1_400)).collect::<Vec<_>>();
+        utxos.push(utxo(201, 60_000));
+
+        for strategy in [CoinSelection::LargestFirst, CoinSelection::SmallestFirst, CoinSelection::BranchAndBound] {
+            let (selected, _) = select(&utxos, 40_000, strategy).unwrap();
+            assert_eq!(selected, vec![60_000], "{:?}", strategy);
+        }
+
+        // They don't count towards what's available either
+        assert_eq!(
+            select(&utxos, 55_000, CoinSelection::LargestFirst),
+            Err(CoinSelectError::InsufficientFunds { available: 60_000, required: 65_000 })
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        for strategy in [CoinSelection::LargestFirst, CoinSelection::SmallestFirst, CoinSelection::BranchAndBound] {
+            assert_eq!(strategy.name().parse::<CoinSelection>(), Ok(strategy));
+            assert_eq!(CoinSelection::from_u8(strategy as u8), Some(strategy));
+        }
+        assert!("random".parse::<CoinSelection>().is_err());
+        assert_eq!(CoinSelection::from_u8(3), None);
+    }
+}
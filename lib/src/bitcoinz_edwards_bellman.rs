@@ -3,8 +3,8 @@
 /// This module implements the exact edwards point serialization format
 /// used by BitcoinZ's bellman 0.1.0 library.
 
-use jubjub::{ExtendedPoint, AffinePoint};
-use ff::PrimeField;
+use jubjub::{ExtendedPoint, AffinePoint, Fq};
+use ff::{Field, PrimeField};
 use group::Curve;
 use std::io;
 use std::convert::TryInto;
@@ -83,12 +83,46 @@ pub fn write_edwards_point_bellman<W: io::Write>(
     writer.write_all(y_repr.as_ref())
 }
 
+/// The Jubjub curve parameter d = -(10240/10241)
+fn edwards_d() -> Fq {
+    -Fq::from(10240) * Fq::from(10241).invert().unwrap()
+}
+
+/// Read an edwards point in BitcoinZ's bellman 0.1.0 format, reversing
+/// `write_edwards_point_bellman`:
+/// - Take the x sign from bit 63 of the 4th u64 and clear it
+/// - Decode the rest as the y coordinate
+/// - Recover x from the curve equation, picking the root with that sign
+///
+/// Returns None if y isn't canonical, no point has that y, or the sign bit is set
+/// when x is zero.
+pub fn read_edwards_point_bellman(bytes: &[u8; 32]) -> Option<ExtendedPoint> {
+    let mut y_repr = *bytes;
+    let x_is_odd = y_repr[31] & 0x80 != 0;
+    y_repr[31] &= 0x7f;
+    let y: Fq = Option::from(Fq::from_repr(y_repr))?;
+    
+    // -x^2 + y^2 = 1 + d·x^2·y^2, so x^2 = (y^2 - 1) / (d·y^2 + 1)
+    let y2 = y.square();
+    let denominator: Fq = Option::from((edwards_d() * y2 + Fq::one()).invert())?;
+    let mut x: Fq = Option::from(((y2 - Fq::one()) * denominator).sqrt())?;
+    
+    if is_repr_odd(&x.to_repr()) != x_is_odd {
+        if bool::from(x.is_zero()) {
+            return None;
+        }
+        x = -x;
+    }
+    
+    Some(AffinePoint::from_raw_unchecked(x, y).into())
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use rand::thread_rng;
-    use group::Group;
+    use group::{Group, GroupEncoding};
     
     #[test]
     fn test_edwards_point_write() {
@@ -111,4 +145,53 @@ mod tests {
         
         assert_eq!(bytes.len(), 32);
     }
+    
+    fn write(point: &ExtendedPoint) -> [u8; 32] {
+        let mut bytes = Vec::new();
+        write_edwards_point_bellman(point, &mut bytes).unwrap();
+        bytes.try_into().unwrap()
+    }
+    
+    #[test]
+    fn test_edwards_point_round_trip() {
+        let mut rng = thread_rng();
+        let generator = ExtendedPoint::from(jubjub::SubgroupPoint::generator());
+        
+        let mut points = vec![ExtendedPoint::identity(), generator, -generator];
+        points.extend((0..50).map(|_| ExtendedPoint::random(&mut rng)));
+        
+        for point in points {
+            let bytes = write(&point);
+            assert_eq!(read_edwards_point_bellman(&bytes), Some(point), "{}", hex::encode(bytes));
+            
+            // The bellman sign bit lands where the standard encoding puts it
+            assert_eq!(bytes, point.to_bytes());
+        }
+    }
+    
+    #[test]
+    fn test_edwards_point_read_agrees_with_jubjub() {
+        // Small y values, with and without the sign bit: some aren't on the curve,
+        // and y = 1 with the sign bit set would be a negative zero x
+        for y in 0u8..64 {
+            for sign in [0u8, 0x80] {
+                let mut bytes = [0u8; 32];
+                bytes[0] = y;
+                bytes[31] = sign;
+                
+                let expected: Option<ExtendedPoint> = Option::from(ExtendedPoint::from_bytes(&bytes));
+                assert_eq!(read_edwards_point_bellman(&bytes), expected, "{}", hex::encode(bytes));
+            }
+        }
+        
+        // y = 1 is the identity, whose x is zero and can't be negative
+        let mut negative_zero = write(&ExtendedPoint::identity());
+        negative_zero[31] |= 0x80;
+        assert_eq!(read_edwards_point_bellman(&negative_zero), None);
+        
+        // y >= q isn't canonical
+        let mut too_big = [0xffu8; 32];
+        too_big[31] = 0x7f;
+        assert_eq!(read_edwards_point_bellman(&too_big), None);
+    }
 }
\ No newline at end of file
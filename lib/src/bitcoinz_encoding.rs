@@ -0,0 +1,190 @@
+/// BitcoinZ Wire Encoding
+///
+/// The pieces of the transaction format that every builder writes: compact sizes,
+/// amounts, scripts and outpoints. The writers append to a `Vec<u8>`, which can't
+/// fail, so they don't return a Result.
+///
+/// Compact sizes must use the shortest encoding for their value. Reading a
+/// truncated one returns `Ok(None)`; reading a longer-than-needed one is an error.
+
+use zcash_primitives::{
+    legacy::Script,
+    transaction::components::{Amount, OutPoint},
+};
+
+/// Number of bytes `write_compact_size` uses for `size`
+pub const fn compact_size_len(size: u64) -> usize {
+    if size < 0xfd {
+        1
+    } else if size <= 0xffff {
+        3
+    } else if size <= 0xffffffff {
+        5
+    } else {
+        9
+    }
+}
+
+/// Write a variable-length integer
+pub fn write_compact_size(writer: &mut Vec<u8>, size: u64) {
+    if size < 0xfd {
+        writer.push(size as u8);
+    } else if size <= 0xffff {
+        writer.push(0xfd);
+        writer.extend_from_slice(&(size as u16).to_le_bytes());
+    } else if size <= 0xffffffff {
+        writer.push(0xfe);
+        writer.extend_from_slice(&(size as u32).to_le_bytes());
+    } else {
+        writer.push(0xff);
+        writer.extend_from_slice(&size.to_le_bytes());
+    }
+}
+
+/// Read a variable-length integer, returning its value and how many bytes it took.
+/// Returns `Ok(None)` if `data` ends before the integer does.
+pub fn read_compact_size(data: &[u8]) -> Result<Option<(u64, usize)>, String> {
+    let len = match data.first() {
+        None => return Ok(None),
+        Some(0xfd) => 3,
+        Some(0xfe) => 5,
+        Some(0xff) => 9,
+        Some(first) => return Ok(Some((*first as u64, 1))),
+    };
+
+    if data.len() < len {
+        return Ok(None);
+    }
+
+    let mut bytes = [0u8; 8];
+    bytes[..len - 1].copy_from_slice(&data[1..len]);
+    let val = u64::from_le_bytes(bytes);
+
+    if compact_size_len(val) != len {
+        return Err(format!("Non-canonical compact size: {} encoded in {} bytes", val, len));
+    }
+
+    Ok(Some((val, len)))
+}
+
+/// Write an amount as a signed 64-bit little-endian integer
+pub fn write_amount(writer: &mut Vec<u8>, amount: Amount) {
+    writer.extend_from_slice(&i64::from(amount).to_le_bytes());
+}
+
+/// Write a script, prefixed with its length
+pub fn write_script(writer: &mut Vec<u8>, script: &Script) {
+    write_compact_size(writer, script.0.len() as u64);
+    writer.extend_from_slice(&script.0);
+}
+
+/// Write a previous output: its txid (in internal byte order) and index
+pub fn write_outpoint(writer: &mut Vec<u8>, outpoint: &OutPoint) {
+    writer.extend_from_slice(outpoint.hash());
+    writer.extend_from_slice(&outpoint.n().to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // Values on both sides of every encoding boundary
+    const BOUNDARIES: &[u64] = &[
+        0,
+        1,
+        0xfc,
+        0xfd,
+        0xfe,
+        0xff,
+        0x100,
+        0xfffe,
+        0xffff,
+        0x10000,
+        0xfffffffe,
+        0xffffffff,
+        0x100000000,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+
+    fn round_trip(val: u64) {
+        let mut buf = vec![];
+        write_compact_size(&mut buf, val);
+        assert_eq!(buf.len(), compact_size_len(val));
+        assert_eq!(read_compact_size(&buf).unwrap(), Some((val, buf.len())), "value {:#x}", val);
+
+        // Trailing data is left alone
+        buf.extend_from_slice(&[0xaa, 0xbb]);
+        assert_eq!(read_compact_size(&buf).unwrap(), Some((val, buf.len() - 2)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        BOUNDARIES.iter().for_each(|v| round_trip(*v));
+
+        // And a spread of random values of every width
+        let mut rng = StdRng::seed_from_u64(0x62747a);
+        for _ in 0..1000 {
+            let bits = rng.gen_range(0..=64);
+            let val = if bits == 64 { rng.gen::<u64>() } else { rng.gen::<u64>() & ((1u64 << bits) - 1) };
+            round_trip(val);
+        }
+    }
+
+    #[test]
+    fn test_encoding_boundaries() {
+        let encode = |val| {
+            let mut buf = vec![];
+            write_compact_size(&mut buf, val);
+            buf
+        };
+
+        assert_eq!(encode(0xfc), vec![0xfc]);
+        assert_eq!(encode(0xfd), vec![0xfd, 0xfd, 0x00]);
+        assert_eq!(encode(0xffff), vec![0xfd, 0xff, 0xff]);
+        assert_eq!(encode(0x10000), vec![0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(encode(0x100000000), vec![0xff, 0, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // Every strict prefix of every encoding is reported as truncated
+        for val in BOUNDARIES {
+            let mut buf = vec![];
+            write_compact_size(&mut buf, *val);
+            for len in 0..buf.len() {
+                assert_eq!(read_compact_size(&buf[..len]).unwrap(), None, "{:#x} cut to {} bytes", val, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_canonical_rejected() {
+        assert!(read_compact_size(&[0xfd, 0xfc, 0x00]).is_err());
+        assert!(read_compact_size(&[0xfe, 0xff, 0xff, 0x00, 0x00]).is_err());
+        assert!(read_compact_size(&[0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]).is_err());
+
+        // The smallest value for each prefix is fine
+        assert_eq!(read_compact_size(&[0xfd, 0xfd, 0x00]).unwrap(), Some((0xfd, 3)));
+    }
+
+    #[test]
+    fn test_amount_script_outpoint() {
+        let mut buf = vec![];
+        write_amount(&mut buf, Amount::from_i64(-5).unwrap());
+        write_amount(&mut buf, Amount::from_u64(100_000).unwrap());
+        assert_eq!(&buf[..8], &(-5i64).to_le_bytes());
+        assert_eq!(&buf[8..], &100_000u64.to_le_bytes());
+
+        let mut buf = vec![];
+        write_script(&mut buf, &Script(vec![0x51; 0xfd]));
+        assert_eq!(&buf[..3], &[0xfd, 0xfd, 0x00]);
+        assert_eq!(buf.len(), 3 + 0xfd);
+
+        let mut buf = vec![];
+        write_outpoint(&mut buf, &OutPoint::new([7u8; 32], 0x01020304));
+        assert_eq!(&buf[..32], &[7u8; 32]);
+        assert_eq!(&buf[32..], &[0x04, 0x03, 0x02, 0x01]);
+    }
+}
@@ -17,6 +17,10 @@ use zcash_primitives::{
 use zcash_client_backend::encoding::{AddressCodec, encode_payment_address};
 use secp256k1::SecretKey;
 
+use crate::bitcoinz_address::secret_key_to_wif;
+use crate::bitcoinz_transaction::compute_txid;
+use crate::bitcoinz_txid::{DisplayTxid, WireTxid};
+
 #[derive(Debug, Serialize)]
 struct JsRequest {
     action: String,
@@ -47,7 +51,7 @@ struct ShieldedOutputJs {
 #[derive(Debug, Serialize, Deserialize)]
 struct JsTransactionResult {
     success: bool,
-    txid: Option<String>,
+    txid: Option<DisplayTxid>,
     hex: Option<String>,
     size: Option<usize>,
     error: Option<String>,
@@ -162,8 +166,7 @@ pub fn build_bitcoinz_js_tx<P: Parameters>(
     let (to_addr, amount) = &outputs[0];
     
     // Convert secret key to WIF format
-    let sk_bytes = sk.as_ref();
-    let wif = secret_key_to_wif(sk_bytes)?;
+    let wif = secret_key_to_wif(sk);
     
     // Get the to address
     let to_address = to_addr.encode(params);
@@ -173,7 +176,7 @@ pub fn build_bitcoinz_js_tx<P: Parameters>(
     
     // Build UTXO JSON
     let utxo_json = serde_json::json!({
-        "txid": hex::encode(outpoint.hash()),
+        "txid": WireTxid::from(outpoint).to_display(),
         "vout": outpoint.n(),
         "scriptPubKey": hex::encode(&_txout.script_pubkey.0),
         "satoshis": u64::from(_txout.value)
@@ -219,6 +222,16 @@ pub fn build_bitcoinz_js_tx<P: Parameters>(
                 let tx_bytes = hex::decode(&hex)
                     .map_err(|e| format!("Failed to decode transaction hex: {}", e))?;
                 
+                // The script reports the txid the way explorers show it
+                if let Some(txid) = result.txid {
+                    let computed = WireTxid::from(compute_txid(&tx_bytes));
+                    if txid.to_wire() != computed {
+                        return Err(format!(
+                            "Script reported txid {}, but the transaction hashes to {}",
+                            txid, computed.to_display()
+                        ));
+                    }
+                }
                 
                 return Ok(tx_bytes);
             } else {
@@ -232,29 +245,9 @@ pub fn build_bitcoinz_js_tx<P: Parameters>(
     Err("Failed to parse Node.js output".to_string())
 }
 
-/// Convert secret key bytes to WIF format
-fn secret_key_to_wif(sk_bytes: &[u8]) -> Result<String, String> {
-    use base58::ToBase58;
-    use sha2::{Sha256, Digest};
-    
-    // BitcoinZ mainnet private key prefix is 0x80
-    let mut data = vec![0x80];
-    data.extend_from_slice(sk_bytes);
-    data.push(0x01); // Compressed pubkey flag
-    
-    // Double SHA256 for checksum
-    let hash1 = Sha256::digest(&data);
-    let hash2 = Sha256::digest(&hash1);
-    
-    // Append first 4 bytes of checksum
-    data.extend_from_slice(&hash2[..4]);
-    
-    Ok(data.to_base58())
-}
-
 #[derive(Debug)]
 pub struct TransparentInput {
-    pub txid: String,
+    pub txid: DisplayTxid,
     pub vout: u32,
     pub script_pubkey: String,
     pub amount: u64,
@@ -0,0 +1,141 @@
+/// BitcoinZ Memos
+///
+/// Every Sapling output carries exactly 512 bytes of memo (ZIP 302). A text memo is
+/// UTF-8, zero padded; "no memo" is 0xF6 followed by zeros; 0xF5 and 0xFF start
+/// arbitrary data, and 0xF7 to 0xFE are reserved. `MemoBytes` only checks the length,
+/// so `Memo` checks the rest before anything is handed to the builder.
+
+use std::convert::TryFrom;
+use std::str;
+
+use zcash_primitives::memo::MemoBytes;
+
+/// The size of every memo on chain
+pub const MEMO_SIZE: usize = 512;
+
+/// A memo that's been checked against the ZIP 302 conventions
+#[derive(Clone, Debug, PartialEq)]
+pub struct Memo(MemoBytes);
+
+impl Memo {
+    /// The "no memo" memo: 0xF6 and zeros
+    pub fn empty() -> Self {
+        Memo(MemoBytes::empty())
+    }
+
+    /// The 512 bytes that go in the output
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+fn check_length(len: usize) -> Result<(), String> {
+    if len > MEMO_SIZE {
+        return Err(format!("Memo is {} bytes, but a memo can hold at most {}", len, MEMO_SIZE));
+    }
+    Ok(())
+}
+
+/// A text memo. An empty string is no memo at all.
+impl TryFrom<&str> for Memo {
+    type Error = String;
+
+    fn try_from(text: &str) -> Result<Self, String> {
+        if text.is_empty() {
+            return Ok(Memo::empty());
+        }
+
+        check_length(text.len())?;
+        MemoBytes::from_bytes(text.as_bytes())
+            .map(Memo)
+            .map_err(|e| format!("Invalid memo: {:?}", e))
+    }
+}
+
+/// A memo as raw bytes, zero padded to 512 if shorter. Bytes that start like text must
+/// be valid UTF-8, and an 0xF6 memo must be all zeros after the first byte.
+impl TryFrom<&[u8]> for Memo {
+    type Error = String;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, String> {
+        check_length(bytes.len())?;
+
+        match bytes.first() {
+            None => return Ok(Memo::empty()),
+            Some(0x00..=0xF4) => {
+                // The padding isn't part of the text
+                let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                str::from_utf8(&bytes[..end]).map_err(|e| format!("Text memo isn't valid UTF-8: {}", e))?;
+            }
+            Some(0xF6) => {
+                if bytes[1..].iter().any(|b| *b != 0) {
+                    return Err("A memo starting with 0xF6 means no memo, so the rest must be zeros".to_string());
+                }
+            }
+            Some(0xF5) | Some(0xFF) => {}
+            Some(first) => return Err(format!("Memos starting with {:#04x} are reserved", first)),
+        }
+
+        MemoBytes::from_bytes(bytes)
+            .map(Memo)
+            .map_err(|e| format!("Invalid memo: {:?}", e))
+    }
+}
+
+impl From<Memo> for MemoBytes {
+    fn from(memo: Memo) -> Self {
+        memo.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_memo() {
+        let empty = Memo::try_from("").unwrap();
+        assert_eq!(MemoBytes::from(empty.clone()), MemoBytes::empty());
+        assert_eq!(empty.as_slice()[0], 0xF6);
+        assert!(empty.as_slice()[1..].iter().all(|b| *b == 0));
+
+        assert_eq!(Memo::try_from(&[][..]).unwrap(), empty);
+        assert_eq!(Memo::try_from(&[0xF6u8, 0, 0][..]).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_text_memo_lengths() {
+        let memo = Memo::try_from("Hello from BitcoinZ").unwrap();
+        assert_eq!(memo.as_slice().len(), MEMO_SIZE);
+        assert_eq!(&memo.as_slice()[..19], b"Hello from BitcoinZ");
+        assert!(memo.as_slice()[19..].iter().all(|b| *b == 0));
+
+        // Exactly 512 bytes fits, with no room for padding
+        let max = "z".repeat(MEMO_SIZE);
+        assert_eq!(Memo::try_from(max.as_str()).unwrap().as_slice(), max.as_bytes());
+
+        // One more byte doesn't, whether it's one more character or a wider one
+        let err = Memo::try_from("z".repeat(MEMO_SIZE + 1).as_str()).unwrap_err();
+        assert!(err.contains("513 bytes"), "{}", err);
+        assert!(Memo::try_from(format!("{}é", "z".repeat(MEMO_SIZE - 1)).as_str()).is_err());
+    }
+
+    #[test]
+    fn test_raw_memo_conventions() {
+        // Text that isn't UTF-8
+        assert!(Memo::try_from(&[b'a', 0xC3, 0x28][..]).is_err());
+        // ... though the zero padding after valid text is fine
+        assert!(Memo::try_from(&[b'a', b'b', 0, 0][..]).is_ok());
+
+        // "No memo" with something after it
+        assert!(Memo::try_from(&[0xF6u8, 1][..]).is_err());
+
+        // Arbitrary data, and the reserved prefixes
+        assert!(Memo::try_from(&[0xF5u8, 0xC3, 0x28][..]).is_ok());
+        assert!(Memo::try_from(&[0xFFu8; MEMO_SIZE][..]).is_ok());
+        assert!(Memo::try_from(&[0xF7u8][..]).is_err());
+        assert!(Memo::try_from(&[0xFEu8][..]).is_err());
+
+        assert!(Memo::try_from(&[0xFFu8; MEMO_SIZE + 1][..]).is_err());
+    }
+}
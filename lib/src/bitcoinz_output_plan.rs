@@ -0,0 +1,168 @@
+/// BitcoinZ Shielded Output Planning
+///
+/// Lays out the shielded outputs of a send across as few transactions as it can.
+/// Change may be split over several outputs for privacy, but every transaction is
+/// capped at `max_outputs` shielded outputs. When the recipients and change splits
+/// don't fit, the change is split fewer times first; if the recipients alone are still
+/// too many, they spill over into more transactions and the last one takes the change.
+
+/// Shielded outputs a single transaction may have unless the caller picks another cap
+pub const MAX_SHIELDED_OUTPUTS: usize = 50;
+
+/// The shielded outputs of one transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedTx<T> {
+    /// Recipients and the value each one is paid
+    pub recipients: Vec<(T, u64)>,
+    /// Values of the change outputs, empty when there's no change in this transaction
+    pub change: Vec<u64>,
+}
+
+impl<T> PlannedTx<T> {
+    pub fn output_count(&self) -> usize {
+        self.recipients.len() + self.change.len()
+    }
+}
+
+/// Split `change` into `splits` outputs of as near the same value as possible. There's
+/// never an output worth nothing: no change makes no outputs, and a tiny change is split
+/// fewer times.
+pub fn split_change(change: u64, splits: usize) -> Vec<u64> {
+    let splits = (splits.max(1) as u64).min(change);
+    if splits == 0 {
+        return vec![];
+    }
+
+    let (part, remainder) = (change / splits, change % splits);
+    (0..splits).map(|i| if i < remainder { part + 1 } else { part }).collect()
+}
+
+/// Plan the transactions that pay `recipients` and return `change` split into up to
+/// `change_splits` outputs, with at most `max_outputs` shielded outputs in each.
+/// Recipients keep their order across the transactions.
+pub fn plan_shielded_outputs<T: Clone>(
+    recipients: &[(T, u64)],
+    change: u64,
+    change_splits: usize,
+    max_outputs: usize,
+) -> Result<Vec<PlannedTx<T>>, String> {
+    if max_outputs < 2 {
+        return Err(format!(
+            "Need room for at least 2 shielded outputs per transaction, the cap is {}",
+            max_outputs
+        ));
+    }
+    let change_outputs = if change == 0 { 0 } else { 1 };
+
+    // Everything fits in one transaction, splitting the change as often as there's room
+    if recipients.len() + change_outputs <= max_outputs {
+        return Ok(vec![PlannedTx {
+            recipients: recipients.to_vec(),
+            change: split_change(change, change_splits.min(max_outputs - recipients.len())),
+        }]);
+    }
+
+    // Spill the recipients over full transactions, leaving the last one room for the change
+    let tx_count = (recipients.len() + change_outputs + max_outputs - 1) / max_outputs;
+    let mut sizes = vec![max_outputs; tx_count - 1];
+    let last = recipients.len() - max_outputs * (tx_count - 1);
+    if last == 0 {
+        // A transaction with nothing but change would only send it back to ourselves
+        sizes[tx_count - 2] -= 1;
+    }
+
+    let mut planned = vec![];
+    let mut rest = recipients;
+    for size in sizes {
+        let (chunk, tail) = rest.split_at(size);
+        planned.push(PlannedTx {
+            recipients: chunk.to_vec(),
+            change: vec![],
+        });
+        rest = tail;
+    }
+    planned.push(PlannedTx {
+        recipients: rest.to_vec(),
+        change: split_change(change, change_splits.min(max_outputs - rest.len())),
+    });
+
+    Ok(planned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipients(count: usize) -> Vec<(usize, u64)> {
+        (0..count).map(|i| (i, 10_000 + i as u64)).collect()
+    }
+
+    fn assert_covers(planned: &[PlannedTx<usize>], count: usize, change: u64, max_outputs: usize) {
+        let paid = planned.iter().flat_map(|tx| tx.recipients.clone()).collect::<Vec<_>>();
+        assert_eq!(paid, recipients(count));
+        assert_eq!(planned.iter().flat_map(|tx| tx.change.iter()).sum::<u64>(), change);
+        assert!(planned.iter().all(|tx| tx.output_count() <= max_outputs && tx.output_count() > 0));
+    }
+
+    #[test]
+    fn test_split_change() {
+        assert_eq!(split_change(100, 3), vec![34, 33, 33]);
+        assert_eq!(split_change(100, 0), vec![100]);
+        assert_eq!(split_change(2, 5), vec![1, 1]);
+        assert!(split_change(0, 5).is_empty());
+    }
+
+    #[test]
+    fn test_fits_in_one_transaction() {
+        let planned = plan_shielded_outputs(&recipients(10), 50_000, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].change, vec![10_000; 5]);
+        assert_covers(&planned, 10, 50_000, MAX_SHIELDED_OUTPUTS);
+
+        // No change, no change outputs
+        let planned = plan_shielded_outputs(&recipients(50), 0, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert!(planned[0].change.is_empty());
+    }
+
+    #[test]
+    fn test_fewer_change_splits() {
+        let planned = plan_shielded_outputs(&recipients(47), 50_000, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].change.len(), 3);
+        assert_covers(&planned, 47, 50_000, MAX_SHIELDED_OUTPUTS);
+
+        // Down to a single change output before anything spills
+        let planned = plan_shielded_outputs(&recipients(49), 50_000, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].change, vec![50_000]);
+    }
+
+    #[test]
+    fn test_spills_into_more_transactions() {
+        let planned = plan_shielded_outputs(&recipients(120), 50_000, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(
+            planned.iter().map(|tx| (tx.recipients.len(), tx.change.len())).collect::<Vec<_>>(),
+            vec![(50, 0), (50, 0), (20, 5)]
+        );
+        assert_covers(&planned, 120, 50_000, MAX_SHIELDED_OUTPUTS);
+
+        // One output too many for a single transaction: the change doesn't go out on its own
+        let planned = plan_shielded_outputs(&recipients(50), 50_000, 5, MAX_SHIELDED_OUTPUTS).unwrap();
+        assert_eq!(
+            planned.iter().map(|tx| (tx.recipients.len(), tx.change.len())).collect::<Vec<_>>(),
+            vec![(49, 0), (1, 5)]
+        );
+        assert_covers(&planned, 50, 50_000, MAX_SHIELDED_OUTPUTS);
+
+        // Every cap from the smallest up covers everyone
+        for max_outputs in 2..=12 {
+            for count in 0..40 {
+                let planned = plan_shielded_outputs(&recipients(count), 7, 3, max_outputs).unwrap();
+                assert_covers(&planned, count, 7, max_outputs);
+            }
+        }
+
+        assert!(plan_shielded_outputs(&recipients(3), 50_000, 5, 1).is_err());
+    }
+}
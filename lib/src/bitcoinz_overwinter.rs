@@ -3,14 +3,21 @@
 /// This module creates Overwinter (v3) transactions for transparent-only transfers
 /// to bypass the Sapling binding signature issue.
 
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
 use zcash_primitives::{
-    consensus::BlockHeight,
+    consensus::{BlockHeight, Parameters},
     legacy::TransparentAddress,
     transaction::{
         components::{Amount, OutPoint, TxOut},
     },
 };
 
+use crate::bitcoinz_overwinter_builder::{build_overwinter_tx_with_sequences, SIGHASH_ALL};
+use crate::bitcoinz_parse::parse_bitcoinz_v4;
+use crate::bitcoinz_script_sig::hash160;
+use crate::bitcoinz_transaction::estimate_tx_size;
+use crate::bitcoinz_transparent_builder::TransparentTxFormat;
+
 /// Overwinter version and version group ID for BitcoinZ
 const OVERWINTER_VERSION: u32 = 0x80000003;  // v3
 const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C48270;
@@ -18,7 +25,7 @@ const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C48270;
 /// Build a raw Overwinter transaction for BitcoinZ
 /// This bypasses the Sapling binding signature requirement
 pub fn build_overwinter_tx(
-    _inputs: &[(OutPoint, TxOut, secp256k1::SecretKey)],
+    _inputs: &[(OutPoint, TxOut, SecretKey)],
     _outputs: &[(TransparentAddress, Amount)],
     _height: BlockHeight,
     _expiry_height: BlockHeight,
@@ -35,9 +42,14 @@ pub fn build_overwinter_tx(
     Err("Overwinter transaction building not yet implemented - requires manual transaction construction".to_string())
 }
 
-/// Strip Sapling components from a v4 transaction to make it v3-like
-/// This is a hack that removes binding signature and other Sapling fields
-pub fn strip_sapling_components(tx_bytes: &[u8]) -> Result<Vec<u8>, String> {
+/// Strip Sapling components from a v4 transaction to make it v3. Anything that isn't
+/// v4 is returned as it is; a v4 transaction is downgraded with `downgrade_to_overwinter`.
+pub fn strip_sapling_components<P: Parameters>(
+    params: &P,
+    tx_bytes: &[u8],
+    coins: &[(TxOut, SecretKey)],
+    height: BlockHeight,
+) -> Result<Vec<u8>, String> {
     if tx_bytes.len() < 8 {
         return Err("Transaction too short".to_string());
     }
@@ -48,34 +60,12 @@ pub fn strip_sapling_components(tx_bytes: &[u8]) -> Result<Vec<u8>, String> {
         return Ok(tx_bytes.to_vec());
     }
     
-    
-    // This is complex because we need to:
-    // 1. Parse the full transaction structure
-    // 2. Extract only transparent components
-    // 3. Rebuild with Overwinter version
-    // 4. Re-sign all inputs
-    
-    // For now, we can't implement this without full transaction parsing
-    Err("Stripping Sapling components requires full transaction parsing".to_string())
+    downgrade_to_overwinter(params, tx_bytes, coins, height)
 }
 
-/// Calculate the size of an Overwinter transaction
+/// Calculate the size of an Overwinter transaction with P2PKH inputs and outputs
 pub fn estimate_overwinter_tx_size(num_inputs: usize, num_outputs: usize) -> usize {
-    // Basic structure:
-    // - Header (4 bytes version + 4 bytes version group)
-    // - VarInt input count
-    // - Inputs (36 bytes outpoint + ~107 bytes scriptSig each)
-    // - VarInt output count  
-    // - Outputs (8 bytes amount + ~25 bytes script each)
-    // - Lock time (4 bytes)
-    // - Expiry height (4 bytes)
-    // - No Sapling components
-    
-    let base_size = 4 + 4 + 1 + 1 + 4 + 4; // headers + counts + locktime + expiry
-    let input_size = num_inputs * (36 + 107); // outpoint + typical scriptSig
-    let output_size = num_outputs * (8 + 25); // amount + P2PKH script
-    
-    base_size + input_size + output_size
+    estimate_tx_size(num_inputs, num_outputs, 0, 0, TransparentTxFormat::Overwinter)
 }
 
 /// Check if we should use Overwinter for this transaction
@@ -90,15 +80,155 @@ pub fn should_use_overwinter(
     transparent_inputs > 0 && transparent_outputs > 0
 }
 
-/// Convert a Sapling transaction to Overwinter format (if possible)
-/// This is a hack that only works for transparent-only transactions
-pub fn downgrade_to_overwinter(sapling_tx_bytes: &[u8]) -> Result<Vec<u8>, String> {
-    // This would need to:
-    // 1. Parse the Sapling transaction
-    // 2. Extract transparent inputs and outputs
-    // 3. Rebuild as Overwinter transaction
-    // 4. Re-sign all inputs
+/// Convert a transparent-only Sapling (v4) transaction to Overwinter (v3).
+///
+/// The v3 sighash is different, so every input is signed again. The prevouts' values
+/// and scripts aren't in the transaction, so `coins` has to give the coin each input
+/// spends, in input order, with the P2PKH key that signs it. Sequences and the expiry
+/// height are kept; every input is signed with SIGHASH_ALL.
+pub fn downgrade_to_overwinter<P: Parameters>(
+    params: &P,
+    sapling_tx_bytes: &[u8],
+    coins: &[(TxOut, SecretKey)],
+    height: BlockHeight,
+) -> Result<Vec<u8>, String> {
+    let tx = parse_bitcoinz_v4(sapling_tx_bytes).map_err(|e| format!("Couldn't parse v4 transaction: {}", e))?;
+    
+    if !tx.shielded_spends.is_empty() || !tx.shielded_outputs.is_empty() || tx.joinsplit_count > 0 {
+        return Err(format!(
+            "Only transparent transactions can be downgraded ({} spends, {} outputs, {} joinsplits)",
+            tx.shielded_spends.len(), tx.shielded_outputs.len(), tx.joinsplit_count
+        ));
+    }
+    // The Overwinter builder always writes a lock time of 0
+    if tx.lock_time != 0 {
+        return Err(format!("Can't downgrade a transaction with lock time {}", tx.lock_time));
+    }
+    if coins.len() != tx.inputs.len() {
+        return Err(format!("Got {} coins for {} inputs", coins.len(), tx.inputs.len()));
+    }
     
-    // This is complex and risky, so not implemented yet
-    Err("Transaction downgrade not implemented".to_string())
-}
\ No newline at end of file
+    let secp = Secp256k1::signing_only();
+    let mut inputs = Vec::with_capacity(tx.inputs.len());
+    for (i, (input, (coin, sk))) in tx.inputs.iter().zip(coins).enumerate() {
+        // Either form of the public key, which the builder matches when it signs
+        let pubkey = PublicKey::from_secret_key(&secp, sk);
+        let pays_key = |pubkey: &[u8]| coin.script_pubkey == TransparentAddress::PublicKey(hash160(pubkey)).script();
+        if !pays_key(&pubkey.serialize()) && !pays_key(&pubkey.serialize_uncompressed()) {
+            return Err(format!("The key for input {} doesn't sign its coin", i));
+        }
+        inputs.push((input.prevout_hash.outpoint(input.prevout_index), coin.clone(), *sk));
+    }
+    let sequences = tx.inputs.iter().map(|input| input.sequence).collect();
+    
+    let outputs = tx
+        .outputs
+        .iter()
+        .enumerate()
+        .map(|(i, output)| {
+            output
+                .script_pubkey
+                .address()
+                .map(|addr| (addr, output.value))
+                .ok_or_else(|| format!("Output {} doesn't pay to a transparent address", i))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    
+    let expiry_height = if tx.expiry_height == 0 { None } else { Some(tx.expiry_height) };
+    build_overwinter_tx_with_sequences(params, inputs, outputs, height, expiry_height, SIGHASH_ALL, sequences)
+        .map(|built| built.raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+    use crate::BITCOINZ_MAINNET;
+    
+    /// Inputs, sequences and outputs of a v3 transaction, read back as (outpoint, sequence)
+    /// and (value, script_pubkey) pairs, with its expiry height
+    fn read_v3(tx: &[u8]) -> (Vec<([u8; 36], u32)>, Vec<(u64, Vec<u8>)>, u32) {
+        let u32_at = |at: usize| u32::from_le_bytes([tx[at], tx[at + 1], tx[at + 2], tx[at + 3]]);
+        assert_eq!(u32_at(0), OVERWINTER_VERSION);
+        
+        let mut cursor = 8;
+        let mut inputs = vec![];
+        for _ in 0..tx[cursor] {
+            let mut outpoint = [0u8; 36];
+            outpoint.copy_from_slice(&tx[cursor + 1..cursor + 37]);
+            cursor += 37 + tx[cursor + 37] as usize;
+            inputs.push((outpoint, u32_at(cursor + 1)));
+            cursor += 4;
+        }
+        cursor += 1;
+        
+        let mut outputs = vec![];
+        for _ in 0..tx[cursor] {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(&tx[cursor + 1..cursor + 9]);
+            let script_len = tx[cursor + 9] as usize;
+            outputs.push((u64::from_le_bytes(value), tx[cursor + 10..cursor + 10 + script_len].to_vec()));
+            cursor += 9 + script_len;
+        }
+        cursor += 1;
+        
+        // Lock time, expiry height and an empty joinsplit count end the transaction
+        assert_eq!(tx.len(), cursor + 9);
+        assert_eq!(u32_at(cursor), 0);
+        (inputs, outputs, u32_at(cursor + 4))
+    }
+    
+    #[test]
+    fn test_downgrade_transparent_tx() {
+        let height = BlockHeight::from(500_000);
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::signing_only(), &sk).serialize();
+        let coin = |value| TxOut {
+            value: Amount::from_u64(value).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey(hash160(&pubkey)).script(),
+        };
+        let coins = vec![(coin(100_000), sk), (coin(250_000), sk)];
+        let inputs = vec![
+            (OutPoint::new([0x11u8; 32], 0), coins[0].0.clone(), sk),
+            (OutPoint::new([0x33u8; 32], 1), coins[1].0.clone(), sk),
+        ];
+        let outputs = vec![
+            (TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(150_000).unwrap()),
+            (TransparentAddress::Script([0x66u8; 20]), Amount::from_u64(190_000).unwrap()),
+        ];
+        let v4 = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs, outputs, height, Some(500_020), None).unwrap().raw;
+        let parsed = parse_bitcoinz_v4(&v4).unwrap();
+        
+        let v3 = downgrade_to_overwinter(&BITCOINZ_MAINNET, &v4, &coins, height).unwrap();
+        let (v3_inputs, v3_outputs, expiry_height) = read_v3(&v3);
+        
+        let v4_inputs: Vec<_> = parsed
+            .inputs
+            .iter()
+            .map(|input| {
+                let mut outpoint = [0u8; 36];
+                outpoint[..32].copy_from_slice(input.prevout_hash.as_bytes());
+                outpoint[32..].copy_from_slice(&input.prevout_index.to_le_bytes());
+                (outpoint, input.sequence)
+            })
+            .collect();
+        let v4_outputs: Vec<_> = parsed
+            .outputs
+            .iter()
+            .map(|output| (u64::from(output.value), output.script_pubkey.0.clone()))
+            .collect();
+        assert_eq!(v3_inputs, v4_inputs);
+        assert_eq!(v3_outputs, v4_outputs);
+        assert_eq!(expiry_height, 500_020);
+        
+        // The same through strip_sapling_components, which leaves the v3 transaction alone
+        assert_eq!(strip_sapling_components(&BITCOINZ_MAINNET, &v4, &coins, height).unwrap(), v3);
+        assert_eq!(strip_sapling_components(&BITCOINZ_MAINNET, &v3, &coins, height).unwrap(), v3);
+        
+        // Each input needs its coin and the key that signs it
+        assert!(downgrade_to_overwinter(&BITCOINZ_MAINNET, &v4, &coins[..1], height).is_err());
+        let other = SecretKey::from_slice(&[0x02u8; 32]).unwrap();
+        let wrong_key = vec![(coin(100_000), sk), (coin(250_000), other)];
+        assert!(downgrade_to_overwinter(&BITCOINZ_MAINNET, &v4, &wrong_key, height).is_err());
+    }
+}
@@ -1,484 +1,410 @@
 /// BitcoinZ Overwinter Transaction Builder
-/// 
+///
 /// This module builds Overwinter (v3) transactions for transparent-only transfers
-/// to bypass the Sapling binding signature issue.
+/// to bypass the Sapling binding signature issue. The building and signing is
+/// `bitcoinz_transparent_builder`'s; new code should use its `TransparentTxBuilder`.
 
-use blake2b_simd::Params;
-use byteorder::{LittleEndian, WriteBytesExt};
-use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
-use std::io::Write;
+use secp256k1::SecretKey;
 use zcash_primitives::{
-    consensus::{BlockHeight, BranchId, Parameters},
-    legacy::{Script, TransparentAddress},
-    transaction::{
-        components::{Amount, OutPoint, TxOut},
-    },
+    consensus::{BlockHeight, Parameters},
+    legacy::TransparentAddress,
+    transaction::components::{Amount, OutPoint, TxOut},
 };
 
-/// BitcoinZ Overwinter constants
-const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C48270;  // Zcash Overwinter
-const BITCOINZ_VERSION_GROUP_ID: u32 = 0x892f2085;    // BitcoinZ uses same for v3 and v4
-const OVERWINTER_TX_VERSION: i32 = 3;
-const SIGHASH_ALL: u32 = 1;
+use crate::bitcoinz_script_sig::pubkey_hash_inputs;
+use crate::bitcoinz_transaction::BuiltTransaction;
+use crate::bitcoinz_transparent_builder::{build_transparent, TransparentParts, TransparentTxFormat};
 
-/// Personalization for BLAKE2b hashing in BitcoinZ
-const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
-const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
-const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
-const ZCASH_SIGHASH_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashSigHash";
+/// Signature hash types (ZIP-143 / ZIP-243)
+pub const SIGHASH_ALL: u32 = 1;
+pub const SIGHASH_NONE: u32 = 2;
+pub const SIGHASH_SINGLE: u32 = 3;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
 
+/// nSequence given to transparent inputs unless set otherwise: final for RBF, but
+/// still lets nLockTime apply
+pub const DEFAULT_SEQUENCE: u32 = 0xfffffffe;
 
-/// Build a raw Overwinter transaction for BitcoinZ transparent-only transfers
+/// nSequence of inputs that signal the transaction can be replaced with a higher fee (BIP 125)
+pub const REPLACEABLE_SEQUENCE: u32 = 0xfffffffd;
+
+/// Build a raw Overwinter transaction for BitcoinZ transparent-only transfers.
+/// The transaction can't be mined after `expiry_height`; None means it never expires.
 pub fn build_overwinter_tx<P: Parameters>(
     params: &P,
     inputs: Vec<(OutPoint, TxOut, SecretKey)>,
     outputs: Vec<(TransparentAddress, Amount)>,
     height: BlockHeight,
-) -> Result<Vec<u8>, String> {
-    
-    // First, build the unsigned transaction
-    let unsigned_tx = build_unsigned_overwinter_tx(&inputs, &outputs, height)?;
-    
-    // Then sign all inputs
-    let signed_tx = sign_overwinter_transaction(params, unsigned_tx, inputs, &outputs, height)?;
-    
-    
-    Ok(signed_tx)
+    expiry_height: Option<u32>,
+) -> Result<BuiltTransaction, String> {
+    build_overwinter_tx_with_sighash(params, inputs, outputs, height, expiry_height, SIGHASH_ALL)
 }
 
-/// Build unsigned Overwinter transaction
-fn build_unsigned_overwinter_tx(
-    inputs: &[(OutPoint, TxOut, SecretKey)],
-    outputs: &[(TransparentAddress, Amount)],
+/// Build a raw Overwinter transaction, signing every input with the given sighash type
+/// (SIGHASH_ALL, SIGHASH_NONE or SIGHASH_SINGLE, optionally OR'd with SIGHASH_ANYONECANPAY)
+pub fn build_overwinter_tx_with_sighash<P: Parameters>(
+    params: &P,
+    inputs: Vec<(OutPoint, TxOut, SecretKey)>,
+    outputs: Vec<(TransparentAddress, Amount)>,
     height: BlockHeight,
-) -> Result<Vec<u8>, String> {
-    let mut tx_data = Vec::new();
-    
-    // Header (version with overwinter flag)
-    let header = 0x80000000u32 | (OVERWINTER_TX_VERSION as u32);
-    tx_data.write_u32::<LittleEndian>(header)
-        .map_err(|e| format!("Failed to write header: {}", e))?;
-    
-    // Version group ID (BitcoinZ uses same ID for v3 and v4)
-    tx_data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
-        .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-    
-    // Input count
-    write_compact_size(&mut tx_data, inputs.len() as u64)?;
-    
-    // Write inputs (with empty script sigs for now)
-    for (outpoint, _, _) in inputs {
-        // Previous output hash (32 bytes)
-        tx_data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        
-        // Previous output index (4 bytes)
-        tx_data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-        
-        // Script sig placeholder (empty for unsigned)
-        write_compact_size(&mut tx_data, 0)?;
-        
-        // Sequence
-        tx_data.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
-    }
-    
-    // Output count
-    write_compact_size(&mut tx_data, outputs.len() as u64)?;
-    
-    // Write outputs
-    for (addr, amount) in outputs {
-        // Amount (8 bytes)
-        tx_data.write_u64::<LittleEndian>(u64::from(*amount))
-            .map_err(|e| format!("Failed to write amount: {}", e))?;
-        
-        // Script pubkey
-        let script = addr.script();
-        write_compact_size(&mut tx_data, script.0.len() as u64)?;
-        tx_data.write_all(&script.0)
-            .map_err(|e| format!("Failed to write script: {}", e))?;
-    }
-    
-    // Lock time
-    tx_data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write lock time: {}", e))?;
-    
-    // Expiry height (Overwinter addition)
-    let expiry_height = height + BlockHeight::from(10);
-    tx_data.write_u32::<LittleEndian>(u32::from(expiry_height))
-        .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-    
-    // Note: BitcoinZ v3 doesn't include joinSplits in the unsigned transaction
-    // They're added during signing
-    
-    Ok(tx_data)
+    expiry_height: Option<u32>,
+    sighash_type: u32,
+) -> Result<BuiltTransaction, String> {
+    let sequences = vec![DEFAULT_SEQUENCE; inputs.len()];
+    build_overwinter_tx_with_sequences(params, inputs, outputs, height, expiry_height, sighash_type, sequences)
 }
 
-/// Sign the Overwinter transaction
-fn sign_overwinter_transaction<P: Parameters>(
+/// Build a raw Overwinter transaction with the given nSequence for each input, in order
+pub fn build_overwinter_tx_with_sequences<P: Parameters>(
     params: &P,
-    unsigned_tx: Vec<u8>,
     inputs: Vec<(OutPoint, TxOut, SecretKey)>,
-    outputs: &[(TransparentAddress, Amount)],
+    outputs: Vec<(TransparentAddress, Amount)>,
     height: BlockHeight,
-) -> Result<Vec<u8>, String> {
-    let secp = Secp256k1::new();
-    let mut signatures = Vec::new();
-    
-    // Compute signatures for each input
-    for (index, (_, txout, sk)) in inputs.iter().enumerate() {
-        // Compute the sighash for this input
-        let sighash = compute_overwinter_sighash(
-            &unsigned_tx,
-            &inputs,
-            &outputs,
-            index,
+    expiry_height: Option<u32>,
+    sighash_type: u32,
+    sequences: Vec<u32>,
+) -> Result<BuiltTransaction, String> {
+    let parts = TransparentParts {
+        sequences,
+        expiry_height: expiry_height.unwrap_or(0),
+        sighash_type,
+        ..TransparentParts::new(pubkey_hash_inputs(&inputs), outputs)
+    };
+    build_transparent(params, height, TransparentTxFormat::Overwinter, &parts)
+}
+
+/// Check that a sighash type is one we know how to compute
+pub fn validate_sighash_type(sighash_type: u32) -> Result<(), String> {
+    if (sighash_type & !(0x1f | SIGHASH_ANYONECANPAY)) != 0 {
+        return Err(format!("Unsupported sighash type: {:#x}", sighash_type));
+    }
+
+    match sighash_type & 0x1f {
+        SIGHASH_ALL | SIGHASH_NONE | SIGHASH_SINGLE => Ok(()),
+        _ => Err(format!("Unsupported sighash type: {:#x}", sighash_type)),
+    }
+}
+
+/// Check if we should use Overwinter for this transaction
+pub fn should_use_overwinter(
+    transparent_inputs: usize,
+    shielded_inputs: usize,
+    transparent_outputs: usize,
+    shielded_outputs: usize,
+) -> bool {
+    // Only use Overwinter for pure transparent transactions
+    shielded_inputs == 0 && shielded_outputs == 0 && transparent_inputs > 0 && transparent_outputs > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BITCOINZ_MAINNET;
+
+    // Expected sighashes were computed independently from the ZIP-143 spec
+    // (BitcoinZ version group ID 0x892f2085, branch ID 0x76b809bb, expiry 500_010)
+    // for the fixture below.
+
+    fn fixture() -> (Vec<(OutPoint, TxOut, SecretKey)>, Vec<(TransparentAddress, Amount)>) {
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let inputs = vec![
+            (
+                OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                sk,
+            ),
+            (
+                OutPoint::new([0x33u8; 32], 1),
+                TxOut {
+                    value: Amount::from_u64(250_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x44u8; 20]).script(),
+                },
+                sk,
+            ),
+        ];
+        let outputs = vec![
+            (
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(150_000).unwrap(),
+            ),
+            (
+                TransparentAddress::Script([0x66u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            ),
+        ];
+        (inputs, outputs)
+    }
+
+    /// The sighash input `input_index` of the fixture signs, paying `outputs`
+    fn overwinter_sighash(
+        outputs: &[(TransparentAddress, Amount)],
+        sequences: &[u32],
+        input_index: usize,
+        sighash_type: u32,
+        expiry_height: u32,
+    ) -> Result<[u8; 32], String> {
+        use crate::bitcoinz_transparent_builder::input_sighash;
+
+        let (inputs, _) = fixture();
+        let (_, txout, _) = &inputs[input_index];
+        let parts = TransparentParts {
+            sequences: sequences.to_vec(),
+            expiry_height,
+            sighash_type,
+            ..TransparentParts::new(pubkey_hash_inputs(&inputs), outputs.to_vec())
+        };
+        input_sighash(
+            &BITCOINZ_MAINNET,
+            BlockHeight::from(500_000),
+            TransparentTxFormat::Overwinter,
+            &parts,
+            input_index,
             &txout.script_pubkey,
             txout.value,
-            SIGHASH_ALL,
-            params,
-            height,
-        )?;
-        
-        // Sign the sighash
-        let msg = Message::from_slice(&sighash)
-            .map_err(|e| format!("Failed to create message: {}", e))?;
-        let sig = secp.sign_ecdsa(&msg, &sk);
-        
-        // Create script sig
-        let pk = PublicKey::from_secret_key(&secp, &sk);
-        let mut script_sig = Vec::new();
-        
-        // Push signature with sighash type
-        let mut sig_bytes = sig.serialize_der().to_vec();
-        sig_bytes.push(SIGHASH_ALL as u8);
-        script_sig.push(sig_bytes.len() as u8);
-        script_sig.extend_from_slice(&sig_bytes);
-        
-        // Push public key
-        let pk_bytes = pk.serialize();
-        script_sig.push(pk_bytes.len() as u8);
-        script_sig.extend_from_slice(&pk_bytes);
-        
-        signatures.push(script_sig);
-    }
-    
-    // Now rebuild the transaction with signatures
-    let mut signed_tx = Vec::new();
-    
-    // Copy header and version group ID (first 8 bytes)
-    signed_tx.extend_from_slice(&unsigned_tx[0..8]);
-    
-    // Write inputs with signatures
-    write_compact_size(&mut signed_tx, inputs.len() as u64)?;
-    
-    for (i, (outpoint, _, _)) in inputs.iter().enumerate() {
-        // Previous output
-        signed_tx.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        signed_tx.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-        
-        // Script sig with signature
-        write_compact_size(&mut signed_tx, signatures[i].len() as u64)?;
-        signed_tx.write_all(&signatures[i])
-            .map_err(|e| format!("Failed to write script sig: {}", e))?;
-        
-        // Sequence
-        signed_tx.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
+        )
     }
-    
-    // Find where outputs start in the unsigned transaction
-    let mut cursor = 8; // After header and version group
-    let (input_count, varint_size) = read_compact_size(&unsigned_tx[cursor..])?
-        .ok_or("Failed to read input count")?;
-    cursor += varint_size;
-    
-    // Skip all inputs in unsigned tx
-    for _ in 0..input_count {
-        cursor += 32 + 4; // outpoint
-        let (script_len, varint_size) = read_compact_size(&unsigned_tx[cursor..])?
-            .ok_or("Failed to read script length")?;
-        cursor += varint_size + script_len as usize;
-        cursor += 4; // sequence
+
+    fn sighash_hex(sighash_type: u32, input_index: usize) -> Result<String, String> {
+        let (_, outputs) = fixture();
+        let sighash = overwinter_sighash(&outputs, &[DEFAULT_SEQUENCE; 2], input_index, sighash_type, 500_010)?;
+        Ok(hex::encode(sighash))
     }
-    
-    // Copy the rest (outputs, locktime, expiry)
-    signed_tx.extend_from_slice(&unsigned_tx[cursor..]);
-    
-    // Add joinsplit count (0 for transparent-only)
-    write_compact_size(&mut signed_tx, 0)?;
-    
-    Ok(signed_tx)
-}
 
-/// Compute Overwinter sighash using BLAKE2b
-fn compute_overwinter_sighash<P: Parameters>(
-    _tx_data: &[u8],
-    inputs: &[(OutPoint, TxOut, SecretKey)],
-    outputs: &[(TransparentAddress, Amount)],
-    input_index: usize,
-    script_code: &Script,
-    value: Amount,
-    sighash_type: u32,
-    params: &P,
-    height: BlockHeight,
-) -> Result<[u8; 32], String> {
-    // This implements the Overwinter sighash algorithm
-    // Reference: ZIP-143
-    
-    let mut data = Vec::new();
-    
-    // 1. Header with Overwinter flag
-    let header = 0x80000000u32 | (OVERWINTER_TX_VERSION as u32);
-    data.write_u32::<LittleEndian>(header)
-        .map_err(|e| format!("Failed to write header: {}", e))?;
-    
-    // 2. Version group ID (BitcoinZ uses same for all versions)
-    data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
-        .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-    
-    // 3. Hash of all prevouts (if not ANYONECANPAY)
-    if (sighash_type & 0x80) == 0 {
-        let prevouts_hash = compute_prevouts_hash(inputs)?;
-        data.write_all(&prevouts_hash)
-            .map_err(|e| format!("Failed to write prevouts hash: {}", e))?;
-    } else {
-        data.write_all(&[0u8; 32])
-            .map_err(|e| format!("Failed to write empty prevouts hash: {}", e))?;
+    #[test]
+    fn test_sighash_all() {
+        assert_eq!(
+            sighash_hex(SIGHASH_ALL, 0).unwrap(),
+            "bc8f9bfb090c1a8e15415e7fbdb7162abd4a287d8d2ef449894ef8af2aae3b3c"
+        );
+        assert_eq!(
+            sighash_hex(SIGHASH_ALL, 1).unwrap(),
+            "0d4c58cd56d68b4fcf35cf64c30f796a77687178b02274705079d70115ba0f5e"
+        );
     }
-    
-    // 4. Hash of all sequences (if not ANYONECANPAY, SINGLE, NONE)
-    if (sighash_type & 0x80) == 0 && (sighash_type & 0x1f) != 2 && (sighash_type & 0x1f) != 3 {
-        let sequences_hash = compute_sequences_hash(inputs)?;
-        data.write_all(&sequences_hash)
-            .map_err(|e| format!("Failed to write sequences hash: {}", e))?;
-    } else {
-        data.write_all(&[0u8; 32])
-            .map_err(|e| format!("Failed to write empty sequences hash: {}", e))?;
+
+    #[test]
+    fn test_sighash_none() {
+        assert_eq!(
+            sighash_hex(SIGHASH_NONE, 0).unwrap(),
+            "7d7dc17b09ed158c3382f9c4ede2b33690a07708764dd99f597594f3eaee3353"
+        );
+        assert_eq!(
+            sighash_hex(SIGHASH_NONE, 1).unwrap(),
+            "ff5dafe9cd4915c73e302e1bb705e73715272773ed5b9baf21ffd7a7164a5f4c"
+        );
     }
-    
-    // 5. Hash of all outputs (if not SINGLE or NONE)
-    if (sighash_type & 0x1f) != 2 && (sighash_type & 0x1f) != 3 {
-        let outputs_hash = compute_outputs_hash(outputs)?;
-        data.write_all(&outputs_hash)
-            .map_err(|e| format!("Failed to write outputs hash: {}", e))?;
-    } else {
-        data.write_all(&[0u8; 32])
-            .map_err(|e| format!("Failed to write empty outputs hash: {}", e))?;
+
+    #[test]
+    fn test_sighash_single() {
+        assert_eq!(
+            sighash_hex(SIGHASH_SINGLE, 0).unwrap(),
+            "318ac67a4c703518e5b77cc3ce5c7b40889b8106101d359f9caf9243c0621623"
+        );
+        assert_eq!(
+            sighash_hex(SIGHASH_SINGLE, 1).unwrap(),
+            "6174c7388ee0aa1efc6a4f130a28d4ddd94f037a718878addd5870fff05f8218"
+        );
     }
-    
-    // 6. JoinSplits hash (empty for Overwinter transparent)
-    data.write_all(&[0u8; 32])
-        .map_err(|e| format!("Failed to write joinsplits hash: {}", e))?;
-    
-    // 7. Lock time (using 0)
-    data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write lock time: {}", e))?;
-    
-    // 8. Expiry height
-    let expiry_height = height + BlockHeight::from(10);
-    data.write_u32::<LittleEndian>(u32::from(expiry_height))
-        .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-    
-    // 9. Sighash type
-    data.write_u32::<LittleEndian>(sighash_type)
-        .map_err(|e| format!("Failed to write sighash type: {}", e))?;
-    
-    // If not ANYONECANPAY, add current input details
-    if (sighash_type & 0x80) == 0 {
-        // 10. Outpoint
-        let (outpoint, _, _) = &inputs[input_index];
-        data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-        
-        // 11. Script code
-        write_compact_size(&mut data, script_code.0.len() as u64)?;
-        data.write_all(&script_code.0)
-            .map_err(|e| format!("Failed to write script code: {}", e))?;
-        
-        // 12. Value
-        data.write_u64::<LittleEndian>(u64::from(value))
-            .map_err(|e| format!("Failed to write value: {}", e))?;
-        
-        // 13. Sequence
-        data.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
+
+    #[test]
+    fn test_sighash_anyonecanpay() {
+        assert_eq!(
+            sighash_hex(SIGHASH_ALL | SIGHASH_ANYONECANPAY, 0).unwrap(),
+            "d500f1da20bf14ea8fbd6200bbeb8c853ff259953dd05ebfbdfb3dfc754fec57"
+        );
+        assert_eq!(
+            sighash_hex(SIGHASH_NONE | SIGHASH_ANYONECANPAY, 1).unwrap(),
+            "c1746b09270c634abc3137584e46bdce84dfbf520e7a6c8213d1702cd4c98b44"
+        );
+        assert_eq!(
+            sighash_hex(SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, 1).unwrap(),
+            "ec95780b6cdf569b1c1d73c2883499dcf644a37a20f6167a8310c1a050f96190"
+        );
     }
-    
-    // Create personalization with consensus branch ID
-    let mut personalization = [0u8; 16];
-    personalization[..12].copy_from_slice(ZCASH_SIGHASH_PERSONALIZATION_PREFIX);
-    let branch_id: u32 = match BranchId::for_height(params, height) {
-        BranchId::Sprout => 0x00000000,
-        BranchId::Overwinter => 0x5ba81b19,
-        BranchId::Sapling => 0x76b809bb,
-        BranchId::Blossom => 0x2bb40e60,
-        BranchId::Heartwood => 0xf5b9230b,
-        BranchId::Canopy => 0xe9ff75a6,
-        BranchId::Nu5 => 0xc2d6d0b4,
-        #[cfg(zcash_unstable = "zfuture")]
-        BranchId::ZFuture => 0xffffffff,
-    };
-    personalization[12..16].copy_from_slice(&branch_id.to_le_bytes());
-    
-    // Compute BLAKE2b hash
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(&personalization)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
 
-/// Compute hash of all prevouts
-fn compute_prevouts_hash(inputs: &[(OutPoint, TxOut, SecretKey)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for (outpoint, _, _) in inputs {
-        data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
+    #[test]
+    fn test_sighash_single_without_matching_output() {
+        let (inputs, mut outputs) = fixture();
+        outputs.truncate(1);
+        assert!(overwinter_sighash(&outputs, &[DEFAULT_SEQUENCE; 2], 1, SIGHASH_SINGLE, 0).is_err());
+
+        let result = build_overwinter_tx_with_sighash(
+            &BITCOINZ_MAINNET,
+            inputs,
+            outputs,
+            BlockHeight::from(500_000),
+            None,
+            SIGHASH_SINGLE,
+        );
+        assert!(result.is_err());
     }
-    
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_PREVOUTS_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
 
-/// Compute hash of all sequences
-fn compute_sequences_hash(inputs: &[(OutPoint, TxOut, SecretKey)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for _ in inputs {
-        data.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
+    #[test]
+    fn test_validate_sighash_type() {
+        assert!(validate_sighash_type(SIGHASH_ALL).is_ok());
+        assert!(validate_sighash_type(SIGHASH_NONE).is_ok());
+        assert!(validate_sighash_type(SIGHASH_SINGLE | SIGHASH_ANYONECANPAY).is_ok());
+        assert!(validate_sighash_type(0).is_err());
+        assert!(validate_sighash_type(4).is_err());
+        assert!(validate_sighash_type(SIGHASH_ALL | 0x40).is_err());
     }
-    
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_SEQUENCE_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
 
-/// Compute hash of all outputs
-fn compute_outputs_hash(outputs: &[(TransparentAddress, Amount)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for (addr, amount) in outputs {
-        // Write amount (8 bytes)
-        data.write_u64::<LittleEndian>(u64::from(*amount))
-            .map_err(|e| format!("Failed to write amount: {}", e))?;
-        
-        // Write script pubkey
-        let script = addr.script();
-        write_compact_size(&mut data, script.0.len() as u64)?;
-        data.write_all(&script.0)
-            .map_err(|e| format!("Failed to write script: {}", e))?;
+    #[test]
+    fn test_script_sig_uses_requested_sighash_type() {
+        let (inputs, outputs) = fixture();
+        let tx = build_overwinter_tx_with_sighash(
+            &BITCOINZ_MAINNET,
+            inputs,
+            outputs,
+            BlockHeight::from(500_000),
+            None,
+            SIGHASH_NONE,
+        )
+        .unwrap()
+        .raw;
+
+        // header(8) + input count(1) + outpoint(36) + script length(1), then the signature push
+        let sig_push_len = tx[8 + 1 + 36 + 1] as usize;
+        let hash_type_byte = tx[8 + 1 + 36 + 1 + sig_push_len];
+        assert_eq!(hash_type_byte as u32, SIGHASH_NONE);
     }
-    
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_OUTPUTS_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
 
-/// Write a variable-length integer
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
+    #[test]
+    fn test_expiry_height() {
+        use std::convert::TryInto;
+
+        let (_, outputs) = fixture();
+        let sighash = |expiry| overwinter_sighash(&outputs, &[DEFAULT_SEQUENCE; 2], 0, SIGHASH_ALL, expiry).unwrap();
+        assert_ne!(sighash(0), sighash(500_010));
+        assert_ne!(sighash(500_010), sighash(500_020));
+
+        // The expiry comes right before the joinsplit count at the end of the transaction
+        let expiry_of = |tx: &[u8]| u32::from_le_bytes(tx[tx.len() - 5..tx.len() - 1].try_into().unwrap());
+        let build = |expiry| {
+            let (inputs, outputs) = fixture();
+            build_overwinter_tx(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), expiry)
+                .unwrap()
+                .raw
+        };
+        assert_eq!(expiry_of(&build(None)), 0);
+        assert_eq!(expiry_of(&build(Some(500_020))), 500_020);
+
+        // Signatures commit to the expiry: the None transaction signs an expiry of 0
+        assert_eq!(build(Some(0)), build(None));
+        assert_ne!(build(Some(0)), build(Some(500_020)));
     }
-    Ok(())
-}
 
-/// Read a variable-length integer
-fn read_compact_size(data: &[u8]) -> Result<Option<(u64, usize)>, String> {
-    if data.is_empty() {
-        return Ok(None);
+    #[test]
+    fn test_built_transaction() {
+        use crate::bitcoinz_transaction::{compute_txid, BitcoinZTxType};
+
+        let (inputs, outputs) = fixture();
+        let built = build_overwinter_tx(
+            &BITCOINZ_MAINNET,
+            inputs,
+            outputs,
+            BlockHeight::from(500_000),
+            Some(500_020),
+        )
+        .unwrap();
+        assert_eq!(built.txid, compute_txid(&built.raw));
+        assert_eq!(built.size, built.raw.len());
+        assert_eq!(built.fee, Amount::from_u64(10_000).unwrap());
+        assert_eq!(built.tx_type, BitcoinZTxType::TransparentToTransparent);
+        assert_eq!(built.expiry_height, 500_020);
+
+        // Outputs the inputs can't pay for aren't built
+        let (inputs, mut outputs) = fixture();
+        outputs[0].1 = Amount::from_u64(200_000).unwrap();
+        assert!(build_overwinter_tx(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), None).is_err());
     }
-    
-    let first = data[0];
-    match first {
-        0..=0xfc => Ok(Some((first as u64, 1))),
-        0xfd => {
-            if data.len() < 3 {
-                return Ok(None);
-            }
-            let val = u16::from_le_bytes([data[1], data[2]]) as u64;
-            Ok(Some((val, 3)))
-        }
-        0xfe => {
-            if data.len() < 5 {
-                return Ok(None);
-            }
-            let val = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as u64;
-            Ok(Some((val, 5)))
+
+    #[test]
+    fn test_custom_sequences() {
+        use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+
+        let (inputs, outputs) = fixture();
+        let sequences = vec![0xfffffffd, 0];
+        let tx = build_overwinter_tx_with_sequences(
+            &BITCOINZ_MAINNET,
+            inputs.clone(),
+            outputs.clone(),
+            BlockHeight::from(500_000),
+            None,
+            SIGHASH_ALL,
+            sequences.clone(),
+        )
+        .unwrap()
+        .raw;
+
+        // Walk the serialized inputs, reading back each scriptSig and sequence
+        let mut cursor = 8 + 1;
+        let mut serialized = vec![];
+        for _ in 0..inputs.len() {
+            cursor += 36;
+            let script_len = tx[cursor] as usize;
+            let script_sig = tx[cursor + 1..cursor + 1 + script_len].to_vec();
+            cursor += 1 + script_len;
+            let sequence = u32::from_le_bytes([tx[cursor], tx[cursor + 1], tx[cursor + 2], tx[cursor + 3]]);
+            cursor += 4;
+            serialized.push((script_sig, sequence));
         }
-        0xff => {
-            if data.len() < 9 {
-                return Ok(None);
-            }
-            let val = u64::from_le_bytes([
-                data[1], data[2], data[3], data[4],
-                data[5], data[6], data[7], data[8]
-            ]);
-            Ok(Some((val, 9)))
+        assert_eq!(serialized.iter().map(|(_, s)| *s).collect::<Vec<_>>(), sequences);
+
+        // The signatures verify against the sighash of the serialized sequences, and
+        // not against the defaults
+        let secp = Secp256k1::verification_only();
+        for (i, (script_sig, _)) in serialized.iter().enumerate() {
+            let sig_len = script_sig[0] as usize;
+            let signature = Signature::from_der(&script_sig[1..sig_len]).unwrap();
+            let pubkey = PublicKey::from_slice(&script_sig[sig_len + 2..]).unwrap();
+
+            let sighash = |sequences: &[u32]| {
+                let hash = overwinter_sighash(&outputs, sequences, i, SIGHASH_ALL, 0).unwrap();
+                Message::from_slice(&hash).unwrap()
+            };
+            assert!(secp.verify_ecdsa(&sighash(&sequences), &signature, &pubkey).is_ok());
+            assert!(secp
+                .verify_ecdsa(&sighash(&[DEFAULT_SEQUENCE; 2]), &signature, &pubkey)
+                .is_err());
         }
+
+        // The defaults are what build_overwinter_tx uses
+        let default = build_overwinter_tx_with_sequences(
+            &BITCOINZ_MAINNET,
+            inputs.clone(),
+            outputs.clone(),
+            BlockHeight::from(500_000),
+            None,
+            SIGHASH_ALL,
+            vec![DEFAULT_SEQUENCE; 2],
+        )
+        .unwrap();
+        assert_eq!(
+            default,
+            build_overwinter_tx(
+                &BITCOINZ_MAINNET,
+                inputs.clone(),
+                outputs.clone(),
+                BlockHeight::from(500_000),
+                None
+            )
+            .unwrap()
+        );
+
+        // One sequence per input
+        assert!(build_overwinter_tx_with_sequences(
+            &BITCOINZ_MAINNET,
+            inputs,
+            outputs,
+            BlockHeight::from(500_000),
+            None,
+            SIGHASH_ALL,
+            vec![0],
+        )
+        .is_err());
     }
 }
-
-/// Check if we should use Overwinter for this transaction
-pub fn should_use_overwinter(
-    transparent_inputs: usize,
-    shielded_inputs: usize,
-    transparent_outputs: usize,
-    shielded_outputs: usize,
-) -> bool {
-    // Only use Overwinter for pure transparent transactions
-    shielded_inputs == 0 && shielded_outputs == 0 && 
-    transparent_inputs > 0 && transparent_outputs > 0
-}
\ No newline at end of file
@@ -0,0 +1,758 @@
+/// BitcoinZ Transaction Parser
+///
+/// Reads back transactions in the layouts our builders produce: v1, v3 (Overwinter)
+/// and v4 (Sapling, header 0x80000004), the last two with the BitcoinZ version group
+/// ID. `BitcoinZTransaction` reads any of them and writes them back byte for byte, so
+/// we can verify our own output and inspect transactions received from the network.
+
+use crate::bitcoinz_binding_fix::{binding_sig_status, BindingSigStatus};
+use crate::bitcoinz_encoding::{read_compact_size, write_compact_size};
+use crate::bitcoinz_transaction::compute_txid;
+use crate::bitcoinz_txid::WireTxid;
+use json::{object, JsonValue};
+use std::convert::TryInto;
+use std::fmt;
+use zcash_primitives::{
+    legacy::Script,
+    transaction::components::Amount,
+};
+
+/// Expected v4 header (version 4 with the overwintered flag set)
+pub const V4_TX_HEADER: u32 = 0x80000004;
+/// Overwinter (v3) header
+pub const V3_TX_HEADER: u32 = 0x80000003;
+/// Header of a pre-Overwinter v1 transaction
+pub const V1_TX_HEADER: u32 = 1;
+/// BitcoinZ Sapling version group ID
+pub const BITCOINZ_VERSION_GROUP_ID: u32 = 0x892f2085;
+
+/// Size of a v4 (Groth16) JoinSplit description
+pub const JOINSPLIT_DESCRIPTION_SIZE: usize = 1698;
+/// Size of a v3 JoinSplit description, whose BCTV14 proof is larger
+pub const V3_JOINSPLIT_DESCRIPTION_SIZE: usize = 1802;
+/// Size of a Sapling spend description
+pub const SPEND_DESCRIPTION_SIZE: usize = 384;
+/// Size of a Sapling output description
+pub const OUTPUT_DESCRIPTION_SIZE: usize = 948;
+
+const GROTH_PROOF_SIZE: usize = 192;
+const ENC_CIPHERTEXT_SIZE: usize = 580;
+const OUT_CIPHERTEXT_SIZE: usize = 80;
+
+/// Errors from parsing BitcoinZ transactions, or from checking the parts of one being built
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitcoinZTxError {
+    /// Ran out of bytes while reading the named field
+    Truncated(&'static str),
+    /// The named count uses a longer encoding than its value needs
+    NonCanonicalCount(&'static str),
+    /// Not a transaction version that can be read here (or, where only v4 will do,
+    /// not 0x80000004)
+    UnsupportedHeader(u32),
+    /// Version group ID is not BitcoinZ's Sapling one
+    WrongVersionGroupId(u32),
+    /// A value field is outside the valid monetary range
+    InvalidAmount(&'static str),
+    /// Bytes left over after the end of the transaction
+    TrailingBytes(usize),
+    /// A note's diversifier has no diversified base (g_d), so the note can't be spent
+    InvalidDiversifier([u8; 11]),
+    /// An output pays less than the dust threshold, so the node won't relay it
+    DustOutput { amount: u64 },
+}
+
+impl fmt::Display for BitcoinZTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitcoinZTxError::Truncated(field) => write!(f, "Transaction too short reading {}", field),
+            BitcoinZTxError::NonCanonicalCount(field) => write!(f, "Non-canonical compact size for {}", field),
+            BitcoinZTxError::UnsupportedHeader(header) => {
+                write!(f, "Unsupported transaction version (header {:#010x})", header)
+            }
+            BitcoinZTxError::WrongVersionGroupId(id) => {
+                write!(f, "Unexpected version group ID {:#010x}", id)
+            }
+            BitcoinZTxError::InvalidAmount(field) => write!(f, "Invalid amount in {}", field),
+            BitcoinZTxError::TrailingBytes(n) => write!(f, "Unexpected {} trailing bytes", n),
+            BitcoinZTxError::InvalidDiversifier(d) => {
+                write!(f, "Diversifier {} has no valid g_d", hex::encode(d))
+            }
+            BitcoinZTxError::DustOutput { amount } => {
+                write!(f, "Output of {} zatoshis is below the dust threshold", amount)
+            }
+        }
+    }
+}
+
+impl From<BitcoinZTxError> for String {
+    fn from(e: BitcoinZTxError) -> Self {
+        e.to_string()
+    }
+}
+
+/// A transparent input
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTxIn {
+    pub prevout_hash: WireTxid,
+    pub prevout_index: u32,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+}
+
+/// A transparent output
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTxOut {
+    pub value: Amount,
+    pub script_pubkey: Script,
+}
+
+/// A Sapling spend description
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedSpend {
+    pub cv: [u8; 32],
+    pub anchor: [u8; 32],
+    pub nullifier: [u8; 32],
+    pub rk: [u8; 32],
+    pub zkproof: [u8; GROTH_PROOF_SIZE],
+    pub spend_auth_sig: [u8; 64],
+}
+
+/// A Sapling output description
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedOutput {
+    pub cv: [u8; 32],
+    pub cmu: [u8; 32],
+    pub ephemeral_key: [u8; 32],
+    pub enc_ciphertext: Vec<u8>,
+    pub out_ciphertext: Vec<u8>,
+    pub zkproof: [u8; GROTH_PROOF_SIZE],
+}
+
+/// Every field of a BitcoinZ v4 transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedV4Tx {
+    pub header: u32,
+    pub version_group_id: u32,
+    pub inputs: Vec<ParsedTxIn>,
+    pub outputs: Vec<ParsedTxOut>,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub value_balance: Amount,
+    pub shielded_spends: Vec<ParsedSpend>,
+    pub shielded_outputs: Vec<ParsedOutput>,
+    /// JoinSplits are skipped over, we only report how many there were
+    pub joinsplit_count: usize,
+    /// Only present when there are shielded spends or outputs
+    pub binding_sig: Option<[u8; 64]>,
+}
+
+impl ParsedV4Tx {
+    pub fn binding_sig_status(&self) -> BindingSigStatus {
+        binding_sig_status(self.binding_sig.as_ref())
+    }
+}
+
+/// Parse a serialized BitcoinZ v4 transaction. The whole slice must be consumed.
+pub fn parse_bitcoinz_v4(bytes: &[u8]) -> Result<ParsedV4Tx, BitcoinZTxError> {
+    let header = TxReader { data: bytes, pos: 0 }.read_u32("header")?;
+    if header != V4_TX_HEADER {
+        return Err(BitcoinZTxError::UnsupportedHeader(header));
+    }
+
+    let tx = BitcoinZTransaction::read(bytes)?;
+    Ok(ParsedV4Tx {
+        header: tx.header,
+        version_group_id: tx.version_group_id,
+        inputs: tx.inputs,
+        outputs: tx.outputs,
+        lock_time: tx.lock_time,
+        expiry_height: tx.expiry_height,
+        value_balance: tx.value_balance,
+        shielded_spends: tx.shielded_spends,
+        shielded_outputs: tx.shielded_outputs,
+        joinsplit_count: tx.joinsplit_count,
+        binding_sig: tx.binding_sig,
+    })
+}
+
+/// A BitcoinZ transaction of any version our builders make. Fields a version doesn't
+/// have are left empty: no version group or expiry for v1, no value balance or Sapling
+/// descriptions before v4, and no JoinSplits for v1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitcoinZTransaction {
+    pub header: u32,
+    /// 0 for v1
+    pub version_group_id: u32,
+    pub inputs: Vec<ParsedTxIn>,
+    pub outputs: Vec<ParsedTxOut>,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub value_balance: Amount,
+    pub shielded_spends: Vec<ParsedSpend>,
+    pub shielded_outputs: Vec<ParsedOutput>,
+    pub joinsplit_count: usize,
+    /// The JoinSplit descriptions with joinSplitPubKey and joinSplitSig, as they were
+    /// read. They aren't decoded, only carried through.
+    pub joinsplits: Vec<u8>,
+    /// Only present when there are shielded spends or outputs
+    pub binding_sig: Option<[u8; 64]>,
+}
+
+impl BitcoinZTransaction {
+    pub fn binding_sig_status(&self) -> BindingSigStatus {
+        binding_sig_status(self.binding_sig.as_ref())
+    }
+
+    /// Parse a serialized v1, v3 or v4 transaction. The whole slice must be consumed.
+    pub fn read(bytes: &[u8]) -> Result<Self, BitcoinZTxError> {
+        let mut reader = TxReader { data: bytes, pos: 0 };
+
+        let header = reader.read_u32("header")?;
+        let (overwintered, sapling) = match header {
+            V1_TX_HEADER => (false, false),
+            V3_TX_HEADER => (true, false),
+            V4_TX_HEADER => (true, true),
+            _ => return Err(BitcoinZTxError::UnsupportedHeader(header)),
+        };
+        let version_group_id = if overwintered {
+            let version_group_id = reader.read_u32("version group ID")?;
+            if version_group_id != BITCOINZ_VERSION_GROUP_ID {
+                return Err(BitcoinZTxError::WrongVersionGroupId(version_group_id));
+            }
+            version_group_id
+        } else {
+            0
+        };
+
+        // Transparent inputs
+        let input_count = reader.read_count("input count")?;
+        let mut inputs = Vec::with_capacity(input_count.min(bytes.len()));
+        for _ in 0..input_count {
+            let prevout_hash = WireTxid::from_bytes(reader.read_array::<32>("outpoint hash")?);
+            let prevout_index = reader.read_u32("outpoint index")?;
+            let script_len = reader.read_count("script sig length")?;
+            let script_sig = reader.take(script_len, "script sig")?.to_vec();
+            let sequence = reader.read_u32("sequence")?;
+            inputs.push(ParsedTxIn {
+                prevout_hash,
+                prevout_index,
+                script_sig,
+                sequence,
+            });
+        }
+
+        // Transparent outputs
+        let output_count = reader.read_count("output count")?;
+        let mut outputs = Vec::with_capacity(output_count.min(bytes.len()));
+        for _ in 0..output_count {
+            let value = Amount::from_u64(reader.read_u64("output value")?)
+                .map_err(|_| BitcoinZTxError::InvalidAmount("output value"))?;
+            let script_len = reader.read_count("script pubkey length")?;
+            let script_pubkey = Script(reader.take(script_len, "script pubkey")?.to_vec());
+            outputs.push(ParsedTxOut { value, script_pubkey });
+        }
+
+        let lock_time = reader.read_u32("lock time")?;
+
+        // v1 ends here
+        if !overwintered {
+            if reader.remaining() > 0 {
+                return Err(BitcoinZTxError::TrailingBytes(reader.remaining()));
+            }
+            return Ok(BitcoinZTransaction {
+                header,
+                version_group_id,
+                inputs,
+                outputs,
+                lock_time,
+                expiry_height: 0,
+                value_balance: Amount::zero(),
+                shielded_spends: vec![],
+                shielded_outputs: vec![],
+                joinsplit_count: 0,
+                joinsplits: vec![],
+                binding_sig: None,
+            });
+        }
+
+        let expiry_height = reader.read_u32("expiry height")?;
+
+        let mut value_balance = Amount::zero();
+        let mut shielded_spends = vec![];
+        let mut shielded_outputs = vec![];
+        if sapling {
+            value_balance = Amount::from_i64(reader.read_u64("value balance")? as i64)
+                .map_err(|_| BitcoinZTxError::InvalidAmount("value balance"))?;
+
+            // Sapling spends
+            let spend_count = reader.read_count("shielded spend count")?;
+            shielded_spends.reserve(spend_count.min(bytes.len() / SPEND_DESCRIPTION_SIZE));
+            for _ in 0..spend_count {
+                shielded_spends.push(ParsedSpend {
+                    cv: reader.read_array("spend cv")?,
+                    anchor: reader.read_array("spend anchor")?,
+                    nullifier: reader.read_array("spend nullifier")?,
+                    rk: reader.read_array("spend rk")?,
+                    zkproof: reader.read_array("spend proof")?,
+                    spend_auth_sig: reader.read_array("spend auth sig")?,
+                });
+            }
+
+            // Sapling outputs
+            let shielded_output_count = reader.read_count("shielded output count")?;
+            shielded_outputs.reserve(shielded_output_count.min(bytes.len() / OUTPUT_DESCRIPTION_SIZE));
+            for _ in 0..shielded_output_count {
+                shielded_outputs.push(ParsedOutput {
+                    cv: reader.read_array("output cv")?,
+                    cmu: reader.read_array("output cmu")?,
+                    ephemeral_key: reader.read_array("output ephemeral key")?,
+                    enc_ciphertext: reader.take(ENC_CIPHERTEXT_SIZE, "output enc ciphertext")?.to_vec(),
+                    out_ciphertext: reader.take(OUT_CIPHERTEXT_SIZE, "output out ciphertext")?.to_vec(),
+                    zkproof: reader.read_array("output proof")?,
+                });
+            }
+        }
+
+        // JoinSplits, followed by joinSplitPubKey and joinSplitSig when there are any
+        let joinsplit_count = reader.read_count("joinsplit count")?;
+        let mut joinsplits = vec![];
+        if joinsplit_count > 0 {
+            let description_size = if sapling {
+                JOINSPLIT_DESCRIPTION_SIZE
+            } else {
+                V3_JOINSPLIT_DESCRIPTION_SIZE
+            };
+            let len = joinsplit_count
+                .checked_mul(description_size)
+                .and_then(|n| n.checked_add(32 + 64))
+                .ok_or(BitcoinZTxError::Truncated("joinsplits"))?;
+            joinsplits = reader.take(len, "joinsplits")?.to_vec();
+        }
+
+        let binding_sig = if !shielded_spends.is_empty() || !shielded_outputs.is_empty() {
+            Some(reader.read_array::<64>("binding signature")?)
+        } else {
+            None
+        };
+
+        if reader.remaining() > 0 {
+            return Err(BitcoinZTxError::TrailingBytes(reader.remaining()));
+        }
+
+        Ok(BitcoinZTransaction {
+            header,
+            version_group_id,
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height,
+            value_balance,
+            shielded_spends,
+            shielded_outputs,
+            joinsplit_count,
+            joinsplits,
+            binding_sig,
+        })
+    }
+
+    /// Serialize the transaction, in the layout its header calls for
+    pub fn write(&self) -> Vec<u8> {
+        let overwintered = self.header != V1_TX_HEADER;
+        let sapling = self.header == V4_TX_HEADER;
+
+        let mut tx = self.header.to_le_bytes().to_vec();
+        if overwintered {
+            tx.extend_from_slice(&self.version_group_id.to_le_bytes());
+        }
+
+        write_compact_size(&mut tx, self.inputs.len() as u64);
+        for input in &self.inputs {
+            tx.extend_from_slice(input.prevout_hash.as_bytes());
+            tx.extend_from_slice(&input.prevout_index.to_le_bytes());
+            write_compact_size(&mut tx, input.script_sig.len() as u64);
+            tx.extend_from_slice(&input.script_sig);
+            tx.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+
+        write_compact_size(&mut tx, self.outputs.len() as u64);
+        for output in &self.outputs {
+            tx.extend_from_slice(&i64::from(output.value).to_le_bytes());
+            write_compact_size(&mut tx, output.script_pubkey.0.len() as u64);
+            tx.extend_from_slice(&output.script_pubkey.0);
+        }
+
+        tx.extend_from_slice(&self.lock_time.to_le_bytes());
+        if !overwintered {
+            return tx;
+        }
+        tx.extend_from_slice(&self.expiry_height.to_le_bytes());
+
+        if sapling {
+            tx.extend_from_slice(&i64::from(self.value_balance).to_le_bytes());
+            write_compact_size(&mut tx, self.shielded_spends.len() as u64);
+            for spend in &self.shielded_spends {
+                tx.extend_from_slice(&spend.cv);
+                tx.extend_from_slice(&spend.anchor);
+                tx.extend_from_slice(&spend.nullifier);
+                tx.extend_from_slice(&spend.rk);
+                tx.extend_from_slice(&spend.zkproof);
+                tx.extend_from_slice(&spend.spend_auth_sig);
+            }
+            write_compact_size(&mut tx, self.shielded_outputs.len() as u64);
+            for output in &self.shielded_outputs {
+                tx.extend_from_slice(&output.cv);
+                tx.extend_from_slice(&output.cmu);
+                tx.extend_from_slice(&output.ephemeral_key);
+                tx.extend_from_slice(&output.enc_ciphertext);
+                tx.extend_from_slice(&output.out_ciphertext);
+                tx.extend_from_slice(&output.zkproof);
+            }
+        }
+
+        write_compact_size(&mut tx, self.joinsplit_count as u64);
+        tx.extend_from_slice(&self.joinsplits);
+
+        if let Some(binding_sig) = &self.binding_sig {
+            tx.extend_from_slice(binding_sig);
+        }
+        tx
+    }
+
+    /// The transaction as `decoderawtransaction` shows it. Txids are displayed reversed,
+    /// values are in zatoshis and everything else binary is hex.
+    pub fn to_json(&self) -> JsonValue {
+        let raw = self.write();
+        let vin = self
+            .inputs
+            .iter()
+            .map(|input| {
+                object! {
+                    "txid" => input.prevout_hash.to_display().to_string(),
+                    "vout" => input.prevout_index,
+                    "scriptSig" => hex::encode(&input.script_sig),
+                    "sequence" => input.sequence,
+                }
+            })
+            .collect::<Vec<_>>();
+        let vout = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(n, output)| {
+                object! {
+                    "value" => i64::from(output.value),
+                    "n" => n,
+                    "scriptPubKey" => hex::encode(&output.script_pubkey.0),
+                }
+            })
+            .collect::<Vec<_>>();
+        let spends = self
+            .shielded_spends
+            .iter()
+            .map(|spend| {
+                object! {
+                    "cv" => hex::encode(spend.cv),
+                    "anchor" => hex::encode(spend.anchor),
+                    "nullifier" => hex::encode(spend.nullifier),
+                    "rk" => hex::encode(spend.rk),
+                }
+            })
+            .collect::<Vec<_>>();
+        let shielded_outputs = self
+            .shielded_outputs
+            .iter()
+            .map(|output| {
+                object! {
+                    "cv" => hex::encode(output.cv),
+                    "cmu" => hex::encode(output.cmu),
+                    "ephemeralKey" => hex::encode(output.ephemeral_key),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        object! {
+            "txid" => compute_txid(&raw).to_string(),
+            "size" => raw.len(),
+            "overwintered" => self.header != V1_TX_HEADER,
+            "version" => self.header & 0x7fff_ffff,
+            "versiongroupid" => format!("{:08x}", self.version_group_id),
+            "locktime" => self.lock_time,
+            "expiryheight" => self.expiry_height,
+            "vin" => vin,
+            "vout" => vout,
+            "valueBalance" => i64::from(self.value_balance),
+            "vShieldedSpend" => spends,
+            "vShieldedOutput" => shielded_outputs,
+            "joinsplits" => self.joinsplit_count,
+            "bindingSig" => self.binding_sig.map(hex::encode),
+        }
+    }
+}
+
+struct TxReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TxReader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize, field: &'static str) -> Result<&'a [u8], BitcoinZTxError> {
+        if len > self.remaining() {
+            return Err(BitcoinZTxError::Truncated(field));
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self, field: &'static str) -> Result<[u8; N], BitcoinZTxError> {
+        Ok(self.take(N, field)?.try_into().unwrap())
+    }
+
+    fn read_u32(&mut self, field: &'static str) -> Result<u32, BitcoinZTxError> {
+        Ok(u32::from_le_bytes(self.read_array(field)?))
+    }
+
+    fn read_u64(&mut self, field: &'static str) -> Result<u64, BitcoinZTxError> {
+        Ok(u64::from_le_bytes(self.read_array(field)?))
+    }
+
+    fn read_count(&mut self, field: &'static str) -> Result<usize, BitcoinZTxError> {
+        let (val, size) = read_compact_size(&self.data[self.pos..])
+            .map_err(|_| BitcoinZTxError::NonCanonicalCount(field))?
+            .ok_or(BitcoinZTxError::Truncated(field))?;
+        self.pos += size;
+        Ok(val as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_script_sig::TransparentInputKeys;
+    use crate::bitcoinz_transparent_builder::{transparent_builder, TransparentTxFormat};
+    use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+    use crate::bitcoinz_v4_shielded::BitcoinZShieldedBuilder;
+    use crate::blaze::test_utils::FakeTxProver;
+    use crate::BITCOINZ_MAINNET;
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use zcash_primitives::{
+        consensus::BlockHeight,
+        legacy::TransparentAddress,
+        memo::MemoBytes,
+        transaction::components::{OutPoint, TxOut},
+        zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
+    };
+
+    fn inputs() -> Vec<(OutPoint, TxOut, SecretKey)> {
+        vec![
+            (
+                OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+            ),
+            (
+                OutPoint::new([0x33u8; 32], 1),
+                TxOut {
+                    value: Amount::from_u64(250_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x44u8; 20]).script(),
+                },
+                SecretKey::from_slice(&[0x02u8; 32]).unwrap(),
+            ),
+        ]
+    }
+
+    fn outputs() -> Vec<(TransparentAddress, Amount)> {
+        vec![
+            (TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(150_000).unwrap()),
+            (TransparentAddress::Script([0x66u8; 20]), Amount::from_u64(190_000).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_transparent() {
+        let tx = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs(), outputs(), BlockHeight::from(500_000), None, None)
+            .unwrap()
+            .raw;
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+
+        assert_eq!(parsed.header, V4_TX_HEADER);
+        assert_eq!(parsed.version_group_id, BITCOINZ_VERSION_GROUP_ID);
+
+        let secp = Secp256k1::new();
+        assert_eq!(parsed.inputs.len(), 2);
+        for (parsed_in, (outpoint, _, sk)) in parsed.inputs.iter().zip(inputs()) {
+            assert_eq!(parsed_in.prevout_hash, WireTxid::from(&outpoint));
+            assert_eq!(parsed_in.prevout_index, outpoint.n());
+            assert_eq!(parsed_in.sequence, 0xfffffffe);
+            // scriptSig ends with the compressed public key of the signer
+            let pk = PublicKey::from_secret_key(&secp, &sk).serialize();
+            assert!(parsed_in.script_sig.ends_with(&pk));
+        }
+
+        assert_eq!(parsed.outputs.len(), 2);
+        for (parsed_out, (addr, value)) in parsed.outputs.iter().zip(outputs()) {
+            assert_eq!(parsed_out.value, value);
+            assert_eq!(parsed_out.script_pubkey, addr.script());
+        }
+
+        assert_eq!(parsed.lock_time, 0);
+        assert_eq!(parsed.expiry_height, 0);
+        assert_eq!(parsed.value_balance, Amount::zero());
+        assert!(parsed.shielded_spends.is_empty());
+        assert!(parsed.shielded_outputs.is_empty());
+        assert_eq!(parsed.joinsplit_count, 0);
+        assert_eq!(parsed.binding_sig, None);
+        assert_eq!(parsed.binding_sig_status(), BindingSigStatus::Absent);
+    }
+
+    #[test]
+    fn test_parse_shielded_output() {
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let (outpoint, coin, sk) = inputs().remove(0);
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder.add_transparent_input(outpoint, coin, sk).unwrap();
+        builder
+            .add_sapling_output(
+                Some(extfvk.fvk.ovk),
+                extfvk.default_address().1,
+                Amount::from_u64(90_000).unwrap(),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        let tx = builder.build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap()).unwrap().raw;
+
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+        assert_eq!(parsed.inputs.len(), 1);
+        assert!(parsed.outputs.is_empty());
+        assert!(parsed.shielded_spends.is_empty());
+        assert_eq!(parsed.shielded_outputs.len(), 1);
+        assert_eq!(parsed.value_balance, Amount::from_i64(-90_000).unwrap());
+        assert_eq!(&parsed.binding_sig.unwrap()[..], &tx[tx.len() - 64..]);
+        assert_eq!(parsed.binding_sig_status(), BindingSigStatus::WellFormed);
+    }
+
+    #[test]
+    fn test_read_write_each_format() {
+        for (format, header) in [
+            (TransparentTxFormat::V1, V1_TX_HEADER),
+            (TransparentTxFormat::Overwinter, V3_TX_HEADER),
+            (TransparentTxFormat::Sapling, V4_TX_HEADER),
+        ] {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            for (outpoint, coin, sk) in inputs() {
+                builder.add_input(outpoint, coin, TransparentInputKeys::PubKeyHash(sk));
+            }
+            for (addr, value) in outputs() {
+                builder.add_output(addr, value);
+            }
+            let raw = builder.build().unwrap().raw;
+
+            let tx = BitcoinZTransaction::read(&raw).unwrap();
+            assert_eq!(tx.header, header);
+            assert_eq!(tx.inputs.len(), 2);
+            assert_eq!(tx.outputs.len(), 2);
+            assert_eq!(tx.write(), raw);
+
+            let json = tx.to_json();
+            assert_eq!(json["txid"], compute_txid(&raw).to_string());
+            assert_eq!(json["vout"][1]["value"], 190_000);
+
+            let mut trailing = raw.clone();
+            trailing.push(0);
+            assert_eq!(BitcoinZTransaction::read(&trailing), Err(BitcoinZTxError::TrailingBytes(1)));
+        }
+    }
+
+    #[test]
+    fn test_read_write_shielded() {
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let (outpoint, coin, sk) = inputs().remove(0);
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder.add_transparent_input(outpoint, coin, sk).unwrap();
+        builder
+            .add_sapling_output(
+                Some(extfvk.fvk.ovk),
+                extfvk.default_address().1,
+                Amount::from_u64(90_000).unwrap(),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        let raw = builder.build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap()).unwrap().raw;
+
+        let tx = BitcoinZTransaction::read(&raw).unwrap();
+        assert_eq!(tx.shielded_outputs.len(), 1);
+        assert_eq!(tx.write(), raw);
+        assert_eq!(tx.to_json()["valueBalance"], -90_000);
+    }
+
+    #[test]
+    fn test_read_v3_joinsplits() {
+        // A v3 transaction with one (zeroed) BCTV14 JoinSplit carried through unread
+        let mut raw = V3_TX_HEADER.to_le_bytes().to_vec();
+        raw.extend_from_slice(&BITCOINZ_VERSION_GROUP_ID.to_le_bytes());
+        raw.extend_from_slice(&[0, 0]); // no transparent inputs or outputs
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&500_020u32.to_le_bytes());
+        raw.push(1);
+        raw.extend_from_slice(&[0u8; V3_JOINSPLIT_DESCRIPTION_SIZE + 32 + 64]);
+
+        let tx = BitcoinZTransaction::read(&raw).unwrap();
+        assert_eq!(tx.joinsplit_count, 1);
+        assert_eq!(tx.expiry_height, 500_020);
+        assert_eq!(tx.write(), raw);
+
+        assert!(matches!(
+            BitcoinZTransaction::read(&raw[..raw.len() - 1]),
+            Err(BitcoinZTxError::Truncated("joinsplits"))
+        ));
+        // parse_bitcoinz_v4 still only takes v4
+        assert_eq!(parse_bitcoinz_v4(&raw), Err(BitcoinZTxError::UnsupportedHeader(V3_TX_HEADER)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let tx = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs(), outputs(), BlockHeight::from(500_000), None, None)
+            .unwrap()
+            .raw;
+
+        assert!(matches!(
+            parse_bitcoinz_v4(&tx[..tx.len() - 1]),
+            Err(BitcoinZTxError::Truncated(_))
+        ));
+
+        let mut trailing = tx.clone();
+        trailing.push(0);
+        assert_eq!(parse_bitcoinz_v4(&trailing), Err(BitcoinZTxError::TrailingBytes(1)));
+
+        let mut v3 = tx.clone();
+        v3[0] = 0x03;
+        assert_eq!(parse_bitcoinz_v4(&v3), Err(BitcoinZTxError::UnsupportedHeader(0x80000003)));
+
+        // The input count, padded out to three bytes
+        let mut padded = tx[..8].to_vec();
+        padded.extend_from_slice(&[0xfd, tx[8], 0x00]);
+        padded.extend_from_slice(&tx[9..]);
+        assert!(matches!(
+            parse_bitcoinz_v4(&padded),
+            Err(BitcoinZTxError::NonCanonicalCount(_))
+        ));
+
+        let mut bad_vgid = tx;
+        bad_vgid[4..8].copy_from_slice(&0x892f2084u32.to_le_bytes());
+        assert_eq!(
+            parse_bitcoinz_v4(&bad_vgid),
+            Err(BitcoinZTxError::WrongVersionGroupId(0x892f2084))
+        );
+    }
+}
@@ -9,23 +9,29 @@ use zcash_primitives::{
     legacy::TransparentAddress,
     transaction::components::{Amount, OutPoint, TxOut},
 };
-use zcash_client_backend::encoding::AddressCodec;
 use secp256k1::SecretKey;
 
+use crate::bitcoinz_address::encode_transparent_address;
+use crate::bitcoinz_encoding::{compact_size_len, write_amount, write_compact_size, write_outpoint, write_script};
+use crate::bitcoinz_parse::parse_bitcoinz_v4;
+use crate::bitcoinz_script_sig::pubkey_hash_inputs;
+use crate::bitcoinz_txid::WireTxid;
+use crate::bitcoinz_transparent_builder::{sign_inputs, TransparentParts, TransparentTxFormat};
+
 /// Create a BitcoinZ transaction using RPC-style approach
 /// This mimics what bitcoinz-cli createrawtransaction does
 pub fn build_bitcoinz_rpc_tx<P: Parameters>(
-    _params: &P,
+    params: &P,
     inputs: Vec<(OutPoint, TxOut, SecretKey)>,
     outputs: Vec<(TransparentAddress, Amount)>,
-    _height: BlockHeight,
+    height: BlockHeight,
 ) -> Result<Vec<u8>, String> {
     
     // Create the transaction in BitcoinZ JSON format
     let mut tx_inputs = Vec::new();
     for (outpoint, _, _) in &inputs {
         tx_inputs.push(json!({
-            "txid": hex::encode(outpoint.hash()),
+            "txid": WireTxid::from(outpoint).to_display().to_string(),
             "vout": outpoint.n(),
             "sequence": 4294967294u32  // 0xfffffffe
         }));
@@ -33,7 +39,7 @@ pub fn build_bitcoinz_rpc_tx<P: Parameters>(
     
     let mut tx_outputs = json!({});
     for (addr, amount) in &outputs {
-        let addr_str = addr.encode(&crate::BITCOINZ_MAINNET);
+        let addr_str = encode_transparent_address(addr, params);
         let btcz_amount = u64::from(*amount) as f64 / 100_000_000.0;
         tx_outputs[addr_str] = json!(btcz_amount);
     }
@@ -46,7 +52,7 @@ pub fn build_bitcoinz_rpc_tx<P: Parameters>(
         "locktime": 0,
         "expiryheight": 0,  // No expiry
         "vin": tx_inputs,
-        "vout": create_outputs_array(&outputs)?,
+        "vout": create_outputs_array(params, &outputs)?,
         "valueBalance": 0.0,
         "vShieldedSpend": [],
         "vShieldedOutput": [],
@@ -57,21 +63,34 @@ pub fn build_bitcoinz_rpc_tx<P: Parameters>(
     // Convert to raw transaction hex
     // This is where we'd normally call bitcoinz-cli createrawtransaction
     // For now, let's build it manually using BitcoinZ format
-    build_raw_transaction_hex(inputs, outputs)
+    let unsigned_tx = build_raw_transaction_hex(inputs.clone(), outputs)?;
+    
+    // And sign it, like signrawtransactionwithkey would
+    sign_bitcoinz_transaction(params, height, unsigned_tx, inputs)
 }
 
 /// Create outputs array in BitcoinZ format
-fn create_outputs_array(outputs: &[(TransparentAddress, Amount)]) -> Result<Vec<Value>, String> {
+fn create_outputs_array<P: Parameters>(
+    params: &P,
+    outputs: &[(TransparentAddress, Amount)],
+) -> Result<Vec<Value>, String> {
     let mut vout = Vec::new();
     
     for (n, (addr, amount)) in outputs.iter().enumerate() {
+        // script() is OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG for a pubkey
+        // hash and OP_HASH160 <hash> OP_EQUAL for a script hash
+        let script_type = match addr {
+            TransparentAddress::PublicKey(_) => "pubkeyhash",
+            TransparentAddress::Script(_) => "scripthash",
+        };
         vout.push(json!({
             "n": n,
             "value": u64::from(*amount) as f64 / 100_000_000.0,
             "valueZat": u64::from(*amount),
             "scriptPubKey": {
                 "hex": hex::encode(&addr.script().0),
-                "addresses": [addr.encode(&crate::BITCOINZ_MAINNET)]
+                "type": script_type,
+                "addresses": [encode_transparent_address(addr, params)]
             }
         }));
     }
@@ -80,7 +99,7 @@ fn create_outputs_array(outputs: &[(TransparentAddress, Amount)]) -> Result<Vec<
 }
 
 /// Build raw transaction hex in BitcoinZ v4 format
-fn build_raw_transaction_hex(
+pub(crate) fn build_raw_transaction_hex(
     inputs: Vec<(OutPoint, TxOut, SecretKey)>,
     outputs: Vec<(TransparentAddress, Amount)>,
 ) -> Result<Vec<u8>, String> {
@@ -98,30 +117,23 @@ fn build_raw_transaction_hex(
         .map_err(|e| format!("Failed to write version group ID: {}", e))?;
     
     // Input count
-    write_compact_size(&mut tx_data, inputs.len() as u64)?;
+    write_compact_size(&mut tx_data, inputs.len() as u64);
     
     // Inputs (unsigned for now)
     for (outpoint, _, _) in &inputs {
-        tx_data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        tx_data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-        write_compact_size(&mut tx_data, 0)?; // Empty scriptSig
+        write_outpoint(&mut tx_data, outpoint);
+        write_compact_size(&mut tx_data, 0); // Empty scriptSig
         tx_data.write_u32::<LittleEndian>(0xfffffffe)
             .map_err(|e| format!("Failed to write sequence: {}", e))?;
     }
     
     // Output count
-    write_compact_size(&mut tx_data, outputs.len() as u64)?;
+    write_compact_size(&mut tx_data, outputs.len() as u64);
     
     // Outputs
     for (addr, amount) in &outputs {
-        tx_data.write_u64::<LittleEndian>(u64::from(*amount))
-            .map_err(|e| format!("Failed to write amount: {}", e))?;
-        let script = addr.script();
-        write_compact_size(&mut tx_data, script.0.len() as u64)?;
-        tx_data.write_all(&script.0)
-            .map_err(|e| format!("Failed to write script: {}", e))?;
+        write_amount(&mut tx_data, *amount);
+        write_script(&mut tx_data, &addr.script());
     }
     
     // Lock time
@@ -137,54 +149,245 @@ fn build_raw_transaction_hex(
         .map_err(|e| format!("Failed to write value balance: {}", e))?;
     
     // No shielded spends
-    write_compact_size(&mut tx_data, 0)?;
+    write_compact_size(&mut tx_data, 0);
     
     // No shielded outputs
-    write_compact_size(&mut tx_data, 0)?;
+    write_compact_size(&mut tx_data, 0);
     
     // No joinsplits
-    write_compact_size(&mut tx_data, 0)?;
+    write_compact_size(&mut tx_data, 0);
     
     // For transparent-only, binding sig should be empty
     // BitcoinZ might expect no binding sig at all for transparent-only
     
-    
-    // Now we need to sign it
-    // In a real implementation, we'd call signrawtransactionwithkey
-    // For now, return unsigned
-    
+    // The scriptSigs are filled in by sign_bitcoinz_transaction
     Ok(tx_data)
 }
 
-/// Write a variable-length integer
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    use byteorder::{LittleEndian, WriteBytesExt};
-    
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
+/// Sign a BitcoinZ transaction
+/// This does what signrawtransactionwithkey would: every input of the unsigned
+/// transaction gets a P2PKH scriptSig made with the matching key in `inputs`.
+pub fn sign_bitcoinz_transaction<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    unsigned_tx: Vec<u8>,
+    inputs: Vec<(OutPoint, TxOut, SecretKey)>,
+) -> Result<Vec<u8>, String> {
+    let tx = parse_bitcoinz_v4(&unsigned_tx)?;
+
+    if tx.inputs.len() != inputs.len() {
+        return Err(format!(
+            "Transaction has {} inputs, but {} keys were given",
+            tx.inputs.len(),
+            inputs.len()
+        ));
+    }
+    for (i, (txin, (outpoint, _, _))) in tx.inputs.iter().zip(inputs.iter()).enumerate() {
+        if txin.prevout_hash != WireTxid::from(outpoint) || txin.prevout_index != outpoint.n() {
+            return Err(format!("Input {} doesn't spend the given outpoint", i));
+        }
+        if !txin.script_sig.is_empty() {
+            return Err(format!("Input {} is already signed", i));
+        }
+        if txin.sequence != 0xfffffffe {
+            return Err(format!("Input {} has unsupported sequence {:#x}", i, txin.sequence));
+        }
     }
-    Ok(())
+
+    // The sighash only covers what build_raw_transaction_hex writes
+    if tx.lock_time != 0
+        || tx.expiry_height != 0
+        || !tx.shielded_spends.is_empty()
+        || !tx.shielded_outputs.is_empty()
+        || tx.joinsplit_count != 0
+    {
+        return Err("Only transparent transactions without lock time or expiry can be signed".to_string());
+    }
+
+    let outputs = tx
+        .outputs
+        .iter()
+        .enumerate()
+        .map(|(i, o)| {
+            o.script_pubkey
+                .address()
+                .map(|addr| (addr, o.value))
+                .ok_or_else(|| format!("Output {} is not a P2PKH or P2SH script", i))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let parts = TransparentParts::new(pubkey_hash_inputs(&inputs), outputs);
+    let script_sigs = sign_inputs(params, height, TransparentTxFormat::Sapling, &parts)?;
+
+    // Header, version group ID and the inputs with their scriptSigs, then
+    // everything after the (empty) unsigned inputs unchanged
+    let inputs_end = 8
+        + compact_size_len(tx.inputs.len() as u64)
+        + tx.inputs.len() * (36 + compact_size_len(0) + 4);
+
+    let mut signed_tx = unsigned_tx[..8].to_vec();
+    write_compact_size(&mut signed_tx, inputs.len() as u64);
+    for ((outpoint, _, _), script_sig) in inputs.iter().zip(script_sigs) {
+        write_outpoint(&mut signed_tx, outpoint);
+        write_compact_size(&mut signed_tx, script_sig.len() as u64);
+        signed_tx.extend_from_slice(&script_sig);
+        signed_tx.extend_from_slice(&0xfffffffeu32.to_le_bytes());
+    }
+    signed_tx.extend_from_slice(&unsigned_tx[inputs_end..]);
+
+    Ok(signed_tx)
 }
 
-/// Sign a BitcoinZ transaction
-/// This would normally use signrawtransactionwithkey RPC
-pub fn sign_bitcoinz_transaction(
-    _unsigned_tx: Vec<u8>,
-    _inputs: Vec<(OutPoint, TxOut, SecretKey)>,
-) -> Result<Vec<u8>, String> {
-    // TODO: Implement proper BitcoinZ signing
-    // This would compute sighashes the BitcoinZ way
-    Err("BitcoinZ signing not yet implemented".to_string())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+    use crate::lightclient::lightclient_config::UnitTestNetwork;
+    use secp256k1::{ecdsa::Signature, PublicKey, Secp256k1};
+
+    #[test]
+    fn test_sign_single_input() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+
+        let inputs = vec![(
+            OutPoint::new([0x33; 32], 1),
+            TxOut {
+                value: Amount::from_u64(200_000).unwrap(),
+                script_pubkey: TransparentAddress::PublicKey([0x22; 20]).script(),
+            },
+            sk,
+        )];
+        let outputs = vec![(TransparentAddress::PublicKey([0x44; 20]), Amount::from_u64(190_000).unwrap())];
+        let height = BlockHeight::from_u32(500_000);
+
+        let tx = build_bitcoinz_rpc_tx(&UnitTestNetwork, inputs.clone(), outputs.clone(), height).unwrap();
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+        assert_eq!(parsed.inputs.len(), 1);
+
+        // <push><DER signature + SIGHASH_ALL><push 33><compressed pubkey>
+        let script_sig = &parsed.inputs[0].script_sig;
+        let sig_len = script_sig[0] as usize;
+        assert_eq!(script_sig[sig_len], 0x01);
+        assert!(Signature::from_der(&script_sig[1..sig_len]).is_ok());
+
+        assert_eq!(script_sig[1 + sig_len], 33);
+        assert_eq!(&script_sig[2 + sig_len..], &pk.serialize()[..]);
+
+        // Signing is deterministic, so this is byte for byte what the no-sig builder makes
+        assert_eq!(tx, build_bitcoinz_v4_no_sig(&UnitTestNetwork, inputs, outputs, height, None, None).unwrap().raw);
+    }
+
+    #[test]
+    fn test_every_input_signature_verifies() {
+        use crate::bitcoinz_script_sig::hash160;
+        use crate::bitcoinz_transparent_builder::input_sighash;
+        use secp256k1::Message;
+
+        // Each coin pays to the key that signs for it, so the scriptSigs would pass the
+        // node's OP_CHECKSIG
+        let secp = Secp256k1::new();
+        let keys = [SecretKey::from_slice(&[0x11; 32]).unwrap(), SecretKey::from_slice(&[0x12; 32]).unwrap()];
+        let inputs = keys
+            .iter()
+            .enumerate()
+            .map(|(i, sk)| {
+                let pk = PublicKey::from_secret_key(&secp, sk);
+                let coin = TxOut {
+                    value: Amount::from_u64(100_000 * (i as u64 + 1)).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey(hash160(&pk.serialize())).script(),
+                };
+                (OutPoint::new([0x33; 32], i as u32), coin, *sk)
+            })
+            .collect::<Vec<_>>();
+        let outputs = vec![(TransparentAddress::PublicKey([0x44; 20]), Amount::from_u64(290_000).unwrap())];
+        let height = BlockHeight::from_u32(500_000);
+
+        let tx = build_bitcoinz_rpc_tx(&UnitTestNetwork, inputs.clone(), outputs.clone(), height).unwrap();
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+        let parts = TransparentParts::new(pubkey_hash_inputs(&inputs), outputs);
+        for (index, (txin, (_, coin, sk))) in parsed.inputs.iter().zip(&inputs).enumerate() {
+            let script_sig = &txin.script_sig;
+            let sig_len = script_sig[0] as usize;
+            let signature = Signature::from_der(&script_sig[1..sig_len]).unwrap();
+            let pushed = &script_sig[2 + sig_len..];
+            assert_eq!(TransparentAddress::PublicKey(hash160(pushed)).script(), coin.script_pubkey);
+
+            let sighash = input_sighash(
+                &UnitTestNetwork,
+                height,
+                TransparentTxFormat::Sapling,
+                &parts,
+                index,
+                &coin.script_pubkey,
+                coin.value,
+            )
+            .unwrap();
+            let pk = PublicKey::from_secret_key(&secp, sk);
+            assert!(secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, &pk).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_outputs_array_with_script_address() {
+        use crate::bitcoinz_address::decode_transparent_address;
+        use crate::BITCOINZ_MAINNET;
+
+        let p2sh = TransparentAddress::Script([0x66; 20]);
+        let t3 = encode_transparent_address(&p2sh, &BITCOINZ_MAINNET);
+        assert_eq!(decode_transparent_address(&t3, &BITCOINZ_MAINNET), Ok(p2sh.clone()));
+
+        let outputs = vec![
+            (TransparentAddress::PublicKey([0x55; 20]), Amount::from_u64(100_000).unwrap()),
+            (p2sh, Amount::from_u64(100_000_000).unwrap()),
+        ];
+        let vout = create_outputs_array(&BITCOINZ_MAINNET, &outputs).unwrap();
+
+        assert_eq!(vout[0]["scriptPubKey"]["type"], "pubkeyhash");
+        assert_eq!(vout[1]["scriptPubKey"]["type"], "scripthash");
+        assert_eq!(vout[1]["scriptPubKey"]["hex"], format!("a914{}87", "66".repeat(20)));
+        assert_eq!(vout[1]["scriptPubKey"]["addresses"][0], t3);
+        assert_eq!(vout[1]["valueZat"], 100_000_000);
+    }
+
+    #[test]
+    fn test_sign_with_script_output() {
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let inputs = vec![(
+            OutPoint::new([0x33; 32], 1),
+            TxOut {
+                value: Amount::from_u64(200_000).unwrap(),
+                script_pubkey: TransparentAddress::PublicKey([0x22; 20]).script(),
+            },
+            sk,
+        )];
+        let outputs = vec![(TransparentAddress::Script([0x66; 20]), Amount::from_u64(190_000).unwrap())];
+        let height = BlockHeight::from_u32(500_000);
+
+        // The P2SH output goes out as OP_HASH160 <hash> OP_EQUAL, and is signed the same
+        // way the other builders sign it
+        let tx = build_bitcoinz_rpc_tx(&UnitTestNetwork, inputs.clone(), outputs.clone(), height).unwrap();
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+        assert_eq!(parsed.outputs[0].script_pubkey, TransparentAddress::Script([0x66; 20]).script());
+        assert_eq!(tx, build_bitcoinz_v4_no_sig(&UnitTestNetwork, inputs, outputs, height, None, None).unwrap().raw);
+    }
+
+    #[test]
+    fn test_sign_rejects_mismatched_inputs() {
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let txout = TxOut {
+            value: Amount::from_u64(200_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey([0x22; 20]).script(),
+        };
+        let outputs = vec![(TransparentAddress::PublicKey([0x44; 20]), Amount::from_u64(190_000).unwrap())];
+        let height = BlockHeight::from_u32(500_000);
+
+        let unsigned = build_raw_transaction_hex(vec![(OutPoint::new([0x33; 32], 1), txout.clone(), sk)], outputs).unwrap();
+
+        // A key for a different outpoint
+        let other = vec![(OutPoint::new([0x33; 32], 2), txout, sk)];
+        assert!(sign_bitcoinz_transaction(&UnitTestNetwork, height, unsigned.clone(), other).is_err());
+        assert!(sign_bitcoinz_transaction(&UnitTestNetwork, height, unsigned, vec![]).is_err());
+    }
+}
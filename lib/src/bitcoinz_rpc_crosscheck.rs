@@ -0,0 +1,377 @@
+/// Cross-check against bitcoinzd's createrawtransaction
+///
+/// `bitcoinz_rpc_builder` writes transactions the way it believes `createrawtransaction`
+/// does. This diagnostic asks a real node to build the same transaction and reports the
+/// first byte where the two disagree, so an encoder bug shows up as an offset and a field
+/// name instead of a rejected broadcast.
+///
+/// Only compiled with the `rpc-crosscheck` feature. It needs access to the node's JSON-RPC
+/// interface: `rpcuser` and `rpcpassword` from bitcoinz.conf, and an `rpcallowip` that
+/// covers this machine. The node doesn't need a wallet or the input coins, since
+/// createrawtransaction doesn't look them up.
+///
+///   BTCZ_RPC_URL       e.g. http://127.0.0.1:1979
+///   BTCZ_RPC_USER      rpcuser from bitcoinz.conf
+///   BTCZ_RPC_PASSWORD  rpcpassword from bitcoinz.conf
+///
+/// `make rpc-crosscheck` runs the live check against the docker regtest node.
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use secp256k1::SecretKey;
+use serde_json::{json, Value};
+use zcash_client_backend::encoding::AddressCodec;
+use zcash_primitives::{
+    consensus::Parameters,
+    legacy::TransparentAddress,
+    transaction::components::{Amount, OutPoint, TxOut},
+};
+
+use crate::bitcoinz_encoding::read_compact_size;
+use crate::bitcoinz_rpc_builder::build_raw_transaction_hex;
+use crate::bitcoinz_txid::WireTxid;
+
+/// Minimal JSON-RPC client for bitcoinzd
+pub struct NodeRpc {
+    host: String,
+    auth: String,
+}
+
+impl NodeRpc {
+    pub fn new(url: &str, user: &str, password: &str) -> Result<Self, String> {
+        let uri: http::Uri = url.parse().map_err(|e| format!("Invalid RPC URL {}: {}", url, e))?;
+        let host = format!(
+            "{}:{}",
+            uri.host().ok_or(format!("RPC URL {} has no host", url))?,
+            uri.port_u16().unwrap_or(1979)
+        );
+
+        Ok(NodeRpc {
+            host,
+            auth: base64::encode(format!("{}:{}", user, password)),
+        })
+    }
+
+    /// Connect using BTCZ_RPC_URL, BTCZ_RPC_USER and BTCZ_RPC_PASSWORD
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or(format!("{} is not set", name))
+        };
+
+        Self::new(&var("BTCZ_RPC_URL")?, &var("BTCZ_RPC_USER")?, &var("BTCZ_RPC_PASSWORD")?)
+    }
+
+    /// Call `method`. `params` is the JSON text of the parameter array, so objects keep
+    /// their key order (the node writes outputs in the order it reads them).
+    pub fn call(&self, method: &str, params: &str) -> Result<Value, String> {
+        let body = format!(
+            "{{\"jsonrpc\":\"1.0\",\"id\":\"rpc-crosscheck\",\"method\":{},\"params\":{}}}",
+            json!(method),
+            params
+        );
+
+        let mut stream = TcpStream::connect(&self.host).map_err(|e| format!("Couldn't connect to bitcoinzd: {}", e))?;
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.host,
+            self.auth,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+
+        let json_start = response
+            .find("\r\n\r\n")
+            .ok_or(format!("Malformed HTTP response from bitcoinzd: {}", response))?;
+        let reply: Value = serde_json::from_str(&response[json_start + 4..])
+            .map_err(|e| format!("Couldn't parse bitcoinzd reply to {}: {}", method, e))?;
+
+        if !reply["error"].is_null() {
+            return Err(format!("{} failed: {}", method, reply["error"]));
+        }
+
+        Ok(reply["result"].clone())
+    }
+}
+
+/// The first byte at which the local and node transactions differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub offset: usize,
+    /// Which field of the transaction the offset falls in
+    pub field: String,
+    /// None if that transaction ended before the offset
+    pub local: Option<u8>,
+    pub node: Option<u8>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let byte = |b: Option<u8>| b.map(|b| format!("{:#04x}", b)).unwrap_or("end of transaction".to_string());
+        write!(
+            f,
+            "First difference at byte {} ({}): local has {}, bitcoinzd has {}",
+            self.offset,
+            self.field,
+            byte(self.local),
+            byte(self.node)
+        )
+    }
+}
+
+pub struct CrosscheckReport {
+    pub local: Vec<u8>,
+    pub node: Vec<u8>,
+    /// None if both transactions are identical
+    pub divergence: Option<Divergence>,
+}
+
+impl fmt::Display for CrosscheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.divergence {
+            None => write!(f, "Local and bitcoinzd transactions match ({} bytes)", self.local.len()),
+            Some(d) => write!(
+                f,
+                "{}\nlocal:     {}\nbitcoinzd: {}",
+                d,
+                hex::encode(&self.local),
+                hex::encode(&self.node)
+            ),
+        }
+    }
+}
+
+/// Build the unsigned transaction both locally and with the node's createrawtransaction,
+/// and compare them
+pub fn crosscheck_raw_transaction<P: Parameters>(
+    rpc: &NodeRpc,
+    params: &P,
+    inputs: &[(OutPoint, TxOut, SecretKey)],
+    outputs: &[(TransparentAddress, Amount)],
+) -> Result<CrosscheckReport, String> {
+    let local = build_raw_transaction_hex(inputs.to_vec(), outputs.to_vec())?;
+
+    let node_hex = rpc.call("createrawtransaction", &createrawtransaction_params(params, inputs, outputs)?)?;
+    let node = hex::decode(node_hex.as_str().ok_or("createrawtransaction didn't return a hex string")?)
+        .map_err(|e| format!("createrawtransaction returned invalid hex: {}", e))?;
+
+    let divergence = first_divergence(&local, &node);
+    Ok(CrosscheckReport { local, node, divergence })
+}
+
+/// Parameters for createrawtransaction, with the same lock time (0) and expiry height (0)
+/// and sequence numbers that build_raw_transaction_hex uses
+fn createrawtransaction_params<P: Parameters>(
+    params: &P,
+    inputs: &[(OutPoint, TxOut, SecretKey)],
+    outputs: &[(TransparentAddress, Amount)],
+) -> Result<String, String> {
+    let vin = inputs
+        .iter()
+        .map(|(outpoint, _, _)| {
+            // RPC txids are in display order
+            let txid = WireTxid::from(outpoint).to_display();
+            json!({ "txid": txid.to_string(), "vout": outpoint.n(), "sequence": 0xfffffffeu32 }).to_string()
+        })
+        .collect::<Vec<_>>();
+
+    let mut addresses = vec![];
+    let mut vout = vec![];
+    for (addr, amount) in outputs {
+        let addr = addr.encode(params);
+        if addresses.contains(&addr) {
+            return Err(format!("createrawtransaction can't pay {} twice", addr));
+        }
+
+        let zats = u64::from(*amount);
+        vout.push(format!("{}:{}.{:08}", json!(addr), zats / 100_000_000, zats % 100_000_000));
+        addresses.push(addr);
+    }
+
+    Ok(format!("[[{}],{{{}}},0,0]", vin.join(","), vout.join(",")))
+}
+
+/// Find the first differing byte, if any
+pub fn first_divergence(local: &[u8], node: &[u8]) -> Option<Divergence> {
+    let offset = match local.iter().zip(node.iter()).position(|(a, b)| a != b) {
+        Some(offset) => offset,
+        None if local.len() == node.len() => return None,
+        None => local.len().min(node.len()),
+    };
+
+    // Name the field using whichever transaction still has a byte there
+    let tx = if offset < local.len() { local } else { node };
+
+    Some(Divergence {
+        offset,
+        field: field_at(tx, offset),
+        local: local.get(offset).cloned(),
+        node: node.get(offset).cloned(),
+    })
+}
+
+struct Layout<'a> {
+    tx: &'a [u8],
+    pos: usize,
+    // (name, start, end)
+    fields: Vec<(String, usize, usize)>,
+}
+
+impl<'a> Layout<'a> {
+    fn field(&mut self, name: String, len: usize) -> Option<()> {
+        if self.pos + len > self.tx.len() {
+            return None;
+        }
+
+        self.fields.push((name, self.pos, self.pos + len));
+        self.pos += len;
+        Some(())
+    }
+
+    fn count(&mut self, name: String) -> Option<usize> {
+        let (val, len) = read_compact_size(self.tx.get(self.pos..)?).ok()??;
+        self.field(name, len)?;
+        Some(val as usize)
+    }
+
+    /// Walk a transparent-only v4 transaction, stopping at the first thing that doesn't fit
+    fn walk(&mut self) -> Option<()> {
+        self.field("header".to_string(), 4)?;
+        self.field("version group ID".to_string(), 4)?;
+
+        for i in 0..self.count("input count".to_string())? {
+            self.field(format!("input {} outpoint", i), 36)?;
+            let len = self.count(format!("input {} scriptSig length", i))?;
+            self.field(format!("input {} scriptSig", i), len)?;
+            self.field(format!("input {} sequence", i), 4)?;
+        }
+
+        for i in 0..self.count("output count".to_string())? {
+            self.field(format!("output {} value", i), 8)?;
+            let len = self.count(format!("output {} script length", i))?;
+            self.field(format!("output {} script", i), len)?;
+        }
+
+        self.field("lock time".to_string(), 4)?;
+        self.field("expiry height".to_string(), 4)?;
+        self.field("value balance".to_string(), 8)?;
+        self.count("shielded spend count".to_string())?;
+        self.count("shielded output count".to_string())?;
+        self.count("joinsplit count".to_string())?;
+
+        Some(())
+    }
+}
+
+fn field_at(tx: &[u8], offset: usize) -> String {
+    let mut layout = Layout {
+        tx,
+        pos: 0,
+        fields: vec![],
+    };
+    layout.walk();
+
+    layout
+        .fields
+        .into_iter()
+        .find(|(_, start, end)| *start <= offset && offset < *end)
+        .map(|(name, _, _)| name)
+        .unwrap_or("unknown field".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lightclient::lightclient_config::UnitTestNetwork;
+    use zcash_primitives::consensus::{MAIN_NETWORK, TEST_NETWORK};
+
+    fn inputs() -> Vec<(OutPoint, TxOut, SecretKey)> {
+        let txout = TxOut {
+            value: Amount::from_u64(200_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey([0x22; 20]).script(),
+        };
+        vec![(OutPoint::new([0x33; 32], 1), txout, SecretKey::from_slice(&[0x11; 32]).unwrap())]
+    }
+
+    fn outputs() -> Vec<(TransparentAddress, Amount)> {
+        vec![
+            (TransparentAddress::PublicKey([0x44; 20]), Amount::from_u64(150_000_000).unwrap()),
+            (TransparentAddress::PublicKey([0x55; 20]), Amount::from_u64(5).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let tx = build_raw_transaction_hex(inputs(), outputs()).unwrap();
+        assert_eq!(first_divergence(&tx, &tx), None);
+
+        // Header (8), input count (1), outpoint (36), empty scriptSig (1), then the sequence
+        let mut other = tx.clone();
+        other[8 + 1 + 36 + 1] = 0xff;
+        let d = first_divergence(&tx, &other).unwrap();
+        assert_eq!(d.offset, 46);
+        assert_eq!(d.field, "input 0 sequence");
+        assert_eq!((d.local, d.node), (Some(0xfe), Some(0xff)));
+
+        // The node wrote a second output value differently
+        let mut other = tx.clone();
+        let second_value = 46 + 4 + 1 + 8 + 1 + 25;
+        other[second_value] ^= 1;
+        assert_eq!(first_divergence(&tx, &other).unwrap().field, "output 1 value");
+
+        // One transaction is a prefix of the other
+        let d = first_divergence(&tx, &tx[..tx.len() - 1]).unwrap();
+        assert_eq!(d.offset, tx.len() - 1);
+        assert_eq!(d.field, "joinsplit count");
+        assert_eq!(d.node, None);
+    }
+
+    #[test]
+    fn test_createrawtransaction_params() {
+        let p = createrawtransaction_params(&UnitTestNetwork, &inputs(), &outputs()).unwrap();
+        let v: Value = serde_json::from_str(&p).unwrap();
+
+        // Txid is in RPC (reversed) order, amounts are exact decimals
+        assert_eq!(v[0][0]["txid"], hex::encode([0x33u8; 32]));
+        assert_eq!(v[0][0]["vout"], 1);
+        assert!(p.contains(":1.50000000"));
+        assert!(p.contains(":0.00000005"));
+
+        // Outputs stay in order
+        let first = TransparentAddress::PublicKey([0x44; 20]).encode(&UnitTestNetwork);
+        let second = TransparentAddress::PublicKey([0x55; 20]).encode(&UnitTestNetwork);
+        assert!(p.find(&first).unwrap() < p.find(&second).unwrap());
+
+        let mut twice = outputs();
+        twice.push(twice[0]);
+        assert!(createrawtransaction_params(&UnitTestNetwork, &inputs(), &twice).is_err());
+    }
+
+    /// Runs against a real node when BTCZ_RPC_* is set, and is skipped otherwise
+    #[test]
+    fn test_against_node() {
+        let rpc = match NodeRpc::from_env() {
+            Ok(rpc) => rpc,
+            Err(e) => {
+                println!("Skipping the bitcoinzd cross-check: {}", e);
+                return;
+            }
+        };
+
+        // Encode the outputs with the prefixes the node expects. Regtest shares testnet's.
+        let report = match rpc.call("getblockchaininfo", "[]").unwrap()["chain"].as_str() {
+            Some("main") => crosscheck_raw_transaction(&rpc, &MAIN_NETWORK, &inputs(), &outputs()),
+            _ => crosscheck_raw_transaction(&rpc, &TEST_NETWORK, &inputs(), &outputs()),
+        }
+        .unwrap();
+        println!("{}", report);
+        assert!(report.divergence.is_none(), "{}", report);
+    }
+}
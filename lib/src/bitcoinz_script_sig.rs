@@ -0,0 +1,680 @@
+/// BitcoinZ Transparent Input Signing
+///
+/// The keys that sign a transparent input, and the scriptSig built from them. A P2PKH
+/// input (t1) is signed by one key and spent with `<sig> <pubkey>`. A P2SH input (t3)
+/// carries its redeem script: the sighash commits to the redeem script rather than
+/// the prevout's script_pubkey, and it is spent with `<sig...> <redeemScript>`. The
+/// signatures of a multisig input can come from several parties: each signs the
+/// input's sighash with `sign_sighash`, and their signatures go in as `ScriptSigner`s.
+/// A P2PKH input whose key is kept elsewhere (a hardware wallet, a remote signer) is
+/// signed through the `Signer` trait instead.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use ripemd160::{Digest, Ripemd160};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey, SignOnly, VerifyOnly};
+use sha2::Sha256;
+use zcash_primitives::{
+    legacy::{Script, TransparentAddress},
+    transaction::components::{OutPoint, TxOut},
+};
+
+lazy_static! {
+    static ref SECP256K1_SIGN_ONLY: Secp256k1<SignOnly> = Secp256k1::signing_only();
+    static ref SECP256K1_VERIFY_ONLY: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
+}
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// Largest redeem script the script interpreter will push
+pub const MAX_REDEEM_SCRIPT_SIZE: usize = 520;
+
+/// Largest DER signature plus its hash type byte
+const MAX_SIG_PUSH_SIZE: usize = 72 + 1;
+
+/// Largest low-R DER signature plus its hash type byte. Signatures aren't ground for a
+/// low R, so about half of them (those with the high bit of R set) are one byte longer.
+pub const LOW_R_SIG_PUSH_SIZE: usize = 70 + 1;
+
+/// Signs transparent sighashes with a key this process never sees
+pub trait Signer: Debug + Send + Sync {
+    /// Sign the sighash of input `input_index`
+    fn sign_sighash(&self, input_index: usize, sighash: [u8; 32]) -> Result<Signature, String>;
+
+    /// Sign several inputs' sighashes, given as (input index, sighash), returning the
+    /// signatures in the same order. The builders collect every sighash first and make
+    /// one call per signer, so a remote signer should override this to sign them all in
+    /// a single round trip.
+    fn sign_sighashes(&self, requests: &[(usize, [u8; 32])]) -> Result<Vec<Signature>, String> {
+        requests
+            .iter()
+            .map(|(input_index, sighash)| self.sign_sighash(*input_index, *sighash))
+            .collect()
+    }
+}
+
+/// A key held in memory, which signs any input it's given
+impl Signer for SecretKey {
+    fn sign_sighash(&self, _input_index: usize, sighash: [u8; 32]) -> Result<Signature, String> {
+        let msg = Message::from_slice(&sighash).map_err(|e| format!("Failed to create message: {}", e))?;
+        Ok(SECP256K1_SIGN_ONLY.sign_ecdsa(&msg, self))
+    }
+}
+
+/// One of the signers of a P2SH input
+#[derive(Clone, Debug)]
+pub enum ScriptSigner {
+    /// Signs when the transaction is built
+    Key(SecretKey),
+    /// Signed elsewhere, over the input's sighash: a DER signature followed by the hash
+    /// type byte, as `sign_sighash` makes it
+    Signature { pubkey: PublicKey, signature: Vec<u8> },
+}
+
+impl ScriptSigner {
+    pub fn pubkey(&self) -> PublicKey {
+        match self {
+            ScriptSigner::Key(sk) => PublicKey::from_secret_key(&SECP256K1_SIGN_ONLY, sk),
+            ScriptSigner::Signature { pubkey, .. } => *pubkey,
+        }
+    }
+}
+
+/// The keys that sign a transparent input
+#[derive(Clone, Debug)]
+pub enum TransparentInputKeys {
+    /// P2PKH, signed by the key the address hashes
+    PubKeyHash(SecretKey),
+    /// P2PKH to the hash of the key's uncompressed (65-byte) public key, as old paper
+    /// wallets and keys imported from legacy tools are paid to
+    UncompressedPubKeyHash(SecretKey),
+    /// P2PKH, signed by `signer` for `pubkey`. `compressed` is the form of the public key
+    /// the coin pays to.
+    External { pubkey: PublicKey, compressed: bool, signer: Arc<dyn Signer> },
+    /// P2SH, spending `redeem_script` with `signers`. For a multisig redeem script the
+    /// signers are kept in the order their public keys appear in the script.
+    ScriptHash { redeem_script: Script, signers: Vec<ScriptSigner> },
+}
+
+impl TransparentInputKeys {
+    /// Keys for a P2PKH input, in whichever form of the public key `coin` pays to. A coin
+    /// that pays to neither is left to fail when it's spent, as it always has.
+    pub fn pubkey_hash(coin: &TxOut, sk: SecretKey) -> Self {
+        let pubkey = PublicKey::from_secret_key(&SECP256K1_SIGN_ONLY, &sk);
+        let uncompressed = TransparentAddress::PublicKey(hash160(&pubkey.serialize_uncompressed())).script();
+        if coin.script_pubkey == uncompressed {
+            TransparentInputKeys::UncompressedPubKeyHash(sk)
+        } else {
+            TransparentInputKeys::PubKeyHash(sk)
+        }
+    }
+
+    /// A P2PKH input signed by `signer`, checking `pubkey` (in either form) is what
+    /// `coin` pays to
+    pub fn external(coin: &TxOut, pubkey: PublicKey, signer: Arc<dyn Signer>) -> Result<Self, String> {
+        let pays = |serialized: &[u8]| coin.script_pubkey == TransparentAddress::PublicKey(hash160(serialized)).script();
+        let compressed = if pays(&pubkey.serialize()) {
+            true
+        } else if pays(&pubkey.serialize_uncompressed()) {
+            false
+        } else {
+            return Err(format!(
+                "Public key {} doesn't spend the prevout script {}",
+                hex::encode(pubkey.serialize()),
+                hex::encode(&coin.script_pubkey.0)
+            ));
+        };
+        Ok(TransparentInputKeys::External { pubkey, compressed, signer })
+    }
+
+    /// Keys for a P2SH input, checking the redeem script is what `coin` pays to
+    pub fn script_hash(coin: &TxOut, redeem_script: Script, keys: Vec<SecretKey>) -> Result<Self, String> {
+        Self::script_hash_with_signers(coin, redeem_script, keys.into_iter().map(ScriptSigner::Key).collect())
+    }
+
+    /// Signers for a P2SH input, checking the redeem script is what `coin` pays to. The
+    /// signers of a multisig input can be given in any order, and there can be fewer
+    /// than the threshold so far; the rest are added with `add_signer`.
+    pub fn script_hash_with_signers(
+        coin: &TxOut,
+        redeem_script: Script,
+        signers: Vec<ScriptSigner>,
+    ) -> Result<Self, String> {
+        if signers.is_empty() {
+            return Err("A P2SH input needs at least one key".to_string());
+        }
+        if redeem_script.0.len() > MAX_REDEEM_SCRIPT_SIZE {
+            return Err(format!(
+                "Redeem script is {} bytes, more than the {} allowed",
+                redeem_script.0.len(),
+                MAX_REDEEM_SCRIPT_SIZE
+            ));
+        }
+
+        let expected = TransparentAddress::Script(hash160(&redeem_script.0)).script();
+        if coin.script_pubkey != expected {
+            return Err(format!(
+                "Redeem script doesn't match the prevout script {}",
+                hex::encode(&coin.script_pubkey.0)
+            ));
+        }
+
+        let signers = order_signers(&redeem_script, signers)?;
+        Ok(TransparentInputKeys::ScriptHash { redeem_script, signers })
+    }
+
+    /// Add another signer to a P2SH input, e.g. the signature of a second party
+    pub fn add_signer(&mut self, signer: ScriptSigner) -> Result<(), String> {
+        match self {
+            TransparentInputKeys::PubKeyHash(_)
+            | TransparentInputKeys::UncompressedPubKeyHash(_)
+            | TransparentInputKeys::External { .. } => Err("A P2PKH input is signed by its one key".to_string()),
+            TransparentInputKeys::ScriptHash { redeem_script, signers } => {
+                let mut all = signers.clone();
+                all.push(signer);
+                *signers = order_signers(redeem_script, all)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// The script the sighash commits to: the redeem script for P2SH, otherwise the
+    /// prevout's own script_pubkey
+    pub fn script_code<'a>(&'a self, script_pubkey: &'a Script) -> &'a Script {
+        match self {
+            TransparentInputKeys::PubKeyHash(_)
+            | TransparentInputKeys::UncompressedPubKeyHash(_)
+            | TransparentInputKeys::External { .. } => script_pubkey,
+            TransparentInputKeys::ScriptHash { redeem_script, .. } => redeem_script,
+        }
+    }
+
+    /// Upper bound on the size of the scriptSig `script_sig` builds
+    pub fn max_script_sig_size(&self) -> usize {
+        self.script_sig_size(MAX_SIG_PUSH_SIZE)
+    }
+
+    /// Size of the scriptSig `script_sig` builds when each signature, with its hash type
+    /// byte, is `sig_push_size` bytes
+    pub fn script_sig_size(&self, sig_push_size: usize) -> usize {
+        match self {
+            // push + signature, push + compressed pubkey
+            TransparentInputKeys::PubKeyHash(_) => 1 + sig_push_size + 1 + 33,
+            TransparentInputKeys::UncompressedPubKeyHash(_) => 1 + sig_push_size + 1 + 65,
+            TransparentInputKeys::External { compressed, .. } => {
+                1 + sig_push_size + 1 + if *compressed { 33 } else { 65 }
+            }
+            TransparentInputKeys::ScriptHash { redeem_script, signers } => {
+                // A multisig input ends up with as many signatures as its threshold
+                let (dummy, signatures) = match multisig_pubkeys(redeem_script) {
+                    Some((threshold, _)) => (1, threshold),
+                    None => (if is_multisig(redeem_script) { 1 } else { 0 }, signers.len()),
+                };
+                dummy + signatures * (1 + sig_push_size) + push_len(redeem_script.0.len()) + redeem_script.0.len()
+            }
+        }
+    }
+
+    /// Sign `sighash` with every key, check the signatures made elsewhere against it,
+    /// and build the scriptSig. An external signer needs the input's index, so its
+    /// inputs are signed with `script_sigs`.
+    pub fn script_sig(&self, sighash: &[u8; 32], sighash_type: u32) -> Result<Vec<u8>, String> {
+        self.signed_script_sig(sighash, sighash_type, None)
+    }
+
+    /// `script_sig`, with the signature an external signer made for this input
+    fn signed_script_sig(
+        &self,
+        sighash: &[u8; 32],
+        sighash_type: u32,
+        external: Option<Signature>,
+    ) -> Result<Vec<u8>, String> {
+        let mut script_sig = Vec::new();
+        match self {
+            TransparentInputKeys::PubKeyHash(sk) => {
+                push_data(&mut script_sig, &sign_sighash(sk, sighash, sighash_type)?);
+                push_data(&mut script_sig, &PublicKey::from_secret_key(&SECP256K1_SIGN_ONLY, sk).serialize());
+            }
+            TransparentInputKeys::UncompressedPubKeyHash(sk) => {
+                push_data(&mut script_sig, &sign_sighash(sk, sighash, sighash_type)?);
+                push_data(
+                    &mut script_sig,
+                    &PublicKey::from_secret_key(&SECP256K1_SIGN_ONLY, sk).serialize_uncompressed(),
+                );
+            }
+            TransparentInputKeys::External { pubkey, compressed, .. } => {
+                let mut signature = external.ok_or("An externally signed input is signed with script_sigs")?;
+                // Only low-S signatures are relayed
+                signature.normalize_s();
+                let mut signature = signature.serialize_der().to_vec();
+                signature.push(sighash_type as u8);
+
+                // Don't trust the signer to have signed what it was asked to
+                check_signature(pubkey, &signature, sighash, sighash_type)?;
+                push_data(&mut script_sig, &signature);
+                if *compressed {
+                    push_data(&mut script_sig, &pubkey.serialize());
+                } else {
+                    push_data(&mut script_sig, &pubkey.serialize_uncompressed());
+                }
+            }
+            TransparentInputKeys::ScriptHash { redeem_script, signers } => {
+                if let Some((threshold, pubkeys)) = multisig_pubkeys(redeem_script) {
+                    if signers.len() < threshold {
+                        return Err(format!(
+                            "A {}-of-{} multisig input needs {} signatures, and has {}",
+                            threshold,
+                            pubkeys.len(),
+                            threshold,
+                            signers.len()
+                        ));
+                    }
+                }
+
+                // OP_CHECKMULTISIG pops one more item than it uses
+                if is_multisig(redeem_script) {
+                    script_sig.push(OP_0);
+                }
+                for signer in signers {
+                    let signature = match signer {
+                        ScriptSigner::Key(sk) => sign_sighash(sk, sighash, sighash_type)?,
+                        ScriptSigner::Signature { pubkey, signature } => {
+                            check_signature(pubkey, signature, sighash, sighash_type)?;
+                            signature.clone()
+                        }
+                    };
+                    push_data(&mut script_sig, &signature);
+                }
+                push_data(&mut script_sig, &redeem_script.0);
+            }
+        }
+
+        Ok(script_sig)
+    }
+}
+
+/// The scriptSigs of inputs whose sighashes are already known, given as each input's
+/// keys with its sighash, in input order. Every external signer is asked for all of its
+/// inputs' signatures in one `sign_sighashes` call.
+pub fn script_sigs(inputs: &[(&TransparentInputKeys, [u8; 32])], sighash_type: u32) -> Result<Vec<Vec<u8>>, String> {
+    let mut batches: Vec<(&Arc<dyn Signer>, Vec<(usize, [u8; 32])>)> = vec![];
+    for (index, (keys, sighash)) in inputs.iter().enumerate() {
+        if let TransparentInputKeys::External { signer, .. } = keys {
+            match batches.iter_mut().find(|(batched, _)| same_signer(batched, signer)) {
+                Some((_, requests)) => requests.push((index, *sighash)),
+                None => batches.push((signer, vec![(index, *sighash)])),
+            }
+        }
+    }
+
+    let mut external = vec![None; inputs.len()];
+    for (signer, requests) in batches {
+        let signatures = signer.sign_sighashes(&requests)?;
+        if signatures.len() != requests.len() {
+            return Err(format!(
+                "Signer returned {} signatures for {} inputs",
+                signatures.len(),
+                requests.len()
+            ));
+        }
+        for ((index, _), signature) in requests.into_iter().zip(signatures) {
+            external[index] = Some(signature);
+        }
+    }
+
+    inputs
+        .iter()
+        .zip(external)
+        .map(|((keys, sighash), signature)| keys.signed_script_sig(sighash, sighash_type, signature))
+        .collect()
+}
+
+/// Whether two signers are the same one, so their inputs go in the same batch
+fn same_signer(a: &Arc<dyn Signer>, b: &Arc<dyn Signer>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+/// Sign `sighash` with `sk`: the DER signature followed by the hash type byte, as it's
+/// pushed in a scriptSig. This is how a second party signs a multisig input whose
+/// sighash they've been given.
+pub fn sign_sighash(sk: &SecretKey, sighash: &[u8; 32], sighash_type: u32) -> Result<Vec<u8>, String> {
+    let mut sig_bytes = Signer::sign_sighash(sk, 0, *sighash)?.serialize_der().to_vec();
+    sig_bytes.push(sighash_type as u8);
+    Ok(sig_bytes)
+}
+
+/// Check a signature made elsewhere is `pubkey`'s, over `sighash`, with `sighash_type`
+fn check_signature(pubkey: &PublicKey, signature: &[u8], sighash: &[u8; 32], sighash_type: u32) -> Result<(), String> {
+    let (hash_type, der) = signature.split_last().ok_or("Empty signature")?;
+    if *hash_type != sighash_type as u8 {
+        return Err(format!(
+            "Signature has hash type {:#04x}, expected {:#04x}",
+            hash_type, sighash_type as u8
+        ));
+    }
+
+    let sig = Signature::from_der(der).map_err(|e| format!("Invalid signature: {}", e))?;
+    let msg = Message::from_slice(sighash).map_err(|e| format!("Failed to create message: {}", e))?;
+    SECP256K1_VERIFY_ONLY
+        .verify_ecdsa(&msg, &sig, pubkey)
+        .map_err(|_| format!("Signature by {} doesn't sign this input", hex::encode(pubkey.serialize())))
+}
+
+/// Put the signers of a multisig input in the order the redeem script lists their
+/// public keys, which is the order OP_CHECKMULTISIG checks them in. Other redeem
+/// scripts keep the signers as given.
+fn order_signers(redeem_script: &Script, signers: Vec<ScriptSigner>) -> Result<Vec<ScriptSigner>, String> {
+    let (threshold, pubkeys) = match multisig_pubkeys(redeem_script) {
+        Some(multisig) => multisig,
+        None => return Ok(signers),
+    };
+
+    let mut positioned = signers
+        .into_iter()
+        .map(|signer| {
+            let pubkey = signer.pubkey();
+            pubkeys
+                .iter()
+                .position(|key| *key == &pubkey.serialize()[..] || *key == &pubkey.serialize_uncompressed()[..])
+                .map(|position| (position, signer))
+                .ok_or_else(|| format!("Key {} isn't in the redeem script", hex::encode(pubkey.serialize())))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    positioned.sort_by_key(|(position, _)| *position);
+
+    if positioned.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err("The same key signs a multisig input twice".to_string());
+    }
+    if positioned.len() > threshold {
+        return Err(format!(
+            "A {}-of-{} multisig input takes {} signatures, not {}",
+            threshold,
+            pubkeys.len(),
+            threshold,
+            positioned.len()
+        ));
+    }
+
+    Ok(positioned.into_iter().map(|(_, signer)| signer).collect())
+}
+
+/// The threshold and public keys of an m-of-n multisig redeem script, or None if
+/// `redeem_script` isn't one
+fn multisig_pubkeys(redeem_script: &Script) -> Option<(usize, Vec<&[u8]>)> {
+    let script = &redeem_script.0;
+    if script.len() < 3 || !is_multisig(redeem_script) {
+        return None;
+    }
+
+    let threshold = script[0].checked_sub(OP_1 - 1)? as usize;
+    let n = script[script.len() - 2].checked_sub(OP_1 - 1)? as usize;
+
+    let mut pubkeys = vec![];
+    let mut i = 1;
+    while i < script.len() - 2 {
+        let len = script[i] as usize;
+        if len != 33 && len != 65 {
+            return None;
+        }
+        pubkeys.push(script.get(i + 1..i + 1 + len)?);
+        i += 1 + len;
+    }
+
+    if threshold == 0 || n > 16 || threshold > n || n != pubkeys.len() || i != script.len() - 2 {
+        return None;
+    }
+    Some((threshold, pubkeys))
+}
+
+/// An m-of-n multisig redeem script: OP_m <pubkey...> OP_n OP_CHECKMULTISIG
+pub fn multisig_redeem_script(threshold: usize, pubkeys: &[PublicKey]) -> Result<Script, String> {
+    if threshold == 0 || threshold > pubkeys.len() || pubkeys.len() > 16 {
+        return Err(format!("Can't make a {}-of-{} multisig script", threshold, pubkeys.len()));
+    }
+
+    let mut script = vec![OP_1 - 1 + threshold as u8];
+    for pubkey in pubkeys {
+        push_data(&mut script, &pubkey.serialize());
+    }
+    script.push(OP_1 - 1 + pubkeys.len() as u8);
+    script.push(OP_CHECKMULTISIG);
+
+    Ok(Script(script))
+}
+
+/// Inputs signed by a single P2PKH key each, as the builders used to take them. Each
+/// is spent with the form of the public key its coin pays to.
+pub fn pubkey_hash_inputs(inputs: &[(OutPoint, TxOut, SecretKey)]) -> Vec<(OutPoint, TxOut, TransparentInputKeys)> {
+    inputs
+        .iter()
+        .map(|(outpoint, coin, sk)| (outpoint.clone(), coin.clone(), TransparentInputKeys::pubkey_hash(coin, *sk)))
+        .collect()
+}
+
+/// RIPEMD160(SHA256(data)), as P2PKH and P2SH addresses hash keys and scripts
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&Ripemd160::digest(&Sha256::digest(data)));
+    hash
+}
+
+fn is_multisig(redeem_script: &Script) -> bool {
+    redeem_script.0.last() == Some(&OP_CHECKMULTISIG)
+}
+
+/// Size of the opcode(s) that push `len` bytes
+fn push_len(len: usize) -> usize {
+    match len {
+        0..=0x4b => 1,
+        0x4c..=0xff => 2,
+        _ => 3,
+    }
+}
+
+/// Push `data` with the smallest push opcode that fits it
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        len @ 0..=0x4b => script.push(len as u8),
+        len @ 0x4c..=0xff => {
+            script.push(OP_PUSHDATA1);
+            script.push(len as u8);
+        }
+        len => {
+            script.push(OP_PUSHDATA2);
+            script.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zcash_primitives::transaction::components::Amount;
+
+    fn one_of_one(sk: &SecretKey) -> Script {
+        multisig_redeem_script(1, &[PublicKey::from_secret_key(&Secp256k1::new(), sk)]).unwrap()
+    }
+
+    fn p2sh_coin(redeem_script: &Script) -> TxOut {
+        TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::Script(hash160(&redeem_script.0)).script(),
+        }
+    }
+
+    #[test]
+    fn test_push_data() {
+        for (len, prefix) in [(0x4b, vec![0x4b]), (0x4c, vec![OP_PUSHDATA1, 0x4c]), (0x100, vec![OP_PUSHDATA2, 0x00, 0x01])] {
+            let mut script = vec![];
+            push_data(&mut script, &vec![0x42u8; len]);
+            assert_eq!(&script[..prefix.len()], &prefix[..]);
+            assert_eq!(script.len(), push_len(len) + len);
+        }
+    }
+
+    #[test]
+    fn test_multisig_redeem_script() {
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<_> = (1..=3u8)
+            .map(|i| PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()))
+            .collect();
+
+        let script = multisig_redeem_script(2, &pubkeys).unwrap().0;
+        assert_eq!(script.len(), 1 + 3 * 34 + 2);
+        assert_eq!((script[0], script[script.len() - 2], script[script.len() - 1]), (0x52, 0x53, OP_CHECKMULTISIG));
+        assert_eq!(&script[2..35], &pubkeys[0].serialize()[..]);
+
+        assert!(multisig_redeem_script(0, &pubkeys).is_err());
+        assert!(multisig_redeem_script(4, &pubkeys).is_err());
+    }
+
+    #[test]
+    fn test_pubkey_hash_form() {
+        let sk = SecretKey::from_slice(&[0x07u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let coin = |pubkey: &[u8]| TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey(hash160(pubkey)).script(),
+        };
+
+        for (serialized, compressed) in [(pubkey.serialize().to_vec(), true), (pubkey.serialize_uncompressed().to_vec(), false)] {
+            let keys = TransparentInputKeys::pubkey_hash(&coin(&serialized), sk);
+            assert_eq!(matches!(keys, TransparentInputKeys::PubKeyHash(_)), compressed);
+
+            // The signature, then the public key in the form the coin hashes
+            let script_sig = keys.script_sig(&[0x01u8; 32], 1).unwrap();
+            let sig_len = script_sig[0] as usize;
+            assert_eq!(script_sig[1 + sig_len] as usize, serialized.len());
+            assert_eq!(&script_sig[2 + sig_len..], &serialized[..]);
+            assert!(script_sig.len() <= keys.max_script_sig_size());
+        }
+    }
+
+    /// Signs with a key it holds, keeping the inputs of each batch it was asked for
+    #[derive(Debug)]
+    struct RecordingSigner {
+        key: SecretKey,
+        batches: std::sync::Mutex<Vec<Vec<usize>>>,
+    }
+
+    impl Signer for RecordingSigner {
+        fn sign_sighash(&self, input_index: usize, sighash: [u8; 32]) -> Result<Signature, String> {
+            self.key.sign_sighash(input_index, sighash)
+        }
+
+        fn sign_sighashes(&self, requests: &[(usize, [u8; 32])]) -> Result<Vec<Signature>, String> {
+            self.batches.lock().unwrap().push(requests.iter().map(|(index, _)| *index).collect());
+            requests.iter().map(|(index, sighash)| self.sign_sighash(*index, *sighash)).collect()
+        }
+    }
+
+    #[test]
+    fn test_external_signer() {
+        let sk = SecretKey::from_slice(&[0x07u8; 32]).unwrap();
+        let other = SecretKey::from_slice(&[0x08u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey(hash160(&pubkey.serialize())).script(),
+        };
+        let signer = Arc::new(RecordingSigner { key: sk, batches: Default::default() });
+
+        let external = TransparentInputKeys::external(&coin, pubkey, signer.clone()).unwrap();
+        let local = TransparentInputKeys::PubKeyHash(other);
+        let sighashes = [[0x01u8; 32], [0x02u8; 32], [0x03u8; 32]];
+        let inputs = [(&external, sighashes[0]), (&local, sighashes[1]), (&external, sighashes[2])];
+        let signed = script_sigs(&inputs, 1).unwrap();
+
+        // Both of the signer's inputs in one batch, signed as the key itself would
+        assert_eq!(*signer.batches.lock().unwrap(), vec![vec![0, 2]]);
+        let held = TransparentInputKeys::PubKeyHash(sk);
+        assert_eq!(signed[0], held.script_sig(&sighashes[0], 1).unwrap());
+        assert_eq!(signed[1], local.script_sig(&sighashes[1], 1).unwrap());
+        assert_eq!(signed[2], held.script_sig(&sighashes[2], 1).unwrap());
+        assert!(signed[0].len() <= external.max_script_sig_size());
+
+        // Without its index, the external input can't be signed on its own
+        assert!(external.script_sig(&sighashes[0], 1).is_err());
+
+        // A public key the coin doesn't pay to, and a signer that signs with another key
+        let other_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &other);
+        assert!(TransparentInputKeys::external(&coin, other_pubkey, signer).is_err());
+        let wrong = TransparentInputKeys::external(&coin, pubkey, Arc::new(other)).unwrap();
+        assert!(script_sigs(&[(&wrong, sighashes[0])], 1).is_err());
+    }
+
+    #[test]
+    fn test_script_hash_keys() {
+        let sk = SecretKey::from_slice(&[0x07u8; 32]).unwrap();
+        let redeem_script = one_of_one(&sk);
+        let coin = p2sh_coin(&redeem_script);
+
+        let keys = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sk]).unwrap();
+        assert_eq!(keys.script_code(&coin.script_pubkey), &redeem_script);
+
+        // OP_0, the signature, then the redeem script
+        let script_sig = keys.script_sig(&[0x01u8; 32], 1).unwrap();
+        assert_eq!(script_sig[0], OP_0);
+        let sig_len = script_sig[1] as usize;
+        assert_eq!(script_sig[1 + sig_len], 1);
+        assert_eq!(script_sig[2 + sig_len] as usize, redeem_script.0.len());
+        assert_eq!(&script_sig[3 + sig_len..], &redeem_script.0[..]);
+        assert!(script_sig.len() <= keys.max_script_sig_size());
+
+        // The redeem script has to be the one the prevout pays to, and needs a key
+        let other = one_of_one(&SecretKey::from_slice(&[0x08u8; 32]).unwrap());
+        assert!(TransparentInputKeys::script_hash(&coin, other, vec![sk]).is_err());
+        assert!(TransparentInputKeys::script_hash(&coin, redeem_script, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_two_of_three_in_any_order() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..=3u8).map(|i| SecretKey::from_slice(&[i; 32]).unwrap()).collect();
+        let pubkeys: Vec<_> = sks.iter().map(|sk| PublicKey::from_secret_key(&secp, sk)).collect();
+        let redeem_script = multisig_redeem_script(2, &pubkeys).unwrap();
+        let coin = p2sh_coin(&redeem_script);
+        assert_eq!(multisig_pubkeys(&redeem_script).map(|(m, keys)| (m, keys.len())), Some((2, 3)));
+
+        // Keys 3 and 1, given backwards, sign as 1 then 3
+        let keys = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[2], sks[0]]).unwrap();
+        let in_order = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[0], sks[2]]).unwrap();
+        let sighash = [0x01u8; 32];
+        let script_sig = keys.script_sig(&sighash, 1).unwrap();
+        assert_eq!(script_sig, in_order.script_sig(&sighash, 1).unwrap());
+        assert!(script_sig.len() <= keys.max_script_sig_size());
+
+        let first_sig_len = script_sig[1] as usize;
+        let first = Signature::from_der(&script_sig[2..1 + first_sig_len]).unwrap();
+        let msg = Message::from_slice(&sighash).unwrap();
+        assert!(secp.verify_ecdsa(&msg, &first, &pubkeys[0]).is_ok());
+
+        // A key from outside the script, the same key twice, or too many keys
+        let outsider = SecretKey::from_slice(&[0x09u8; 32]).unwrap();
+        assert!(TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[0], outsider]).is_err());
+        assert!(TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[1], sks[1]]).is_err());
+        assert!(TransparentInputKeys::script_hash(&coin, redeem_script.clone(), sks.clone()).is_err());
+
+        // One key isn't enough to spend, until the second party's signature is added
+        let mut partial = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[2]]).unwrap();
+        assert!(partial.script_sig(&sighash, 1).is_err());
+        let signature = sign_sighash(&sks[0], &sighash, 1).unwrap();
+        partial
+            .add_signer(ScriptSigner::Signature { pubkey: pubkeys[0], signature })
+            .unwrap();
+        assert_eq!(partial.script_sig(&sighash, 1).unwrap(), script_sig);
+
+        // A signature over some other sighash is refused
+        let mut wrong = TransparentInputKeys::script_hash(&coin, redeem_script, vec![sks[2]]).unwrap();
+        let signature = sign_sighash(&sks[0], &[0x02u8; 32], 1).unwrap();
+        wrong.add_signer(ScriptSigner::Signature { pubkey: pubkeys[0], signature }).unwrap();
+        assert!(wrong.script_sig(&sighash, 1).is_err());
+    }
+}
@@ -0,0 +1,550 @@
+/// BitcoinZ Send
+///
+/// One entry point for building a payment from whatever it spends and pays to. The
+/// recipients and inputs decide the transaction type, and the type decides the
+/// builder: a transparent-only payment is built by the transparent builder, in the
+/// format the node takes at the height (v4 without a binding signature since Sapling),
+/// and anything with a shielded part goes through `BitcoinZShieldedBuilder`.
+/// `build_transactions` splits a payment too big to relay into a chain of them.
+
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use zcash_primitives::{
+    consensus::{BlockHeight, Parameters},
+    keys::OutgoingViewingKey,
+    legacy::TransparentAddress,
+    memo::MemoBytes,
+    sapling::{keys::ExpandedSpendingKey, prover::TxProver, Diversifier, Node, Note},
+    transaction::components::{Amount, OutPoint, TxOut},
+};
+
+use crate::bitcoinz_address::AddressKind;
+use crate::bitcoinz_script_sig::{hash160, pubkey_hash_inputs};
+use crate::bitcoinz_transaction::{
+    detect_tx_type, estimate_tx_size, fee_for_size, transparent_fee, BitcoinZTxType, BuiltTransaction, FeePolicy,
+    DUST_THRESHOLD, MAX_STANDARD_TX_SIZE,
+};
+use crate::bitcoinz_transparent_builder::{
+    build_transparent, select_format, FormatPolicy, TransparentParts, TransparentTxFormat,
+};
+use crate::bitcoinz_txid::WireTxid;
+use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+use crate::bitcoinz_v4_shielded::{BitcoinZShieldedBuilder, DEFAULT_MIN_SAPLING_OUTPUTS};
+
+/// Something a payment spends
+#[derive(Clone)]
+pub enum SendInput {
+    /// A P2PKH coin and the key it pays to
+    Transparent {
+        outpoint: OutPoint,
+        coin: TxOut,
+        key: SecretKey,
+    },
+    /// A Sapling note, with the 32 sibling nodes of its Merkle path from the leaf up and
+    /// its position in the tree
+    Sapling {
+        extsk: ExpandedSpendingKey,
+        diversifier: Diversifier,
+        note: Note,
+        merkle_path: Vec<Node>,
+        position: u64,
+    },
+}
+
+/// Build a payment of `recipients` (each with an optional memo, which only a Sapling
+/// recipient can take) from `inputs`. Whatever the inputs hold beyond the recipients'
+/// total has to be exactly `fee`, so change is one of the recipients. The transaction
+/// can't be mined after `expiry_height`; None means it never expires. Sapling outputs
+/// are encrypted to `ovk`; `prover` is only used when something is shielded.
+pub fn build_transaction<P: Parameters, Pr: TxProver>(
+    params: P,
+    height: BlockHeight,
+    inputs: Vec<SendInput>,
+    recipients: Vec<(AddressKind, Amount, Option<MemoBytes>)>,
+    fee: Amount,
+    expiry_height: Option<u32>,
+    ovk: Option<OutgoingViewingKey>,
+    prover: &Pr,
+) -> Result<BuiltTransaction, String> {
+    let transparent_inputs = inputs
+        .iter()
+        .filter(|i| matches!(i, SendInput::Transparent { .. }))
+        .count();
+    let transparent_outputs = recipients
+        .iter()
+        .filter(|(to, _, _)| matches!(to, AddressKind::Transparent(_)))
+        .count();
+    let tx_type = detect_tx_type(
+        transparent_inputs,
+        inputs.len() - transparent_inputs,
+        transparent_outputs,
+        recipients.len() - transparent_outputs,
+    );
+
+    if tx_type == BitcoinZTxType::TransparentToTransparent {
+        let mut coins = vec![];
+        for input in inputs {
+            if let SendInput::Transparent { outpoint, coin, key } = input {
+                coins.push((outpoint, coin, key));
+            }
+        }
+        let mut outputs = vec![];
+        for (to, value, memo) in recipients {
+            if memo.is_some() {
+                return Err("Memos can only be sent to shielded addresses".to_string());
+            }
+            if let AddressKind::Transparent(to) = to {
+                outputs.push((to, value));
+            }
+        }
+
+        // The transparent builder takes whatever's left over as the fee
+        let left_over = transparent_fee(
+            coins.iter().map(|(_, coin, _)| coin.value),
+            outputs.iter().map(|(_, value)| *value),
+        )?;
+        if left_over != fee {
+            return Err(format!(
+                "The inputs leave a fee of {} zatoshis, not {}",
+                i64::from(left_over),
+                i64::from(fee)
+            ));
+        }
+
+        let parts = TransparentParts {
+            expiry_height: expiry_height.unwrap_or(0),
+            ..TransparentParts::new(pubkey_hash_inputs(&coins), outputs)
+        };
+        let format = select_format(&params, height, FormatPolicy::Current);
+        return build_transparent(&params, height, format, &parts);
+    }
+
+    let mut builder = BitcoinZShieldedBuilder::new_with_entropy(params, height);
+    builder.set_expiry_height(expiry_height);
+    for input in inputs {
+        match input {
+            SendInput::Transparent { outpoint, coin, key } => builder.add_transparent_input(outpoint, coin, key)?,
+            SendInput::Sapling {
+                extsk,
+                diversifier,
+                note,
+                merkle_path,
+                position,
+            } => builder.add_sapling_spend(extsk, diversifier, note, merkle_path, position)?,
+        }
+    }
+    for (to, value, memo) in recipients {
+        match to {
+            AddressKind::Transparent(to) => {
+                if memo.is_some() {
+                    return Err("Memos can only be sent to shielded addresses".to_string());
+                }
+                builder.add_transparent_output(to, value)?;
+            }
+            AddressKind::Sapling(to) => {
+                builder.add_sapling_output(ovk, to, value, memo.unwrap_or_else(MemoBytes::empty))?
+            }
+        }
+    }
+    builder.build(prover, fee)
+}
+
+/// Build a payment of `recipients` from `inputs` like `build_transaction`, working out
+/// the fee under `policy` and paying the change to `change`, and split it into several
+/// transactions if it's too big to relay as one. Transparent coins that don't fit are
+/// first swept, as many at a time as fit, into a coin paying the first swept coin's
+/// address, each sweep paying the default fee for its size; the last transaction spends
+/// the swept coins with the rest of the inputs. Change that would be dust is left to the
+/// fee. Every transaction expires at `expiry_height`. Returns the transactions in the
+/// order they have to be broadcast.
+///
+/// Sapling notes can't be chained like this, since a new note can't be spent until it's
+/// mined, so a payment whose notes alone don't fit is refused.
+pub fn build_transactions<P: Parameters, Pr: TxProver>(
+    params: P,
+    height: BlockHeight,
+    inputs: Vec<SendInput>,
+    recipients: Vec<(AddressKind, Amount, Option<MemoBytes>)>,
+    change: AddressKind,
+    policy: &FeePolicy,
+    expiry_height: Option<u32>,
+    ovk: Option<OutgoingViewingKey>,
+    prover: &Pr,
+) -> Result<Vec<BuiltTransaction>, String> {
+    let mut coins = vec![];
+    let mut notes = vec![];
+    for input in inputs {
+        match input {
+            SendInput::Transparent { outpoint, coin, key } => coins.push((outpoint, coin, key)),
+            note @ SendInput::Sapling { .. } => notes.push(note),
+        }
+    }
+
+    // The last transaction, with `coins` transparent inputs, the recipients and maybe the
+    // change. Spending notes pads the Sapling outputs like the shielded builder does.
+    let transparent_outputs = recipients
+        .iter()
+        .filter(|(to, _, _)| matches!(to, AddressKind::Transparent(_)))
+        .count();
+    let sapling_outputs = recipients.len() - transparent_outputs;
+    let last_size = |coins: usize, with_change: bool| {
+        let (transparent_change, sapling_change) = match (&change, with_change) {
+            (AddressKind::Transparent(_), true) => (1, 0),
+            (AddressKind::Sapling(_), true) => (0, 1),
+            (_, false) => (0, 0),
+        };
+        let sapling_outputs = sapling_outputs + sapling_change;
+        let padded_sapling_outputs = if notes.is_empty() {
+            sapling_outputs
+        } else {
+            sapling_outputs.max(DEFAULT_MIN_SAPLING_OUTPUTS)
+        };
+        estimate_tx_size(
+            coins,
+            transparent_outputs + transparent_change,
+            notes.len(),
+            padded_sapling_outputs,
+            TransparentTxFormat::Sapling,
+        )
+    };
+    if last_size(0, true) > MAX_STANDARD_TX_SIZE {
+        return Err(format!(
+            "{} Sapling notes and {} recipients don't fit in one transaction, and spending notes can't be split",
+            notes.len(),
+            recipients.len()
+        ));
+    }
+
+    // Sweep from the front until what's left fits
+    let sweep_size = |coins: usize| estimate_tx_size(coins, 1, 0, 0, TransparentTxFormat::Sapling);
+    let per_sweep = (2..)
+        .take_while(|coins| sweep_size(*coins) <= MAX_STANDARD_TX_SIZE)
+        .last()
+        .unwrap_or(2);
+    let mut transactions = vec![];
+    while last_size(coins.len(), true) > MAX_STANDARD_TX_SIZE {
+        let swept = coins.drain(..per_sweep.min(coins.len())).collect::<Vec<_>>();
+        let total = swept.iter().map(|(_, coin, _)| i64::from(coin.value)).sum::<i64>();
+        let fee = fee_for_size(sweep_size(swept.len()));
+        let value = total - i64::from(fee);
+        if value < DUST_THRESHOLD as i64 {
+            return Err(format!(
+                "{} coins worth {} zatoshis don't cover the {} zatoshi fee to sweep them",
+                swept.len(),
+                total,
+                i64::from(fee)
+            ));
+        }
+        let value = Amount::from_i64(value).map_err(|_| format!("Invalid sweep of {} zatoshis", value))?;
+
+        // Into a coin the first swept coin's key can spend
+        let key = swept[0].2;
+        let address = TransparentAddress::PublicKey(hash160(
+            &PublicKey::from_secret_key(&Secp256k1::signing_only(), &key).serialize(),
+        ));
+        let sweep = vec![(address.clone(), value)];
+        let built = build_bitcoinz_v4_no_sig(&params, swept, sweep, height, expiry_height, None)?;
+        coins.push((
+            WireTxid::from(built.txid).outpoint(0),
+            TxOut {
+                value,
+                script_pubkey: address.script(),
+            },
+            key,
+        ));
+        transactions.push(built);
+    }
+
+    // Whatever the last transaction doesn't pay out or spend on the fee is change
+    let total_in = coins.iter().map(|(_, coin, _)| i64::from(coin.value)).sum::<i64>()
+        + notes
+            .iter()
+            .map(|note| match note {
+                SendInput::Sapling { note, .. } => note.value as i64,
+                SendInput::Transparent { .. } => 0,
+            })
+            .sum::<i64>();
+    let total_out = recipients.iter().map(|(_, value, _)| i64::from(*value)).sum::<i64>();
+    let fee_with_change = i64::from(policy.fee_for_size(last_size(coins.len(), true)));
+    let left_over = total_in - total_out;
+    let mut recipients = recipients;
+    let fee = if left_over - fee_with_change >= DUST_THRESHOLD as i64 {
+        let value = left_over - fee_with_change;
+        let value = Amount::from_i64(value).map_err(|_| format!("Invalid change of {} zatoshis", value))?;
+        recipients.push((change, value, None));
+        fee_with_change
+    } else {
+        // Too little to be worth an output, so it all goes to the fee
+        let fee = i64::from(policy.fee_for_size(last_size(coins.len(), false)));
+        if left_over < fee {
+            return Err(format!(
+                "Insufficient funds: {} zatoshis to pay {} zatoshis and a {} zatoshi fee",
+                total_in, total_out, fee
+            ));
+        }
+        left_over
+    };
+
+    let inputs = coins
+        .into_iter()
+        .map(|(outpoint, coin, key)| SendInput::Transparent { outpoint, coin, key })
+        .chain(notes)
+        .collect();
+    let fee = Amount::from_i64(fee).map_err(|_| format!("Invalid fee of {} zatoshis", fee))?;
+    transactions.push(build_transaction(
+        params,
+        height,
+        inputs,
+        recipients,
+        fee,
+        expiry_height,
+        ovk,
+        prover,
+    )?);
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_parse::parse_bitcoinz_v4;
+    use crate::blaze::test_utils::FakeTxProver;
+    use crate::BITCOINZ_MAINNET;
+    use zcash_primitives::{
+        sapling::Rseed,
+        zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
+    };
+
+    fn zats(value: u64) -> Amount {
+        Amount::from_u64(value).unwrap()
+    }
+
+    fn transparent_input() -> SendInput {
+        SendInput::Transparent {
+            outpoint: OutPoint::new([0x11u8; 32], 0),
+            coin: TxOut {
+                value: zats(100_000),
+                script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+            },
+            key: SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+        }
+    }
+
+    fn extfvk() -> ExtendedFullViewingKey {
+        ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[1u8; 32]))
+    }
+
+    fn sapling_input() -> SendInput {
+        let to = extfvk().default_address().1;
+        SendInput::Sapling {
+            extsk: ExtendedSpendingKey::master(&[1u8; 32]).expsk,
+            diversifier: *to.diversifier(),
+            note: to
+                .create_note(100_000, Rseed::BeforeZip212(jubjub::Fr::from(7u64)))
+                .unwrap(),
+            merkle_path: vec![Node::new([0u8; 32]); 32],
+            position: 0,
+        }
+    }
+
+    fn send(input: SendInput, to: AddressKind) -> Result<BuiltTransaction, String> {
+        send_expiring(input, to, None)
+    }
+
+    fn send_expiring(
+        input: SendInput,
+        to: AddressKind,
+        expiry_height: Option<u32>,
+    ) -> Result<BuiltTransaction, String> {
+        build_transaction(
+            BITCOINZ_MAINNET,
+            BlockHeight::from(500_000),
+            vec![input],
+            vec![(to, zats(90_000), None)],
+            zats(10_000),
+            expiry_height,
+            Some(extfvk().fvk.ovk),
+            &FakeTxProver {},
+        )
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let taddr = AddressKind::Transparent(TransparentAddress::PublicKey([0x55u8; 20]));
+        let zaddr = AddressKind::Sapling(extfvk().default_address().1);
+
+        // t→t is the v4 transaction without a binding signature
+        let built = send(transparent_input(), taddr.clone()).unwrap();
+        assert_eq!(built.tx_type, BitcoinZTxType::TransparentToTransparent);
+        assert_eq!(parse_bitcoinz_v4(&built.raw).unwrap().binding_sig, None);
+
+        // Everything else is shielded, and ends in a binding signature
+        for (input, to, tx_type) in vec![
+            (
+                transparent_input(),
+                zaddr.clone(),
+                BitcoinZTxType::TransparentToShielded,
+            ),
+            (sapling_input(), taddr, BitcoinZTxType::ShieldedToTransparent),
+            (sapling_input(), zaddr, BitcoinZTxType::ShieldedToShielded),
+        ] {
+            let built = send(input, to).unwrap();
+            assert_eq!(built.tx_type, tx_type);
+            assert!(parse_bitcoinz_v4(&built.raw).unwrap().binding_sig.is_some());
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let taddr = AddressKind::Transparent(TransparentAddress::PublicKey([0x55u8; 20]));
+        let zaddr = AddressKind::Sapling(extfvk().default_address().1);
+
+        // Both builders sign and serialize the same expiry
+        for (input, to) in vec![(transparent_input(), taddr), (sapling_input(), zaddr)] {
+            let built = send_expiring(input, to, Some(500_020)).unwrap();
+            assert_eq!(built.expiry_height, 500_020);
+            assert_eq!(parse_bitcoinz_v4(&built.raw).unwrap().expiry_height, 500_020);
+        }
+    }
+
+    #[test]
+    fn test_fee_and_memo_checks() {
+        let taddr = TransparentAddress::PublicKey([0x55u8; 20]);
+        let build = |value: u64, memo: Option<MemoBytes>| {
+            build_transaction(
+                BITCOINZ_MAINNET,
+                BlockHeight::from(500_000),
+                vec![transparent_input()],
+                vec![(AddressKind::Transparent(taddr.clone()), zats(value), memo)],
+                zats(10_000),
+                None,
+                None,
+                &FakeTxProver {},
+            )
+        };
+
+        assert!(build(90_000, None).is_ok());
+        // Change that was left out would go to the miner
+        assert!(build(80_000, None).unwrap_err().contains("fee of 20000"));
+        assert!(build(90_000, Some(MemoBytes::empty())).is_err());
+    }
+
+    fn coins(count: u32) -> Vec<SendInput> {
+        (0..count)
+            .map(|i| SendInput::Transparent {
+                outpoint: OutPoint::new([0x11u8; 32], i),
+                coin: TxOut {
+                    value: zats(10_000),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                key: SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+            })
+            .collect()
+    }
+
+    fn send_split(inputs: Vec<SendInput>, value: u64) -> Result<Vec<BuiltTransaction>, String> {
+        send_split_with(
+            inputs,
+            value,
+            AddressKind::Transparent(TransparentAddress::PublicKey([0x77u8; 20])),
+            &FeePolicy::default(),
+        )
+    }
+
+    fn send_split_with(
+        inputs: Vec<SendInput>,
+        value: u64,
+        change: AddressKind,
+        policy: &FeePolicy,
+    ) -> Result<Vec<BuiltTransaction>, String> {
+        build_transactions(
+            BITCOINZ_MAINNET,
+            BlockHeight::from(500_000),
+            inputs,
+            vec![(
+                AddressKind::Transparent(TransparentAddress::PublicKey([0x55u8; 20])),
+                zats(value),
+                None,
+            )],
+            change,
+            policy,
+            None,
+            Some(extfvk().fvk.ovk),
+            &FakeTxProver {},
+        )
+    }
+
+    #[test]
+    fn test_send_fits_in_one_transaction() {
+        let built = send_split(coins(10), 50_000).unwrap();
+        assert_eq!(built.len(), 1);
+
+        // 50,000 to the recipient, the fee, and the rest back as change
+        let parsed = parse_bitcoinz_v4(&built[0].raw).unwrap();
+        assert_eq!(parsed.inputs.len(), 10);
+        assert_eq!(parsed.outputs.len(), 2);
+        assert_eq!(
+            built[0].fee,
+            fee_for_size(estimate_tx_size(10, 2, 0, 0, TransparentTxFormat::Sapling))
+        );
+        assert_eq!(
+            i64::from(parsed.outputs[1].value),
+            100_000 - 50_000 - i64::from(built[0].fee)
+        );
+    }
+
+    #[test]
+    fn test_oversized_send_is_split() {
+        let built = send_split(coins(1_000), 5_000_000).unwrap();
+        assert_eq!(built.len(), 2);
+        assert!(built.iter().all(|tx| tx.size <= MAX_STANDARD_TX_SIZE));
+
+        // The sweep pays one coin to the first swept coin's address, and the payment spends it
+        let sweep = parse_bitcoinz_v4(&built[0].raw).unwrap();
+        let payment = parse_bitcoinz_v4(&built[1].raw).unwrap();
+        assert_eq!(sweep.outputs.len(), 1);
+        assert_eq!(sweep.inputs.len() + payment.inputs.len(), 1_000 + 1);
+        let chained = payment.inputs.last().unwrap();
+        assert_eq!(
+            (chained.prevout_hash, chained.prevout_index),
+            (WireTxid::from(built[0].txid), 0)
+        );
+
+        // Everything not paid out went on fees or came back as change
+        let change = i64::from(payment.outputs[1].value);
+        let fees = built.iter().map(|tx| i64::from(tx.fee)).sum::<i64>();
+        assert_eq!(10_000_000 - 5_000_000, change + fees);
+
+        assert!(send_split(coins(1_000), 9_900_000)
+            .unwrap_err()
+            .contains("Insufficient funds"));
+    }
+
+    #[test]
+    fn test_change_and_fee_policy() {
+        // Change too small for an output goes to the fee
+        let built = send_split(coins(10), 100_000 - 2_000 - 40).unwrap();
+        let parsed = parse_bitcoinz_v4(&built[0].raw).unwrap();
+        assert_eq!(parsed.outputs.len(), 1);
+        assert_eq!(i64::from(built[0].fee), 2_040);
+
+        // A flat fee, with the change shielded
+        let flat = FeePolicy {
+            per_kb: Amount::zero(),
+            min: zats(1_000),
+        };
+        let zaddr = AddressKind::Sapling(extfvk().default_address().1);
+        let built = send_split_with(vec![sapling_input()], 50_000, zaddr, &flat).unwrap();
+        assert_eq!(built.len(), 1);
+        assert_eq!(built[0].fee, zats(1_000));
+        let parsed = parse_bitcoinz_v4(&built[0].raw).unwrap();
+        assert_eq!(parsed.outputs.len(), 1);
+        assert_eq!(i64::from(parsed.value_balance), 100_000 - 49_000);
+    }
+
+    #[test]
+    fn test_too_many_notes() {
+        let notes = (0..300).map(|_| sapling_input()).collect();
+        let err = send_split(notes, 50_000).unwrap_err();
+        assert!(err.contains("can't be split"), "{}", err);
+    }
+}
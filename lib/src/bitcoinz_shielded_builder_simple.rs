@@ -26,6 +26,8 @@ use zcash_primitives::{
     },
 };
 
+use crate::bitcoinz_encoding::{write_amount, write_compact_size, write_outpoint};
+
 /// Build a BitcoinZ shielded transaction with custom binding signature
 pub fn build_shielded_transaction<P: Parameters>(
     params: &P,
@@ -44,27 +46,24 @@ pub fn build_shielded_transaction<P: Parameters>(
     tx_data.write_u32::<LittleEndian>(0x892f2085).map_err(|e| e.to_string())?; // BitcoinZ version group
     
     // Write transparent inputs
-    write_compact_size(&mut tx_data, transparent_inputs.len() as u64)?;
+    write_compact_size(&mut tx_data, transparent_inputs.len() as u64);
     for (outpoint, _coin, _key) in &transparent_inputs {
-        // Write outpoint
         // Write the outpoint hash and index
-        let mut outpoint_bytes = Vec::new();
-        outpoint.write(&mut outpoint_bytes).map_err(|e| e.to_string())?;
-        tx_data.write_all(&outpoint_bytes).map_err(|e| e.to_string())?;
+        write_outpoint(&mut tx_data, outpoint);
         
         // Placeholder script sig (will be filled later)
-        write_compact_size(&mut tx_data, 0)?;
+        write_compact_size(&mut tx_data, 0);
         
         // Sequence
         tx_data.write_u32::<LittleEndian>(0xfffffffe).map_err(|e| e.to_string())?;
     }
     
     // Write transparent outputs
-    write_compact_size(&mut tx_data, transparent_outputs.len() as u64)?;
+    write_compact_size(&mut tx_data, transparent_outputs.len() as u64);
     for (_addr, amount) in &transparent_outputs {
-        tx_data.write_i64::<LittleEndian>(amount.into()).map_err(|e| e.to_string())?;
+        write_amount(&mut tx_data, *amount);
         // Placeholder script pubkey
-        write_compact_size(&mut tx_data, 25)?; // P2PKH script
+        write_compact_size(&mut tx_data, 25); // P2PKH script
         tx_data.write_all(&vec![0u8; 25]).map_err(|e| e.to_string())?;
     }
     
@@ -78,10 +77,10 @@ pub fn build_shielded_transaction<P: Parameters>(
     tx_data.write_i64::<LittleEndian>(0).map_err(|e| e.to_string())?;
     
     // Shielded spends (none for now)
-    write_compact_size(&mut tx_data, 0)?;
+    write_compact_size(&mut tx_data, 0);
     
     // Shielded outputs
-    write_compact_size(&mut tx_data, shielded_outputs.len() as u64)?;
+    write_compact_size(&mut tx_data, shielded_outputs.len() as u64);
     for (_addr, _amount, _memo) in &shielded_outputs {
         // Placeholder output description
         // cv (32 bytes)
@@ -99,7 +98,7 @@ pub fn build_shielded_transaction<P: Parameters>(
     }
     
     // JoinSplits (none)
-    write_compact_size(&mut tx_data, 0)?;
+    write_compact_size(&mut tx_data, 0);
     
     // Binding signature (placeholder - would need proper computation)
     tx_data.write_all(&[0u8; 64]).map_err(|e| e.to_string())?;
@@ -125,19 +124,3 @@ pub fn compute_bitcoinz_sighash(tx_data: &[u8]) -> [u8; 32] {
     result.copy_from_slice(hash.as_bytes());
     result
 }
-
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16).map_err(|e| e.to_string())?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32).map_err(|e| e.to_string())?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size).map_err(|e| e.to_string())?;
-    }
-    Ok(())
-}
\ No newline at end of file
@@ -18,6 +18,8 @@ use zcash_primitives::{
     },
 };
 
+use crate::bitcoinz_encoding::{write_amount, write_compact_size, write_outpoint, write_script};
+
 /// BitcoinZ constants
 const CONSENSUS_BRANCH_ID: u32 = 1991772603; // 0x76b809bb
 const SAPLING_TX_VERSION: i32 = 4;
@@ -108,15 +110,14 @@ pub fn compute_shielded_sighash(
     // If sighash type is for a specific input, add input-specific data
     if let Some(input_data) = tx_metadata.input_data {
         // Prevout
-        data.write_all(input_data.prevout.hash())?;
-        data.write_u32::<LittleEndian>(input_data.prevout.n())?;
+        write_outpoint(&mut data, &input_data.prevout);
         
         // Script code
-        write_compact_size(&mut data, input_data.script_code.len() as u64)?;
+        write_compact_size(&mut data, input_data.script_code.len() as u64);
         data.write_all(&input_data.script_code)?;
         
         // Amount
-        data.write_u64::<LittleEndian>(u64::from(input_data.amount))?;
+        write_amount(&mut data, input_data.amount);
         
         // Sequence
         data.write_u32::<LittleEndian>(input_data.sequence)?;
@@ -164,8 +165,7 @@ fn compute_prevouts_hash(
     let mut data = Vec::new();
     
     for (outpoint, _) in inputs {
-        data.write_all(outpoint.hash())?;
-        data.write_u32::<LittleEndian>(outpoint.n())?;
+        write_outpoint(&mut data, outpoint);
     }
     
     let hash = Params::new()
@@ -205,9 +205,8 @@ fn compute_transparent_outputs_hash(outputs: &[TxOut]) -> Result<[u8; 32], Strin
     let mut data = Vec::new();
     
     for output in outputs {
-        data.write_u64::<LittleEndian>(u64::from(output.value))?;
-        write_compact_size(&mut data, output.script_pubkey.0.len() as u64)?;
-        data.write_all(&output.script_pubkey.0)?;
+        write_amount(&mut data, output.value);
+        write_script(&mut data, &output.script_pubkey);
     }
     
     let hash = Params::new()
@@ -286,23 +285,6 @@ fn compute_shielded_outputs_hash<A: Authorization>(
     Ok(result)
 }
 
-/// Write variable-length integer
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16)?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32)?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size)?;
-    }
-    Ok(())
-}
-
 // Re-export WriteBytesExt trait for error conversion
 trait WriteExt: Write {
     fn write_u16<T: byteorder::ByteOrder>(&mut self, n: u16) -> Result<(), String> {
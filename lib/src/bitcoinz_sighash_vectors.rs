@@ -0,0 +1,182 @@
+/// BitcoinZ Sighash Vectors
+///
+/// Checks the transparent sighash against transactions that were actually signed: for
+/// each input, the signature in its scriptSig has to verify, against the public key
+/// pushed after it, over the sighash we compute from the transaction and the coin it
+/// spends. Both builders sign that digest as BLAKE2b outputs it (see
+/// `bitcoinz_sighash`), so the check also makes sure the byte-reversed digest doesn't
+/// verify.
+///
+/// `MAINNET_VECTORS` holds transactions captured from the chain. To add one, take the
+/// raw hex from `getrawtransaction <txid>`, the height it was mined at, and the
+/// scriptPubKey and value of the output each input spends. Only transparent v4
+/// transactions spending P2PKH coins can be checked here.
+///
+/// No vectors have been captured yet: they have to come from a node or an explorer, and
+/// the only mainnet transaction this tree records (the t→z send in TESTING_SUCCESS.md)
+/// is shielded and kept only by txid. `test_mainnet_vectors` fails on an empty table
+/// rather than passing without checking anything, and is ignored until the table is
+/// filled in.
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bitcoinz_parse::parse_bitcoinz_v4,
+        bitcoinz_script_sig::{hash160, TransparentInputKeys},
+        bitcoinz_transparent_builder::{
+            input_sighash, SaplingTransparentTxBuilder, TransparentParts, TransparentTxBuilder, TransparentTxFormat,
+        },
+        BITCOINZ_MAINNET,
+    };
+
+    use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+    use zcash_primitives::{
+        consensus::BlockHeight,
+        legacy::{Script, TransparentAddress},
+        transaction::components::{Amount, OutPoint, TxOut},
+    };
+
+    /// The height a transaction was mined at, its raw hex, and the scriptPubKey (hex)
+    /// and value of the coin each of its inputs spends
+    type SighashVector = (u32, &'static str, &'static [(&'static str, u64)]);
+
+    /// Transactions captured from mainnet. There are none yet: they have to come from a
+    /// node, and a vector made up here would only check the code against itself.
+    const MAINNET_VECTORS: &[SighashVector] = &[];
+
+    /// The signature (DER and hash type) and public key of a P2PKH scriptSig
+    fn p2pkh_script_sig(script_sig: &[u8]) -> Option<(&[u8], &[u8])> {
+        let sig_len = *script_sig.first()? as usize;
+        let signature = script_sig.get(1..1 + sig_len)?;
+        let key_len = *script_sig.get(1 + sig_len)? as usize;
+        let pubkey = script_sig.get(2 + sig_len..)?;
+        if sig_len > 0x4b || (key_len != 33 && key_len != 65) || pubkey.len() != key_len {
+            return None;
+        }
+        Some((signature, pubkey))
+    }
+
+    /// Check every input of `raw` is signed over the sighash we compute for it
+    fn check_signatures(height: u32, raw: &[u8], coins: &[TxOut]) -> Result<(), String> {
+        let tx = parse_bitcoinz_v4(raw)?;
+        if !tx.shielded_spends.is_empty() || !tx.shielded_outputs.is_empty() || tx.joinsplit_count > 0 {
+            return Err("Only transparent transactions can be checked".to_string());
+        }
+        if coins.len() != tx.inputs.len() {
+            return Err(format!("Got {} coins for {} inputs", coins.len(), tx.inputs.len()));
+        }
+
+        let outputs = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, output)| {
+                output
+                    .script_pubkey
+                    .address()
+                    .map(|addr| (addr, output.value))
+                    .ok_or_else(|| format!("Output {} doesn't pay to a transparent address", i))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // The keys don't go into the sighash, so any will do
+        let unused = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let inputs = tx
+            .inputs
+            .iter()
+            .zip(coins)
+            .map(|(input, coin)| {
+                let outpoint = input.prevout_hash.outpoint(input.prevout_index);
+                (outpoint, coin.clone(), TransparentInputKeys::PubKeyHash(unused))
+            })
+            .collect::<Vec<_>>();
+
+        let secp = Secp256k1::verification_only();
+        for (index, (input, coin)) in tx.inputs.iter().zip(coins).enumerate() {
+            let (signature, pubkey) =
+                p2pkh_script_sig(&input.script_sig).ok_or_else(|| format!("Input {} isn't a P2PKH spend", index))?;
+            if coin.script_pubkey != TransparentAddress::PublicKey(hash160(pubkey)).script() {
+                return Err(format!("Input {} pushes a key its coin doesn't pay to", index));
+            }
+            let (hash_type, der) = signature.split_last().ok_or("Empty signature")?;
+            let signature = Signature::from_der(der).map_err(|e| format!("Input {}: {}", index, e))?;
+            let pubkey = PublicKey::from_slice(pubkey).map_err(|e| format!("Input {}: {}", index, e))?;
+
+            let parts = TransparentParts {
+                sequences: tx.inputs.iter().map(|input| input.sequence).collect(),
+                lock_time: tx.lock_time,
+                expiry_height: tx.expiry_height,
+                sighash_type: *hash_type as u32,
+                ..TransparentParts::new(inputs.clone(), outputs.clone())
+            };
+            let mut sighash = input_sighash(
+                &BITCOINZ_MAINNET,
+                BlockHeight::from(height),
+                TransparentTxFormat::Sapling,
+                &parts,
+                index,
+                &coin.script_pubkey,
+                coin.value,
+            )?;
+
+            let verifies = |sighash: &[u8; 32]| {
+                secp.verify_ecdsa(&Message::from_slice(sighash).unwrap(), &signature, &pubkey).is_ok()
+            };
+            if !verifies(&sighash) {
+                return Err(format!("Input {}'s signature doesn't verify over our sighash", index));
+            }
+            sighash.reverse();
+            if verifies(&sighash) {
+                return Err(format!("Input {}'s signature also verifies over the reversed sighash", index));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore] // Needs at least one transaction captured from mainnet in MAINNET_VECTORS
+    fn test_mainnet_vectors() {
+        assert!(!MAINNET_VECTORS.is_empty(), "No mainnet transactions to check against");
+        for (height, raw, prevouts) in MAINNET_VECTORS {
+            let coins = prevouts
+                .iter()
+                .map(|(script, value)| TxOut {
+                    value: Amount::from_u64(*value).unwrap(),
+                    script_pubkey: Script(hex::decode(script).unwrap()),
+                })
+                .collect::<Vec<_>>();
+            if let Err(e) = check_signatures(*height, &hex::decode(raw).unwrap(), &coins) {
+                panic!("{}: {}", raw, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_signatures() {
+        // What the transparent builder signs passes, so a captured transaction that
+        // fails is a difference from the chain and not from the harness
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let coin = |value| TxOut {
+            value: Amount::from_u64(value).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey(hash160(&pubkey.serialize())).script(),
+        };
+        let coins = vec![coin(100_000), coin(250_000)];
+
+        let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        for (i, coin) in coins.iter().enumerate() {
+            builder.add_input(OutPoint::new([0x11u8; 32], i as u32), coin.clone(), TransparentInputKeys::PubKeyHash(sk));
+        }
+        builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(340_000).unwrap());
+        builder.set_expiry(Some(500_020)).unwrap();
+        let raw = builder.build().unwrap().raw;
+        check_signatures(500_000, &raw, &coins).unwrap();
+
+        // A coin of another value is signed over another sighash
+        let mut wrong = coins.clone();
+        wrong[1] = coin(250_001);
+        let err = check_signatures(500_000, &raw, &wrong).unwrap_err();
+        assert!(err.contains("Input 1"), "{}", err);
+    }
+}
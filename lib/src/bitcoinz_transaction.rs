@@ -3,14 +3,60 @@
 /// This module handles BitcoinZ-specific transaction creation and fixes
 /// the binding signature issue that prevents transactions from being accepted.
 
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use json::{object, JsonValue};
+use sha2::{Digest, Sha256};
 use zcash_primitives::{
     consensus::BlockHeight,
+    legacy::{Script, TransparentAddress},
     transaction::{
-        components::{TxOut, transparent},
-        Transaction, TxVersion,
+        components::{amount::DEFAULT_FEE, Amount, OutPoint, TxOut, transparent},
+        Transaction, TxId, TxVersion,
     },
 };
 
+use crate::bitcoinz_encoding::compact_size_len;
+use crate::bitcoinz_parse::{BitcoinZTxError, OUTPUT_DESCRIPTION_SIZE, SPEND_DESCRIPTION_SIZE};
+use crate::bitcoinz_script_sig::{TransparentInputKeys, LOW_R_SIG_PUSH_SIZE};
+use crate::bitcoinz_transparent_builder::TransparentTxFormat;
+use crate::bitcoinz_txid::WireTxid;
+
+/// A signed P2PKH input: outpoint (36), script length (1), a scriptSig of up to
+/// 107 bytes and the sequence (4)
+pub const P2PKH_INPUT_SIZE: usize = 148;
+
+/// A P2PKH output: value (8), script length (1) and a 25 byte script
+pub const P2PKH_OUTPUT_SIZE: usize = 34;
+
+/// Transactions are charged `DEFAULT_FEE` for every started block of this many bytes
+pub const FEE_SIZE_UNIT: usize = 1_000;
+
+/// A fee more than this many times what a transaction's size calls for is taken to be
+/// a mistake, and the builders refuse it
+pub const MAX_FEE_MULTIPLIER: i64 = 10;
+
+/// The largest transaction the node relays as standard, in bytes
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+
+/// The node's default minimum relay fee, in zatoshis per 1,000 bytes
+pub const MIN_RELAY_FEE_PER_KB: u64 = 100;
+
+/// Transparent outputs below this many zatoshis are dust: they'd cost more to spend than
+/// they're worth, and the node won't relay a transaction that makes one. This is the
+/// threshold for a P2PKH output (P2SH comes out the same); the builders take a different
+/// one (e.g. for a testnet node with another relay fee).
+pub const DUST_THRESHOLD: u64 = dust_threshold(25, MIN_RELAY_FEE_PER_KB);
+
+/// The dust threshold for an output with a `script_len` byte script, at a relay fee of
+/// `relay_fee_per_kb`: as the node works it out, three times the fee for the output and
+/// a P2PKH input to spend it
+pub const fn dust_threshold(script_len: usize, relay_fee_per_kb: u64) -> u64 {
+    let spend_size = (8 + compact_size_len(script_len as u64) + script_len + P2PKH_INPUT_SIZE) as u64;
+    3 * spend_size * relay_fee_per_kb / 1_000
+}
+
 /// Transaction type detection
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BitcoinZTxType {
@@ -18,6 +64,7 @@ pub enum BitcoinZTxType {
     TransparentToShielded,     // t→z
     ShieldedToTransparent,     // z→t
     ShieldedToShielded,        // z→z
+    Mixed,                     // any other combination, e.g. t+z → t+z
 }
 
 /// Detect the type of transaction being created
@@ -32,11 +79,320 @@ pub fn detect_tx_type(
         (true, false, false, true) => BitcoinZTxType::TransparentToShielded,
         (false, true, true, false) => BitcoinZTxType::ShieldedToTransparent,
         (false, true, false, true) => BitcoinZTxType::ShieldedToShielded,
-        _ => BitcoinZTxType::TransparentToTransparent, // Default for mixed cases
+        _ => BitcoinZTxType::Mixed,
+    }
+}
+
+/// A signed transaction as the builders return it
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltTransaction {
+    pub raw: Vec<u8>,
+    pub txid: TxId,
+    /// What the inputs pay beyond the outputs
+    pub fee: Amount,
+    /// Serialized size in bytes
+    pub size: usize,
+    pub tx_type: BitcoinZTxType,
+    /// 0 when the transaction doesn't expire
+    pub expiry_height: u32,
+}
+
+impl BuiltTransaction {
+    pub fn new(raw: Vec<u8>, fee: Amount, tx_type: BitcoinZTxType, expiry_height: u32) -> Self {
+        BuiltTransaction {
+            txid: compute_txid(&raw),
+            size: raw.len(),
+            raw,
+            fee,
+            tx_type,
+            expiry_height,
+        }
+    }
+
+    /// Weight as relay policy counts it. BitcoinZ has no segwit, so every byte weighs
+    /// the same and this is just the serialized size.
+    pub fn weight(&self) -> usize {
+        self.size
+    }
+
+    /// The txid as the node displays it, the raw transaction in hex and its size, for
+    /// the CLI and anything expecting what `sendrawtransaction` callers usually get
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            "txid" => self.txid.to_string(),
+            "hex" => hex::encode(&self.raw),
+            "size" => self.size,
+        }
+    }
+}
+
+/// The txid of a serialized transaction, its double SHA-256. `TxId` holds the hash in
+/// the order it's computed and displays it reversed, the way bitcoinzd shows txids.
+pub fn compute_txid(raw: &[u8]) -> TxId {
+    let mut txid = [0u8; 32];
+    txid.copy_from_slice(&Sha256::digest(&Sha256::digest(raw)));
+    TxId::from_bytes(txid)
+}
+
+/// Passes a transaction through to `inner`, hashing it on the way so the txid is known
+/// without holding the whole transaction
+pub struct TxidWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    written: usize,
+}
+
+impl<W: Write> TxidWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TxidWriter { inner, hasher: Sha256::new(), written: 0 }
+    }
+
+    /// Bytes written so far
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// The txid of everything written so far
+    pub fn txid(&self) -> TxId {
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&Sha256::digest(&self.hasher.clone().finalize()));
+        TxId::from_bytes(txid)
+    }
+}
+
+impl<W: Write> Write for TxidWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The fee paid by transparent inputs worth `input_values` to outputs worth `output_values`
+pub fn transparent_fee(
+    input_values: impl IntoIterator<Item = Amount>,
+    output_values: impl IntoIterator<Item = Amount>,
+) -> Result<Amount, String> {
+    let sum = |values: &mut dyn Iterator<Item = Amount>| {
+        values
+            .try_fold(0i64, |total, value| total.checked_add(i64::from(value)))
+            .ok_or_else(|| "Transaction amounts overflow".to_string())
+    };
+    let total_in = sum(&mut input_values.into_iter())?;
+    let total_out = sum(&mut output_values.into_iter())?;
+    
+    if total_out > total_in {
+        return Err(format!(
+            "The outputs ({} zatoshis) are worth more than the inputs ({} zatoshis)",
+            total_out, total_in
+        ));
+    }
+    Amount::from_i64(total_in - total_out).map_err(|_| format!("Invalid fee of {} zatoshis", total_in - total_out))
+}
+
+
+/// Serialized size of a transparent input whose scriptSig is `script_sig_size` bytes:
+/// outpoint, script length, scriptSig and sequence
+pub fn transparent_input_size(script_sig_size: usize) -> usize {
+    36 + compact_size_len(script_sig_size as u64) + script_sig_size + 4
+}
+
+/// Serialized size of a transaction with the given components in `format`, assuming
+/// P2PKH transparent inputs and outputs. Only v4 (`Sapling`) transactions have shielded
+/// components; the shielded counts must be 0 for the older formats. Inputs are counted
+/// at their largest, so a built transaction can come out up to a byte per input smaller.
+pub fn estimate_tx_size(
+    transparent_inputs: usize,
+    transparent_outputs: usize,
+    shielded_spends: usize,
+    shielded_outputs: usize,
+    format: TransparentTxFormat,
+) -> usize {
+    let shielded = shielded_spends + shielded_outputs > 0;
+    debug_assert!(!shielded || format == TransparentTxFormat::Sapling, "{:?} can't have shielded components", format);
+    
+    // Header, plus the version group ID from Overwinter on
+    let mut size = match format {
+        TransparentTxFormat::V1 => 4,
+        TransparentTxFormat::Overwinter | TransparentTxFormat::Sapling => 8,
+    };
+    
+    size += compact_size_len(transparent_inputs as u64) + transparent_inputs * P2PKH_INPUT_SIZE;
+    size += compact_size_len(transparent_outputs as u64) + transparent_outputs * P2PKH_OUTPUT_SIZE;
+    
+    // Lock time
+    size += 4;
+    
+    match format {
+        TransparentTxFormat::V1 => size,
+        // Expiry height and the JoinSplit count (always 0)
+        TransparentTxFormat::Overwinter => size + 4 + 1,
+        TransparentTxFormat::Sapling => {
+            // Expiry height and value balance
+            size += 4 + 8;
+            size += compact_size_len(shielded_spends as u64) + shielded_spends * SPEND_DESCRIPTION_SIZE;
+            size += compact_size_len(shielded_outputs as u64) + shielded_outputs * OUTPUT_DESCRIPTION_SIZE;
+            
+            // JoinSplit count (always 0), and a binding signature if anything is shielded
+            size + 1 + if shielded { 64 } else { 0 }
+        }
+    }
+}
+
+/// The finalized contents of a v4 transaction, before anything is signed or proved
+pub struct TxPlan {
+    /// The keys that will sign each transparent input
+    pub transparent_inputs: Vec<TransparentInputKeys>,
+    /// The script_pubkey of each transparent output
+    pub transparent_outputs: Vec<Script>,
+    pub shielded_spends: usize,
+    pub shielded_outputs: usize,
+    /// Whether the transaction ends in a binding signature. `BitcoinZShieldedBuilder`
+    /// always writes one; the transparent-only v4 builder doesn't.
+    pub binding_sig: bool,
+}
+
+/// Serialized size of the transaction `plan` describes. Every signature is taken to be
+/// a low-R one (`LOW_R_SIG_PUSH_SIZE`); one with a high R is a byte longer, so the real
+/// size can be larger by up to one byte per signature.
+pub fn serialized_size(plan: &TxPlan) -> usize {
+    // Header + version group ID, lock time, expiry height and value balance
+    let mut size = 8 + 4 + 4 + 8;
+    
+    size += compact_size_len(plan.transparent_inputs.len() as u64);
+    for keys in &plan.transparent_inputs {
+        size += transparent_input_size(keys.script_sig_size(LOW_R_SIG_PUSH_SIZE));
+    }
+    
+    size += compact_size_len(plan.transparent_outputs.len() as u64);
+    for script in &plan.transparent_outputs {
+        size += 8 + compact_size_len(script.0.len() as u64) + script.0.len();
+    }
+    
+    size += compact_size_len(plan.shielded_spends as u64) + plan.shielded_spends * SPEND_DESCRIPTION_SIZE;
+    size += compact_size_len(plan.shielded_outputs as u64) + plan.shielded_outputs * OUTPUT_DESCRIPTION_SIZE;
+    
+    // JoinSplit count (always 0)
+    size += 1;
+    
+    if plan.binding_sig {
+        size += 64;
+    }
+    
+    size
+}
+
+/// How much a transaction pays for its size: `per_kb` for every started 1,000 bytes,
+/// and never less than `min`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeePolicy {
+    pub per_kb: Amount,
+    pub min: Amount,
+}
+
+impl Default for FeePolicy {
+    /// BitcoinZ wallets pay `DEFAULT_FEE`, which covers the first 1,000 bytes; bigger
+    /// transactions pay it again for every further 1,000 bytes started, so they still
+    /// clear the node's per-kB relay minimum.
+    fn default() -> Self {
+        FeePolicy { per_kb: DEFAULT_FEE, min: DEFAULT_FEE }
+    }
+}
+
+impl FeePolicy {
+    /// The fee for a transaction of `size` bytes
+    pub fn fee_for_size(&self, size: usize) -> Amount {
+        let units = ((size + FEE_SIZE_UNIT - 1) / FEE_SIZE_UNIT) as i64;
+        let fee = (i64::from(self.per_kb) * units).max(i64::from(self.min));
+        
+        Amount::from_i64(fee).expect("fee is within the money range")
+    }
+}
+
+/// Fee under the default `FeePolicy` for a v4 transaction with the given components
+pub fn estimate_fee(
+    transparent_inputs: usize,
+    transparent_outputs: usize,
+    shielded_spends: usize,
+    shielded_outputs: usize,
+) -> Amount {
+    fee_for_size(estimate_tx_size(
+        transparent_inputs,
+        transparent_outputs,
+        shielded_spends,
+        shielded_outputs,
+        TransparentTxFormat::Sapling,
+    ))
+}
+
+/// The default `FeePolicy`'s fee for a transaction of `size` bytes
+pub fn fee_for_size(size: usize) -> Amount {
+    FeePolicy::default().fee_for_size(size)
+}
+
+/// The most a transaction of `size` bytes may pay. Shielded transactions are several
+/// times the size of transparent ones, so they're allowed a proportionately larger fee.
+pub fn max_fee(size: usize) -> Amount {
+    Amount::from_i64(i64::from(fee_for_size(size)) * MAX_FEE_MULTIPLIER).expect("fee is within the money range")
+}
+
+/// Refuse a fee that's absurd for a transaction of `size` bytes
+pub fn check_fee(fee: Amount, size: usize) -> Result<(), String> {
+    let max = max_fee(size);
+    if i64::from(fee) > i64::from(max) {
+        return Err(format!(
+            "Fee of {} zatoshis is absurdly high for a {} byte transaction (the most allowed is {} zatoshis)",
+            i64::from(fee), size, i64::from(max)
+        ));
+    }
+    Ok(())
+}
+
+/// Refuse a transaction of `size` bytes if it's too big to relay
+pub fn check_size(size: usize) -> Result<(), String> {
+    if size > MAX_STANDARD_TX_SIZE {
+        return Err(format!(
+            "Transaction would be {} bytes, over the {} byte limit the node relays",
+            size, MAX_STANDARD_TX_SIZE
+        ));
     }
+    Ok(())
 }
 
+/// Put transparent inputs, each with its sequence, and outputs in BIP 69 order, so the
+/// order doesn't give away how the wallet picked them or which output is change. Inputs
+/// go by the txid they spend as the node displays it, then output index; outputs by
+/// value, then scriptPubKey.
+pub fn sort_bip69(
+    inputs: &mut Vec<(OutPoint, TxOut, TransparentInputKeys)>,
+    sequences: &mut Vec<u32>,
+    outputs: &mut [(TransparentAddress, Amount)],
+) {
+    debug_assert_eq!(inputs.len(), sequences.len());
+    let mut sequenced = std::mem::take(inputs).into_iter().zip(std::mem::take(sequences)).collect::<Vec<_>>();
+    sequenced.sort_by_key(|((outpoint, _, _), _)| (*WireTxid::from(outpoint).to_display().as_bytes(), outpoint.n()));
+    let (sorted, sorted_sequences): (Vec<_>, Vec<_>) = sequenced.into_iter().unzip();
+    *inputs = sorted;
+    *sequences = sorted_sequences;
+    
+    outputs.sort_by(|(a, a_value), (b, b_value)| {
+        i64::from(*a_value).cmp(&i64::from(*b_value)).then_with(|| a.script().0.cmp(&b.script().0))
+    });
+}
 
+/// Refuse an output of `amount` if it's below `threshold`
+pub fn check_dust(amount: Amount, threshold: u64) -> Result<(), BitcoinZTxError> {
+    // Negative amounts fail elsewhere; they aren't dust
+    match u64::try_from(i64::from(amount)) {
+        Ok(amount) if amount < threshold => Err(BitcoinZTxError::DustOutput { amount }),
+        _ => Ok(()),
+    }
+}
 
 /// Attempt to fix a Sapling transaction for BitcoinZ
 /// 
@@ -78,4 +434,254 @@ pub fn create_overwinter_tx(
     // - Sign with standard script signatures
     
     Err("Overwinter transaction creation not yet implemented".to_string())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_txid_of_known_transaction() {
+        // The coinbase of Bitcoin's genesis block, which BitcoinZ's txids are computed like
+        let raw = hex::decode(
+            "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d\
+             0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f\
+             66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0\
+             fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c38\
+             4df7ba0b8d578a4c702b6bf11d5fac00000000",
+        )
+        .unwrap();
+        let txid = compute_txid(&raw);
+        assert_eq!(txid.to_string(), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        
+        let built = BuiltTransaction::new(raw.clone(), Amount::zero(), BitcoinZTxType::Mixed, 0);
+        assert_eq!((built.txid, built.size), (txid, raw.len()));
+    }
+    
+    #[test]
+    fn test_dust_threshold() {
+        use zcash_primitives::legacy::TransparentAddress;
+        
+        assert_eq!(DUST_THRESHOLD, 54);
+        let p2pkh = TransparentAddress::PublicKey([0u8; 20]).script().0.len();
+        let p2sh = TransparentAddress::Script([0u8; 20]).script().0.len();
+        assert_eq!(dust_threshold(p2pkh, MIN_RELAY_FEE_PER_KB), DUST_THRESHOLD);
+        assert_eq!(dust_threshold(p2sh, MIN_RELAY_FEE_PER_KB), DUST_THRESHOLD);
+        
+        // Bigger scripts cost more to spend, and so does a higher relay fee
+        assert!(dust_threshold(1_000, MIN_RELAY_FEE_PER_KB) > DUST_THRESHOLD);
+        assert_eq!(dust_threshold(p2pkh, 1_000), 546);
+        // A script over 0xffff bytes has a 5 byte length
+        assert_eq!(dust_threshold(0x10000, 1_000), 3 * (8 + 5 + 0x10000 + 148));
+        
+        // The boundary: the threshold itself is fine
+        assert!(check_dust(Amount::from_u64(DUST_THRESHOLD).unwrap(), DUST_THRESHOLD).is_ok());
+        assert_eq!(
+            check_dust(Amount::from_u64(DUST_THRESHOLD - 1).unwrap(), DUST_THRESHOLD),
+            Err(BitcoinZTxError::DustOutput { amount: DUST_THRESHOLD - 1 })
+        );
+    }
+    
+    #[test]
+    fn test_transparent_fee() {
+        let amounts = |values: &[u64]| values.iter().map(|v| Amount::from_u64(*v).unwrap()).collect::<Vec<_>>();
+        assert_eq!(transparent_fee(amounts(&[100_000, 250_000]), amounts(&[340_000])), Ok(Amount::from_u64(10_000).unwrap()));
+        assert_eq!(transparent_fee(amounts(&[100_000]), amounts(&[100_000])), Ok(Amount::zero()));
+        assert!(transparent_fee(amounts(&[100_000]), amounts(&[100_001])).is_err());
+    }
+    
+    #[test]
+    fn test_estimate_tx_size() {
+        use TransparentTxFormat::*;
+        
+        // 1 input, 2 outputs, transparent only: no binding signature
+        assert_eq!(estimate_tx_size(1, 2, 0, 0, Sapling), 8 + 1 + 148 + 1 + 68 + 16 + 1 + 1 + 1);
+        // t→z with one shielded output
+        assert_eq!(estimate_tx_size(1, 0, 0, 1, Sapling), 8 + 1 + 148 + 1 + 16 + 1 + 1 + 948 + 1 + 64);
+        
+        // The older formats, without the v4 fields
+        assert_eq!(estimate_tx_size(1, 2, 0, 0, Overwinter), 8 + 1 + 148 + 1 + 68 + 4 + 4 + 1);
+        assert_eq!(estimate_tx_size(1, 2, 0, 0, V1), 4 + 1 + 148 + 1 + 68 + 4);
+    }
+    
+    #[test]
+    fn test_estimate_tx_size_matches_built() {
+        use secp256k1::SecretKey;
+        use zcash_primitives::{legacy::TransparentAddress, transaction::components::OutPoint};
+        
+        use crate::bitcoinz_script_sig::TransparentInputKeys;
+        use crate::bitcoinz_transparent_builder::transparent_builder;
+        use crate::BITCOINZ_MAINNET;
+        
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        
+        // Up to 300 inputs, so the input count takes a 3 byte compact size
+        for (inputs, outputs) in [(1, 1), (2, 3), (40, 1), (300, 2)] {
+            for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+                let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+                for i in 0..inputs {
+                    let coin = TxOut {
+                        value: Amount::from_u64(100_000).unwrap(),
+                        script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                    };
+                    builder.add_input(OutPoint::new([0x11u8; 32], i as u32), coin, TransparentInputKeys::PubKeyHash(sk));
+                }
+                
+                // Whatever isn't paid out is the fee, so make it what the estimate asks for
+                let estimate = estimate_tx_size(inputs, outputs, 0, 0, format);
+                let fee = u64::from(fee_for_size(estimate));
+                let value = (inputs as u64 * 100_000 - fee) / outputs as u64;
+                for _ in 0..outputs {
+                    builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(value).unwrap());
+                }
+                
+                // A signature is a byte shorter with a low R, and now and then shorter still
+                let actual = builder.build().unwrap().raw.len();
+                assert!(
+                    actual <= estimate && estimate - actual <= 2 * inputs,
+                    "{:?} with {} inputs and {} outputs: estimated {}, built {}",
+                    format, inputs, outputs, estimate, actual
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn test_fee_policy() {
+        let fee = |zats: u64| Amount::from_u64(zats).unwrap();
+        
+        // The default is what fee_for_size charges
+        for size in [0, 1, 999, 1_000, 1_001, 25_000] {
+            assert_eq!(FeePolicy::default().fee_for_size(size), fee_for_size(size));
+        }
+        
+        let policy = FeePolicy { per_kb: fee(1_000), min: fee(2_500) };
+        assert_eq!(policy.fee_for_size(0), fee(2_500));
+        assert_eq!(policy.fee_for_size(2_001), fee(3_000));
+        assert_eq!(policy.fee_for_size(10_000), fee(10_000));
+        
+        // Consolidating many UTXOs pays for all of them
+        let consolidate = estimate_tx_size(100, 1, 0, 0, TransparentTxFormat::Sapling);
+        assert_eq!(policy.fee_for_size(consolidate), fee(1_000 * 15));
+        assert_eq!(fee_for_size(consolidate), fee(150_000));
+    }
+    
+    #[test]
+    fn test_serialized_size_of_transparent_tx() {
+        use secp256k1::SecretKey;
+        use zcash_primitives::{legacy::TransparentAddress, transaction::components::OutPoint};
+        
+        use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+        use crate::BITCOINZ_MAINNET;
+        
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let coin = |value: u64, hash: u8| TxOut {
+            value: Amount::from_u64(value).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey([hash; 20]).script(),
+        };
+        let inputs = vec![
+            (OutPoint::new([0x11u8; 32], 0), coin(100_000, 0x22), sk),
+            (OutPoint::new([0x33u8; 32], 1), coin(250_000, 0x44), sk),
+        ];
+        let outputs = vec![
+            (TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(150_000).unwrap()),
+            (TransparentAddress::Script([0x66u8; 20]), Amount::from_u64(190_000).unwrap()),
+        ];
+        
+        let plan = TxPlan {
+            transparent_inputs: inputs.iter().map(|(_, _, sk)| TransparentInputKeys::PubKeyHash(*sk)).collect(),
+            transparent_outputs: outputs.iter().map(|(addr, _)| addr.script()).collect(),
+            shielded_spends: 0,
+            shielded_outputs: 0,
+            binding_sig: false,
+        };
+        let predicted = serialized_size(&plan);
+        
+        let tx = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), None, None).unwrap().raw;
+        assert!(tx.len() >= predicted && tx.len() - predicted <= 1, "predicted {}, built {}", predicted, tx.len());
+    }
+    
+    #[test]
+    fn test_small_transactions_pay_default_fee() {
+        assert_eq!(estimate_fee(0, 0, 0, 0), DEFAULT_FEE);
+        assert_eq!(estimate_fee(1, 2, 0, 0), DEFAULT_FEE);
+        assert_eq!(estimate_fee(2, 0, 1, 0), DEFAULT_FEE);
+        
+        // One shielded output and a transparent input is already past 1,000 bytes
+        assert_eq!(estimate_fee(1, 0, 0, 1), (DEFAULT_FEE + DEFAULT_FEE).unwrap());
+    }
+    
+    #[test]
+    fn test_fee_grows_with_each_component() {
+        let base = [1, 1, 1, 1];
+        for component in 0..4 {
+            let mut counts = base;
+            let mut last = estimate_fee(counts[0], counts[1], counts[2], counts[3]);
+            for _ in 0..50 {
+                counts[component] += 1;
+                let fee = estimate_fee(counts[0], counts[1], counts[2], counts[3]);
+                assert!(fee >= last, "fee dropped adding component {}", component);
+                last = fee;
+            }
+            
+            // 50 more of anything is well over another 1,000 bytes
+            assert!(last > estimate_fee(base[0], base[1], base[2], base[3]));
+        }
+    }
+    
+    #[test]
+    fn test_max_fee_scales_with_size() {
+        let fee = |zats: u64| Amount::from_u64(zats).unwrap();
+        
+        assert_eq!(fee_for_size(0), DEFAULT_FEE);
+        assert_eq!(fee_for_size(1_001), fee(20_000));
+        assert_eq!(max_fee(250), fee(100_000));
+        assert_eq!(max_fee(2_500), fee(300_000));
+        
+        assert!(check_fee(DEFAULT_FEE, 250).is_ok());
+        assert!(check_fee(fee(100_000), 250).is_ok());
+        assert!(check_fee(fee(100_001), 250).is_err());
+        
+        // The same fee is fine once the transaction is big enough to call for it
+        let t_size = estimate_tx_size(1, 2, 0, 0, TransparentTxFormat::Sapling);
+        let z_size = estimate_tx_size(0, 1, 2, 2, TransparentTxFormat::Sapling);
+        assert!(check_fee(fee(150_000), t_size).is_err());
+        assert!(check_fee(fee(150_000), z_size).is_ok());
+    }
+    
+    #[test]
+    fn test_sort_bip69() {
+        use secp256k1::SecretKey;
+        
+        let keys = TransparentInputKeys::PubKeyHash(SecretKey::from_slice(&[0x01u8; 32]).unwrap());
+        let input = |hash: [u8; 32], n: u32| {
+            let coin = TxOut { value: Amount::from_u64(100_000).unwrap(), script_pubkey: Script(vec![]) };
+            (OutPoint::new(hash, n), coin, keys.clone())
+        };
+        // Displayed, the first of these txids starts ff.. and the second 00..
+        let mut late = [0u8; 32];
+        late[31] = 0xff;
+        let mut early = [0u8; 32];
+        early[0] = 0xff;
+        
+        let mut inputs = vec![input(late, 0), input(early, 2), input(early, 1)];
+        let mut sequences = vec![1, 2, 3];
+        let zats = |value: u64| Amount::from_u64(value).unwrap();
+        let mut outputs = vec![
+            (TransparentAddress::Script([0x00u8; 20]), zats(60_000)),
+            (TransparentAddress::PublicKey([0xffu8; 20]), zats(60_000)),
+            (TransparentAddress::Script([0x00u8; 20]), zats(50_000)),
+        ];
+        sort_bip69(&mut inputs, &mut sequences, &mut outputs);
+        
+        let order = inputs.iter().map(|(outpoint, _, _)| (*outpoint.hash(), outpoint.n())).collect::<Vec<_>>();
+        assert_eq!(order, vec![(early, 1), (early, 2), (late, 0)]);
+        // Sequences go with their inputs
+        assert_eq!(sequences, vec![3, 2, 1]);
+        // By value, then by script: a P2PKH script (0x76..) sorts before a P2SH one (0xa9..)
+        assert_eq!(outputs, vec![
+            (TransparentAddress::Script([0x00u8; 20]), zats(50_000)),
+            (TransparentAddress::PublicKey([0xffu8; 20]), zats(60_000)),
+            (TransparentAddress::Script([0x00u8; 20]), zats(60_000)),
+        ]);
+    }
+}
@@ -0,0 +1,1799 @@
+/// BitcoinZ Transparent Transaction Builders
+///
+/// Builds t→t transactions in each serialization bitcoinzd has accepted: v1 before
+/// Overwinter, v3 once Overwinter activates, and v4 with no shielded parts and no
+/// binding signature from Sapling on. The formats share their inputs, outputs and
+/// signing; only the header, the sighash and the fields after the lock time differ.
+/// `select_format` picks the one the node accepts at a height, and
+/// `transparent_builder` gives a `TransparentTxBuilder` for it. `build_sweep_tx`
+/// empties a set of coins into one address.
+
+use blake2b_simd::Params;
+use secp256k1::{PublicKey, SecretKey};
+use std::borrow::Cow;
+use sha2::{Digest, Sha256};
+use zcash_primitives::{
+    consensus::{BlockHeight, BranchId, Parameters},
+    keys::OutgoingViewingKey,
+    legacy::{Script, TransparentAddress},
+    memo::MemoBytes,
+    sapling::prover::TxProver,
+    transaction::components::{Amount, OutPoint, TxOut},
+};
+
+use crate::bitcoinz_address::AddressKind;
+use crate::bitcoinz_branch::{bitcoinz_branch_id_for_height, bitcoinz_consensus_branch_id};
+use crate::bitcoinz_encoding::{write_amount, write_compact_size, write_outpoint, write_script};
+use crate::bitcoinz_overwinter_builder::{
+    validate_sighash_type, DEFAULT_SEQUENCE, REPLACEABLE_SEQUENCE, SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_NONE,
+    SIGHASH_SINGLE,
+};
+use crate::bitcoinz_script_sig::{pubkey_hash_inputs, script_sigs, ScriptSigner, TransparentInputKeys};
+use crate::bitcoinz_sighash::{finalize_sighash, sighash_state, REVERSE_TRANSPARENT_SIGHASH};
+use crate::bitcoinz_transaction::{
+    check_dust, check_fee, check_size, detect_tx_type, sort_bip69, transparent_fee, transparent_input_size,
+    BuiltTransaction, FeePolicy, DUST_THRESHOLD,
+};
+use crate::bitcoinz_v4_shielded::{BitcoinZShieldedBuilder, TxEstimate};
+
+/// Set in the header of every Overwinter and later transaction
+const OVERWINTER_FLAG: u32 = 0x8000_0000;
+
+/// BitcoinZ uses the same version group ID for v3 and v4
+const BITCOINZ_VERSION_GROUP_ID: u32 = 0x892f_2085;
+
+const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
+const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
+const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
+
+/// How a transparent transaction is serialized and signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransparentTxFormat {
+    /// Bitcoin's v1, signed with the legacy double SHA-256 sighash
+    V1,
+    /// v3, signed as ZIP-143 describes
+    Overwinter,
+    /// v4 with no shielded parts, signed as ZIP-243 describes
+    Sapling,
+}
+
+impl TransparentTxFormat {
+    pub fn version(&self) -> u32 {
+        match self {
+            TransparentTxFormat::V1 => 1,
+            TransparentTxFormat::Overwinter => 3,
+            TransparentTxFormat::Sapling => 4,
+        }
+    }
+}
+
+/// How `select_format` picks a format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatPolicy {
+    /// The format consensus requires at the height
+    Current,
+    /// This format whatever the height, for a node that doesn't follow the network's
+    /// upgrade schedule
+    Force(TransparentTxFormat),
+}
+
+/// The format to build a transparent transaction in for a node at `height`
+pub fn select_format<P: Parameters>(params: &P, height: BlockHeight, policy: FormatPolicy) -> TransparentTxFormat {
+    match policy {
+        FormatPolicy::Force(format) => format,
+        FormatPolicy::Current => match bitcoinz_branch_id_for_height(params, height) {
+            BranchId::Sprout => TransparentTxFormat::V1,
+            BranchId::Overwinter => TransparentTxFormat::Overwinter,
+            _ => TransparentTxFormat::Sapling,
+        },
+    }
+}
+
+/// Lock times below this are block heights, and from it on Unix times
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// nLockTime: the transaction can't be mined until after a block height, or a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockTime {
+    /// Only mined in a block above this height; 0 for no lock time
+    Height(u32),
+    /// Only mined once the median time of the last blocks is past this Unix time
+    Time(u32),
+}
+
+impl LockTime {
+    /// The nLockTime field, as long as the node will read it as the same kind of lock
+    pub fn to_u32(self) -> Result<u32, String> {
+        match self {
+            LockTime::Height(height) if height < LOCKTIME_THRESHOLD => Ok(height),
+            LockTime::Height(height) => Err(format!("Lock height {} would be taken for a time", height)),
+            LockTime::Time(time) if time >= LOCKTIME_THRESHOLD => Ok(time),
+            LockTime::Time(time) => Err(format!("Lock time {} would be taken for a block height", time)),
+        }
+    }
+}
+
+/// Builds a transparent-only transaction in one format
+pub trait TransparentTxBuilder {
+    fn format(&self) -> TransparentTxFormat;
+
+    /// Spend `coin` at `outpoint`, signed with `keys`
+    fn add_input(&mut self, outpoint: OutPoint, coin: TxOut, keys: TransparentInputKeys);
+
+    fn add_output(&mut self, to: TransparentAddress, value: Amount);
+
+    /// The transaction can't be mined after `expiry_height`; None means it never expires
+    fn set_expiry(&mut self, expiry_height: Option<u32>) -> Result<(), String>;
+
+    /// Don't let the transaction be mined until after `lock_time`. `LockTime::Height(0)`, the
+    /// default, doesn't lock it.
+    fn set_lock_time(&mut self, lock_time: LockTime) -> Result<(), String>;
+
+    /// Sign for `consensus_branch_id` rather than the branch active at the builder's
+    /// height, e.g. for an upgrade this wallet doesn't know about yet. None (the
+    /// default) follows the height.
+    fn set_consensus_branch_id(&mut self, consensus_branch_id: Option<u32>) -> Result<(), String>;
+
+    /// The sighash input `index` signs. For a multisig input, the other parties sign it
+    /// with `sign_sighash` and their signatures go in with `add_signature`.
+    fn sighash(&self, index: usize) -> Result<[u8; 32], String>;
+
+    /// Add `pubkey`'s signature, made elsewhere over `sighash(index)`, to P2SH input `index`
+    fn add_signature(&mut self, index: usize, pubkey: PublicKey, signature: Vec<u8>) -> Result<(), String>;
+
+    /// Refuse outputs below `dust_threshold` rather than `DUST_THRESHOLD`, e.g. for a
+    /// testnet node that relays with a different fee
+    fn set_dust_threshold(&mut self, dust_threshold: u64);
+
+    /// What `bump_fee` does when taking the extra fee out of the change would leave it
+    /// below the dust threshold (defaults to `DustChange::Reject`)
+    fn set_dust_change(&mut self, dust_change: DustChange);
+
+    /// Give every input, including ones added later, `REPLACEABLE_SEQUENCE`, so a node that
+    /// follows BIP 125 lets `bump_fee` replace the transaction
+    fn set_replaceable(&mut self, replaceable: bool);
+
+    /// Sort the inputs and outputs into BIP 69 order before signing (off by default).
+    /// Input and output indices, as `sighash`, `add_signature` and `bump_fee` take them,
+    /// still count in the order they were added.
+    fn set_canonical_order(&mut self, canonical_order: bool);
+
+    /// The size of the transaction as it stands and the fee `fee_policy` charges for it,
+    /// without signing. Every scriptSig is taken at its largest, so a multisig input
+    /// counts a 72-byte signature for each of its threshold as well as its redeem script.
+    fn estimate(&self, fee_policy: &FeePolicy) -> TxEstimate;
+
+    /// Rebuild `original`, which this builder built, paying `new_fee`. The extra fee comes
+    /// out of the change at output `change_index`, and the inputs and every other output
+    /// stay as they were. Refused unless `original` is replaceable and the change covers
+    /// the extra; change that would be left as dust is refused too, or with
+    /// `DustChange::AddToFee` dropped, paying more than `new_fee`.
+    fn bump_fee(
+        &mut self,
+        original: &BuiltTransaction,
+        new_fee: Amount,
+        change_index: usize,
+    ) -> Result<BuiltTransaction, String>;
+
+    /// Sign every input and serialize the transaction. The fee is whatever the inputs
+    /// have left over after the outputs.
+    fn build(&self) -> Result<BuiltTransaction, String>;
+}
+
+/// The expiry height for a transaction targeting `height` that should expire `offset`
+/// blocks later. An offset of 0 means it never expires, as the v4 builder does by default.
+pub fn expiry_for_offset(height: BlockHeight, offset: u32) -> Option<u32> {
+    if offset == 0 {
+        None
+    } else {
+        Some(u32::from(height).saturating_add(offset))
+    }
+}
+
+/// A builder for `format`, signing for a node at `height`
+pub fn transparent_builder<P: Parameters + Send + 'static>(
+    params: P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+) -> Box<dyn TransparentTxBuilder + Send> {
+    match format {
+        TransparentTxFormat::V1 => Box::new(LegacyTxBuilder::new(params, height)),
+        TransparentTxFormat::Overwinter => Box::new(OverwinterTxBuilder::new(params, height)),
+        TransparentTxFormat::Sapling => Box::new(SaplingTransparentTxBuilder::new(params, height)),
+    }
+}
+
+/// Spend every one of `inputs` into a single output paying `destination` all they hold
+/// less the fee `fee_policy` charges for the transaction's size, with no change. A
+/// transparent destination gets a transparent transaction in the format the node takes
+/// at `height`. A Sapling one goes through the shielded builder, which encrypts the
+/// output to `ovk` and proves it with `prover`. Refused if what's left would be dust.
+pub fn build_sweep_tx<P: Parameters, Pr: TxProver>(
+    params: P,
+    inputs: Vec<(OutPoint, TxOut, SecretKey)>,
+    destination: AddressKind,
+    fee_policy: &FeePolicy,
+    height: BlockHeight,
+    ovk: Option<OutgoingViewingKey>,
+    prover: &Pr,
+) -> Result<BuiltTransaction, String> {
+    if inputs.is_empty() {
+        return Err("There are no coins to sweep".to_string());
+    }
+    let total = transparent_fee(inputs.iter().map(|(_, coin, _)| coin.value), std::iter::empty())?;
+    let swept = |fee: Amount| {
+        let value = i64::from(total) - i64::from(fee);
+        if value < DUST_THRESHOLD as i64 {
+            return Err(format!(
+                "{} coins worth {} zatoshis leave {} zatoshis after the {} zatoshi fee, which is dust",
+                inputs.len(),
+                i64::from(total),
+                value,
+                i64::from(fee)
+            ));
+        }
+        Ok(Amount::from_i64(value).expect("less than the inputs"))
+    };
+
+    match destination {
+        AddressKind::Transparent(to) => {
+            let format = select_format(&params, height, FormatPolicy::Current);
+            let mut parts = TransparentParts::new(pubkey_hash_inputs(&inputs), vec![(to, total)]);
+            // The output's value doesn't change the size, so the whole balance stands in
+            parts.outputs[0].1 = swept(estimate_transparent(format, &parts, fee_policy).fee)?;
+            build_transparent(&params, height, format, &parts)
+        }
+        AddressKind::Sapling(to) => {
+            let sweep_to = |value: Amount| {
+                let mut builder = BitcoinZShieldedBuilder::new_with_entropy(params.clone(), height);
+                builder.set_fee_policy(*fee_policy);
+                for (outpoint, coin, key) in &inputs {
+                    builder.add_transparent_input(outpoint.clone(), coin.clone(), *key)?;
+                }
+                builder.add_sapling_output(ovk, to.clone(), value, MemoBytes::empty())?;
+                Ok::<_, String>(builder)
+            };
+            let fee = sweep_to(total)?.estimate().fee;
+            sweep_to(swept(fee)?)?.build(prover, fee)
+        }
+    }
+}
+
+/// Builds v1 transactions, which have no expiry height
+pub struct LegacyTxBuilder<P: Parameters> {
+    params: P,
+    height: BlockHeight,
+    parts: TransparentParts,
+}
+
+/// Builds v3 (Overwinter) transactions
+pub struct OverwinterTxBuilder<P: Parameters> {
+    params: P,
+    height: BlockHeight,
+    parts: TransparentParts,
+}
+
+/// Builds v4 (Sapling) transactions with no shielded parts
+pub struct SaplingTransparentTxBuilder<P: Parameters> {
+    params: P,
+    height: BlockHeight,
+    parts: TransparentParts,
+}
+
+impl<P: Parameters> LegacyTxBuilder<P> {
+    pub fn new(params: P, height: BlockHeight) -> Self {
+        LegacyTxBuilder { params, height, parts: TransparentParts::default() }
+    }
+}
+
+impl<P: Parameters> OverwinterTxBuilder<P> {
+    pub fn new(params: P, height: BlockHeight) -> Self {
+        OverwinterTxBuilder { params, height, parts: TransparentParts::default() }
+    }
+}
+
+impl<P: Parameters> SaplingTransparentTxBuilder<P> {
+    pub fn new(params: P, height: BlockHeight) -> Self {
+        SaplingTransparentTxBuilder { params, height, parts: TransparentParts::default() }
+    }
+}
+
+impl<P: Parameters> TransparentTxBuilder for LegacyTxBuilder<P> {
+    fn format(&self) -> TransparentTxFormat {
+        TransparentTxFormat::V1
+    }
+
+    fn add_input(&mut self, outpoint: OutPoint, coin: TxOut, keys: TransparentInputKeys) {
+        self.parts.add_input(outpoint, coin, keys);
+    }
+
+    fn add_output(&mut self, to: TransparentAddress, value: Amount) {
+        self.parts.outputs.push((to, value));
+    }
+
+    fn set_expiry(&mut self, expiry_height: Option<u32>) -> Result<(), String> {
+        match expiry_height {
+            None => Ok(()),
+            Some(height) => Err(format!("v1 transactions can't expire (asked for expiry at {})", height)),
+        }
+    }
+
+    fn set_lock_time(&mut self, lock_time: LockTime) -> Result<(), String> {
+        self.parts.lock_time = lock_time.to_u32()?;
+        Ok(())
+    }
+
+    fn set_consensus_branch_id(&mut self, consensus_branch_id: Option<u32>) -> Result<(), String> {
+        match consensus_branch_id {
+            None => Ok(()),
+            Some(id) => Err(format!("v1 signatures don't commit to a branch id (asked for {:#010x})", id)),
+        }
+    }
+
+    fn sighash(&self, index: usize) -> Result<[u8; 32], String> {
+        self.parts.sighash(&self.params, self.height, self.format(), index)
+    }
+
+    fn add_signature(&mut self, index: usize, pubkey: PublicKey, signature: Vec<u8>) -> Result<(), String> {
+        self.parts.add_signature(index, pubkey, signature)
+    }
+
+    fn set_dust_threshold(&mut self, dust_threshold: u64) {
+        self.parts.dust_threshold = dust_threshold;
+    }
+
+    fn set_dust_change(&mut self, dust_change: DustChange) {
+        self.parts.dust_change = dust_change;
+    }
+
+    fn set_replaceable(&mut self, replaceable: bool) {
+        self.parts.set_replaceable(replaceable);
+    }
+
+    fn set_canonical_order(&mut self, canonical_order: bool) {
+        self.parts.canonical_order = canonical_order;
+    }
+
+    fn estimate(&self, fee_policy: &FeePolicy) -> TxEstimate {
+        estimate_transparent(self.format(), &self.parts, fee_policy)
+    }
+
+    fn bump_fee(
+        &mut self,
+        original: &BuiltTransaction,
+        new_fee: Amount,
+        change_index: usize,
+    ) -> Result<BuiltTransaction, String> {
+        let format = self.format();
+        bump_fee(&self.params, self.height, format, &mut self.parts, original, new_fee, change_index)
+    }
+
+    fn build(&self) -> Result<BuiltTransaction, String> {
+        build_transparent(&self.params, self.height, self.format(), &self.parts)
+    }
+}
+
+impl<P: Parameters> TransparentTxBuilder for OverwinterTxBuilder<P> {
+    fn format(&self) -> TransparentTxFormat {
+        TransparentTxFormat::Overwinter
+    }
+
+    fn add_input(&mut self, outpoint: OutPoint, coin: TxOut, keys: TransparentInputKeys) {
+        self.parts.add_input(outpoint, coin, keys);
+    }
+
+    fn add_output(&mut self, to: TransparentAddress, value: Amount) {
+        self.parts.outputs.push((to, value));
+    }
+
+    fn set_expiry(&mut self, expiry_height: Option<u32>) -> Result<(), String> {
+        self.parts.expiry_height = expiry_height.unwrap_or(0);
+        Ok(())
+    }
+
+    fn set_lock_time(&mut self, lock_time: LockTime) -> Result<(), String> {
+        self.parts.lock_time = lock_time.to_u32()?;
+        Ok(())
+    }
+
+    fn set_consensus_branch_id(&mut self, consensus_branch_id: Option<u32>) -> Result<(), String> {
+        self.parts.consensus_branch_id = consensus_branch_id;
+        Ok(())
+    }
+
+    fn sighash(&self, index: usize) -> Result<[u8; 32], String> {
+        self.parts.sighash(&self.params, self.height, self.format(), index)
+    }
+
+    fn add_signature(&mut self, index: usize, pubkey: PublicKey, signature: Vec<u8>) -> Result<(), String> {
+        self.parts.add_signature(index, pubkey, signature)
+    }
+
+    fn set_dust_threshold(&mut self, dust_threshold: u64) {
+        self.parts.dust_threshold = dust_threshold;
+    }
+
+    fn set_dust_change(&mut self, dust_change: DustChange) {
+        self.parts.dust_change = dust_change;
+    }
+
+    fn set_replaceable(&mut self, replaceable: bool) {
+        self.parts.set_replaceable(replaceable);
+    }
+
+    fn set_canonical_order(&mut self, canonical_order: bool) {
+        self.parts.canonical_order = canonical_order;
+    }
+
+    fn estimate(&self, fee_policy: &FeePolicy) -> TxEstimate {
+        estimate_transparent(self.format(), &self.parts, fee_policy)
+    }
+
+    fn bump_fee(
+        &mut self,
+        original: &BuiltTransaction,
+        new_fee: Amount,
+        change_index: usize,
+    ) -> Result<BuiltTransaction, String> {
+        let format = self.format();
+        bump_fee(&self.params, self.height, format, &mut self.parts, original, new_fee, change_index)
+    }
+
+    fn build(&self) -> Result<BuiltTransaction, String> {
+        build_transparent(&self.params, self.height, self.format(), &self.parts)
+    }
+}
+
+impl<P: Parameters> TransparentTxBuilder for SaplingTransparentTxBuilder<P> {
+    fn format(&self) -> TransparentTxFormat {
+        TransparentTxFormat::Sapling
+    }
+
+    fn add_input(&mut self, outpoint: OutPoint, coin: TxOut, keys: TransparentInputKeys) {
+        self.parts.add_input(outpoint, coin, keys);
+    }
+
+    fn add_output(&mut self, to: TransparentAddress, value: Amount) {
+        self.parts.outputs.push((to, value));
+    }
+
+    fn set_expiry(&mut self, expiry_height: Option<u32>) -> Result<(), String> {
+        self.parts.expiry_height = expiry_height.unwrap_or(0);
+        Ok(())
+    }
+
+    fn set_lock_time(&mut self, lock_time: LockTime) -> Result<(), String> {
+        self.parts.lock_time = lock_time.to_u32()?;
+        Ok(())
+    }
+
+    fn set_consensus_branch_id(&mut self, consensus_branch_id: Option<u32>) -> Result<(), String> {
+        self.parts.consensus_branch_id = consensus_branch_id;
+        Ok(())
+    }
+
+    fn sighash(&self, index: usize) -> Result<[u8; 32], String> {
+        self.parts.sighash(&self.params, self.height, self.format(), index)
+    }
+
+    fn add_signature(&mut self, index: usize, pubkey: PublicKey, signature: Vec<u8>) -> Result<(), String> {
+        self.parts.add_signature(index, pubkey, signature)
+    }
+
+    fn set_dust_threshold(&mut self, dust_threshold: u64) {
+        self.parts.dust_threshold = dust_threshold;
+    }
+
+    fn set_dust_change(&mut self, dust_change: DustChange) {
+        self.parts.dust_change = dust_change;
+    }
+
+    fn set_replaceable(&mut self, replaceable: bool) {
+        self.parts.set_replaceable(replaceable);
+    }
+
+    fn set_canonical_order(&mut self, canonical_order: bool) {
+        self.parts.canonical_order = canonical_order;
+    }
+
+    fn estimate(&self, fee_policy: &FeePolicy) -> TxEstimate {
+        estimate_transparent(self.format(), &self.parts, fee_policy)
+    }
+
+    fn bump_fee(
+        &mut self,
+        original: &BuiltTransaction,
+        new_fee: Amount,
+        change_index: usize,
+    ) -> Result<BuiltTransaction, String> {
+        let format = self.format();
+        bump_fee(&self.params, self.height, format, &mut self.parts, original, new_fee, change_index)
+    }
+
+    fn build(&self) -> Result<BuiltTransaction, String> {
+        build_transparent(&self.params, self.height, self.format(), &self.parts)
+    }
+}
+
+/// What to do with change that would be below the dust threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DustChange {
+    /// Refuse to build
+    Reject,
+    /// Leave the change output out, so what it held goes to the fee
+    AddToFee,
+}
+
+/// What a transparent transaction is made of, whatever its format
+#[derive(Clone, Debug)]
+pub(crate) struct TransparentParts {
+    pub inputs: Vec<(OutPoint, TxOut, TransparentInputKeys)>,
+    /// nSequence of each input, in order
+    pub sequences: Vec<u32>,
+    pub outputs: Vec<(TransparentAddress, Amount)>,
+    /// nLockTime, 0 for none
+    pub lock_time: u32,
+    /// 0 for no expiry
+    pub expiry_height: u32,
+    /// Every input is signed with this
+    pub sighash_type: u32,
+    /// Overrides the branch id for the builder's height in the sighash
+    pub consensus_branch_id: Option<u32>,
+    /// Outputs paying less than this are refused as dust
+    pub dust_threshold: u64,
+    /// What `bump_fee` does with change that would become dust
+    pub dust_change: DustChange,
+    /// Inputs get `REPLACEABLE_SEQUENCE` rather than `DEFAULT_SEQUENCE`
+    pub replaceable: bool,
+    /// Signed and serialized in BIP 69 order rather than the order they were added
+    pub canonical_order: bool,
+}
+
+impl Default for TransparentParts {
+    fn default() -> Self {
+        TransparentParts {
+            inputs: vec![],
+            sequences: vec![],
+            outputs: vec![],
+            lock_time: 0,
+            expiry_height: 0,
+            sighash_type: SIGHASH_ALL,
+            consensus_branch_id: None,
+            dust_threshold: DUST_THRESHOLD,
+            dust_change: DustChange::Reject,
+            replaceable: false,
+            canonical_order: false,
+        }
+    }
+}
+
+impl TransparentParts {
+    /// `inputs` to `outputs` with the default sequences, no expiry and SIGHASH_ALL
+    pub fn new(inputs: Vec<(OutPoint, TxOut, TransparentInputKeys)>, outputs: Vec<(TransparentAddress, Amount)>) -> Self {
+        let sequences = vec![DEFAULT_SEQUENCE; inputs.len()];
+        TransparentParts { inputs, sequences, outputs, ..TransparentParts::default() }
+    }
+
+    fn add_input(&mut self, outpoint: OutPoint, coin: TxOut, keys: TransparentInputKeys) {
+        self.inputs.push((outpoint, coin, keys));
+        self.sequences.push(self.sequence());
+    }
+
+    fn sequence(&self) -> u32 {
+        if self.replaceable {
+            REPLACEABLE_SEQUENCE
+        } else {
+            DEFAULT_SEQUENCE
+        }
+    }
+
+    fn set_replaceable(&mut self, replaceable: bool) {
+        self.replaceable = replaceable;
+        self.sequences = vec![self.sequence(); self.inputs.len()];
+    }
+
+    /// BIP 125: any input with a sequence below `DEFAULT_SEQUENCE` opts the transaction in
+    fn signals_replaceable(&self) -> bool {
+        self.sequences.iter().any(|sequence| *sequence < DEFAULT_SEQUENCE)
+    }
+
+    /// These parts as they're signed: in BIP 69 order if `canonical_order` is set
+    fn ordered(&self) -> Cow<'_, TransparentParts> {
+        if !self.canonical_order {
+            return Cow::Borrowed(self);
+        }
+        let mut sorted = self.clone();
+        sort_bip69(&mut sorted.inputs, &mut sorted.sequences, &mut sorted.outputs);
+        Cow::Owned(sorted)
+    }
+
+    fn sighash<P: Parameters>(
+        &self,
+        params: &P,
+        height: BlockHeight,
+        format: TransparentTxFormat,
+        index: usize,
+    ) -> Result<[u8; 32], String> {
+        let (outpoint, coin, keys) = self
+            .inputs
+            .get(index)
+            .ok_or_else(|| format!("No input {} ({} inputs)", index, self.inputs.len()))?;
+        // Signed where sorting put it
+        let ordered = self.ordered();
+        let signed_index = ordered
+            .inputs
+            .iter()
+            .position(|(o, _, _)| o == outpoint)
+            .expect("sorting keeps every input");
+        let script_code = keys.script_code(&coin.script_pubkey);
+        input_sighash(params, height, format, &ordered, signed_index, script_code, coin.value)
+    }
+
+    fn add_signature(&mut self, index: usize, pubkey: PublicKey, signature: Vec<u8>) -> Result<(), String> {
+        let inputs = self.inputs.len();
+        let (_, _, keys) = self
+            .inputs
+            .get_mut(index)
+            .ok_or_else(|| format!("No input {} ({} inputs)", index, inputs))?;
+        keys.add_signer(ScriptSigner::Signature { pubkey, signature })
+    }
+}
+
+/// Rebuild `original` from `parts` with `new_fee`, taking the extra from output `change_index`,
+/// and keep the bumped outputs in `parts` if it builds
+pub(crate) fn bump_fee<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+    parts: &mut TransparentParts,
+    original: &BuiltTransaction,
+    new_fee: Amount,
+    change_index: usize,
+) -> Result<BuiltTransaction, String> {
+    // Signing is deterministic, so the same parts give the same transaction
+    if build_transparent(params, height, format, parts)?.txid != original.txid {
+        return Err(format!("Transaction {} wasn't built from these inputs and outputs", original.txid));
+    }
+    if !parts.signals_replaceable() {
+        return Err(format!(
+            "Transaction {} doesn't signal it can be replaced, so nodes won't take a higher fee version",
+            original.txid
+        ));
+    }
+
+    let extra = i64::from(new_fee) - i64::from(original.fee);
+    if extra <= 0 {
+        return Err(format!(
+            "The new fee of {} zats has to be more than the {} zats already paid",
+            i64::from(new_fee),
+            i64::from(original.fee)
+        ));
+    }
+
+    let (change_addr, change) = parts
+        .outputs
+        .get(change_index)
+        .cloned()
+        .ok_or_else(|| format!("No output {} ({} outputs)", change_index, parts.outputs.len()))?;
+    let reduced = i64::from(change) - extra;
+    let mut bumped = parts.clone();
+    if reduced >= parts.dust_threshold as i64 {
+        bumped.outputs[change_index] = (change_addr, Amount::from_i64(reduced).expect("less than the change"));
+    } else if reduced >= 0 && parts.dust_change == DustChange::AddToFee {
+        // Too little left to be worth an output, so all of it goes to the fee
+        bumped.outputs.remove(change_index);
+    } else {
+        return Err(format!(
+            "Change of {} zats can't pay {} zats more in fees and stay above the dust threshold",
+            i64::from(change),
+            extra
+        ));
+    }
+    let built = build_transparent(params, height, format, &bumped)?;
+    *parts = bumped;
+    Ok(built)
+}
+
+/// Sign and serialize `parts` in `format`
+pub(crate) fn build_transparent<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+    parts: &TransparentParts,
+) -> Result<BuiltTransaction, String> {
+    let ordered = parts.ordered();
+    let parts = &*ordered;
+    if format == TransparentTxFormat::V1 && parts.expiry_height != 0 {
+        return Err("v1 transactions can't have an expiry height".to_string());
+    }
+    // The node ignores the lock time when every input is final
+    if parts.lock_time != 0 && !parts.inputs.is_empty() && parts.sequences.iter().all(|s| *s == u32::MAX) {
+        return Err(format!(
+            "Lock time {} wouldn't be enforced, since every input's sequence is final",
+            parts.lock_time
+        ));
+    }
+    for (_, amount) in &parts.outputs {
+        check_dust(*amount, parts.dust_threshold)?;
+    }
+
+    let fee = transparent_fee(
+        parts.inputs.iter().map(|(_, coin, _)| coin.value),
+        parts.outputs.iter().map(|(_, amount)| *amount),
+    )?;
+    let tx_type = detect_tx_type(parts.inputs.len(), 0, parts.outputs.len(), 0);
+    check_size(max_serialized_size(format, parts))?;
+
+    let script_sigs = sign_inputs(params, height, format, parts)?;
+    let raw = serialize(format, parts, &script_sigs);
+    check_fee(fee, raw.len())?;
+
+    Ok(BuiltTransaction::new(raw, fee, tx_type, parts.expiry_height))
+}
+
+/// `parts`' size in `format` with every scriptSig at its largest, broken down by input,
+/// and the fee `fee_policy` charges for it
+fn estimate_transparent(format: TransparentTxFormat, parts: &TransparentParts, fee_policy: &FeePolicy) -> TxEstimate {
+    let size = max_serialized_size(format, parts);
+    let transparent_input_sizes = parts
+        .inputs
+        .iter()
+        .map(|(_, _, keys)| transparent_input_size(keys.max_script_sig_size()))
+        .collect();
+    TxEstimate { size, fee: fee_policy.fee_for_size(size), transparent_input_sizes }
+}
+
+/// The most `parts` can serialize to in `format`, with every scriptSig at its largest
+fn max_serialized_size(format: TransparentTxFormat, parts: &TransparentParts) -> usize {
+    let script_sigs = parts
+        .inputs
+        .iter()
+        .map(|(_, _, keys)| vec![0u8; keys.max_script_sig_size()])
+        .collect::<Vec<_>>();
+    serialize(format, parts, &script_sigs).len()
+}
+
+/// The scriptSig of every input of `parts`, in order
+pub(crate) fn sign_inputs<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+    parts: &TransparentParts,
+) -> Result<Vec<Vec<u8>>, String> {
+    validate_sighash_type(parts.sighash_type)?;
+
+    if parts.sequences.len() != parts.inputs.len() {
+        return Err(format!(
+            "Got {} sequences for {} inputs",
+            parts.sequences.len(),
+            parts.inputs.len()
+        ));
+    }
+
+    // SIGHASH_SINGLE needs an output at every input's index
+    if (parts.sighash_type & 0x1f) == SIGHASH_SINGLE && parts.inputs.len() > parts.outputs.len() {
+        return Err(format!(
+            "SIGHASH_SINGLE requires an output for every input ({} inputs, {} outputs)",
+            parts.inputs.len(),
+            parts.outputs.len()
+        ));
+    }
+
+    // Every sighash first, so each external signer is asked only once
+    let sighashes = parts
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(index, (_, coin, keys))| {
+            // P2SH inputs sign over their redeem script
+            let script_code = keys.script_code(&coin.script_pubkey);
+            let sighash = input_sighash(params, height, format, parts, index, script_code, coin.value)?;
+            Ok((keys, sighash))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    script_sigs(&sighashes, parts.sighash_type)
+}
+
+/// The sighash input `index` of `parts` signs in `format`
+pub(crate) fn input_sighash<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+    parts: &TransparentParts,
+    index: usize,
+    script_code: &Script,
+    value: Amount,
+) -> Result<[u8; 32], String> {
+    match format {
+        TransparentTxFormat::V1 => legacy_sighash(parts, index, script_code),
+        TransparentTxFormat::Overwinter | TransparentTxFormat::Sapling => {
+            zip143_sighash(params, height, format, parts, index, script_code, value, &SaplingDigests::default())
+        }
+    }
+}
+
+/// The legacy sighash: double SHA-256 of the transaction with the script code as the
+/// signed input's scriptSig, every other scriptSig empty, and the hash type appended
+fn legacy_sighash(parts: &TransparentParts, index: usize, script_code: &Script) -> Result<[u8; 32], String> {
+    // Only SIGHASH_ALL leaves the rest of the transaction as it is
+    if parts.sighash_type != SIGHASH_ALL {
+        return Err(format!(
+            "v1 transactions are only signed with SIGHASH_ALL, not {:#x}",
+            parts.sighash_type
+        ));
+    }
+
+    let script_sigs = (0..parts.inputs.len())
+        .map(|i| if i == index { script_code.0.clone() } else { vec![] })
+        .collect::<Vec<_>>();
+    let mut data = serialize(TransparentTxFormat::V1, parts, &script_sigs);
+    data.extend_from_slice(&parts.sighash_type.to_le_bytes());
+
+    let mut sighash = [0u8; 32];
+    sighash.copy_from_slice(&Sha256::digest(&Sha256::digest(&data)));
+    Ok(sighash)
+}
+
+/// The Sapling parts of a ZIP-243 sighash: the hashes of the shielded spends and outputs
+/// (zeros when there are none) and the value balance. The default is a transaction with
+/// nothing shielded.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SaplingDigests {
+    pub spends: [u8; 32],
+    pub outputs: [u8; 32],
+    pub value_balance: i64,
+}
+
+/// The ZIP-143 sighash, or for v4 the ZIP-243 one, which adds the shielded spends and
+/// outputs and the value balance. The shielded builder signs its transparent inputs
+/// with this too.
+pub(crate) fn zip143_sighash<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    format: TransparentTxFormat,
+    parts: &TransparentParts,
+    index: usize,
+    script_code: &Script,
+    value: Amount,
+    sapling: &SaplingDigests,
+) -> Result<[u8; 32], String> {
+    let anyone_can_pay = (parts.sighash_type & SIGHASH_ANYONECANPAY) != 0;
+    let base_type = parts.sighash_type & 0x1f;
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&(OVERWINTER_FLAG | format.version()).to_le_bytes());
+    data.extend_from_slice(&BITCOINZ_VERSION_GROUP_ID.to_le_bytes());
+
+    // Prevouts, unless ANYONECANPAY
+    if anyone_can_pay {
+        data.extend_from_slice(&[0u8; 32]);
+    } else {
+        let mut prevouts = Vec::new();
+        for (outpoint, _, _) in &parts.inputs {
+            write_outpoint(&mut prevouts, outpoint);
+        }
+        data.extend_from_slice(&blake2b(ZCASH_PREVOUTS_HASH_PERSONALIZATION, &prevouts));
+    }
+
+    // Sequences, unless ANYONECANPAY, SINGLE or NONE
+    if anyone_can_pay || base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+        data.extend_from_slice(&[0u8; 32]);
+    } else {
+        let sequences = parts.sequences.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>();
+        data.extend_from_slice(&blake2b(ZCASH_SEQUENCE_HASH_PERSONALIZATION, &sequences));
+    }
+
+    // Outputs: all of them, only the one at this input's index for SINGLE, none for NONE
+    let signed_outputs = match base_type {
+        SIGHASH_NONE => None,
+        SIGHASH_SINGLE => match parts.outputs.get(index) {
+            Some(output) => Some(std::slice::from_ref(output)),
+            None => {
+                return Err(format!(
+                    "SIGHASH_SINGLE: no output matching input {} ({} outputs)",
+                    index,
+                    parts.outputs.len()
+                ))
+            }
+        },
+        _ => Some(&parts.outputs[..]),
+    };
+    match signed_outputs {
+        Some(outputs) => {
+            let mut serialized = Vec::new();
+            for (to, amount) in outputs {
+                write_amount(&mut serialized, *amount);
+                write_script(&mut serialized, &to.script());
+            }
+            data.extend_from_slice(&blake2b(ZCASH_OUTPUTS_HASH_PERSONALIZATION, &serialized));
+        }
+        None => data.extend_from_slice(&[0u8; 32]),
+    }
+
+    // No joinsplits, and for v4 the shielded spends and outputs
+    data.extend_from_slice(&[0u8; 32]);
+    if format == TransparentTxFormat::Sapling {
+        data.extend_from_slice(&sapling.spends);
+        data.extend_from_slice(&sapling.outputs);
+    }
+
+    data.extend_from_slice(&parts.lock_time.to_le_bytes());
+    data.extend_from_slice(&parts.expiry_height.to_le_bytes());
+    if format == TransparentTxFormat::Sapling {
+        data.extend_from_slice(&sapling.value_balance.to_le_bytes());
+    }
+    data.extend_from_slice(&parts.sighash_type.to_le_bytes());
+
+    // The input being signed, which is there even under ANYONECANPAY
+    let (outpoint, _, _) = &parts.inputs[index];
+    write_outpoint(&mut data, outpoint);
+    write_script(&mut data, script_code);
+    write_amount(&mut data, value);
+    data.extend_from_slice(&parts.sequences[index].to_le_bytes());
+
+    let consensus_branch_id = parts
+        .consensus_branch_id
+        .unwrap_or_else(|| bitcoinz_consensus_branch_id(params, height));
+    let mut state = sighash_state(consensus_branch_id);
+    state.update(&data);
+    Ok(finalize_sighash(state, REVERSE_TRANSPARENT_SIGHASH))
+}
+
+fn blake2b(personalization: &[u8; 16], data: &[u8]) -> [u8; 32] {
+    let hash = Params::new().hash_length(32).personal(personalization).hash(data);
+    let mut result = [0u8; 32];
+    result.copy_from_slice(hash.as_bytes());
+    result
+}
+
+/// Serialize `parts` in `format` with the given scriptSigs
+fn serialize(format: TransparentTxFormat, parts: &TransparentParts, script_sigs: &[Vec<u8>]) -> Vec<u8> {
+    let mut tx_data = Vec::new();
+
+    match format {
+        TransparentTxFormat::V1 => tx_data.extend_from_slice(&1u32.to_le_bytes()),
+        TransparentTxFormat::Overwinter | TransparentTxFormat::Sapling => {
+            tx_data.extend_from_slice(&(OVERWINTER_FLAG | format.version()).to_le_bytes());
+            tx_data.extend_from_slice(&BITCOINZ_VERSION_GROUP_ID.to_le_bytes());
+        }
+    }
+
+    write_compact_size(&mut tx_data, parts.inputs.len() as u64);
+    for (((outpoint, _, _), script_sig), sequence) in parts.inputs.iter().zip(script_sigs).zip(&parts.sequences) {
+        write_outpoint(&mut tx_data, outpoint);
+        write_compact_size(&mut tx_data, script_sig.len() as u64);
+        tx_data.extend_from_slice(script_sig);
+        tx_data.extend_from_slice(&sequence.to_le_bytes());
+    }
+
+    write_compact_size(&mut tx_data, parts.outputs.len() as u64);
+    for (to, amount) in &parts.outputs {
+        write_amount(&mut tx_data, *amount);
+        write_script(&mut tx_data, &to.script());
+    }
+
+    tx_data.extend_from_slice(&parts.lock_time.to_le_bytes());
+
+    match format {
+        TransparentTxFormat::V1 => {}
+        TransparentTxFormat::Overwinter => {
+            tx_data.extend_from_slice(&parts.expiry_height.to_le_bytes());
+            write_compact_size(&mut tx_data, 0); // joinsplits
+        }
+        TransparentTxFormat::Sapling => {
+            tx_data.extend_from_slice(&parts.expiry_height.to_le_bytes());
+            tx_data.extend_from_slice(&0i64.to_le_bytes()); // value balance
+            write_compact_size(&mut tx_data, 0); // shielded spends
+            write_compact_size(&mut tx_data, 0); // shielded outputs
+            write_compact_size(&mut tx_data, 0); // joinsplits
+            // and no binding signature, since there's nothing shielded
+        }
+    }
+
+    tx_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_branch::StagedUpgradesNetwork;
+    use crate::BITCOINZ_MAINNET;
+    use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+
+    fn fill(builder: &mut dyn TransparentTxBuilder) -> PublicKey {
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        builder.add_input(
+            OutPoint::new([0x11u8; 32], 0),
+            TxOut {
+                value: Amount::from_u64(100_000).unwrap(),
+                script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+            },
+            TransparentInputKeys::PubKeyHash(sk),
+        );
+        builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap());
+        PublicKey::from_secret_key(&Secp256k1::new(), &sk)
+    }
+
+    #[test]
+    fn test_select_format() {
+        let format = |height: u32| select_format(&StagedUpgradesNetwork, BlockHeight::from(height), FormatPolicy::Current);
+        assert_eq!(format(StagedUpgradesNetwork::OVERWINTER - 1), TransparentTxFormat::V1);
+        assert_eq!(format(StagedUpgradesNetwork::OVERWINTER), TransparentTxFormat::Overwinter);
+        assert_eq!(format(StagedUpgradesNetwork::SAPLING), TransparentTxFormat::Sapling);
+        assert_eq!(format(StagedUpgradesNetwork::CANOPY), TransparentTxFormat::Sapling);
+
+        // Overwinter and Sapling activate together on mainnet
+        assert_eq!(
+            select_format(&BITCOINZ_MAINNET, BlockHeight::from(328_500), FormatPolicy::Current),
+            TransparentTxFormat::Sapling
+        );
+        assert_eq!(
+            select_format(
+                &BITCOINZ_MAINNET,
+                BlockHeight::from(500_000),
+                FormatPolicy::Force(TransparentTxFormat::Overwinter)
+            ),
+            TransparentTxFormat::Overwinter
+        );
+    }
+
+    #[test]
+    fn test_every_format() {
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            assert_eq!(builder.format(), format);
+            fill(builder.as_mut());
+
+            let built = builder.build().unwrap();
+            assert_eq!(built.fee, Amount::from_u64(10_000).unwrap());
+            assert_eq!(built.size, built.raw.len());
+
+            let header = u32::from_le_bytes([built.raw[0], built.raw[1], built.raw[2], built.raw[3]]);
+            match format {
+                TransparentTxFormat::V1 => assert_eq!(header, 1),
+                _ => assert_eq!(header, OVERWINTER_FLAG | format.version()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_disproportionate_fee() {
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(200_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+        };
+
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            // 110,000 for a one-input, one-output transaction
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            builder.add_input(OutPoint::new([0x11u8; 32], 0), coin.clone(), TransparentInputKeys::PubKeyHash(sk));
+            builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap());
+            let err = builder.build().unwrap_err();
+            assert!(err.contains("absurdly high"), "{}", err);
+
+            builder.add_output(TransparentAddress::PublicKey([0x66u8; 20]), Amount::from_u64(100_000).unwrap());
+            assert_eq!(builder.build().unwrap().fee, Amount::from_u64(10_000).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_dust_output() {
+        use crate::bitcoinz_parse::BitcoinZTxError;
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+        };
+        let with_change = |format: TransparentTxFormat, change: u64| {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            builder.add_input(OutPoint::new([0x11u8; 32], 0), coin.clone(), TransparentInputKeys::PubKeyHash(sk));
+            builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(80_000).unwrap());
+            builder.add_output(TransparentAddress::PublicKey([0x66u8; 20]), Amount::from_u64(change).unwrap());
+            builder
+        };
+
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let err = with_change(format, DUST_THRESHOLD - 1).build().unwrap_err();
+            assert_eq!(err, String::from(BitcoinZTxError::DustOutput { amount: DUST_THRESHOLD - 1 }));
+            assert!(with_change(format, DUST_THRESHOLD).build().is_ok());
+
+            let mut builder = with_change(format, DUST_THRESHOLD - 1);
+            builder.set_dust_threshold(DUST_THRESHOLD - 1);
+            assert!(builder.build().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_sweep() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use crate::blaze::test_utils::FakeTxProver;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let coins = |values: &[u64]| {
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let coin = TxOut {
+                        value: Amount::from_u64(*value).unwrap(),
+                        script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                    };
+                    (OutPoint::new([0x11u8; 32], i as u32), coin, sk)
+                })
+                .collect::<Vec<_>>()
+        };
+        let extfvk = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[1u8; 32]));
+        let sweep = |values: &[u64], destination: AddressKind| {
+            build_sweep_tx(
+                BITCOINZ_MAINNET,
+                coins(values),
+                destination,
+                &FeePolicy::default(),
+                BlockHeight::from(500_000),
+                Some(extfvk.fvk.ovk),
+                &FakeTxProver {},
+            )
+        };
+        let taddr = AddressKind::Transparent(TransparentAddress::PublicKey([0x55u8; 20]));
+
+        // Every coin goes into the one output, less the fee for the size
+        let built = sweep(&[30_000, 50_000, 70_000], taddr.clone()).unwrap();
+        let parsed = parse_bitcoinz_v4(&built.raw).unwrap();
+        assert_eq!(parsed.inputs.len(), 3);
+        assert_eq!(parsed.outputs.len(), 1);
+        assert_eq!(built.fee, FeePolicy::default().fee_for_size(built.size));
+        assert_eq!(i64::from(parsed.outputs[0].value), 150_000 - i64::from(built.fee));
+
+        // Or into one Sapling output
+        let zaddr = AddressKind::Sapling(extfvk.default_address().1);
+        let built = sweep(&[30_000, 50_000, 70_000], zaddr).unwrap();
+        let parsed = parse_bitcoinz_v4(&built.raw).unwrap();
+        assert_eq!(parsed.inputs.len(), 3);
+        assert!(parsed.outputs.is_empty());
+        assert_eq!(parsed.shielded_outputs.len(), 1);
+        assert_eq!(i64::from(parsed.value_balance), i64::from(built.fee) - 150_000);
+
+        // The fee is 10,000 zatoshis, which these leave nothing worth sending after
+        let err = sweep(&[10_000 + DUST_THRESHOLD - 1], taddr.clone()).unwrap_err();
+        assert!(err.contains("dust"), "{}", err);
+        assert!(sweep(&[10_000 + DUST_THRESHOLD], taddr.clone()).is_ok());
+        assert!(sweep(&[], taddr).is_err());
+    }
+
+    #[test]
+    fn test_estimate_multisig_input() {
+        use crate::bitcoinz_script_sig::{hash160, multisig_redeem_script};
+
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..=3u8).map(|i| SecretKey::from_slice(&[i; 32]).unwrap()).collect();
+        let pubkeys: Vec<_> = sks.iter().map(|sk| PublicKey::from_secret_key(&secp, sk)).collect();
+        let redeem_script = multisig_redeem_script(2, &pubkeys).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::Script(hash160(&redeem_script.0)).script(),
+        };
+
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            fill(builder.as_mut());
+            let keys = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sks[0], sks[2]]).unwrap();
+            builder.add_input(OutPoint::new([0x11u8; 32], 1), coin.clone(), keys);
+            builder.add_output(TransparentAddress::Script([0x66u8; 20]), Amount::from_u64(90_000).unwrap());
+
+            // The P2PKH input, then OP_0, two signatures and the 105-byte 2-of-3 script, a
+            // 256-byte scriptSig that needs a 3-byte length
+            let estimate = builder.estimate(&FeePolicy::default());
+            assert_eq!(redeem_script.0.len(), 105);
+            assert_eq!(estimate.transparent_input_sizes, vec![36 + 1 + 108 + 4, 36 + 3 + (1 + 2 * 74 + 2 + 105) + 4]);
+            // The version group ID and the fields after the lock time
+            let format_fields = match format {
+                TransparentTxFormat::V1 => 0,
+                TransparentTxFormat::Overwinter => 4 + 4 + 1,
+                TransparentTxFormat::Sapling => 4 + 4 + 8 + 3,
+            };
+            assert_eq!(estimate.size, 4 + 1 + 149 + 299 + 1 + 34 + 32 + 4 + format_fields);
+            assert_eq!(estimate.fee, FeePolicy::default().fee_for_size(estimate.size));
+
+            // Only the DER signature lengths, and so maybe the scriptSig's length prefix,
+            // can come in under it
+            let built = builder.build().unwrap();
+            assert!(estimate.size >= built.size);
+            assert!(estimate.size - built.size <= 3 * 2 + 2, "estimate {} vs actual {}", estimate.size, built.size);
+        }
+    }
+
+    #[test]
+    fn test_oversized_transaction() {
+        use crate::bitcoinz_transaction::MAX_STANDARD_TX_SIZE;
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let consolidate = |format: TransparentTxFormat, inputs: u32| {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            for i in 0..inputs {
+                let coin = TxOut {
+                    value: Amount::from_u64(10_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                };
+                builder.add_input(OutPoint::new([0x11u8; 32], i), coin, TransparentInputKeys::PubKeyHash(sk));
+            }
+            let value = inputs as u64 * 10_000 - 1_000_000;
+            builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(value).unwrap());
+            builder.build()
+        };
+
+        // 148 bytes an input: 600 fit in 100 kB, 700 don't, and it's refused before signing
+        assert!(consolidate(TransparentTxFormat::Sapling, 600).unwrap().size <= MAX_STANDARD_TX_SIZE);
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let err = consolidate(format, 700).unwrap_err();
+            assert!(err.contains("byte limit"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let inputs = (0..3u8)
+            .map(|i| {
+                let coin = TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                };
+                (OutPoint::new([0x10 * (i + 1); 32], i as u32), coin)
+            })
+            .collect::<Vec<_>>();
+        let outputs = vec![
+            (TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(200_000).unwrap()),
+            (TransparentAddress::PublicKey([0x66u8; 20]), Amount::from_u64(90_000).unwrap()),
+        ];
+        let build = |reversed: bool, canonical_order: bool| {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), TransparentTxFormat::Sapling);
+            builder.set_canonical_order(canonical_order);
+            let (mut inputs, mut outputs) = (inputs.clone(), outputs.clone());
+            if reversed {
+                inputs.reverse();
+                outputs.reverse();
+            }
+            for (outpoint, coin) in inputs {
+                builder.add_input(outpoint, coin, TransparentInputKeys::PubKeyHash(sk));
+            }
+            for (to, value) in outputs {
+                builder.add_output(to, value);
+            }
+            builder
+        };
+
+        // Any order in, the same transaction out
+        let forward = build(false, true);
+        let backward = build(true, true);
+        let built = forward.build().unwrap();
+        assert_eq!(built.raw, backward.build().unwrap().raw);
+        assert_ne!(build(false, false).build().unwrap().raw, build(true, false).build().unwrap().raw);
+
+        // Smallest output first, and `sighash` still counts inputs in the order they were added
+        let parsed = parse_bitcoinz_v4(&built.raw).unwrap();
+        assert_eq!(parsed.outputs[0].value, Amount::from_u64(90_000).unwrap());
+        assert_eq!(forward.sighash(0).unwrap(), backward.sighash(2).unwrap());
+        assert_ne!(forward.sighash(0).unwrap(), build(false, false).sighash(0).unwrap());
+    }
+
+    #[test]
+    fn test_lock_time() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use std::convert::TryInto;
+
+        let lock_time_of = |format: TransparentTxFormat, tx: &[u8]| match format {
+            TransparentTxFormat::V1 => u32::from_le_bytes(tx[tx.len() - 4..].try_into().unwrap()),
+            TransparentTxFormat::Overwinter => u32::from_le_bytes(tx[tx.len() - 9..tx.len() - 5].try_into().unwrap()),
+            TransparentTxFormat::Sapling => parse_bitcoinz_v4(tx).unwrap().lock_time,
+        };
+
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            fill(builder.as_mut());
+            let unlocked = builder.sighash(0).unwrap();
+            assert_eq!(lock_time_of(format, &builder.build().unwrap().raw), 0);
+
+            // Written to the transaction, and signed
+            builder.set_lock_time(LockTime::Height(600_000)).unwrap();
+            assert_eq!(lock_time_of(format, &builder.build().unwrap().raw), 600_000);
+            let locked = builder.sighash(0).unwrap();
+            assert_ne!(locked, unlocked);
+
+            builder.set_lock_time(LockTime::Time(1_700_000_000)).unwrap();
+            assert_eq!(lock_time_of(format, &builder.build().unwrap().raw), 1_700_000_000);
+            assert_ne!(builder.sighash(0).unwrap(), locked);
+
+            builder.set_lock_time(LockTime::Height(0)).unwrap();
+            assert_eq!(builder.sighash(0).unwrap(), unlocked);
+        }
+
+        // A height or time the node would read as the other kind
+        assert!(LockTime::Height(LOCKTIME_THRESHOLD).to_u32().is_err());
+        assert!(LockTime::Time(LOCKTIME_THRESHOLD - 1).to_u32().is_err());
+        assert_eq!(LockTime::Time(LOCKTIME_THRESHOLD).to_u32(), Ok(LOCKTIME_THRESHOLD));
+    }
+
+    #[test]
+    fn test_lock_time_needs_a_non_final_input() {
+        let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut builder);
+        builder.set_lock_time(LockTime::Height(600_000)).unwrap();
+        assert!(DEFAULT_SEQUENCE < u32::MAX);
+        assert!(builder.build().is_ok());
+
+        // With every sequence final, the lock time would be ignored
+        let mut parts = builder.parts.clone();
+        parts.sequences = vec![u32::MAX; parts.inputs.len()];
+        let err = build_transparent(&BITCOINZ_MAINNET, BlockHeight::from(500_000), TransparentTxFormat::Sapling, &parts)
+            .unwrap_err();
+        assert!(err.contains("final"), "{}", err);
+
+        parts.lock_time = 0;
+        assert!(build_transparent(&BITCOINZ_MAINNET, BlockHeight::from(500_000), TransparentTxFormat::Sapling, &parts).is_ok());
+    }
+
+    #[test]
+    fn test_bump_fee() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use std::convert::TryInto;
+
+        let change_addr = TransparentAddress::PublicKey([0x66u8; 20]);
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            // 100,000 in, 60,000 out, 30,000 change and a 10,000 fee
+            let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            builder.set_replaceable(true);
+            let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+            builder.add_input(
+                OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                TransparentInputKeys::PubKeyHash(sk),
+            );
+            builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(60_000).unwrap());
+            builder.add_output(change_addr.clone(), Amount::from_u64(30_000).unwrap());
+            let original = builder.build().unwrap();
+
+            // Not for less, or change that can't cover the difference
+            assert!(builder.bump_fee(&original, Amount::from_u64(10_000).unwrap(), 1).is_err());
+            let err = builder.bump_fee(&original, Amount::from_u64(40_000 - DUST_THRESHOLD + 1).unwrap(), 1).unwrap_err();
+            assert!(err.contains("dust"), "{}", err);
+
+            let bumped = builder.bump_fee(&original, Amount::from_u64(15_000).unwrap(), 1).unwrap();
+            assert_eq!(bumped.fee, Amount::from_u64(15_000).unwrap());
+            assert_ne!(bumped.txid, original.txid);
+
+            if format == TransparentTxFormat::Sapling {
+                let (before, after) = (parse_bitcoinz_v4(&original.raw).unwrap(), parse_bitcoinz_v4(&bumped.raw).unwrap());
+                assert_eq!(before.inputs.len(), after.inputs.len());
+                for (a, b) in before.inputs.iter().zip(&after.inputs) {
+                    assert_eq!((a.prevout_hash, a.prevout_index), (b.prevout_hash, b.prevout_index));
+                    assert_eq!(b.sequence, REPLACEABLE_SEQUENCE);
+                }
+                assert_eq!(after.outputs[0], before.outputs[0]);
+                assert_eq!(after.outputs[1].value, Amount::from_u64(25_000).unwrap());
+                assert_eq!(after.outputs[1].script_pubkey, change_addr.script());
+            } else {
+                // The same outpoint after the header and input count, and the change as the
+                // last output, before the lock time (and for v3 the expiry and joinsplits)
+                let start = if format == TransparentTxFormat::V1 { 5 } else { 9 };
+                assert_eq!(original.raw[start..start + 36], bumped.raw[start..start + 36]);
+
+                let tail = if format == TransparentTxFormat::V1 { 4 } else { 9 };
+                let change_of = |tx: &[u8]| {
+                    let value = tx.len() - tail - 34;
+                    u64::from_le_bytes(tx[value..value + 8].try_into().unwrap())
+                };
+                assert_eq!(change_of(&original.raw), 30_000);
+                assert_eq!(change_of(&bumped.raw), 25_000);
+            }
+
+            // It can be bumped again, from the bumped transaction
+            assert!(builder.bump_fee(&original, Amount::from_u64(20_000).unwrap(), 1).is_err());
+            assert_eq!(
+                builder.bump_fee(&bumped, Amount::from_u64(20_000).unwrap(), 1).unwrap().fee,
+                Amount::from_u64(20_000).unwrap()
+            );
+        }
+
+        // Without opting in, there's nothing to replace
+        let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut builder);
+        builder.add_output(change_addr, Amount::from_u64(5_000).unwrap());
+        let original = builder.build().unwrap();
+        let err = builder.bump_fee(&original, Amount::from_u64(6_000).unwrap(), 1).unwrap_err();
+        assert!(err.contains("replaced"), "{}", err);
+    }
+
+    #[test]
+    fn test_bump_fee_dust_change() {
+        let change_addr = TransparentAddress::PublicKey([0x66u8; 20]);
+        let build = |dust_change| {
+            // 100,000 in, 60,000 out, 30,000 change and a 10,000 fee
+            let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+            builder.set_replaceable(true);
+            builder.set_dust_change(dust_change);
+            builder.add_input(
+                OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                TransparentInputKeys::PubKeyHash(SecretKey::from_slice(&[0x01u8; 32]).unwrap()),
+            );
+            builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(60_000).unwrap());
+            builder.add_output(change_addr.clone(), Amount::from_u64(30_000).unwrap());
+            let original = builder.build().unwrap();
+            (builder, original)
+        };
+        // What's left of the change once the fee is 40,000 - left
+        let fee_leaving = |left: u64| Amount::from_u64(40_000 - left).unwrap();
+
+        // Right at the threshold the change stays either way
+        for dust_change in [DustChange::Reject, DustChange::AddToFee] {
+            let (mut builder, original) = build(dust_change);
+            let bumped = builder.bump_fee(&original, fee_leaving(DUST_THRESHOLD), 1).unwrap();
+            assert_eq!(bumped.fee, fee_leaving(DUST_THRESHOLD));
+            assert_eq!(builder.parts.outputs.len(), 2);
+        }
+
+        // Below it, the change is refused or goes to the fee
+        let (mut builder, original) = build(DustChange::Reject);
+        assert!(builder.bump_fee(&original, fee_leaving(DUST_THRESHOLD - 1), 1).unwrap_err().contains("dust"));
+
+        let (mut builder, original) = build(DustChange::AddToFee);
+        let bumped = builder.bump_fee(&original, fee_leaving(DUST_THRESHOLD - 1), 1).unwrap();
+        assert_eq!(bumped.fee, Amount::from_u64(40_000).unwrap());
+        assert_eq!(builder.parts.outputs.len(), 1);
+        assert!(bumped.raw.len() < original.raw.len());
+
+        // Change that can't pay the extra at all is still refused
+        let (mut builder, original) = build(DustChange::AddToFee);
+        assert!(builder.bump_fee(&original, Amount::from_u64(40_001).unwrap(), 1).is_err());
+    }
+
+    #[test]
+    fn test_sapling_format_parses() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+
+        let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut builder);
+        builder.set_expiry(Some(500_020)).unwrap();
+
+        let parsed = parse_bitcoinz_v4(&builder.build().unwrap().raw).unwrap();
+        assert_eq!((parsed.inputs.len(), parsed.outputs.len()), (1, 1));
+        assert_eq!(parsed.expiry_height, 500_020);
+        assert_eq!(parsed.inputs[0].sequence, DEFAULT_SEQUENCE);
+        assert_eq!(parsed.binding_sig, None);
+    }
+
+    #[test]
+    fn test_expiry_offset_signed_and_serialized() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use std::convert::TryInto;
+
+        let height = BlockHeight::from(500_000);
+        assert_eq!(expiry_for_offset(height, 0), None);
+        assert_eq!(expiry_for_offset(height, 40), Some(500_040));
+
+        // The expiry is set once, on the parts that are both signed and serialized, so
+        // the signature always verifies against the expiry read back from the bytes
+        let check = |format: TransparentTxFormat, parts: &TransparentParts, pubkey: &PublicKey, tx: &[u8], expiry: u32| {
+            let serialized = match format {
+                TransparentTxFormat::Sapling => parse_bitcoinz_v4(tx).unwrap().expiry_height,
+                _ => u32::from_le_bytes(tx[tx.len() - 5..tx.len() - 1].try_into().unwrap()),
+            };
+            assert_eq!(serialized, expiry);
+
+            // header(4) + version group ID(4) + input count(1) + outpoint(36), then the scriptSig
+            let sig_len = tx[46] as usize;
+            let signature = Signature::from_der(&tx[47..46 + sig_len]).unwrap();
+
+            let mut signed = parts.clone();
+            signed.expiry_height = serialized;
+            let coin = &signed.inputs[0].1;
+            let sighash = input_sighash(&BITCOINZ_MAINNET, height, format, &signed, 0, &coin.script_pubkey, coin.value).unwrap();
+            let secp = Secp256k1::verification_only();
+            assert!(secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, pubkey).is_ok());
+        };
+
+        for offset in [0, 10, 40] {
+            let expiry = expiry_for_offset(height, offset);
+
+            let mut builder = OverwinterTxBuilder::new(BITCOINZ_MAINNET, height);
+            let pubkey = fill(&mut builder);
+            builder.set_expiry(expiry).unwrap();
+            let built = builder.build().unwrap();
+            assert_eq!(built.expiry_height, expiry.unwrap_or(0));
+            check(TransparentTxFormat::Overwinter, &builder.parts, &pubkey, &built.raw, expiry.unwrap_or(0));
+
+            let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, height);
+            let pubkey = fill(&mut builder);
+            builder.set_expiry(expiry).unwrap();
+            let built = builder.build().unwrap();
+            assert_eq!(built.expiry_height, expiry.unwrap_or(0));
+            check(TransparentTxFormat::Sapling, &builder.parts, &pubkey, &built.raw, expiry.unwrap_or(0));
+        }
+    }
+
+    #[test]
+    fn test_v1_signature() {
+        let mut builder = LegacyTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        let pubkey = fill(&mut builder);
+        let tx = builder.build().unwrap().raw;
+
+        // version(4) + input count(1) + outpoint(36), then the scriptSig
+        let script_len = tx[41] as usize;
+        let script_sig = &tx[42..42 + script_len];
+        let sig_len = script_sig[0] as usize;
+        assert_eq!(script_sig[sig_len], SIGHASH_ALL as u8);
+        let signature = Signature::from_der(&script_sig[1..sig_len]).unwrap();
+        assert_eq!(&script_sig[sig_len + 2..], &pubkey.serialize()[..]);
+
+        // The legacy preimage: the prevout's script in place of the scriptSig, and the
+        // hash type on the end
+        let script_pubkey = TransparentAddress::PublicKey([0x22u8; 20]).script().0;
+        let mut preimage = tx[..41].to_vec();
+        preimage.push(script_pubkey.len() as u8);
+        preimage.extend_from_slice(&script_pubkey);
+        preimage.extend_from_slice(&tx[42 + script_len..]);
+        preimage.extend_from_slice(&1u32.to_le_bytes());
+        let sighash = Sha256::digest(&Sha256::digest(&preimage));
+
+        let secp = Secp256k1::verification_only();
+        assert!(secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, &pubkey).is_ok());
+    }
+
+    #[test]
+    fn test_v1_multi_input_signatures() {
+        use crate::bitcoinz_parse::BitcoinZTransaction;
+        use crate::bitcoinz_script_sig::pubkey_hash_inputs;
+        use crate::testing::{assert_v1_signatures, three_pubkey_hash_coins};
+
+        // Compressed and uncompressed keys, so the scriptSigs before each input differ
+        // in length
+        let inputs = three_pubkey_hash_coins();
+        let parts = TransparentParts::new(
+            pubkey_hash_inputs(&inputs),
+            vec![(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(290_000).unwrap())],
+        );
+        let raw = build_transparent(&BITCOINZ_MAINNET, BlockHeight::from(500_000), TransparentTxFormat::V1, &parts)
+            .unwrap()
+            .raw;
+        let tx = BitcoinZTransaction::read(&raw).unwrap();
+
+        assert_v1_signatures(&tx, &inputs);
+    }
+
+    #[test]
+    fn test_uncompressed_key_input() {
+        use crate::bitcoinz_script_sig::{hash160, pubkey_hash_inputs};
+
+        // A coin paid to the uncompressed public key, as an old paper wallet would be
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::PublicKey(hash160(&pubkey.serialize_uncompressed())).script(),
+        };
+        let inputs = pubkey_hash_inputs(&[(OutPoint::new([0x11u8; 32], 0), coin.clone(), sk)]);
+        assert!(matches!(inputs[0].2, TransparentInputKeys::UncompressedPubKeyHash(_)));
+        let parts = TransparentParts::new(
+            inputs,
+            vec![(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap())],
+        );
+
+        let height = BlockHeight::from(500_000);
+        let secp = Secp256k1::verification_only();
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let tx = build_transparent(&BITCOINZ_MAINNET, height, format, &parts).unwrap().raw;
+
+            // The scriptSig follows the header, input count and outpoint
+            let start = if format == TransparentTxFormat::V1 { 41 } else { 45 };
+            let script_sig = &tx[start + 1..start + 1 + tx[start] as usize];
+            let sig_len = script_sig[0] as usize;
+            let signature = Signature::from_der(&script_sig[1..sig_len]).unwrap();
+
+            // What OP_DUP OP_HASH160 OP_EQUALVERIFY OP_CHECKSIG checks: the pushed key
+            // hashes to the prevout's, and signs the input's sighash
+            let pushed = &script_sig[sig_len + 2..];
+            assert_eq!(script_sig[sig_len + 1] as usize, 65);
+            assert_eq!(TransparentAddress::PublicKey(hash160(pushed)).script(), coin.script_pubkey);
+            let sighash = input_sighash(&BITCOINZ_MAINNET, height, format, &parts, 0, &coin.script_pubkey, coin.value).unwrap();
+            let pushed = PublicKey::from_slice(pushed).unwrap();
+            assert!(secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, &pushed).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_external_signer_matches_key() {
+        use std::sync::Arc;
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let mut held = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            let mut external = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+            for (i, value) in [60_000u64, 40_000].iter().enumerate() {
+                let coin = TxOut {
+                    value: Amount::from_u64(*value).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey(crate::bitcoinz_script_sig::hash160(&pubkey.serialize())).script(),
+                };
+                let outpoint = OutPoint::new([0x11u8; 32], i as u32);
+                held.add_input(outpoint.clone(), coin.clone(), TransparentInputKeys::PubKeyHash(sk));
+                let keys = TransparentInputKeys::external(&coin, pubkey, Arc::new(sk)).unwrap();
+                external.add_input(outpoint, coin, keys);
+            }
+            for builder in [&mut held, &mut external] {
+                builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap());
+            }
+
+            // Signatures are deterministic, so the same key signs the same transaction
+            // whether it's held here or behind the trait
+            assert_eq!(held.build().unwrap(), external.build().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_v1_has_no_expiry() {
+        let mut builder = LegacyTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut builder);
+        assert!(builder.set_expiry(Some(500_020)).is_err());
+        assert!(builder.set_expiry(None).is_ok());
+
+        // Nor anything but SIGHASH_ALL
+        let mut parts = builder.parts.clone();
+        parts.sighash_type = SIGHASH_NONE;
+        assert!(build_transparent(&BITCOINZ_MAINNET, BlockHeight::from(500_000), TransparentTxFormat::V1, &parts).is_err());
+    }
+
+    #[test]
+    fn test_consensus_branch_id_override() {
+        use crate::bitcoinz_branch::{bitcoinz_branch_id_to_u32, BITCOINZ_SAPLING_BRANCH_ID};
+        use zcash_primitives::consensus::BranchId;
+
+        let height = BlockHeight::from(500_000);
+        let sighash = |parts: &TransparentParts| {
+            let coin = &parts.inputs[0].1;
+            input_sighash(&BITCOINZ_MAINNET, height, TransparentTxFormat::Sapling, parts, 0, &coin.script_pubkey, coin.value)
+                .unwrap()
+        };
+
+        let mut builder = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, height);
+        fill(&mut builder);
+        let current = builder.build().unwrap();
+        let current_sighash = sighash(&builder.parts);
+
+        // Naming the current branch is the same as following the height
+        builder.set_consensus_branch_id(Some(BITCOINZ_SAPLING_BRANCH_ID)).unwrap();
+        assert_eq!(sighash(&builder.parts), current_sighash);
+        assert_eq!(builder.build().unwrap().txid, current.txid);
+
+        // A different branch signs a different sighash for the same inputs
+        builder.set_consensus_branch_id(Some(bitcoinz_branch_id_to_u32(BranchId::Canopy))).unwrap();
+        assert_ne!(sighash(&builder.parts), current_sighash);
+        assert_ne!(builder.build().unwrap().txid, current.txid);
+
+        builder.set_consensus_branch_id(None).unwrap();
+        assert_eq!(builder.build().unwrap().txid, current.txid);
+
+        let mut legacy = LegacyTxBuilder::new(BITCOINZ_MAINNET, height);
+        assert!(legacy.set_consensus_branch_id(Some(BITCOINZ_SAPLING_BRANCH_ID)).is_err());
+        assert!(legacy.set_consensus_branch_id(None).is_ok());
+    }
+
+    #[test]
+    fn test_multisig_signed_by_two_parties() {
+        use crate::bitcoinz_script_sig::{hash160, multisig_redeem_script, sign_sighash};
+
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..=3u8).map(|i| SecretKey::from_slice(&[i; 32]).unwrap()).collect();
+        let pubkeys: Vec<_> = sks.iter().map(|sk| PublicKey::from_secret_key(&secp, sk)).collect();
+        let redeem_script = multisig_redeem_script(2, &pubkeys).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::Script(hash160(&redeem_script.0)).script(),
+        };
+
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let builder_with = |keys: Vec<SecretKey>| {
+                let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+                let keys = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), keys).unwrap();
+                builder.add_input(OutPoint::new([0x11u8; 32], 0), coin.clone(), keys);
+                builder.add_output(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap());
+                builder
+            };
+
+            // Keys 3 and 1 together, in the wrong order
+            let both = builder_with(vec![sks[2], sks[0]]).build().unwrap();
+
+            // Key 3's holder can't spend alone, but can hand the sighash to key 1's
+            let mut builder = builder_with(vec![sks[2]]);
+            assert!(builder.build().is_err());
+            let sighash = builder.sighash(0).unwrap();
+            let signature = sign_sighash(&sks[0], &sighash, SIGHASH_ALL).unwrap();
+            builder.add_signature(0, pubkeys[0], signature).unwrap();
+            assert_eq!(builder.build().unwrap().raw, both.raw);
+
+            // The scriptSig holds signatures by keys 1 then 3, in the script's order
+            let script_len_at = (if format == TransparentTxFormat::V1 { 4 } else { 8 }) + 1 + 36;
+            let script_sig_start = script_len_at + if both.raw[script_len_at] < 0xfd { 1 } else { 3 };
+            assert_eq!(both.raw[script_sig_start], 0x00);
+            let sig_len = both.raw[script_sig_start + 1] as usize;
+            let first = &both.raw[script_sig_start + 2..script_sig_start + 1 + sig_len];
+            let msg = Message::from_slice(&sighash).unwrap();
+            assert!(secp.verify_ecdsa(&msg, &Signature::from_der(first).unwrap(), &pubkeys[0]).is_ok());
+
+            assert!(builder.sighash(1).is_err());
+            assert!(builder.add_signature(1, pubkeys[1], vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_signatures_commit_to_format() {
+        let mut overwinter = OverwinterTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut overwinter);
+        let mut sapling = SaplingTransparentTxBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        fill(&mut sapling);
+
+        let parts = &overwinter.parts;
+        let coin = &parts.inputs[0].1;
+        let sighash = |format| {
+            input_sighash(&BITCOINZ_MAINNET, BlockHeight::from(500_000), format, parts, 0, &coin.script_pubkey, coin.value)
+                .unwrap()
+        };
+        assert_ne!(sighash(TransparentTxFormat::Overwinter), sighash(TransparentTxFormat::Sapling));
+        assert_ne!(overwinter.build().unwrap().txid, sapling.build().unwrap().txid);
+    }
+
+    #[test]
+    fn test_script_hash_output() {
+        for format in [TransparentTxFormat::V1, TransparentTxFormat::Overwinter, TransparentTxFormat::Sapling] {
+            let build = |to: TransparentAddress| {
+                let mut builder = transparent_builder(BITCOINZ_MAINNET, BlockHeight::from(500_000), format);
+                fill(builder.as_mut());
+                builder.add_output(to, Amount::from_u64(5_000).unwrap());
+                builder.build().unwrap()
+            };
+            let p2sh = build(TransparentAddress::Script([0x66u8; 20]));
+            let p2pkh = build(TransparentAddress::PublicKey([0x66u8; 20]));
+
+            // OP_HASH160 <hash> OP_EQUAL, where the pubkey hash output has its 25 byte script
+            let mut script = vec![0x17, 0xa9, 0x14];
+            script.extend_from_slice(&[0x66u8; 20]);
+            script.push(0x87);
+            assert!(p2sh.raw.windows(script.len()).any(|w| w == &script[..]));
+            assert_eq!(p2pkh.raw.len(), p2sh.raw.len() + 2);
+
+            // The outputs hash, and so every signature, commits to which kind of output it is
+            let parts = |to: TransparentAddress| {
+                TransparentParts::new(
+                    vec![(
+                        OutPoint::new([0x11u8; 32], 0),
+                        TxOut {
+                            value: Amount::from_u64(100_000).unwrap(),
+                            script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                        },
+                        TransparentInputKeys::PubKeyHash(SecretKey::from_slice(&[0x01u8; 32]).unwrap()),
+                    )],
+                    vec![(to, Amount::from_u64(90_000).unwrap())],
+                )
+            };
+            let sighash = |parts: &TransparentParts| {
+                let coin = &parts.inputs[0].1;
+                input_sighash(&BITCOINZ_MAINNET, BlockHeight::from(500_000), format, parts, 0, &coin.script_pubkey, coin.value)
+                    .unwrap()
+            };
+            assert_ne!(
+                sighash(&parts(TransparentAddress::Script([0x66u8; 20]))),
+                sighash(&parts(TransparentAddress::PublicKey([0x66u8; 20])))
+            );
+        }
+    }
+}
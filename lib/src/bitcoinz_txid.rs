@@ -0,0 +1,177 @@
+/// BitcoinZ Transaction IDs
+///
+/// A txid is the double SHA-256 of a transaction, and it gets written in two orders.
+/// Outpoints, compact blocks and `TxId` hold it as the hash comes out (wire order);
+/// bitcoinzd's RPC, lightwalletd's send response, explorers and bitcore-lib-btcz show it
+/// byte-reversed (display order). `WireTxid` and `DisplayTxid` keep the two apart, so
+/// getting from one to the other always takes an explicit `to_display`/`to_wire`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use zcash_primitives::transaction::{components::OutPoint, TxId};
+
+/// A txid in wire order, as it's serialized in outpoints
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WireTxid([u8; 32]);
+
+/// A txid in display order, as the node's RPC and explorers show it.
+///
+/// It isn't a `WireTxid`, and it has to be converted to be used as one:
+///
+/// ```compile_fail
+/// use bitcoinzwalletlib::bitcoinz_txid::{DisplayTxid, WireTxid};
+///
+/// fn spend(_txid: WireTxid) {}
+/// spend(DisplayTxid::from_bytes([0u8; 32]));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DisplayTxid([u8; 32]);
+
+impl WireTxid {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        WireTxid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_display(self) -> DisplayTxid {
+        let mut bytes = self.0;
+        bytes.reverse();
+        DisplayTxid(bytes)
+    }
+
+    /// The outpoint spending output `n` of this transaction
+    pub fn outpoint(self, n: u32) -> OutPoint {
+        OutPoint::new(self.0, n)
+    }
+}
+
+impl DisplayTxid {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        DisplayTxid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_wire(self) -> WireTxid {
+        let mut bytes = self.0;
+        bytes.reverse();
+        WireTxid(bytes)
+    }
+
+    /// Parse the 64 hex digits of a txid as the node shows it
+    pub fn from_hex(txid: &str) -> Result<Self, String> {
+        let bytes = hex::decode(txid).map_err(|e| format!("Invalid txid {}: {}", txid, e))?;
+        let bytes = <[u8; 32]>::try_from(&bytes[..]).map_err(|_| format!("Invalid txid {}: not 32 bytes", txid))?;
+        Ok(DisplayTxid(bytes))
+    }
+}
+
+impl From<TxId> for WireTxid {
+    fn from(txid: TxId) -> Self {
+        WireTxid(*txid.as_ref())
+    }
+}
+
+impl From<WireTxid> for TxId {
+    fn from(txid: WireTxid) -> Self {
+        TxId::from_bytes(txid.0)
+    }
+}
+
+impl From<&OutPoint> for WireTxid {
+    fn from(outpoint: &OutPoint) -> Self {
+        WireTxid(*outpoint.hash())
+    }
+}
+
+impl fmt::Display for DisplayTxid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for DisplayTxid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DisplayTxid({})", self)
+    }
+}
+
+/// Shows the display-order hex too, since that's what can be looked up
+impl fmt::Debug for WireTxid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WireTxid({}, displayed as {})", hex::encode(self.0), self.to_display())
+    }
+}
+
+impl TryFrom<String> for DisplayTxid {
+    type Error = String;
+
+    fn try_from(txid: String) -> Result<Self, String> {
+        DisplayTxid::from_hex(&txid)
+    }
+}
+
+impl From<DisplayTxid> for String {
+    fn from(txid: DisplayTxid) -> Self {
+        txid.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut bytes = [0u8; 32];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        let wire = WireTxid::from_bytes(bytes);
+        let display = wire.to_display();
+        assert_eq!(display.as_bytes()[0], 31);
+        assert_eq!(display.to_wire(), wire);
+        assert_eq!(DisplayTxid::from_hex(&display.to_string()).unwrap(), display);
+
+        // TxId holds wire order and shows display order
+        let txid = TxId::from_bytes(bytes);
+        assert_eq!(WireTxid::from(txid), wire);
+        assert_eq!(TxId::from(wire), txid);
+        assert_eq!(txid.to_string(), display.to_string());
+        assert_eq!(WireTxid::from(&wire.outpoint(3)), wire);
+    }
+
+    #[test]
+    fn test_orders_are_distinct() {
+        // A txid that reads the same both ways is the only one where the orders agree
+        let wire = WireTxid::from_bytes([0xabu8; 32]);
+        assert_eq!(wire.to_display().as_bytes(), wire.as_bytes());
+
+        let mut bytes = [0u8; 32];
+        bytes[0] = 1;
+        let wire = WireTxid::from_bytes(bytes);
+        assert_ne!(wire.to_display().as_bytes(), wire.as_bytes());
+        assert_eq!(wire.to_display().to_string(), format!("{}01", "00".repeat(31)));
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let hex = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let display: DisplayTxid = serde_json::from_str(&format!("\"{}\"", hex)).unwrap();
+        assert_eq!(serde_json::to_string(&display).unwrap(), format!("\"{}\"", hex));
+        assert_eq!(display.to_wire().as_bytes()[0], 0x3b);
+
+        assert!(DisplayTxid::from_hex("4a5e").is_err());
+        assert!(DisplayTxid::from_hex(&"zz".repeat(32)).is_err());
+        assert!(serde_json::from_str::<DisplayTxid>("\"4a5e\"").is_err());
+    }
+}
@@ -1,378 +1,295 @@
 /// BitcoinZ v4 Transaction Builder (No Binding Signature)
 /// 
 /// This module builds v4 Sapling transactions without binding signatures
-/// for transparent-only transfers, matching what BitcoinZ expects.
+/// for transparent-only transfers, matching what BitcoinZ expects. The building and
+/// signing is `bitcoinz_transparent_builder`'s; new code should use its
+/// `TransparentTxBuilder`.
 
-use byteorder::{LittleEndian, WriteBytesExt};
-use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
-use blake2b_simd::{Params};
-use std::io::Write;
+use secp256k1::SecretKey;
 use zcash_primitives::{
     consensus::{BlockHeight, Parameters},
-    legacy::{Script, TransparentAddress},
+    legacy::TransparentAddress,
     transaction::{
         components::{Amount, OutPoint, TxOut},
     },
 };
 
-/// BitcoinZ Sapling constants
-const SAPLING_TX_VERSION: i32 = 4;
-const BITCOINZ_VERSION_GROUP_ID: u32 = 0x892f2085;
-const SIGHASH_ALL: u32 = 1;
+use crate::bitcoinz_script_sig::{pubkey_hash_inputs, TransparentInputKeys};
+use crate::bitcoinz_transaction::{transparent_fee, BuiltTransaction, DUST_THRESHOLD};
+use crate::bitcoinz_transparent_builder::{build_transparent, TransparentParts, TransparentTxFormat};
 
-/// Personalization strings for BLAKE2b
-const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
-const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
-const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
-const ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashSigHash";
-
-/// Build a BitcoinZ v4 transaction without binding signature
+/// Build a BitcoinZ v4 transaction without binding signature. The transaction can't be
+/// mined after `expiry_height`; None means it never expires.
+///
+/// With `change` set to an address and a fee, whatever the inputs hold beyond the outputs
+/// and that fee is paid back to the address as one more output, or left to the fee if
+/// it would be dust. Without it, the fee is whatever the outputs leave over.
 pub fn build_bitcoinz_v4_no_sig<P: Parameters>(
     params: &P,
     inputs: Vec<(OutPoint, TxOut, SecretKey)>,
     outputs: Vec<(TransparentAddress, Amount)>,
     height: BlockHeight,
-) -> Result<Vec<u8>, String> {
-    
-    // Build and sign the transaction
-    let signed_tx = build_and_sign_v4_tx(params, inputs, outputs, height)?;
-    
-    
-    Ok(signed_tx)
+    expiry_height: Option<u32>,
+    change: Option<(TransparentAddress, Amount)>,
+) -> Result<BuiltTransaction, String> {
+    build_bitcoinz_v4_no_sig_with_keys(params, pubkey_hash_inputs(&inputs), outputs, height, expiry_height, change)
 }
 
-/// Build and sign v4 transaction
-fn build_and_sign_v4_tx<P: Parameters>(
+/// Build a BitcoinZ v4 transaction without binding signature, from inputs that may be
+/// P2PKH or P2SH
+pub fn build_bitcoinz_v4_no_sig_with_keys<P: Parameters>(
     params: &P,
-    inputs: Vec<(OutPoint, TxOut, SecretKey)>,
-    outputs: Vec<(TransparentAddress, Amount)>,
+    inputs: Vec<(OutPoint, TxOut, TransparentInputKeys)>,
+    mut outputs: Vec<(TransparentAddress, Amount)>,
     height: BlockHeight,
-) -> Result<Vec<u8>, String> {
-    let secp = Secp256k1::new();
-    let mut tx_data = Vec::new();
-    
-    // Header (version 4 with overwinter flag)
-    let header = 0x80000000u32 | (SAPLING_TX_VERSION as u32);
-    tx_data.write_u32::<LittleEndian>(header)
-        .map_err(|e| format!("Failed to write header: {}", e))?;
-    
-    // Version group ID (BitcoinZ)
-    tx_data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
-        .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-    
-    // Build unsigned transaction structure first to compute sighashes
-    let mut unsigned_tx: Vec<u8> = Vec::new();
+    expiry_height: Option<u32>,
+    change: Option<(TransparentAddress, Amount)>,
+) -> Result<BuiltTransaction, String> {
+    if let Some((change_address, fee)) = change {
+        let left_over = transparent_fee(
+            inputs.iter().map(|(_, coin, _)| coin.value),
+            outputs.iter().map(|(_, value)| *value),
+        )?;
+        let change = i64::from(left_over) - i64::from(fee);
+        if change < 0 {
+            return Err(format!(
+                "Insufficient funds: the inputs leave {} zatoshis after the outputs, short of the {} zatoshi fee",
+                i64::from(left_over),
+                i64::from(fee)
+            ));
+        }
+        // Dust change isn't worth an output, so it goes to the fee
+        if change >= DUST_THRESHOLD as i64 {
+            outputs.push((change_address, Amount::from_i64(change).expect("less than the inputs")));
+        }
+    }
     
-    // Prevouts hash
-    let prevouts_hash = compute_prevouts_hash(&inputs)?;
+    let parts = TransparentParts {
+        expiry_height: expiry_height.unwrap_or(0),
+        ..TransparentParts::new(inputs, outputs)
+    };
+    build_transparent(params, height, TransparentTxFormat::Sapling, &parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BITCOINZ_MAINNET;
+    
+    // Golden transactions for the fixture below. Signing is RFC 6979 deterministic, so
+    // the same keys, inputs and outputs always give the same bytes. The sighashes match
+    // the SIGHASH_ALL vectors in bitcoinz_v4_shielded's tests.
+    //
+    // Only regenerate these for an intentional change to the format: print
+    // `hex::encode(&tx)` from the test, check the new bytes against an independent
+    // implementation (or a node accepting the transaction), and replace them here.
+    
+    const GOLDEN_NO_EXPIRY: &str = concat!(
+        // Header, version group ID, 2 inputs
+        "0400008085202f8902",
+        // Input 0: outpoint, signature push, public key push, sequence
+        "111111111111111111111111111111111111111111111111111111111111111100000000",
+        "6a473044022017b2880cffda51da4f0497ebc3a4a7d7a4ab6e9cc67d285aa8cd2de4f180ab03022028c7df5151c1d60f20fcdc4b4964f9442a286cf4953183d490917a00a1daea9001",
+        "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        "feffffff",
+        // Input 1
+        "333333333333333333333333333333333333333333333333333333333333333301000000",
+        "6b483045022100b69781136e1ebd39a63c7a0abc2f3bc6591f7cf7d8950ead8d3e3080bb2a91b902203cde42399c96bf0ae63ee2443af907b9ab339be98d6fd07b6cd3d773e36f5e2c01",
+        "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        "feffffff",
+        // 2 outputs: P2PKH, P2SH
+        "02",
+        "f0490200000000001976a914555555555555555555555555555555555555555588ac",
+        "30e602000000000017a914666666666666666666666666666666666666666687",
+        // Lock time, expiry, value balance, no spends, outputs or joinsplits
+        "00000000",
+        "00000000",
+        "0000000000000000",
+        "000000",
+    );
+    
+    const GOLDEN_EXPIRY_500_010: &str = concat!(
+        "0400008085202f8902",
+        "111111111111111111111111111111111111111111111111111111111111111100000000",
+        "6a47304402204f9ef1a2baf89237bed1c829628383dde65e1adc269ea9e4d94c97ebbc4618a402201b402ce497cbc9f438d972e62426f752e37cace8c96661f781ae71d81f06ab1701",
+        "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        "feffffff",
+        "333333333333333333333333333333333333333333333333333333333333333301000000",
+        "6b4830450221009e2d8fff04ad8731f3a6213c14e43fc17b26aa501bf5549a4de9e3226cc3f3bf02201f6c373ad6f8dcf051c75bf4d7bc1813888669fc23c9fbdb559d496a6ba9435301",
+        "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        "feffffff",
+        "02",
+        "f0490200000000001976a914555555555555555555555555555555555555555588ac",
+        "30e602000000000017a914666666666666666666666666666666666666666687",
+        "00000000",
+        "2aa10700",
+        "0000000000000000",
+        "000000",
+    );
+    
+    fn fixture() -> (Vec<(OutPoint, TxOut, SecretKey)>, Vec<(TransparentAddress, Amount)>) {
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let inputs = vec![
+            (
+                OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                sk,
+            ),
+            (
+                OutPoint::new([0x33u8; 32], 1),
+                TxOut {
+                    value: Amount::from_u64(250_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x44u8; 20]).script(),
+                },
+                sk,
+            ),
+        ];
+        let outputs = vec![
+            (TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(150_000).unwrap()),
+            (TransparentAddress::Script([0x66u8; 20]), Amount::from_u64(190_000).unwrap()),
+        ];
+        (inputs, outputs)
+    }
     
-    // Sequence hash
-    let sequence_hash = compute_sequence_hash(&inputs)?;
+    fn build(expiry_height: Option<u32>) -> String {
+        let (inputs, outputs) = fixture();
+        let tx = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), expiry_height, None).unwrap();
+        hex::encode(tx.raw)
+    }
     
-    // Outputs hash
-    let outputs_hash = compute_outputs_hash(&outputs)?;
+    #[test]
+    fn test_golden_transaction() {
+        assert_eq!(build(None), GOLDEN_NO_EXPIRY);
+        assert_eq!(build(Some(500_010)), GOLDEN_EXPIRY_500_010);
+    }
     
-    // Compute signatures for each input
-    let mut signatures = Vec::new();
-    for (index, (_outpoint, txout, sk)) in inputs.iter().enumerate() {
+    #[test]
+    fn test_golden_transaction_txid() {
+        use crate::bitcoinz_transaction::BitcoinZTxType;
         
-        let sighash = compute_sapling_sighash(
-            params,
-            height,
-            &prevouts_hash,
-            &sequence_hash,
-            &outputs_hash,
-            &inputs,
-            index,
-            &txout.script_pubkey,
-            txout.value,
-        )?;
+        // Double SHA-256 of the golden bytes, as bitcoinzd shows it
+        let (inputs, outputs) = fixture();
+        let built = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), None, None).unwrap();
+        assert_eq!(built.txid.to_string(), "9e538b22207a878b10737a658c3935857c399251d65ee2c8f1f1e519f7d6e278");
+        assert_eq!(built.size, 390);
+        assert_eq!(built.fee, Amount::from_u64(10_000).unwrap());
+        assert_eq!(built.tx_type, BitcoinZTxType::TransparentToTransparent);
+        assert_eq!(built.expiry_height, 0);
         
+        let (inputs, outputs) = fixture();
+        let built = build_bitcoinz_v4_no_sig(&BITCOINZ_MAINNET, inputs, outputs, BlockHeight::from(500_000), Some(500_010), None).unwrap();
+        assert_eq!(built.txid.to_string(), "de151b5a2c7a76fabc8b9c4162076c8ccb4953ec0f766d67313628a90f003cc7");
+        assert_eq!(built.expiry_height, 500_010);
+    }
+    
+    #[test]
+    fn test_change() {
+        let (inputs, outputs) = fixture();
+        let change_address = TransparentAddress::PublicKey([0x77u8; 20]);
+        let build = |fee: u64| {
+            build_bitcoinz_v4_no_sig(
+                &BITCOINZ_MAINNET,
+                inputs.clone(),
+                outputs.clone(),
+                BlockHeight::from(500_000),
+                None,
+                Some((change_address.clone(), Amount::from_u64(fee).unwrap())),
+            )
+        };
         
-        let msg = Message::from_slice(&sighash)
-            .map_err(|e| format!("Failed to create message: {}", e))?;
-        let sig = secp.sign_ecdsa(&msg, &sk);
-        
-        let pk = PublicKey::from_secret_key(&secp, &sk);
-        
-        let mut script_sig = Vec::new();
-        
-        // Signature with sighash type
-        let mut sig_bytes = sig.serialize_der().to_vec();
-        sig_bytes.push(SIGHASH_ALL as u8);
-        
-        script_sig.push(sig_bytes.len() as u8);
-        script_sig.extend_from_slice(&sig_bytes);
+        // The fixture's outputs leave exactly 10,000, so that fee needs no change
+        let exact = build(10_000).unwrap();
+        assert_eq!(hex::encode(&exact.raw), GOLDEN_NO_EXPIRY);
         
-        // Public key
-        let pk_bytes = pk.serialize();
-        script_sig.push(pk_bytes.len() as u8);
-        script_sig.extend_from_slice(&pk_bytes);
+        // A smaller fee sends the rest back
+        let with_change = build(4_000).unwrap();
+        assert_eq!(with_change.fee, Amount::from_u64(4_000).unwrap());
+        let outputs = crate::bitcoinz_parse::parse_bitcoinz_v4(&with_change.raw).unwrap().outputs;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[2].value, Amount::from_u64(6_000).unwrap());
+        assert_eq!(outputs[2].script_pubkey, change_address.script());
         
+        // Unless what's left is dust, which goes to the fee
+        let dust = build(10_000 - (DUST_THRESHOLD - 1)).unwrap();
+        assert_eq!(dust.raw, exact.raw);
         
-        signatures.push(script_sig);
+        let err = build(10_001).unwrap_err();
+        assert!(err.contains("Insufficient funds"), "{}", err);
     }
     
-    // Now build the complete transaction
-    
-    // Input count
-    write_compact_size(&mut tx_data, inputs.len() as u64)?;
-    
-    // Write inputs with signatures
-    for (i, (outpoint, _, _)) in inputs.iter().enumerate() {
-        tx_data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        tx_data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
+    #[test]
+    fn test_signatures_follow_branch_id_for_height() {
+        use crate::bitcoinz_branch::StagedUpgradesNetwork;
         
-        write_compact_size(&mut tx_data, signatures[i].len() as u64)?;
-        tx_data.write_all(&signatures[i])
-            .map_err(|e| format!("Failed to write script sig: {}", e))?;
+        let build_at = |height: u32| {
+            let (inputs, outputs) = fixture();
+            hex::encode(build_bitcoinz_v4_no_sig(&StagedUpgradesNetwork, inputs, outputs, BlockHeight::from(height), None, None).unwrap().raw)
+        };
         
-        tx_data.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
+        // Signed for Sapling until Blossom activates
+        assert_eq!(build_at(StagedUpgradesNetwork::BLOSSOM - 1), GOLDEN_NO_EXPIRY);
+        assert_ne!(build_at(StagedUpgradesNetwork::BLOSSOM), GOLDEN_NO_EXPIRY);
     }
     
-    // Output count
-    write_compact_size(&mut tx_data, outputs.len() as u64)?;
-    
-    // Write outputs
-    for (addr, amount) in &outputs {
-        tx_data.write_u64::<LittleEndian>(u64::from(*amount))
-            .map_err(|e| format!("Failed to write amount: {}", e))?;
+    #[test]
+    fn test_golden_transaction_parses() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
         
-        let script = addr.script();
-        write_compact_size(&mut tx_data, script.0.len() as u64)?;
-        tx_data.write_all(&script.0)
-            .map_err(|e| format!("Failed to write script: {}", e))?;
-    }
-    
-    // Lock time
-    tx_data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write lock time: {}", e))?;
-    
-    // Expiry height (BitcoinZ uses 0 for no expiry)
-    tx_data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-    
-    // Value balance (0 for transparent only)
-    tx_data.write_i64::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write value balance: {}", e))?;
-    
-    // No shielded spends
-    write_compact_size(&mut tx_data, 0)?;
-    
-    // No shielded outputs
-    write_compact_size(&mut tx_data, 0)?;
-    
-    // No joinsplits
-    write_compact_size(&mut tx_data, 0)?;
-    
-    // For transparent-only transactions, BitcoinZ doesn't expect any binding signature
-    // Don't add any binding signature bytes
-    
-    
-    Ok(tx_data)
-}
-
-/// Compute Sapling sighash
-fn compute_sapling_sighash<P: Parameters>(
-    params: &P,
-    height: BlockHeight,
-    prevouts_hash: &[u8; 32],
-    sequence_hash: &[u8; 32],
-    outputs_hash: &[u8; 32],
-    inputs: &[(OutPoint, TxOut, SecretKey)],
-    input_index: usize,
-    script_code: &Script,
-    value: Amount,
-) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    // 1. Header
-    let header = 0x80000000u32 | (SAPLING_TX_VERSION as u32);
-    data.write_u32::<LittleEndian>(header)
-        .map_err(|e| format!("Failed to write header: {}", e))?;
-    
-    // 2. Version group ID
-    data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
-        .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-    
-    // 3. Prevouts hash
-    data.write_all(prevouts_hash)
-        .map_err(|e| format!("Failed to write prevouts hash: {}", e))?;
-    
-    // 4. Sequence hash
-    data.write_all(sequence_hash)
-        .map_err(|e| format!("Failed to write sequence hash: {}", e))?;
-    
-    // 5. Outputs hash
-    data.write_all(outputs_hash)
-        .map_err(|e| format!("Failed to write outputs hash: {}", e))?;
-    
-    // 6. JoinSplits hash (empty)
-    data.write_all(&[0u8; 32])
-        .map_err(|e| format!("Failed to write joinsplits hash: {}", e))?;
-    
-    // 7. ShieldedSpends hash (empty)
-    data.write_all(&[0u8; 32])
-        .map_err(|e| format!("Failed to write shielded spends hash: {}", e))?;
-    
-    // 8. ShieldedOutputs hash (empty)
-    data.write_all(&[0u8; 32])
-        .map_err(|e| format!("Failed to write shielded outputs hash: {}", e))?;
-    
-    // 9. Lock time
-    data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write lock time: {}", e))?;
-    
-    // 10. Expiry height (BitcoinZ uses 0 for no expiry)
-    data.write_u32::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-    
-    // 11. Value balance
-    data.write_i64::<LittleEndian>(0)
-        .map_err(|e| format!("Failed to write value balance: {}", e))?;
-    
-    // 12. Sighash type
-    data.write_u32::<LittleEndian>(SIGHASH_ALL)
-        .map_err(|e| format!("Failed to write sighash type: {}", e))?;
-    
-    // 13. Input details (for the input being signed)
-    // This part is only included for non-ANYONECANPAY
-    
-    // Outpoint
-    let (outpoint, _, _) = &inputs[input_index];
-    data.write_all(outpoint.hash())
-        .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-    data.write_u32::<LittleEndian>(outpoint.n())
-        .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-    
-    // Script code
-    write_compact_size(&mut data, script_code.0.len() as u64)?;
-    data.write_all(&script_code.0)
-        .map_err(|e| format!("Failed to write script code: {}", e))?;
-    
-    // Value
-    data.write_u64::<LittleEndian>(u64::from(value))
-        .map_err(|e| format!("Failed to write value: {}", e))?;
-    
-    // Sequence
-    data.write_u32::<LittleEndian>(0xfffffffe)
-        .map_err(|e| format!("Failed to write sequence: {}", e))?;
-    
-    // Create personalization with branch ID
-    let mut personalization = [0u8; 16];
-    personalization[..12].copy_from_slice(ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX);
-    // BitcoinZ uses a fixed consensus branch ID regardless of height
-    let branch_id: u32 = 1991772603; // 0x76b809bb
-    personalization[12..16].copy_from_slice(&branch_id.to_le_bytes());
-    
-    
-    // Compute BLAKE2b hash
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(&personalization)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    // Actually, let me try NOT reversing to see if that helps
-    // result.reverse();
-    
-    Ok(result)
-}
-
-/// Helper functions (same as in Overwinter builder)
-
-fn compute_prevouts_hash(inputs: &[(OutPoint, TxOut, SecretKey)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for (outpoint, _, _) in inputs {
-        data.write_all(outpoint.hash())
-            .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-        data.write_u32::<LittleEndian>(outpoint.n())
-            .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-    }
-    
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_PREVOUTS_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
-
-fn compute_sequence_hash(inputs: &[(OutPoint, TxOut, SecretKey)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for _ in inputs {
-        data.write_u32::<LittleEndian>(0xfffffffe)
-            .map_err(|e| format!("Failed to write sequence: {}", e))?;
+        let parsed = parse_bitcoinz_v4(&hex::decode(GOLDEN_NO_EXPIRY).unwrap()).unwrap();
+        assert_eq!(parsed.inputs.len(), 2);
+        assert_eq!(parsed.outputs.len(), 2);
+        assert!(parsed.shielded_spends.is_empty() && parsed.shielded_outputs.is_empty());
+        assert_eq!(parsed.binding_sig, None);
+        
+        let parsed = parse_bitcoinz_v4(&hex::decode(GOLDEN_EXPIRY_500_010).unwrap()).unwrap();
+        assert_eq!(parsed.expiry_height, 500_010);
     }
     
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_SEQUENCE_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
-}
-
-fn compute_outputs_hash(outputs: &[(TransparentAddress, Amount)]) -> Result<[u8; 32], String> {
-    let mut data = Vec::new();
-    
-    for (addr, amount) in outputs {
-        data.write_u64::<LittleEndian>(u64::from(*amount))
-            .map_err(|e| format!("Failed to write amount: {}", e))?;
+    #[test]
+    fn test_p2sh_input() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use crate::bitcoinz_script_sig::{hash160, multisig_redeem_script};
+        use crate::bitcoinz_transparent_builder::input_sighash;
+        use zcash_primitives::legacy::Script;
+        use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0x07u8; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &sk);
+        let redeem_script = multisig_redeem_script(1, &[pubkey]).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::Script(hash160(&redeem_script.0)).script(),
+        };
+        let keys = TransparentInputKeys::script_hash(&coin, redeem_script.clone(), vec![sk]).unwrap();
+        let inputs = vec![(OutPoint::new([0x11u8; 32], 0), coin.clone(), keys)];
+        let outputs = vec![(TransparentAddress::PublicKey([0x55u8; 20]), Amount::from_u64(90_000).unwrap())];
+        
+        let tx = build_bitcoinz_v4_no_sig_with_keys(&BITCOINZ_MAINNET, inputs.clone(), outputs.clone(), BlockHeight::from(500_000), None, None).unwrap().raw;
+        
+        // OP_0 <sig> <redeemScript>, with the signature over the redeem script
+        let script_sig = &parse_bitcoinz_v4(&tx).unwrap().inputs[0].script_sig;
+        let sig_len = script_sig[1] as usize;
+        let signature = Signature::from_der(&script_sig[2..1 + sig_len]).unwrap();
+        assert_eq!(&script_sig[3 + sig_len..], &redeem_script.0[..]);
         
-        let script = addr.script();
-        write_compact_size(&mut data, script.0.len() as u64)?;
-        data.write_all(&script.0)
-            .map_err(|e| format!("Failed to write script: {}", e))?;
+        let parts = TransparentParts::new(inputs, outputs);
+        let sighash = |script_code: &Script| {
+            let hash = input_sighash(
+                &BITCOINZ_MAINNET,
+                BlockHeight::from(500_000),
+                TransparentTxFormat::Sapling,
+                &parts,
+                0,
+                script_code,
+                coin.value,
+            ).unwrap();
+            Message::from_slice(&hash).unwrap()
+        };
+        assert!(secp.verify_ecdsa(&sighash(&redeem_script), &signature, &pubkey).is_ok());
+        assert!(secp.verify_ecdsa(&sighash(&coin.script_pubkey), &signature, &pubkey).is_err());
     }
-    
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(ZCASH_OUTPUTS_HASH_PERSONALIZATION)
-        .to_state()
-        .update(&data)
-        .finalize();
-    
-    let mut result = [0u8; 32];
-    result.copy_from_slice(hash.as_bytes());
-    
-    Ok(result)
 }
-
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    }
-    Ok(())
-}
\ No newline at end of file
@@ -1,61 +1,89 @@
 /// BitcoinZ v4 Shielded Transaction Builder
-/// 
+///
 /// This module builds v4 Sapling transactions with proper binding signatures
 /// for shielded transfers (t→z, z→t, z→z), using BitcoinZ's specific
 /// binding signature algorithm.
-
+use blake2b_simd::Params;
 use byteorder::{LittleEndian, WriteBytesExt};
 use ff::{Field, PrimeField};
-use group::{GroupEncoding, Group};
-use rand::{thread_rng, Rng, RngCore, SeedableRng};
+use group::{Group, GroupEncoding};
 use hex;
+use log::{debug, trace};
 use rand::rngs::StdRng;
-use secp256k1::{Message, PublicKey as SecpPublicKey, Secp256k1, SecretKey};
-use blake2b_simd::Params;
+use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use secp256k1::SecretKey;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::io::Write;
-use std::convert::TryInto;
+use std::sync::Arc;
 
 use zcash_primitives::{
     consensus::{BlockHeight, BranchId, Parameters},
+    constants::VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
     keys::OutgoingViewingKey,
     legacy::{Script, TransparentAddress},
     memo::MemoBytes,
+    merkle_tree::{CommitmentTree, Hashable, IncrementalWitness, MerklePath},
     sapling::{
         keys::{ExpandedSpendingKey, FullViewingKey},
         note_encryption::sapling_note_encryption,
         prover::TxProver,
         redjubjub::{PrivateKey, PublicKey, Signature},
-        Diversifier, Node, Note, PaymentAddress, ProofGenerationKey, Rseed,
-        NoteValue, ValueCommitment,
+        spend_sig, Diversifier, Node, Note, NoteValue, PaymentAddress, ProofGenerationKey, Rseed, ValueCommitment,
     },
     transaction::{
         components::{
-            sapling::{
-                Authorization, Authorized, Bundle, GrothProofBytes,
-                OutputDescription, SpendDescription,
-            },
+            amount::MAX_MONEY,
+            sapling::{Authorization, Authorized, Bundle, GrothProofBytes, OutputDescription, SpendDescription},
             transparent::{self, TxIn, TxOut},
             Amount, GROTH_PROOF_SIZE,
         },
         sighash::{signature_hash, SignableInput},
         txid::TxIdDigester,
-        TransactionData, TxVersion, Unauthorized,
+        TransactionData, TxId, TxVersion, Unauthorized,
     },
+    zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
 };
 
-use crate::bitcoinz_js_bridge::{generate_shielded_output as js_generate_shielded_output};
-use crate::bitcoinz_compat::{serialize_value_commitment_bitcoinz, serialize_ephemeral_key_bitcoinz};
-use crate::bitcoinz_compat_v2::{serialize_edwards_point_bitcoinz_v2, serialize_edwards_point_bitcoinz_v3, serialize_edwards_point_bitcoinz_v4, serialize_edwards_point_bitcoinz_exact, debug_point_formats};
+use crate::bitcoinz_address::wif_to_secret_key;
+use crate::bitcoinz_binding_sig_fix::{binding_verification_key, compute_bitcoinz_binding_message};
+use crate::bitcoinz_branch::bitcoinz_consensus_branch_id;
+use crate::bitcoinz_compat::{serialize_ephemeral_key_bitcoinz, serialize_value_commitment_bitcoinz};
+use crate::bitcoinz_compat_v2::{
+    serialize_edwards_point_bitcoinz_exact, serialize_edwards_point_bitcoinz_v2, serialize_edwards_point_bitcoinz_v3,
+    serialize_edwards_point_bitcoinz_v4,
+};
 use crate::bitcoinz_edwards_bellman::write_edwards_point_bellman;
+use crate::bitcoinz_encoding::{compact_size_len, write_amount, write_compact_size, write_outpoint, write_script};
+#[cfg(feature = "js-bridge")]
+use crate::bitcoinz_js_bridge::generate_shielded_output as js_generate_shielded_output;
+use crate::bitcoinz_memo::Memo;
+use crate::bitcoinz_overwinter_builder::{validate_sighash_type, DEFAULT_SEQUENCE, SIGHASH_ALL, SIGHASH_SINGLE};
+use crate::bitcoinz_parse::BitcoinZTxError;
+use crate::bitcoinz_script_sig::{hash160, script_sigs, Signer, TransparentInputKeys};
+use crate::bitcoinz_sighash::{finalize_sighash, sighash_state, REVERSE_BINDING_SIGHASH};
+use crate::bitcoinz_transaction::{
+    check_dust, check_fee, check_size, detect_tx_type, sort_bip69, transparent_input_size, BuiltTransaction, FeePolicy,
+    TxidWriter, DUST_THRESHOLD,
+};
+use crate::bitcoinz_transparent_builder::{zip143_sighash, SaplingDigests, TransparentParts, TransparentTxFormat};
+use crate::bitcoinz_txid::DisplayTxid;
 
 /// BitcoinZ Sapling constants
 const SAPLING_TX_VERSION: i32 = 4;
 const BITCOINZ_VERSION_GROUP_ID: u32 = 0x892f2085;
-const CONSENSUS_BRANCH_ID: u32 = 1991772603; // 0x76b809bb
-const SIGHASH_ALL: u32 = 1;
+
+/// Serialized sizes used by the dry-run estimator
+const SPEND_DESCRIPTION_SIZE: usize = 384;
+const OUTPUT_DESCRIPTION_SIZE: usize = 948;
+const BINDING_SIG_SIZE: usize = 64;
+
+/// Transactions that spend Sapling notes are padded with dummy outputs up to this many
+/// Sapling outputs, so a z→t (no outputs) or a z→z without change (one output) doesn't
+/// stand out
+pub const DEFAULT_MIN_SAPLING_OUTPUTS: usize = 2;
 
 /// Personalization strings for BLAKE2b
-const ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZcashSigHash";
 const ZCASH_PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
 const ZCASH_SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
 const ZCASH_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
@@ -76,50 +104,282 @@ pub struct ShieldedSpend {
 
 /// A shielded output to be created in the transaction
 pub struct ShieldedOutput {
-    pub ovk: OutgoingViewingKey,
+    /// None makes an output the sender can't recover afterwards
+    pub ovk: Option<OutgoingViewingKey>,
     pub to: PaymentAddress,
     pub value: Amount,
     pub memo: MemoBytes,
 }
 
+/// Phase reported by `build_with_progress`. Proving dominates the build time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildProgress {
+    /// Checking the selected inputs and outputs against the fee
+    Selecting,
+    /// Proving spend `current` (1-based) of `total`
+    ProvingSpend { current: usize, total: usize },
+    /// Proving output `current` (1-based) of `total`
+    ProvingOutput { current: usize, total: usize },
+    /// Spend authorization and binding signatures
+    Signing,
+    /// Writing the transaction, including signing the transparent inputs
+    Serializing,
+}
+
+/// Size and fee estimate for a transaction, computed without generating proofs
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxEstimate {
+    /// Serialized size in bytes. Exact except for the DER signature length of each
+    /// transparent input, which is assumed to be the 72-byte maximum.
+    pub size: usize,
+    /// Recommended fee
+    pub fee: Amount,
+    /// How much of `size` each transparent input takes, in the order they were added.
+    /// A P2PKH input is about 150 bytes; a P2SH one carries its redeem script and, for
+    /// multisig, a signature for each of the threshold, so it can be several times that.
+    pub transparent_input_sizes: Vec<usize>,
+}
+
+/// Why the inputs, outputs and fee of a transaction don't add up
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueBalanceError {
+    /// A sum of amounts doesn't fit in an i64, or is more than MAX_MONEY
+    Overflow,
+    NegativeFee(i64),
+    /// The outputs and the fee need `missing` more zatoshis than the inputs provide
+    InsufficientFunds {
+        missing: u64,
+    },
+    /// The inputs provide `excess` more zatoshis than the outputs and the fee use
+    Unbalanced {
+        excess: u64,
+    },
+}
+
+impl fmt::Display for ValueBalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueBalanceError::Overflow => write!(f, "Transaction amounts overflow"),
+            ValueBalanceError::NegativeFee(fee) => write!(f, "Fee can't be negative ({} zatoshis)", fee),
+            ValueBalanceError::InsufficientFunds { missing } => write!(
+                f,
+                "Insufficient funds: the outputs and fee need {} zatoshis more than the inputs provide",
+                missing
+            ),
+            ValueBalanceError::Unbalanced { excess } => write!(
+                f,
+                "Transaction is unbalanced: the inputs exceed the outputs and fee by {} zatoshis",
+                excess
+            ),
+        }
+    }
+}
+
+impl From<ValueBalanceError> for String {
+    fn from(e: ValueBalanceError) -> Self {
+        e.to_string()
+    }
+}
+
+fn checked_sum(values: impl IntoIterator<Item = i64>) -> Result<i64, ValueBalanceError> {
+    values
+        .into_iter()
+        .try_fold(0i64, |total, value| total.checked_add(value))
+        .ok_or(ValueBalanceError::Overflow)
+}
+
+/// `total`, if no more than MAX_MONEY could have gone into it
+fn within_max_money(total: i64) -> Result<i64, ValueBalanceError> {
+    if total > MAX_MONEY {
+        return Err(ValueBalanceError::Overflow);
+    }
+    Ok(total)
+}
+
 /// Builder for BitcoinZ v4 shielded transactions
-pub struct BitcoinZShieldedBuilder<P: Parameters> {
+pub struct BitcoinZShieldedBuilder<P: Parameters, R: RngCore + CryptoRng = StdRng> {
     params: P,
     height: BlockHeight,
-    
+
     // Transparent components
-    transparent_inputs: Vec<(transparent::OutPoint, TxOut, SecretKey)>,
+    transparent_inputs: Vec<(transparent::OutPoint, TxOut, TransparentInputKeys)>,
+    // nSequence of each transparent input, by index
+    transparent_sequences: Vec<u32>,
     transparent_outputs: Vec<(TransparentAddress, Amount)>,
-    
+
     // Shielded components
     sapling_spends: Vec<ShieldedSpend>,
     sapling_outputs: Vec<ShieldedOutput>,
-    
+
     // Keys
     spending_keys: Vec<ExpandedSpendingKey>,
-    
-    // Random number generator
-    rng: StdRng,
+
+    // Anchor (and the tree it is the root of) that every spend must be witnessed
+    // against, when it has been agreed with other parties
+    pinned_anchor: Option<(Node, CommitmentTree<Node>)>,
+
+    // Sighash type used when signing transparent inputs
+    sighash_type: u32,
+
+    // Last block the transaction can be mined in (None for no expiry)
+    expiry_height: Option<u32>,
+
+    // Branch id the sighashes commit to (None for the one active at the height)
+    consensus_branch_id: Option<u32>,
+
+    // Minimum number of Sapling outputs when there are Sapling spends, and whether
+    // to pad up to it at all
+    min_sapling_outputs: usize,
+    output_padding: bool,
+
+    // Whether to verify the binding signature before serializing
+    verify_on_build: bool,
+
+    // Outputs paying less than this are refused as dust
+    dust_threshold: u64,
+
+    // What `estimate` charges for the transaction's size
+    fee_policy: FeePolicy,
+
+    // Whether the transparent inputs and outputs are put in BIP 69 order before signing
+    canonical_order: bool,
+
+    // Source of all the builder's randomness: alpha, note rseed, the output esk and
+    // encryption, and spend authorization signatures. The value commitment randomness
+    // isn't drawn here: the prover picks it inside `spend_proof` and `output_proof` and
+    // keeps the sum in its proving context for the binding signature.
+    rng: R,
+}
+
+impl<P: Parameters> BitcoinZShieldedBuilder<P, StdRng> {
+    /// Create a new builder seeded from the OS entropy source
+    pub fn new_with_entropy(params: P, height: BlockHeight) -> Self {
+        Self::new(params, height, StdRng::from_entropy())
+    }
 }
 
-impl<P: Parameters> BitcoinZShieldedBuilder<P> {
-    /// Create a new builder instance
-    pub fn new(
-        params: P,
-        height: BlockHeight,
-    ) -> Self {
+impl<P: Parameters, R: RngCore + CryptoRng> BitcoinZShieldedBuilder<P, R> {
+    /// Create a new builder instance that takes its randomness from `rng`
+    pub fn new(params: P, height: BlockHeight, rng: R) -> Self {
         Self {
             params,
             height,
             transparent_inputs: Vec::new(),
+            transparent_sequences: Vec::new(),
             transparent_outputs: Vec::new(),
             sapling_spends: Vec::new(),
             sapling_outputs: Vec::new(),
             spending_keys: Vec::new(),
-            rng: StdRng::from_entropy(),
+            pinned_anchor: None,
+            sighash_type: SIGHASH_ALL,
+            expiry_height: None,
+            consensus_branch_id: None,
+            min_sapling_outputs: DEFAULT_MIN_SAPLING_OUTPUTS,
+            output_padding: true,
+            verify_on_build: true,
+            dust_threshold: DUST_THRESHOLD,
+            fee_policy: FeePolicy::default(),
+            canonical_order: false,
+            rng,
+        }
+    }
+
+    /// Set the sighash type used to sign transparent inputs (defaults to SIGHASH_ALL).
+    /// The binding signature always commits to the whole transaction.
+    pub fn set_sighash_type(&mut self, sighash_type: u32) -> Result<(), String> {
+        validate_sighash_type(sighash_type)?;
+        self.sighash_type = sighash_type;
+        Ok(())
+    }
+
+    /// Set the last block height the transaction can be mined in. None (the default)
+    /// writes an expiry of 0, so the transaction never expires.
+    pub fn set_expiry_height(&mut self, expiry_height: Option<u32>) {
+        self.expiry_height = expiry_height;
+    }
+
+    /// Sign for `consensus_branch_id` instead of the branch active at the builder's
+    /// height, e.g. once BitcoinZ activates an upgrade this wallet doesn't know about
+    /// yet. It goes into both the transparent and the binding sighash. None (the
+    /// default) follows the height.
+    pub fn set_consensus_branch_id(&mut self, consensus_branch_id: Option<u32>) {
+        self.consensus_branch_id = consensus_branch_id;
+    }
+
+    /// Set the smallest transparent output the builder accepts (defaults to
+    /// `DUST_THRESHOLD`), for a node that relays with a different fee, e.g. on testnet.
+    /// Outputs already added aren't checked again.
+    pub fn set_dust_threshold(&mut self, dust_threshold: u64) {
+        self.dust_threshold = dust_threshold;
+    }
+
+    /// Set what `estimate` recommends paying for the transaction's size (defaults to
+    /// `FeePolicy::default()`)
+    pub fn set_fee_policy(&mut self, fee_policy: FeePolicy) {
+        self.fee_policy = fee_policy;
+    }
+
+    /// Set the number of Sapling outputs a transaction with Sapling spends is padded up to
+    /// (defaults to `DEFAULT_MIN_SAPLING_OUTPUTS`)
+    pub fn set_min_sapling_outputs(&mut self, min_sapling_outputs: usize) {
+        self.min_sapling_outputs = min_sapling_outputs;
+    }
+
+    /// Turn dummy output padding on or off. Padding is on by default; turning it off
+    /// is only meant for debugging, since the output count then gives away whether a
+    /// shielded transaction has change.
+    pub fn set_output_padding(&mut self, output_padding: bool) {
+        self.output_padding = output_padding;
+    }
+
+    /// Sort the transparent inputs and outputs into BIP 69 order before signing, so their
+    /// order doesn't give away which output is change (off by default). `set_sequence`
+    /// still takes inputs in the order they were added.
+    pub fn set_canonical_order(&mut self, canonical_order: bool) {
+        self.canonical_order = canonical_order;
+    }
+
+    /// Turn the binding signature self-check in `build` on or off. It's on by default,
+    /// and catches a signature the node would reject before it is broadcast.
+    pub fn set_verify_on_build(&mut self, verify_on_build: bool) {
+        self.verify_on_build = verify_on_build;
+    }
+
+    /// Number of dummy outputs `build` will add
+    pub fn padding_outputs(&self) -> usize {
+        if self.output_padding && !self.sapling_spends.is_empty() {
+            self.min_sapling_outputs.saturating_sub(self.sapling_outputs.len())
+        } else {
+            0
+        }
+    }
+
+    /// Pin the anchor that all Sapling spends must be witnessed against, together with
+    /// the tree state it is the root of. Used for coordinated transactions where every
+    /// participant has to anchor to the same tree. Spends already added are checked too.
+    pub fn pin_anchor(&mut self, anchor: Node, tree: CommitmentTree<Node>) -> Result<(), String> {
+        if tree.root() != anchor {
+            return Err(format!(
+                "Anchor {} is not the root of the pinned tree ({})",
+                hex::encode(node_bytes(&anchor)),
+                hex::encode(node_bytes(&tree.root()))
+            ));
+        }
+
+        for (i, spend) in self.sapling_spends.iter().enumerate() {
+            check_spend_anchor(&spend.note, &spend.merkle_path, spend.witness_position, &anchor, &tree)
+                .map_err(|e| format!("Sapling spend {}: {}", i, e))?;
         }
+
+        self.pinned_anchor = Some((anchor, tree));
+        Ok(())
+    }
+
+    /// The pinned anchor, if any
+    pub fn pinned_anchor(&self) -> Option<Node> {
+        self.pinned_anchor.as_ref().map(|(anchor, _)| *anchor)
     }
-    
+
     /// Add a transparent input
     pub fn add_transparent_input(
         &mut self,
@@ -127,21 +387,117 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         coin: TxOut,
         key: SecretKey,
     ) -> Result<(), String> {
-        self.transparent_inputs.push((outpoint, coin, key));
+        let keys = TransparentInputKeys::pubkey_hash(&coin, key);
+        self.transparent_inputs.push((outpoint, coin, keys));
+        self.transparent_sequences.push(DEFAULT_SEQUENCE);
         Ok(())
     }
-    
-    /// Add a transparent output
-    pub fn add_transparent_output(
+
+    /// Add a P2PKH transparent input paid to `pubkey`, signed by `signer` once every
+    /// sighash is known
+    pub fn add_transparent_input_with_signer(
         &mut self,
-        to: TransparentAddress,
-        value: Amount,
+        outpoint: transparent::OutPoint,
+        coin: TxOut,
+        pubkey: secp256k1::PublicKey,
+        signer: Arc<dyn Signer>,
+    ) -> Result<(), String> {
+        let keys = TransparentInputKeys::external(&coin, pubkey, signer)?;
+        self.transparent_inputs.push((outpoint, coin, keys));
+        self.transparent_sequences.push(DEFAULT_SEQUENCE);
+        Ok(())
+    }
+
+    /// Add a P2PKH transparent input from a UTXO as the node lists it: the txid as it's
+    /// displayed, the scriptPubKey in hex and the key that spends it in WIF. The key has
+    /// to be the one the script pays to, in the form (compressed or not) the WIF says.
+    pub fn add_transparent_input_from_utxo(
+        &mut self,
+        txid: &str,
+        vout: u32,
+        script_pubkey_hex: &str,
+        value: u64,
+        wif: &str,
+    ) -> Result<(), String> {
+        let outpoint = DisplayTxid::from_hex(txid)?.to_wire().outpoint(vout);
+        let script_pubkey =
+            Script(hex::decode(script_pubkey_hex).map_err(|e| format!("Invalid script {}: {}", script_pubkey_hex, e))?);
+        let value = Amount::from_u64(value).map_err(|_| format!("Invalid UTXO value {}", value))?;
+        let (key, compressed) = wif_to_secret_key(wif)?;
+
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp256k1::Secp256k1::signing_only(), &key);
+        let pubkey = if compressed {
+            pubkey.serialize().to_vec()
+        } else {
+            pubkey.serialize_uncompressed().to_vec()
+        };
+        if script_pubkey != TransparentAddress::PublicKey(hash160(&pubkey)).script() {
+            return Err(format!(
+                "The key doesn't spend {}:{}, its script pays someone else",
+                txid, vout
+            ));
+        }
+
+        self.add_transparent_input(outpoint, TxOut { value, script_pubkey }, key)
+    }
+
+    /// Add a P2SH transparent input, spent with `redeem_script` signed by `keys`. The
+    /// keys of a multisig input can be in any order.
+    pub fn add_transparent_script_input(
+        &mut self,
+        outpoint: transparent::OutPoint,
+        coin: TxOut,
+        redeem_script: Script,
+        keys: Vec<SecretKey>,
     ) -> Result<(), String> {
+        let keys = TransparentInputKeys::script_hash(&coin, redeem_script, keys)?;
+        self.transparent_inputs.push((outpoint, coin, keys));
+        self.transparent_sequences.push(DEFAULT_SEQUENCE);
+        Ok(())
+    }
+
+    /// Set the nSequence of a transparent input already added (defaults to
+    /// `DEFAULT_SEQUENCE`). The sighash and the serialized input both use it.
+    pub fn set_sequence(&mut self, input_index: usize, sequence: u32) -> Result<(), String> {
+        let slot = self.transparent_sequences.get_mut(input_index).ok_or_else(|| {
+            format!(
+                "No transparent input {} to set the sequence of ({} inputs)",
+                input_index,
+                self.transparent_inputs.len()
+            )
+        })?;
+        *slot = sequence;
+        Ok(())
+    }
+
+    /// Add a transparent output
+    pub fn add_transparent_output(&mut self, to: TransparentAddress, value: Amount) -> Result<(), String> {
+        check_dust(value, self.dust_threshold)?;
         self.transparent_outputs.push((to, value));
         Ok(())
     }
-    
-    /// Add a Sapling spend (shielded input)
+
+    /// Add a Sapling spend of the note `witness` is for. The path, the side each node
+    /// is on and the note's position all come from the witness.
+    pub fn add_sapling_spend_from_witness(
+        &mut self,
+        extsk: ExpandedSpendingKey,
+        diversifier: Diversifier,
+        note: Note,
+        witness: &IncrementalWitness<Node>,
+    ) -> Result<(), String> {
+        let path = witness.path().ok_or("Witness has no Merkle path")?;
+        let (merkle_path, position) = witness_path_nodes(&path)?;
+
+        if path.root(note_commitment_node(&note)) != witness.root() {
+            return Err("Witness is not for this note".to_string());
+        }
+
+        self.add_sapling_spend(extsk, diversifier, note, merkle_path, position)
+    }
+
+    /// Add a Sapling spend (shielded input). `merkle_path` holds the 32 sibling nodes
+    /// from the leaf up; which side each one is on follows from `witness_position`.
     pub fn add_sapling_spend(
         &mut self,
         extsk: ExpandedSpendingKey,
@@ -150,8 +506,12 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         merkle_path: Vec<Node>,
         witness_position: u64,
     ) -> Result<(), String> {
+        if let Some((anchor, tree)) = &self.pinned_anchor {
+            check_spend_anchor(&note, &merkle_path, witness_position, anchor, tree)?;
+        }
+
         let alpha = jubjub::Fr::random(&mut self.rng);
-        
+
         self.spending_keys.push(extsk);
         self.sapling_spends.push(ShieldedSpend {
             note,
@@ -160,274 +520,491 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             witness_position,
             alpha,
         });
-        
+
         Ok(())
     }
-    
-    /// Add a Sapling output
+
+    /// Add a Sapling output. With `ovk` set to None the outgoing ciphertext is
+    /// encrypted under a throwaway key, so not even the sending wallet can decrypt
+    /// the output later. The dust threshold doesn't apply: the node only counts
+    /// transparent outputs as dust.
     pub fn add_sapling_output(
         &mut self,
-        ovk: OutgoingViewingKey,
+        ovk: Option<OutgoingViewingKey>,
         to: PaymentAddress,
         value: Amount,
         memo: MemoBytes,
     ) -> Result<(), String> {
-        self.sapling_outputs.push(ShieldedOutput {
-            ovk,
-            to,
-            value,
-            memo,
-        });
-        
+        self.sapling_outputs.push(ShieldedOutput { ovk, to, value, memo });
+
         Ok(())
     }
-    
+
+    /// Add a Sapling output with a text memo, which has to fit in 512 bytes of UTF-8.
+    /// An empty `memo` is no memo.
+    pub fn add_sapling_output_with_text(
+        &mut self,
+        ovk: Option<OutgoingViewingKey>,
+        to: PaymentAddress,
+        value: Amount,
+        memo: &str,
+    ) -> Result<(), String> {
+        let memo = Memo::try_from(memo)?;
+        self.add_sapling_output(ovk, to, value, memo.into())
+    }
+
+    /// Estimate the serialized size and fee of the transaction without touching
+    /// the prover
+    pub fn estimate(&self) -> TxEstimate {
+        // Header + version group ID
+        let mut size = 8;
+
+        let transparent_input_sizes = self
+            .transparent_inputs
+            .iter()
+            .map(|(_, _, keys)| transparent_input_size(keys.max_script_sig_size()))
+            .collect::<Vec<_>>();
+        size += compact_size_len(self.transparent_inputs.len() as u64);
+        size += transparent_input_sizes.iter().sum::<usize>();
+
+        size += compact_size_len(self.transparent_outputs.len() as u64);
+        for (addr, _) in &self.transparent_outputs {
+            let script_len = addr.script().0.len();
+            size += 8 + compact_size_len(script_len as u64) + script_len;
+        }
+
+        // Lock time, expiry height, value balance
+        size += 4 + 4 + 8;
+
+        size += compact_size_len(self.sapling_spends.len() as u64);
+        size += self.sapling_spends.len() * SPEND_DESCRIPTION_SIZE;
+        let outputs = self.sapling_outputs.len() + self.padding_outputs();
+        size += compact_size_len(outputs as u64);
+        size += outputs * OUTPUT_DESCRIPTION_SIZE;
+
+        // JoinSplit count (always 0)
+        size += 1;
+
+        // The builder always writes a binding signature
+        size += BINDING_SIG_SIZE;
+
+        TxEstimate {
+            size,
+            fee: self.fee_policy.fee_for_size(size),
+            transparent_input_sizes,
+        }
+    }
+
+    /// The serialized size `estimate` works out from the component counts. There's no
+    /// segwit discount on BitcoinZ, so this is the weight as well; once built, the real
+    /// length is `BuiltTransaction::size`.
+    pub fn estimated_size(&self) -> usize {
+        self.estimate().size
+    }
+
     /// Build and sign the transaction
-    pub fn build<Pr: TxProver>(
+    pub fn build<Pr: TxProver>(self, prover: &Pr, fee: Amount) -> Result<BuiltTransaction, String> {
+        self.build_with_progress(prover, fee, |_| {})
+    }
+
+    /// Build and sign the transaction, calling `progress` as each phase starts.
+    /// The callback runs on the calling thread, between the (slow) proofs.
+    pub fn build_with_progress<Pr: TxProver>(
+        self,
+        prover: &Pr,
+        fee: Amount,
+        progress: impl FnMut(BuildProgress),
+    ) -> Result<BuiltTransaction, String> {
+        // Typed by what was asked for, before any padding outputs
+        let tx_type = detect_tx_type(
+            self.transparent_inputs.len(),
+            self.sapling_spends.len(),
+            self.transparent_outputs.len(),
+            self.sapling_outputs.len(),
+        );
+        let expiry_height = self.expiry_height.unwrap_or(0);
+
+        let mut tx_data = Vec::new();
+        self.build_to_writer_with_progress(prover, fee, &mut tx_data, progress)?;
+
+        Ok(BuiltTransaction::new(tx_data, fee, tx_type, expiry_height))
+    }
+
+    /// Build and sign the transaction, writing it straight to `writer` rather than
+    /// collecting it first. Returns the txid of what was written.
+    pub fn build_to_writer<Pr: TxProver, W: Write>(
+        self,
+        prover: &Pr,
+        fee: Amount,
+        writer: &mut W,
+    ) -> Result<TxId, String> {
+        self.build_to_writer_with_progress(prover, fee, writer, |_| {})
+    }
+
+    /// `build_to_writer`, calling `progress` as each phase starts. Only one spend or
+    /// output description is held in memory at a time, and if `writer` fails part of the
+    /// transaction may already have been written.
+    pub fn build_to_writer_with_progress<Pr: TxProver, W: Write>(
         mut self,
         prover: &Pr,
         fee: Amount,
-    ) -> Result<Vec<u8>, String> {
-        println!("BitcoinZ Builder: {} transparent inputs, {} transparent outputs, {} sapling spends, {} sapling outputs",
-                 self.transparent_inputs.len(), self.transparent_outputs.len(), 
-                 self.sapling_spends.len(), self.sapling_outputs.len());
-        // Calculate value balance
+        writer: &mut W,
+        mut progress: impl FnMut(BuildProgress),
+    ) -> Result<TxId, String> {
+        progress(BuildProgress::Selecting);
+        if self.canonical_order {
+            sort_bip69(
+                &mut self.transparent_inputs,
+                &mut self.transparent_sequences,
+                &mut self.transparent_outputs,
+            );
+        }
+        debug!(
+            "BitcoinZ Builder: {} transparent inputs, {} transparent outputs, {} sapling spends, {} sapling outputs",
+            self.transparent_inputs.len(),
+            self.transparent_outputs.len(),
+            self.sapling_spends.len(),
+            self.sapling_outputs.len()
+        );
+
+        // Fail before proving if SIGHASH_SINGLE can't be satisfied
+        if (self.sighash_type & 0x1f) == SIGHASH_SINGLE
+            && self.transparent_inputs.len() > self.transparent_outputs.len()
+        {
+            return Err(format!(
+                "SIGHASH_SINGLE requires a transparent output for every transparent input ({} inputs, {} outputs)",
+                self.transparent_inputs.len(),
+                self.transparent_outputs.len()
+            ));
+        }
+
+        // Calculate value balance (also checks that the transparent and shielded
+        // sides together balance against the fee)
         let value_balance = self.calculate_value_balance(fee)?;
-        
-        // Generate binding signature key
-        let mut bsk = jubjub::Fr::zero();
-        
+
+        // A fee that's out of proportion to the size is more likely a mistake than
+        // a deliberate overpayment, and a transaction over the relay limit would only
+        // be refused after all the proving
+        let estimated_size = self.estimate().size;
+        check_fee(fee, estimated_size)?;
+        check_size(estimated_size)?;
+
+        // Zero-value dummy outputs leave the value balance as it is. They're proved like
+        // any other output, so their value commitment randomness goes into bsk too.
+        self.add_padding_outputs();
+
+        // One proving context for the whole transaction. It accumulates the value
+        // commitment randomness, which is the binding signing key.
+        let mut ctx = prover.new_sapling_proving_context();
+
         // Build spend descriptions
         let mut shielded_spends = Vec::new();
         for (i, spend) in self.sapling_spends.iter().enumerate() {
-            let spend_desc = self.build_spend_description(
-                prover,
-                &self.spending_keys[i],
-                spend,
-                &mut bsk,
-            )?;
+            progress(BuildProgress::ProvingSpend {
+                current: i + 1,
+                total: self.sapling_spends.len(),
+            });
+            let spend_desc = self.build_spend_description(prover, &self.spending_keys[i], spend, &mut ctx)?;
             shielded_spends.push(spend_desc);
         }
-        
-        // Build output descriptions
+
+        // Build output descriptions. They draw from self.rng, so the outputs are
+        // taken out of self while they're built.
+        let sapling_outputs = std::mem::take(&mut self.sapling_outputs);
         let mut shielded_outputs = Vec::new();
-        for (i, output) in self.sapling_outputs.iter().enumerate() {
-            println!("BitcoinZ: Building output {} to address {:?}", i, output.to);
-            let output_desc = self.build_output_description(
-                prover,
-                output,
-                &mut bsk,
-            )?;
+        for (i, output) in sapling_outputs.iter().enumerate() {
+            progress(BuildProgress::ProvingOutput {
+                current: i + 1,
+                total: sapling_outputs.len(),
+            });
+            trace!("BitcoinZ: Building output {}", i);
+            let output_desc = self.build_output_description(prover, output, &mut ctx)?;
             shielded_outputs.push(output_desc);
         }
-        
-        // Convert bsk to RedJubjub private key
-        let bsk = PrivateKey(bsk);
-        
-        // Compute binding verification key
-        // Compute bvk = bsk * G on the Jubjub curve
-        // Compute bvk directly from bsk
-        // Compute bvk = bsk * G on the Jubjub curve
-        let bvk = PublicKey::from_private(&bsk, jubjub::SubgroupPoint::generator());
-        
-        // Now build the full transaction
-        let mut tx_data = Vec::new();
-        
-        // Write transaction header
-        self.write_header(&mut tx_data)?;
-        println!("BitcoinZ: After header, tx size: {} bytes", tx_data.len());
-        
-        // Write transparent inputs
-        self.write_transparent_inputs(&mut tx_data)?;
-        println!("BitcoinZ: After transparent inputs, tx size: {} bytes", tx_data.len());
-        
-        // Write transparent outputs  
-        self.write_transparent_outputs(&mut tx_data)?;
-        println!("BitcoinZ: After transparent outputs, tx size: {} bytes", tx_data.len());
-        
-        // Write lock time and expiry
-        tx_data.write_u32::<LittleEndian>(0).map_err(|e| e.to_string())?; // lock_time
-        tx_data.write_u32::<LittleEndian>(0).map_err(|e| e.to_string())?; // expiry_height
-        println!("BitcoinZ: After locktime/expiry, tx size: {} bytes", tx_data.len());
-        
-        // Write value balance
-        println!("BitcoinZ: Writing value balance: {} ({:#x})", value_balance, value_balance);
-        tx_data.write_i64::<LittleEndian>(value_balance).map_err(|e| e.to_string())?;
-        println!("BitcoinZ: After value balance, tx size: {} bytes", tx_data.len());
-        
-        // Write shielded spends
-        println!("BitcoinZ: Writing {} shielded spends", shielded_spends.len());
-        write_compact_size(&mut tx_data, shielded_spends.len() as u64)?;
+        self.sapling_outputs = sapling_outputs;
+
+        progress(BuildProgress::Signing);
+
+        // Compute the shielded sighash up front. It commits to the transparent
+        // inputs/outputs and to the spend/output descriptions (minus signatures),
+        // so it is the same whatever mix of components the transaction carries.
+        let sighash = self.compute_binding_sig_sighash(&shielded_spends, &shielded_outputs, value_balance)?;
+
+        // Spend authorization signatures over the same sighash
+        for (i, spend_desc) in shielded_spends.iter_mut().enumerate() {
+            spend_desc.spend_auth_sig = spend_sig(
+                PrivateKey(self.spending_keys[i].ask),
+                self.sapling_spends[i].alpha,
+                &sighash,
+                &mut self.rng,
+            );
+        }
+
+        // Compute BitcoinZ binding signature over bvk || sighash. The prover refuses
+        // if its bsk doesn't match the value commitments and the value balance.
+        trace!("BitcoinZ: Computing BitcoinZ binding signature with 64-byte message");
+        let value_balance_amount =
+            Amount::from_i64(value_balance).map_err(|_| format!("Invalid value balance {}", value_balance))?;
+        let binding_sig = prover
+            .binding_sig(&mut ctx, value_balance_amount, &sighash)
+            .map_err(|_| {
+                format!(
+                    "Failed to create binding signature: the value commitments do not add up to the value balance ({})",
+                    value_balance
+                )
+            })?;
+
+        // Check it the way the node will, rather than finding out from a rejected broadcast
+        if self.verify_on_build {
+            let bvk = binding_verification_key(
+                &shielded_spends.iter().map(|s| s.cv).collect::<Vec<_>>(),
+                &shielded_outputs.iter().map(|o| o.cv).collect::<Vec<_>>(),
+                value_balance,
+            );
+            verify_binding_signature(&bvk, &sighash, &binding_sig)?;
+        }
+
+        // The transparent signatures commit to the shielded descriptions but not to the
+        // binding signature, so they're ready before anything is written
+        if !self.transparent_inputs.is_empty() {
+            trace!("BitcoinZ: Signing {} transparent inputs", self.transparent_inputs.len());
+        }
+        let script_sigs = self.transparent_script_sigs(&shielded_spends, &shielded_outputs, value_balance)?;
+
+        progress(BuildProgress::Serializing);
+
+        // Write the transaction a section at a time
+        let mut out = TxidWriter::new(writer);
+        let mut section = Vec::new();
+
+        self.write_header(&mut section)?;
+        self.write_transparent_inputs(&mut section, &script_sigs)?;
+        self.write_transparent_outputs(&mut section)?;
+
+        // Lock time and expiry
+        section.write_u32::<LittleEndian>(0).map_err(|e| e.to_string())?; // lock_time
+        section
+            .write_u32::<LittleEndian>(self.expiry_height.unwrap_or(0))
+            .map_err(|e| e.to_string())?; // expiry_height
+
+        section
+            .write_i64::<LittleEndian>(value_balance)
+            .map_err(|e| e.to_string())?;
+        write_section(&mut out, &mut section)?;
+
+        write_compact_size(&mut section, shielded_spends.len() as u64);
         for spend in &shielded_spends {
-            self.write_spend_description(&mut tx_data, spend)?;
+            self.write_spend_description(&mut section, spend)?;
+            write_section(&mut out, &mut section)?;
         }
-        println!("BitcoinZ: After shielded spends, tx size: {} bytes", tx_data.len());
-        
-        // Write shielded outputs
-        println!("BitcoinZ: Writing {} shielded outputs", shielded_outputs.len());
-        write_compact_size(&mut tx_data, shielded_outputs.len() as u64)?;
-        let output_start = tx_data.len();
-        for (i, output) in shielded_outputs.iter().enumerate() {
-            println!("BitcoinZ: Writing output description {}", i);
-            println!("  cv: {} bytes", output.cv.to_bytes().len());
-            println!("  cmu: {} bytes", output.cmu.to_repr().len());
-            println!("  ephemeral_key: {} bytes", output.ephemeral_key.0.len());
-            println!("  enc_ciphertext: {} bytes", output.enc_ciphertext.len());
-            println!("  out_ciphertext: {} bytes", output.out_ciphertext.len());
-            println!("  zkproof: {} bytes", output.zkproof.len());
-            let before_output = tx_data.len();
-            self.write_output_description(&mut tx_data, output)?;
-            println!("  Output {} size: {} bytes", i, tx_data.len() - before_output);
+        write_section(&mut out, &mut section)?;
+
+        write_compact_size(&mut section, shielded_outputs.len() as u64);
+        for output in &shielded_outputs {
+            self.write_output_description(&mut section, output)?;
+            write_section(&mut out, &mut section)?;
         }
-        println!("BitcoinZ: Total shielded outputs size: {} bytes", tx_data.len() - output_start);
-        println!("BitcoinZ: After shielded outputs, tx size: {} bytes", tx_data.len());
-        
+        write_section(&mut out, &mut section)?;
+
         // No JoinSplits in v4
-        write_compact_size(&mut tx_data, 0)?;
-        println!("BitcoinZ: After JoinSplits count (0), tx size: {} bytes", tx_data.len());
-        
-        // Compute sighash for binding signature (before adding the signature)
-        println!("BitcoinZ: Computing binding signature sighash");
-        let sighash = self.compute_binding_sig_sighash(
-            &shielded_spends,
-            &shielded_outputs,
-            value_balance,
-        )?;
-        println!("BitcoinZ: Binding signature sighash: {}", hex::encode(&sighash));
-        
-        // Compute BitcoinZ binding signature
-        println!("BitcoinZ: Computing BitcoinZ binding signature with 64-byte message");
-        let binding_sig = compute_bitcoinz_binding_signature(&bsk, &bvk, &sighash)?;
-        
+        write_compact_size(&mut section, 0);
+
         // Write binding signature
-        // Serialize the signature
         let sig_bytes = {
             let mut bytes = [0u8; 64];
             binding_sig.write(&mut bytes[..]).map_err(|e| e.to_string())?;
             bytes
         };
-        println!("BitcoinZ: Binding signature bytes (hex): {}", hex::encode(&sig_bytes));
-        tx_data.write_all(&sig_bytes).map_err(|e| e.to_string())?;
-        println!("BitcoinZ: After binding signature, tx size: {} bytes", tx_data.len());
-        
-        // Now sign the transparent inputs if any
-        if !self.transparent_inputs.is_empty() {
-            println!("BitcoinZ: Signing {} transparent inputs", self.transparent_inputs.len());
-            tx_data = self.sign_transparent_inputs(
-                tx_data,
-                &shielded_spends,
-                &shielded_outputs,
-                value_balance,
-            )?;
-        }
-        
-        // Debug: Print transaction hex
-        println!("BitcoinZ: Final transaction size: {} bytes", tx_data.len());
-        println!("BitcoinZ: Transaction structure summary:");
-        println!("  Header: 8 bytes");
-        println!("  Transparent inputs: {} count", self.transparent_inputs.len());
-        println!("  Transparent outputs: {} count", self.transparent_outputs.len());
-        println!("  Shielded spends: {} count", shielded_spends.len());
-        println!("  Shielded outputs: {} count", shielded_outputs.len());
-        println!("  Value balance: {}", value_balance);
-        println!("BitcoinZ: Transaction hex: {}", hex::encode(&tx_data));
-        
-        Ok(tx_data)
-    }
-    
+        section.write_all(&sig_bytes).map_err(|e| e.to_string())?;
+        write_section(&mut out, &mut section)?;
+
+        out.flush().map_err(|e| format!("Failed to write transaction: {}", e))?;
+
+        debug!("BitcoinZ: Built a {} byte transaction", out.written());
+
+        Ok(out.txid())
+    }
+
     // Helper methods continue below...
+
+    /// Pad the Sapling outputs up to the minimum with zero-value notes, each to a fresh
+    /// internal address that no one has the key for
+    fn add_padding_outputs(&mut self) {
+        let padding = self.padding_outputs();
+        if padding > 0 {
+            trace!("BitcoinZ: Padding with {} dummy sapling outputs", padding);
+        }
+
+        for _ in 0..padding {
+            let mut seed = [0u8; 32];
+            self.rng.fill_bytes(&mut seed);
+            let extfvk = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&seed));
+
+            self.sapling_outputs.push(ShieldedOutput {
+                ovk: None,
+                to: extfvk.derive_internal().default_address().1,
+                value: Amount::zero(),
+                memo: MemoBytes::empty(),
+            });
+        }
+    }
+}
+
+/// Check that a point as serialized into the transaction decodes back to a point in the
+/// prime-order subgroup, as bitcoinzd requires of cv and epk. Rejecting small-order
+/// points isn't enough, since a point with a small-order component isn't small order
+/// itself. This also catches a bad bellman encoding.
+fn check_prime_subgroup_encoding(name: &str, bytes: &[u8]) -> Result<(), String> {
+    let encoding: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| format!("{} is {} bytes, not 32", name, bytes.len()))?;
+
+    if jubjub::SubgroupPoint::from_bytes(&encoding).is_none().into() {
+        return Err(format!(
+            "{} {} is not the encoding of a point in the prime-order subgroup",
+            name,
+            hex::encode(encoding)
+        ));
+    }
+
+    Ok(())
 }
 
-/// Compute BitcoinZ binding signature with 64-byte message
-fn compute_bitcoinz_binding_signature(
-    bsk: &PrivateKey,
-    bvk: &PublicKey, 
-    sighash: &[u8; 32],
-) -> Result<Signature, String> {
-    // BitcoinZ expects: sign(bsk, bvk || sighash)
-    let mut message = [0u8; 64];
-    // Serialize bvk
-    let mut bvk_bytes = [0u8; 32];
-    bvk.write(&mut bvk_bytes[..]).map_err(|e| e.to_string())?;
-    message[..32].copy_from_slice(&bvk_bytes);
-    message[32..].copy_from_slice(sighash);
-    
-    // Sign the 64-byte message
-    let mut rng = thread_rng();
-    // Sign with the generator point
-    let generator = jubjub::SubgroupPoint::generator();
-    Ok(bsk.sign(&message, &mut rng, generator))
+/// Check a BitcoinZ binding signature: RedJubjub over the 64-byte message bvk || sighash
+pub fn verify_bitcoinz_binding_signature(bvk: &PublicKey, sighash: &[u8; 32], sig: &Signature) -> bool {
+    let message = compute_bitcoinz_binding_message(&bvk.0.to_bytes(), sighash);
+    bvk.verify(&message, sig, VALUE_COMMITMENT_RANDOMNESS_GENERATOR)
 }
 
-/// Write compact size
-fn write_compact_size(writer: &mut Vec<u8>, size: u64) -> Result<(), String> {
-    if size < 0xfd {
-        writer.push(size as u8);
-    } else if size <= 0xffff {
-        writer.push(0xfd);
-        writer.write_u16::<LittleEndian>(size as u16)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else if size <= 0xffffffff {
-        writer.push(0xfe);
-        writer.write_u32::<LittleEndian>(size as u32)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
-    } else {
-        writer.push(0xff);
-        writer.write_u64::<LittleEndian>(size)
-            .map_err(|e| format!("Failed to write compact size: {}", e))?;
+/// `verify_bitcoinz_binding_signature`, with an error saying what didn't verify
+fn verify_binding_signature(bvk: &PublicKey, sighash: &[u8; 32], binding_sig: &Signature) -> Result<(), String> {
+    if !verify_bitcoinz_binding_signature(bvk, sighash, binding_sig) {
+        // The error ends up in the logs, so it doesn't carry the sighash
+        return Err("Binding signature does not verify against the value commitments and sighash".to_string());
     }
+
     Ok(())
 }
 
-/// Read a variable-length integer
-fn read_compact_size(data: &[u8]) -> Result<Option<(u64, usize)>, String> {
-    if data.is_empty() {
-        return Ok(None);
-    }
-    
-    let first = data[0];
-    match first {
-        0..=0xfc => Ok(Some((first as u64, 1))),
-        0xfd => {
-            if data.len() < 3 {
-                return Ok(None);
-            }
-            let val = u16::from_le_bytes([data[1], data[2]]) as u64;
-            Ok(Some((val, 3)))
-        }
-        0xfe => {
-            if data.len() < 5 {
-                return Ok(None);
-            }
-            let val = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as u64;
-            Ok(Some((val, 5)))
-        }
-        0xff => {
-            if data.len() < 9 {
-                return Ok(None);
-            }
-            let val = u64::from_le_bytes([
-                data[1], data[2], data[3], data[4],
-                data[5], data[6], data[7], data[8]
-            ]);
-            Ok(Some((val, 9)))
+/// Write out a serialized section of the transaction and empty it for the next one
+fn write_section<W: Write>(out: &mut W, section: &mut Vec<u8>) -> Result<(), String> {
+    out.write_all(section)
+        .map_err(|e| format!("Failed to write transaction: {}", e))?;
+    section.clear();
+    Ok(())
+}
+
+/// Serialized bytes of a tree node
+fn node_bytes(node: &Node) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    node.write(&mut bytes[..]).expect("A node is 32 bytes");
+    bytes
+}
+
+/// Leaf node for a note's commitment
+fn note_commitment_node(note: &Note) -> Node {
+    Node::new(note.cmu().to_repr())
+}
+
+/// Turn the 32 sibling nodes of a witness into a Merkle path. Each level's side
+/// comes from the matching bit of the note's position.
+pub(crate) fn spend_merkle_path(merkle_path: &[Node], position: u64) -> Result<MerklePath<Node>, String> {
+    if merkle_path.len() != 32 {
+        return Err("Invalid merkle path length: expected 32 nodes".to_string());
+    }
+
+    let auth_path = merkle_path
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (*node, (position >> i) & 1 == 1))
+        .collect();
+
+    Ok(MerklePath::from_path(auth_path, position))
+}
+
+/// The sibling nodes and position of a witness's Merkle path, checking that the side of
+/// each node agrees with the position, as `spend_merkle_path` will rebuild it from there
+pub(crate) fn witness_path_nodes(path: &MerklePath<Node>) -> Result<(Vec<Node>, u64), String> {
+    if path.auth_path.len() != 32 {
+        return Err("Invalid merkle path length: expected 32 nodes".to_string());
+    }
+
+    for (i, (_, is_right)) in path.auth_path.iter().enumerate() {
+        if *is_right != ((path.position >> i) & 1 == 1) {
+            return Err(format!(
+                "Merkle path level {} is on the wrong side for position {}",
+                i, path.position
+            ));
         }
     }
+
+    Ok((path.auth_path.iter().map(|(node, _)| *node).collect(), path.position))
 }
 
-/// Compute hash of all shielded spends
-fn compute_shielded_spends_hash(
-    spends: &[SpendDescription<Authorized>],
-) -> Result<[u8; 32], String> {
+/// Check that a spend's witness is for a note in the pinned tree and has the pinned anchor as its root
+fn check_spend_anchor(
+    note: &Note,
+    merkle_path: &[Node],
+    position: u64,
+    anchor: &Node,
+    tree: &CommitmentTree<Node>,
+) -> Result<(), String> {
+    if position >= tree.size() as u64 {
+        return Err(format!(
+            "Witness position {} is outside the pinned tree ({} notes)",
+            position,
+            tree.size()
+        ));
+    }
+
+    let root = spend_merkle_path(merkle_path, position)?.root(note_commitment_node(note));
+    if root != *anchor {
+        return Err(format!(
+            "Witness root {} does not match the pinned anchor {}",
+            hex::encode(node_bytes(&root)),
+            hex::encode(node_bytes(anchor))
+        ));
+    }
+
+    Ok(())
+}
+
+/// Compute the ZIP-243 outputs hash over a set of transparent outputs
+fn hash_transparent_outputs(outputs: &[(TransparentAddress, Amount)]) -> Result<[u8; 32], String> {
     let mut data = Vec::new();
-    
-    for spend in spends {
-        // cv
-        data.write_all(&spend.cv.to_bytes())
-            .map_err(|e| format!("Failed to write cv: {}", e))?;
-        // anchor
-        data.write_all(&spend.anchor.to_repr())
-            .map_err(|e| format!("Failed to write anchor: {}", e))?;
+
+    for (addr, amount) in outputs {
+        // Write amount (8 bytes)
+        write_amount(&mut data, *amount);
+
+        // Write script pubkey
+        write_script(&mut data, &addr.script());
+    }
+
+    let hash = Params::new()
+        .hash_length(32)
+        .personal(ZCASH_OUTPUTS_HASH_PERSONALIZATION)
+        .to_state()
+        .update(&data)
+        .finalize();
+
+    let mut result = [0u8; 32];
+    result.copy_from_slice(hash.as_bytes());
+
+    Ok(result)
+}
+
+/// Compute hash of all shielded spends
+fn compute_shielded_spends_hash(spends: &[SpendDescription<Authorized>]) -> Result<[u8; 32], String> {
+    let mut data = Vec::new();
+
+    for spend in spends {
+        // cv
+        data.write_all(&spend.cv.to_bytes())
+            .map_err(|e| format!("Failed to write cv: {}", e))?;
+        // anchor
+        data.write_all(&spend.anchor.to_repr())
+            .map_err(|e| format!("Failed to write anchor: {}", e))?;
         // nullifier
         data.write_all(&spend.nullifier.0)
             .map_err(|e| format!("Failed to write nullifier: {}", e))?;
@@ -440,26 +1017,24 @@ fn compute_shielded_spends_hash(
         data.write_all(&spend.zkproof)
             .map_err(|e| format!("Failed to write zkproof: {}", e))?;
     }
-    
+
     let hash = Params::new()
         .hash_length(32)
         .personal(ZCASH_SHIELDED_SPENDS_HASH_PERSONALIZATION)
         .to_state()
         .update(&data)
         .finalize();
-    
+
     let mut result = [0u8; 32];
     result.copy_from_slice(hash.as_bytes());
-    
+
     Ok(result)
 }
 
 /// Compute hash of all shielded outputs
-fn compute_shielded_outputs_hash(
-    outputs: &[OutputDescription<GrothProofBytes>],
-) -> Result<[u8; 32], String> {
+fn compute_shielded_outputs_hash(outputs: &[OutputDescription<GrothProofBytes>]) -> Result<[u8; 32], String> {
     let mut data = Vec::new();
-    
+
     for output in outputs {
         // cv
         data.write_all(&output.cv.to_bytes())
@@ -480,294 +1055,242 @@ fn compute_shielded_outputs_hash(
         data.write_all(&output.zkproof)
             .map_err(|e| format!("Failed to write zkproof: {}", e))?;
     }
-    
+
     let hash = Params::new()
         .hash_length(32)
         .personal(ZCASH_SHIELDED_OUTPUTS_HASH_PERSONALIZATION)
         .to_state()
         .update(&data)
         .finalize();
-    
+
     let mut result = [0u8; 32];
     result.copy_from_slice(hash.as_bytes());
-    
+
     Ok(result)
 }
 
-
-impl<P: Parameters> BitcoinZShieldedBuilder<P> {
-    /// Calculate the value balance for the transaction
-    fn calculate_value_balance(&self, fee: Amount) -> Result<i64, String> {
-        let mut total_in = 0i64;
-        let mut total_out = 0i64;
-        
-        // Add transparent inputs
-        for (_, coin, _) in &self.transparent_inputs {
-            total_in += i64::from(coin.value);
+impl<P: Parameters, R: RngCore + CryptoRng> BitcoinZShieldedBuilder<P, R> {
+    /// Calculate the Sapling value balance for the transaction
+    ///
+    /// The value balance only covers the shielded side (spends minus outputs).
+    /// The transparent side must make up the difference together with the fee,
+    /// which holds for any mix of t-inputs, t-outputs, spends and outputs.
+    /// Neither side may add up to more than MAX_MONEY, which also keeps the balance
+    /// itself within MAX_MONEY either way.
+    fn calculate_value_balance(&self, fee: Amount) -> Result<i64, ValueBalanceError> {
+        let fee = i64::from(fee);
+        if fee < 0 {
+            return Err(ValueBalanceError::NegativeFee(fee));
         }
-        
-        // Add shielded inputs (spends)
-        for spend in &self.sapling_spends {
-            total_in += spend.note.value as i64;
-        }
-        
-        // Subtract transparent outputs
-        for (_, amount) in &self.transparent_outputs {
-            total_out += i64::from(*amount);
+
+        let transparent_in = checked_sum(self.transparent_inputs.iter().map(|(_, coin, _)| i64::from(coin.value)))?;
+        let transparent_out = checked_sum(self.transparent_outputs.iter().map(|(_, amount)| i64::from(*amount)))?;
+
+        // Note values are u64, so they may not even fit in an i64
+        let spent = self
+            .sapling_spends
+            .iter()
+            .try_fold(0i64, |total, spend| {
+                i64::try_from(spend.note.value)
+                    .ok()
+                    .and_then(|value| total.checked_add(value))
+            })
+            .ok_or(ValueBalanceError::Overflow)?;
+        let shielded_out = checked_sum(self.sapling_outputs.iter().map(|output| i64::from(output.value)))?;
+
+        // Spends minus outputs
+        let value_balance = spent.checked_sub(shielded_out).ok_or(ValueBalanceError::Overflow)?;
+
+        // Whatever leaves the shielded pool plus the transparent inputs must
+        // pay for the transparent outputs and the fee exactly
+        let total_in = within_max_money(transparent_in.checked_add(spent).ok_or(ValueBalanceError::Overflow)?)?;
+        let total_out = within_max_money(checked_sum(vec![transparent_out, shielded_out, fee])?)?;
+
+        if total_in < total_out {
+            return Err(ValueBalanceError::InsufficientFunds {
+                missing: (total_out - total_in) as u64,
+            });
         }
-        
-        // Subtract shielded outputs
-        for output in &self.sapling_outputs {
-            total_out += i64::from(output.value);
+        if total_in > total_out {
+            return Err(ValueBalanceError::Unbalanced {
+                excess: (total_in - total_out) as u64,
+            });
         }
-        
-        // Subtract fee
-        total_out += i64::from(fee);
-        
-        // Value balance is the net flow from transparent to shielded
+
         // Negative means funds flowing into shielded pool (t→z)
         // Positive means funds flowing out of shielded pool (z→t)
-        Ok(total_in - total_out)
+        Ok(value_balance)
     }
-    
+
     /// Build a spend description
     fn build_spend_description<Pr: TxProver>(
         &self,
         prover: &Pr,
         extsk: &ExpandedSpendingKey,
         spend: &ShieldedSpend,
-        bsk: &mut jubjub::Fr,
+        ctx: &mut Pr::SaplingProvingContext,
     ) -> Result<SpendDescription<Authorized>, String> {
         // Derive the full viewing key
         let fvk = FullViewingKey::from_expanded_spending_key(extsk);
-        
-        // Compute nullifier
+
+        // The nullifier commits to where the note is in the tree
         let nk = fvk.vk.nk;
-        let nullifier = spend.note.nf(&nk, 0); // position 0 for now
-        
-        // Randomize the spend authorization key
-        // Randomize the spend authorization key
-        let rsk = PrivateKey(extsk.ask + spend.alpha);
-        
-        // Create value commitment
-        let rcv = jubjub::Fr::random(&mut thread_rng());
-        // Create value commitment - the cv will be computed by the prover
-        // We just need to track rcv for the binding signature key
-        
-        // Update binding signature key
-        *bsk += rcv;
-        
-        // Generate proof
-        // For v0.7, we need to create the proof context and use different parameters
-        let mut ctx = prover.new_sapling_proving_context();
-        
-        // Use the diversifier from the spend
+        let nullifier = spend.note.nf(&nk, spend.witness_position);
+
+        // Generate proof. The prover picks the value commitment randomness and
+        // adds it to the binding signing key in the context.
+
+        // Use the diversifier from the spend. The prover derives g_d from it and fails
+        // without saying why when there isn't one, so check first.
         let diversifier = spend.diversifier;
-        
-        // Create merkle path from the witness nodes
-        if spend.merkle_path.len() != 32 {
-            return Err("Invalid merkle path length: expected 32 nodes".to_string());
-        }
-        
-        let mut auth_path = Vec::with_capacity(32);
-        for node in &spend.merkle_path {
-            auth_path.push((*node, false)); // The bool indicates whether this is the right child
+        if diversifier.g_d().is_none() {
+            return Err(BitcoinZTxError::InvalidDiversifier(diversifier.0).into());
         }
-        
-        let merkle_path = zcash_primitives::merkle_tree::MerklePath {
-            auth_path: auth_path.try_into()
-                .map_err(|_| "Failed to convert merkle path")?,
-            position: spend.witness_position,
+
+        // Create merkle path from the witness nodes
+        let merkle_path = spend_merkle_path(&spend.merkle_path, spend.witness_position)?;
+
+        // The anchor is the pinned one if set (add_sapling_spend already checked the
+        // witness against it), otherwise the root the witness commits to
+        let anchor = match &self.pinned_anchor {
+            Some((anchor, _)) => *anchor,
+            None => merkle_path.root(note_commitment_node(&spend.note)),
         };
-        
+        let anchor = bls12_381::Scalar::from_bytes(&node_bytes(&anchor)).unwrap();
+
+        // The proof shows the note is in the tree with this root, so it takes the same
+        // anchor as the description
         let (proof, cv_proof, rk) = prover
             .spend_proof(
-                &mut ctx,
+                ctx,
                 extsk.proof_generation_key(),
                 diversifier,
                 spend.note.rseed,
                 spend.alpha,
                 spend.note.value,
-                spend.note.cmu(),
+                anchor,
                 merkle_path,
             )
             .map_err(|e| format!("Failed to create spend proof: {:?}", e))?;
-        
+
         // Create spend auth signature (placeholder for now)
-        let spend_auth_sig = Signature::read(&[0u8; 64][..])
-            .map_err(|e| format!("Failed to create spend auth sig: {:?}", e))?;
-        
-        // Get the anchor from merkle path root
-        // For now use a placeholder anchor - this might be the issue!
-        let anchor = [0u8; 32];
-        println!("BitcoinZ WARNING: Using placeholder anchor, this needs to be fixed!");
-        
+        let spend_auth_sig =
+            Signature::read(&[0u8; 64][..]).map_err(|e| format!("Failed to create spend auth sig: {:?}", e))?;
+
         Ok(SpendDescription {
             cv: cv_proof.into(),
-            anchor: bls12_381::Scalar::from_bytes(&anchor).unwrap(),
+            anchor,
             nullifier,
             rk,
             zkproof: proof,
             spend_auth_sig,
         })
     }
-    
+
+    /// Build an output description with bitcore-lib-btcz through the Node.js bridge
+    #[cfg(feature = "js-bridge")]
+    fn build_output_description_js(
+        &self,
+        output: &ShieldedOutput,
+    ) -> Result<OutputDescription<GrothProofBytes>, String> {
+        trace!("BitcoinZ: Using JavaScript bridge to generate shielded output");
+
+        let js_output = js_generate_shielded_output(&self.params, &output.to, output.value, &output.memo)?;
+        trace!("BitcoinZ: Successfully generated output using JS bridge");
+
+        // Parse the components from JS bridge
+        if js_output.cv.len() != 32 {
+            return Err(format!("Invalid cv length from JS: {}", js_output.cv.len()));
+        }
+        if js_output.cmu.len() != 32 {
+            return Err(format!("Invalid cmu length from JS: {}", js_output.cmu.len()));
+        }
+        if js_output.ephemeral_key.len() != 32 {
+            return Err(format!(
+                "Invalid ephemeral_key length from JS: {}",
+                js_output.ephemeral_key.len()
+            ));
+        }
+        if js_output.zkproof.len() != GROTH_PROOF_SIZE {
+            return Err(format!("Invalid zkproof length from JS: {}", js_output.zkproof.len()));
+        }
+
+        // The randomness behind this cv never reaches the proving context, so
+        // the binding signature check in build() will reject the transaction
+
+        // Convert to proper types
+        let cv = jubjub::ExtendedPoint::from_bytes(&js_output.cv.try_into().unwrap()).unwrap();
+        let cmu = bls12_381::Scalar::from_bytes(&js_output.cmu.try_into().unwrap()).unwrap();
+        let ephemeral_key = zcash_note_encryption::EphemeralKeyBytes(js_output.ephemeral_key.try_into().unwrap());
+
+        Ok(OutputDescription {
+            cv: cv.into(),
+            cmu,
+            ephemeral_key,
+            enc_ciphertext: js_output.enc_ciphertext.try_into().unwrap(),
+            out_ciphertext: js_output.out_ciphertext.try_into().unwrap(),
+            zkproof: js_output.zkproof.try_into().unwrap(),
+        })
+    }
+
     /// Build an output description
     fn build_output_description<Pr: TxProver>(
-        &self,
+        &mut self,
         prover: &Pr,
         output: &ShieldedOutput,
-        bsk: &mut jubjub::Fr,
+        ctx: &mut Pr::SaplingProvingContext,
     ) -> Result<OutputDescription<GrothProofBytes>, String> {
-        println!("BitcoinZ: Building output description");
-        println!("  Payment address: {:?}", output.to);
-        println!("  Value: {:?}", output.value);
-        println!("  Memo: {} bytes", output.memo.as_array().len());
-        
-        println!("BitcoinZ: Using JavaScript bridge to generate shielded output");
-        
-        // Try using the JavaScript bridge first
-        match js_generate_shielded_output(
-            &self.params,
-            &output.to,
-            output.value,
-            &output.memo,
-        ) {
-            Ok(js_output) => {
-                println!("BitcoinZ: Successfully generated output using JS bridge");
-                
-                // Parse the components from JS bridge
-                if js_output.cv.len() != 32 {
-                    return Err(format!("Invalid cv length from JS: {}", js_output.cv.len()));
-                }
-                if js_output.cmu.len() != 32 {
-                    return Err(format!("Invalid cmu length from JS: {}", js_output.cmu.len()));
-                }
-                if js_output.ephemeral_key.len() != 32 {
-                    return Err(format!("Invalid ephemeral_key length from JS: {}", js_output.ephemeral_key.len()));
-                }
-                if js_output.zkproof.len() != GROTH_PROOF_SIZE {
-                    return Err(format!("Invalid zkproof length from JS: {}", js_output.zkproof.len()));
-                }
-                
-                // Convert cv bytes to ExtendedPoint for bsk calculation
-                // Note: This is approximate - we're using a placeholder rcv value
-                let rcv = jubjub::Fr::random(&mut thread_rng());
-                *bsk -= rcv;
-                
-                // Convert to proper types
-                let cv = jubjub::ExtendedPoint::from_bytes(&js_output.cv.try_into().unwrap()).unwrap();
-                let cmu = bls12_381::Scalar::from_bytes(&js_output.cmu.try_into().unwrap()).unwrap();
-                let ephemeral_key = zcash_note_encryption::EphemeralKeyBytes(js_output.ephemeral_key.try_into().unwrap());
-                
-                return Ok(OutputDescription {
-                    cv: cv.into(),
-                    cmu,
-                    ephemeral_key,
-                    enc_ciphertext: js_output.enc_ciphertext.try_into().unwrap(),
-                    out_ciphertext: js_output.out_ciphertext.try_into().unwrap(),
-                    zkproof: js_output.zkproof.try_into().unwrap(),
-                });
-            }
-            Err(e) => {
-                println!("BitcoinZ: JS bridge failed: {}, falling back to native implementation", e);
-            }
+        // The Node.js bridge is only tried when it is compiled in; the native path below is
+        // what a default build uses
+        #[cfg(feature = "js-bridge")]
+        match self.build_output_description_js(output) {
+            Ok(js_output) => return Ok(js_output),
+            Err(e) => debug!(
+                "BitcoinZ: JS bridge failed: {}, falling back to native implementation",
+                e
+            ),
         }
-        
-        // Fallback to native implementation
-        println!("BitcoinZ: Using native implementation for output description");
-        
+
         // Generate note
-        let rseed = Rseed::AfterZip212(thread_rng().gen());
-        println!("  Generated rseed");
-        
-        let note = output.to.create_note(
-            u64::from(output.value),
-            rseed,
-        ).ok_or("Failed to create note")?;
-        println!("  Created note with value: {}", note.value);
-        println!("  Note cmu: {}", hex::encode(note.cmu().to_repr()));
-        
-        // Create value commitment
-        let rcv = jubjub::Fr::random(&mut thread_rng());
-        println!("  Generated rcv for value commitment");
-        
-        // Update binding signature key (negative for outputs)
-        *bsk -= rcv;
-        
-        // Generate proof first to get cv
-        // For v0.7, we need the proving context
-        println!("  Creating proving context");
-        let mut ctx = prover.new_sapling_proving_context();
-        
-        println!("  Generating output proof with:");
-        println!("    rcv: {:?}", rcv);
-        println!("    payment_address pk_d: {:?}", output.to.pk_d());
-        println!("    payment_address diversifier: {:?}", output.to.diversifier());
-        println!("    note rcm: {:?}", note.rcm());
-        println!("    note value: {}", note.value);
-        
-        let (proof, cv_proof) = prover
-            .output_proof(
-                &mut ctx,
-                rcv,
-                output.to.clone(),
-                note.rcm(),
-                note.value,
-            );
-        
-        println!("  Generated proof, size: {} bytes", proof.len());
-        println!("  cv (value commitment): {}", hex::encode(cv_proof.to_bytes()));
-        
+        let rseed = Rseed::AfterZip212(self.rng.gen());
+
+        let note = output
+            .to
+            .create_note(u64::from(output.value), rseed)
+            .ok_or("Failed to create note")?;
+
+        // The encryptor draws the esk, which the output proof has to commit to as well,
+        // or the proof's epk won't be the one the ciphertext is encrypted to
+        let ne = sapling_note_encryption::<_, P>(
+            output.ovk,
+            note.clone(),
+            output.to.clone(),
+            output.memo.clone(),
+            &mut self.rng,
+        );
+
+        // The prover picks the value commitment randomness and adds it to the context
+        let (proof, cv_proof) = prover.output_proof(ctx, *ne.esk(), output.to.clone(), note.rcm(), note.value);
+
         // Validate cv is not small order
         // Small order check: point * 8 should not be zero
         let cv_times_8 = cv_proof.double().double().double();
         if bool::from(cv_times_8.is_identity()) {
             return Err("Generated cv is of small order".to_string());
         }
-        
+
         // Encrypt the note with the actual cv
-        println!("  Starting note encryption");
-        let ne = sapling_note_encryption::<_, P>(
-            Some(output.ovk),
-            note.clone(),
-            output.to.clone(),
-            output.memo.clone(),
-            &mut thread_rng(),
-        );
-        
         let enc_ciphertext = ne.encrypt_note_plaintext();
-        println!("  Encrypted note plaintext, size: {} bytes", enc_ciphertext.len());
-        
-        let out_ciphertext = ne.encrypt_outgoing_plaintext(&cv_proof, &note.cmu(), &mut thread_rng());
-        println!("  Encrypted outgoing plaintext, size: {} bytes", out_ciphertext.len());
-        
+        let out_ciphertext = ne.encrypt_outgoing_plaintext(&cv_proof, &note.cmu(), &mut self.rng);
         let ephemeral_key = ne.epk();
-        println!("  Ephemeral key: {}", hex::encode(ephemeral_key.to_bytes()));
-        
+
         // Validate ephemeral key is not small order
         // Note: ephemeral_key is already an ExtendedPoint
         let epk_times_8 = ephemeral_key.double().double().double();
         if bool::from(epk_times_8.is_identity()) {
             return Err("Generated ephemeral key is of small order".to_string());
         }
-        
-        // Debug: print the cv bytes to verify serialization
-        println!("BitcoinZ: Analyzing cv format:");
-        debug_point_formats(&cv_proof);
-        println!("BitcoinZ: cmu bytes (hex): {}", hex::encode(note.cmu().to_repr()));
-        println!("BitcoinZ: Analyzing ephemeral key format:");
-        debug_point_formats(&ephemeral_key);
-        
-        // Log the complete output description structure
-        println!("BitcoinZ: Complete output description:");
-        println!("  cv: {} bytes", cv_proof.to_bytes().len());
-        println!("  cmu: {} bytes", 32);
-        println!("  ephemeral_key: {} bytes", ephemeral_key.to_bytes().len());
-        println!("  enc_ciphertext: {} bytes", enc_ciphertext.len());
-        println!("  out_ciphertext: {} bytes", out_ciphertext.len());
-        println!("  zkproof: {} bytes", proof.len());
-        
+
         // Store cv and ephemeral_key in a format we can serialize later
         Ok(OutputDescription {
             cv: cv_proof.into(),
@@ -778,56 +1301,54 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             zkproof: proof,
         })
     }
-    
+
     /// Write transaction header
     fn write_header(&self, tx_data: &mut Vec<u8>) -> Result<(), String> {
         let header = 0x80000000u32 | (SAPLING_TX_VERSION as u32);
-        tx_data.write_u32::<LittleEndian>(header)
+        tx_data
+            .write_u32::<LittleEndian>(header)
             .map_err(|e| format!("Failed to write header: {}", e))?;
-        
-        tx_data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
+
+        tx_data
+            .write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
             .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-        
+
         Ok(())
     }
-    
-    /// Write transparent inputs
-    fn write_transparent_inputs(&self, tx_data: &mut Vec<u8>) -> Result<(), String> {
-        write_compact_size(tx_data, self.transparent_inputs.len() as u64)?;
-        
-        for (outpoint, _, _) in &self.transparent_inputs {
-            tx_data.write_all(outpoint.hash())
-                .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-            tx_data.write_u32::<LittleEndian>(outpoint.n())
-                .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-            
-            // Script sig will be added after signing
-            write_compact_size(tx_data, 0)?;
-            
-            tx_data.write_u32::<LittleEndian>(0xfffffffe)
+
+    /// Write transparent inputs with their signed script sigs
+    fn write_transparent_inputs(&self, tx_data: &mut Vec<u8>, script_sigs: &[Vec<u8>]) -> Result<(), String> {
+        write_compact_size(tx_data, self.transparent_inputs.len() as u64);
+
+        for (i, (outpoint, _, _)) in self.transparent_inputs.iter().enumerate() {
+            write_outpoint(tx_data, outpoint);
+
+            write_compact_size(tx_data, script_sigs[i].len() as u64);
+            tx_data
+                .write_all(&script_sigs[i])
+                .map_err(|e| format!("Failed to write script sig: {}", e))?;
+
+            tx_data
+                .write_u32::<LittleEndian>(self.transparent_sequences[i])
                 .map_err(|e| format!("Failed to write sequence: {}", e))?;
         }
-        
+
         Ok(())
     }
-    
+
     /// Write transparent outputs
     fn write_transparent_outputs(&self, tx_data: &mut Vec<u8>) -> Result<(), String> {
-        write_compact_size(tx_data, self.transparent_outputs.len() as u64)?;
-        
+        write_compact_size(tx_data, self.transparent_outputs.len() as u64);
+
         for (addr, amount) in &self.transparent_outputs {
-            tx_data.write_u64::<LittleEndian>(u64::from(*amount))
-                .map_err(|e| format!("Failed to write amount: {}", e))?;
-            
-            let script = addr.script();
-            write_compact_size(tx_data, script.0.len() as u64)?;
-            tx_data.write_all(&script.0)
-                .map_err(|e| format!("Failed to write script: {}", e))?;
+            write_amount(tx_data, *amount);
+
+            write_script(tx_data, &addr.script());
         }
-        
+
         Ok(())
     }
-    
+
     /// Write spend description
     fn write_spend_description(
         &self,
@@ -835,100 +1356,93 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         spend: &SpendDescription<Authorized>,
     ) -> Result<(), String> {
         // Write cv using standard format
-        tx_data.write_all(&spend.cv.to_bytes())
+        tx_data
+            .write_all(&spend.cv.to_bytes())
             .map_err(|e| format!("Failed to write cv: {}", e))?;
-        
+
         // Write anchor
-        tx_data.write_all(&spend.anchor.to_repr())
+        tx_data
+            .write_all(&spend.anchor.to_repr())
             .map_err(|e| format!("Failed to write anchor: {}", e))?;
-        
+
         // Write nullifier
-        tx_data.write_all(&spend.nullifier.0)
+        tx_data
+            .write_all(&spend.nullifier.0)
             .map_err(|e| format!("Failed to write nullifier: {}", e))?;
-        
+
         // Write rk
         let mut rk_bytes = [0u8; 32];
         spend.rk.write(&mut rk_bytes[..]).map_err(|e| e.to_string())?;
-        tx_data.write_all(&rk_bytes)
+        tx_data
+            .write_all(&rk_bytes)
             .map_err(|e| format!("Failed to write rk: {}", e))?;
-        
+
         // Write zkproof
-        tx_data.write_all(&spend.zkproof)
+        tx_data
+            .write_all(&spend.zkproof)
             .map_err(|e| format!("Failed to write zkproof: {}", e))?;
-        
+
         // Write spend_auth_sig
         let mut sig_bytes = [0u8; 64];
-        spend.spend_auth_sig.write(&mut sig_bytes[..]).map_err(|e| e.to_string())?;
-        tx_data.write_all(&sig_bytes)
+        spend
+            .spend_auth_sig
+            .write(&mut sig_bytes[..])
+            .map_err(|e| e.to_string())?;
+        tx_data
+            .write_all(&sig_bytes)
             .map_err(|e| format!("Failed to write spend_auth_sig: {}", e))?;
-        
+
         Ok(())
     }
-    
+
     /// Write output description
     fn write_output_description(
         &self,
         tx_data: &mut Vec<u8>,
         output: &OutputDescription<GrothProofBytes>,
     ) -> Result<(), String> {
-        println!("BitcoinZ: Writing output description details:");
-        
         // Write cv using BitcoinZ's bellman 0.1.0 format
-        println!("  Writing cv in bellman 0.1.0 format");
         let cv_standard = output.cv.to_bytes();
-        println!("    cv standard format: {}", hex::encode(&cv_standard));
         let cv_start = tx_data.len();
         // Convert cv bytes to ExtendedPoint
         let cv_point = jubjub::ExtendedPoint::from_bytes(&cv_standard).unwrap();
-        write_edwards_point_bellman(&cv_point, &mut *tx_data)
-            .map_err(|e| format!("Failed to write cv: {}", e))?;
+        write_edwards_point_bellman(&cv_point, &mut *tx_data).map_err(|e| format!("Failed to write cv: {}", e))?;
         let cv_bellman = &tx_data[cv_start..];
-        println!("    cv bellman format: {}", hex::encode(cv_bellman));
-        println!("    formats differ: {}", cv_standard != cv_bellman);
-        
+        check_prime_subgroup_encoding("cv", cv_bellman)?;
+
         // Write cmu
         let cmu_bytes = output.cmu.to_repr();
-        println!("  cmu bytes (hex): {}", hex::encode(&cmu_bytes));
-        tx_data.write_all(&cmu_bytes)
+        tx_data
+            .write_all(&cmu_bytes)
             .map_err(|e| format!("Failed to write cmu: {}", e))?;
-        
+
         // Write ephemeral_key using bellman 0.1.0 format
-        println!("  Writing ephemeral_key in bellman 0.1.0 format");
-        let epk_standard = &output.ephemeral_key.0;
-        println!("    ephemeral_key standard format: {}", hex::encode(epk_standard));
         // First decode the ephemeral key from bytes
-        let epk_point = jubjub::ExtendedPoint::from_bytes(&output.ephemeral_key.0)
-            .unwrap();
+        let epk_point = jubjub::ExtendedPoint::from_bytes(&output.ephemeral_key.0).unwrap();
         let epk_start = tx_data.len();
         write_edwards_point_bellman(&epk_point, &mut *tx_data)
             .map_err(|e| format!("Failed to write ephemeral_key: {}", e))?;
         let epk_bellman = &tx_data[epk_start..];
-        println!("    ephemeral_key bellman format: {}", hex::encode(epk_bellman));
-        println!("    formats differ: {}", epk_standard != epk_bellman);
-        
+        check_prime_subgroup_encoding("ephemeral_key", epk_bellman)?;
+
         // Write enc_ciphertext
-        println!("  enc_ciphertext first 32 bytes (hex): {}", hex::encode(&output.enc_ciphertext[..32]));
-        println!("  enc_ciphertext last 32 bytes (hex): {}", hex::encode(&output.enc_ciphertext[output.enc_ciphertext.len()-32..]));
-        tx_data.write_all(&output.enc_ciphertext)
+        tx_data
+            .write_all(&output.enc_ciphertext)
             .map_err(|e| format!("Failed to write enc_ciphertext: {}", e))?;
-        
+
         // Write out_ciphertext
-        println!("  out_ciphertext first 32 bytes (hex): {}", hex::encode(&output.out_ciphertext[..32]));
-        tx_data.write_all(&output.out_ciphertext)
+        tx_data
+            .write_all(&output.out_ciphertext)
             .map_err(|e| format!("Failed to write out_ciphertext: {}", e))?;
-        
+
         // Write zkproof
-        println!("  zkproof first 32 bytes (hex): {}", hex::encode(&output.zkproof[..32]));
-        println!("  zkproof last 32 bytes (hex): {}", hex::encode(&output.zkproof[output.zkproof.len()-32..]));
-        tx_data.write_all(&output.zkproof)
+        tx_data
+            .write_all(&output.zkproof)
             .map_err(|e| format!("Failed to write zkproof: {}", e))?;
-        
-        println!("  Total output description size: {} bytes", 
-            32 + 32 + 32 + output.enc_ciphertext.len() + output.out_ciphertext.len() + output.zkproof.len());
-        
+
         Ok(())
     }
-    
+
     /// Compute sighash for binding signature according to ZIP-243
     fn compute_binding_sig_sighash(
         &self,
@@ -937,16 +1451,16 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         value_balance: i64,
     ) -> Result<[u8; 32], String> {
         let mut data = Vec::new();
-        
+
         // 1. Header with Sapling flag
         let header = 0x80000000u32 | (SAPLING_TX_VERSION as u32);
         data.write_u32::<LittleEndian>(header)
             .map_err(|e| format!("Failed to write header: {}", e))?;
-        
+
         // 2. Version group ID
         data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
             .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-        
+
         // 3. Prevouts hash (hash of all transparent input prevouts)
         if self.transparent_inputs.is_empty() {
             data.write_all(&[0u8; 32])
@@ -956,7 +1470,7 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             data.write_all(&prevouts_hash)
                 .map_err(|e| format!("Failed to write prevouts hash: {}", e))?;
         }
-        
+
         // 4. Sequence hash
         if self.transparent_inputs.is_empty() {
             data.write_all(&[0u8; 32])
@@ -966,7 +1480,7 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             data.write_all(&sequences_hash)
                 .map_err(|e| format!("Failed to write sequences hash: {}", e))?;
         }
-        
+
         // 5. Outputs hash
         if self.transparent_outputs.is_empty() {
             data.write_all(&[0u8; 32])
@@ -976,11 +1490,11 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             data.write_all(&outputs_hash)
                 .map_err(|e| format!("Failed to write outputs hash: {}", e))?;
         }
-        
+
         // 6. JoinSplits hash (empty for v4)
         data.write_all(&[0u8; 32])
             .map_err(|e| format!("Failed to write joinsplits hash: {}", e))?;
-        
+
         // 7. Shielded spends hash
         if shielded_spends.is_empty() {
             data.write_all(&[0u8; 32])
@@ -990,7 +1504,7 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             data.write_all(&shielded_spends_hash)
                 .map_err(|e| format!("Failed to write shielded spends hash: {}", e))?;
         }
-        
+
         // 8. Shielded outputs hash
         if shielded_outputs.is_empty() {
             data.write_all(&[0u8; 32])
@@ -1000,42 +1514,29 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
             data.write_all(&shielded_outputs_hash)
                 .map_err(|e| format!("Failed to write shielded outputs hash: {}", e))?;
         }
-        
+
         // 9. Lock time (using 0)
         data.write_u32::<LittleEndian>(0)
             .map_err(|e| format!("Failed to write lock time: {}", e))?;
-        
-        // 10. Expiry height (using 0)
-        data.write_u32::<LittleEndian>(0)
+
+        // 10. Expiry height
+        data.write_u32::<LittleEndian>(self.expiry_height.unwrap_or(0))
             .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-        
+
         // 11. Value balance
         data.write_i64::<LittleEndian>(value_balance)
             .map_err(|e| format!("Failed to write value balance: {}", e))?;
-        
+
         // 12. Hash type (SIGHASH_ALL for binding signature)
         data.write_u32::<LittleEndian>(1)
             .map_err(|e| format!("Failed to write hash type: {}", e))?;
-        
+
         // Compute final hash with BitcoinZ personalization
-        let mut personalization = [0u8; 16];
-        personalization[..12].copy_from_slice(ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX);
-        personalization[12..16].copy_from_slice(&CONSENSUS_BRANCH_ID.to_le_bytes());
-        
-        let hash = Params::new()
-            .hash_length(32)
-            .personal(&personalization)
-            .to_state()
-            .update(&data)
-            .finalize();
-        
-        let mut result = [0u8; 32];
-        result.copy_from_slice(hash.as_bytes());
-        
-        // BitcoinZ: Do NOT reverse the bytes
-        Ok(result)
+        let mut state = sighash_state(self.consensus_branch_id());
+        state.update(&data);
+        Ok(finalize_sighash(state, REVERSE_BINDING_SIGHASH))
     }
-    
+
     /// Compute sighash for binding signature
     fn compute_sighash_for_binding(&self, tx_data: &[u8]) -> Result<[u8; 32], String> {
         // Compute sighash without binding signature (up to the point before binding sig)
@@ -1048,125 +1549,47 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         // - Shielded spends and outputs
         // - JoinSplit count (1 byte for v4)
         // Then comes the binding signature which we don't include
-        
+
         // For v4 the sighash excludes the binding signature itself
         let tx_without_binding_sig = &tx_data[..tx_data.len() - 64]; // Exclude 64-byte signature
-        
-        let mut personalization = [0u8; 16];
-        personalization[..12].copy_from_slice(ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX);
-        personalization[12..16].copy_from_slice(&CONSENSUS_BRANCH_ID.to_le_bytes());
-        
-        let hash = Params::new()
-            .hash_length(32)
-            .personal(&personalization)
-            .to_state()
-            .update(tx_without_binding_sig)
-            .finalize();
-        
-        let mut result = [0u8; 32];
-        result.copy_from_slice(hash.as_bytes());
-        
-        Ok(result)
+
+        let mut state = sighash_state(self.consensus_branch_id());
+        state.update(tx_without_binding_sig);
+        Ok(finalize_sighash(state, REVERSE_BINDING_SIGHASH))
     }
-    
-    /// Sign transparent inputs
-    fn sign_transparent_inputs(
+
+    /// Sign each transparent input and build its script sig
+    fn transparent_script_sigs(
         &self,
-        unsigned_tx: Vec<u8>,
         shielded_spends: &[SpendDescription<Authorized>],
         shielded_outputs: &[OutputDescription<GrothProofBytes>],
         value_balance: i64,
-    ) -> Result<Vec<u8>, String> {
-        let secp = Secp256k1::new();
-        let mut signatures = Vec::new();
-        
-        // Compute signatures for each input
-        for (index, (_, txout, sk)) in self.transparent_inputs.iter().enumerate() {
-            // Compute the sighash for this input
+    ) -> Result<Vec<Vec<u8>>, String> {
+        let mut sighashes = Vec::new();
+
+        for (index, (_, txout, keys)) in self.transparent_inputs.iter().enumerate() {
+            // Compute the sighash for this input, over the redeem script for P2SH
             let sighash = self.compute_sapling_sighash(
-                &unsigned_tx,
+                &[],
                 index,
-                &txout.script_pubkey,
+                keys.script_code(&txout.script_pubkey),
                 txout.value,
-                SIGHASH_ALL,
+                self.sighash_type,
                 shielded_spends,
                 shielded_outputs,
                 value_balance,
             )?;
-            
-            // Sign the sighash
-            let msg = Message::from_slice(&sighash)
-                .map_err(|e| format!("Failed to create message: {}", e))?;
-            let sig = secp.sign_ecdsa(&msg, &sk);
-            
-            // Create script sig
-            let pk = SecpPublicKey::from_secret_key(&secp, &sk);
-            let mut script_sig = Vec::new();
-            
-            // Push signature with sighash type
-            let mut sig_bytes = sig.serialize_der().to_vec();
-            sig_bytes.push(SIGHASH_ALL as u8);
-            script_sig.push(sig_bytes.len() as u8);
-            script_sig.extend_from_slice(&sig_bytes);
-            
-            // Push public key
-            let pk_bytes = pk.serialize();
-            script_sig.push(pk_bytes.len() as u8);
-            script_sig.extend_from_slice(&pk_bytes);
-            
-            signatures.push(script_sig);
-        }
-        
-        // Now rebuild the transaction with signatures
-        let mut signed_tx = Vec::new();
-        
-        // Copy header and version group ID (first 8 bytes)
-        signed_tx.extend_from_slice(&unsigned_tx[0..8]);
-        
-        // Write inputs with signatures
-        write_compact_size(&mut signed_tx, self.transparent_inputs.len() as u64)?;
-        
-        for (i, (outpoint, _, _)) in self.transparent_inputs.iter().enumerate() {
-            // Previous output
-            signed_tx.write_all(outpoint.hash())
-                .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-            signed_tx.write_u32::<LittleEndian>(outpoint.n())
-                .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-            
-            // Script sig with signature
-            write_compact_size(&mut signed_tx, signatures[i].len() as u64)?;
-            signed_tx.write_all(&signatures[i])
-                .map_err(|e| format!("Failed to write script sig: {}", e))?;
-            
-            // Sequence
-            signed_tx.write_u32::<LittleEndian>(0xfffffffe)
-                .map_err(|e| format!("Failed to write sequence: {}", e))?;
-        }
-        
-        // Find where outputs start in the unsigned transaction
-        let mut cursor = 8; // After header and version group
-        let (input_count, varint_size) = read_compact_size(&unsigned_tx[cursor..])
-            .map_err(|e| format!("Failed to read input count: {}", e))?
-            .ok_or("Failed to read input count")?;
-        cursor += varint_size;
-        
-        // Skip all inputs in unsigned tx
-        for _ in 0..input_count {
-            cursor += 32 + 4; // outpoint
-            let (script_len, varint_size) = read_compact_size(&unsigned_tx[cursor..])
-                .map_err(|e| format!("Failed to read script length: {}", e))?
-                .ok_or("Failed to read script length")?;
-            cursor += varint_size + script_len as usize;
-            cursor += 4; // sequence
+
+            sighashes.push((keys, sighash));
         }
-        
-        // Copy the rest (outputs, locktime, expiry, value balance, shielded data, binding sig)
-        signed_tx.extend_from_slice(&unsigned_tx[cursor..]);
-        
-        Ok(signed_tx)
-    }
-    
-    /// Compute Sapling (v4) sighash using BLAKE2b
+
+        // Sign them all, asking each external signer once, and build the script sigs
+        script_sigs(&sighashes, self.sighash_type)
+    }
+
+    /// The ZIP-243 sighash of transparent input `input_index`. It's the transparent
+    /// builder's, with this transaction's shielded parts and value balance added, so the
+    /// two builders sign the same digest.
     fn compute_sapling_sighash(
         &self,
         _tx_data: &[u8],
@@ -1178,204 +1601,1831 @@ impl<P: Parameters> BitcoinZShieldedBuilder<P> {
         shielded_outputs: &[OutputDescription<GrothProofBytes>],
         value_balance: i64,
     ) -> Result<[u8; 32], String> {
-        // This implements the Sapling sighash algorithm (ZIP-243)
-        let mut data = Vec::new();
-        
-        // 1. Header with Sapling flag
-        let header = 0x80000000u32 | (SAPLING_TX_VERSION as u32);
-        data.write_u32::<LittleEndian>(header)
-            .map_err(|e| format!("Failed to write header: {}", e))?;
-        
-        // 2. Version group ID
-        data.write_u32::<LittleEndian>(BITCOINZ_VERSION_GROUP_ID)
-            .map_err(|e| format!("Failed to write version group ID: {}", e))?;
-        
-        // 3. Hash of all prevouts (if not ANYONECANPAY)
-        if (sighash_type & 0x80) == 0 {
-            let prevouts_hash = self.compute_prevouts_hash()?;
-            data.write_all(&prevouts_hash)
-                .map_err(|e| format!("Failed to write prevouts hash: {}", e))?;
-        } else {
-            data.write_all(&[0u8; 32])
-                .map_err(|e| format!("Failed to write empty prevouts hash: {}", e))?;
-        }
-        
-        // 4. Hash of all sequences (if not ANYONECANPAY, SINGLE, NONE)
-        if (sighash_type & 0x80) == 0 && (sighash_type & 0x1f) != 2 && (sighash_type & 0x1f) != 3 {
-            let sequences_hash = self.compute_sequences_hash()?;
-            data.write_all(&sequences_hash)
-                .map_err(|e| format!("Failed to write sequences hash: {}", e))?;
-        } else {
-            data.write_all(&[0u8; 32])
-                .map_err(|e| format!("Failed to write empty sequences hash: {}", e))?;
-        }
-        
-        // 5. Hash of all outputs (if not SINGLE or NONE)
-        if (sighash_type & 0x1f) != 2 && (sighash_type & 0x1f) != 3 {
-            let outputs_hash = self.compute_outputs_hash()?;
-            data.write_all(&outputs_hash)
-                .map_err(|e| format!("Failed to write outputs hash: {}", e))?;
-        } else {
-            data.write_all(&[0u8; 32])
-                .map_err(|e| format!("Failed to write empty outputs hash: {}", e))?;
-        }
-        
-        // 6. JoinSplits hash (empty for v4)
-        data.write_all(&[0u8; 32])
-            .map_err(|e| format!("Failed to write joinsplits hash: {}", e))?;
-        
-        // 7. Shielded spends hash
-        if shielded_spends.is_empty() {
-            data.write_all(&[0u8; 32])
-                .map_err(|e| format!("Failed to write empty shielded spends hash: {}", e))?;
-        } else {
-            let shielded_spends_hash = compute_shielded_spends_hash(shielded_spends)?;
-            data.write_all(&shielded_spends_hash)
-                .map_err(|e| format!("Failed to write shielded spends hash: {}", e))?;
-        }
-        
-        // 8. Shielded outputs hash
-        if shielded_outputs.is_empty() {
-            data.write_all(&[0u8; 32])
-                .map_err(|e| format!("Failed to write empty shielded outputs hash: {}", e))?;
-        } else {
-            let shielded_outputs_hash = compute_shielded_outputs_hash(shielded_outputs)?;
-            data.write_all(&shielded_outputs_hash)
-                .map_err(|e| format!("Failed to write shielded outputs hash: {}", e))?;
-        }
-        
-        // 9. Lock time (using 0)
-        data.write_u32::<LittleEndian>(0)
-            .map_err(|e| format!("Failed to write lock time: {}", e))?;
-        
-        // 10. Expiry height (using 0)
-        data.write_u32::<LittleEndian>(0)
-            .map_err(|e| format!("Failed to write expiry height: {}", e))?;
-        
-        // 11. Value balance
-        data.write_i64::<LittleEndian>(value_balance)
-            .map_err(|e| format!("Failed to write value balance: {}", e))?;
-        
-        // 12. Sighash type
-        data.write_u32::<LittleEndian>(sighash_type)
-            .map_err(|e| format!("Failed to write sighash type: {}", e))?;
-        
-        // If not ANYONECANPAY, add current input details
-        if (sighash_type & 0x80) == 0 {
-            // 13. Outpoint
-            let (outpoint, _, _) = &self.transparent_inputs[input_index];
-            data.write_all(outpoint.hash())
-                .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-            data.write_u32::<LittleEndian>(outpoint.n())
-                .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
-            
-            // 14. Script code
-            write_compact_size(&mut data, script_code.0.len() as u64)?;
-            data.write_all(&script_code.0)
-                .map_err(|e| format!("Failed to write script code: {}", e))?;
-            
-            // 15. Value
-            data.write_u64::<LittleEndian>(u64::from(value))
-                .map_err(|e| format!("Failed to write value: {}", e))?;
-            
-            // 16. Sequence
-            data.write_u32::<LittleEndian>(0xfffffffe)
-                .map_err(|e| format!("Failed to write sequence: {}", e))?;
-        }
-        
-        // Create personalization with consensus branch ID
-        let mut personalization = [0u8; 16];
-        personalization[..12].copy_from_slice(ZCASH_SAPLING_SIGHASH_PERSONALIZATION_PREFIX);
-        personalization[12..16].copy_from_slice(&CONSENSUS_BRANCH_ID.to_le_bytes());
-        
-        // Compute BLAKE2b hash
-        let hash = Params::new()
-            .hash_length(32)
-            .personal(&personalization)
-            .to_state()
-            .update(&data)
-            .finalize();
-        
-        let mut result = [0u8; 32];
-        result.copy_from_slice(hash.as_bytes());
-        
-        // BitcoinZ: DOES reverse the bytes (confirmed from bitcore-lib-btcz)
-        result.reverse();
-        Ok(result)
+        let parts = TransparentParts {
+            sequences: self.transparent_sequences.clone(),
+            expiry_height: self.expiry_height.unwrap_or(0),
+            sighash_type,
+            consensus_branch_id: Some(self.consensus_branch_id()),
+            ..TransparentParts::new(self.transparent_inputs.clone(), self.transparent_outputs.clone())
+        };
+        let sapling = SaplingDigests {
+            spends: if shielded_spends.is_empty() {
+                [0u8; 32]
+            } else {
+                compute_shielded_spends_hash(shielded_spends)?
+            },
+            outputs: if shielded_outputs.is_empty() {
+                [0u8; 32]
+            } else {
+                compute_shielded_outputs_hash(shielded_outputs)?
+            },
+            value_balance,
+        };
+
+        zip143_sighash(
+            &self.params,
+            self.height,
+            TransparentTxFormat::Sapling,
+            &parts,
+            input_index,
+            script_code,
+            value,
+            &sapling,
+        )
+    }
+
+    /// Branch ID for the sighash personalization: the override if there is one, or the
+    /// upgrade active at the builder's height
+    fn consensus_branch_id(&self) -> u32 {
+        self.consensus_branch_id
+            .unwrap_or_else(|| bitcoinz_consensus_branch_id(&self.params, self.height))
     }
-    
+
     /// Compute hash of all prevouts
     fn compute_prevouts_hash(&self) -> Result<[u8; 32], String> {
         let mut data = Vec::new();
-        
+
         for (outpoint, _, _) in &self.transparent_inputs {
-            data.write_all(outpoint.hash())
-                .map_err(|e| format!("Failed to write outpoint hash: {}", e))?;
-            data.write_u32::<LittleEndian>(outpoint.n())
-                .map_err(|e| format!("Failed to write outpoint index: {}", e))?;
+            write_outpoint(&mut data, outpoint);
         }
-        
+
         let hash = Params::new()
             .hash_length(32)
             .personal(ZCASH_PREVOUTS_HASH_PERSONALIZATION)
             .to_state()
             .update(&data)
             .finalize();
-        
+
         let mut result = [0u8; 32];
         result.copy_from_slice(hash.as_bytes());
-        
+
         Ok(result)
     }
-    
+
     /// Compute hash of all sequences
     fn compute_sequences_hash(&self) -> Result<[u8; 32], String> {
         let mut data = Vec::new();
-        
-        for _ in &self.transparent_inputs {
-            data.write_u32::<LittleEndian>(0xfffffffe)
+
+        for sequence in &self.transparent_sequences[..self.transparent_inputs.len()] {
+            data.write_u32::<LittleEndian>(*sequence)
                 .map_err(|e| format!("Failed to write sequence: {}", e))?;
         }
-        
+
         let hash = Params::new()
             .hash_length(32)
             .personal(ZCASH_SEQUENCE_HASH_PERSONALIZATION)
             .to_state()
             .update(&data)
             .finalize();
-        
+
         let mut result = [0u8; 32];
         result.copy_from_slice(hash.as_bytes());
-        
+
         Ok(result)
     }
-    
+
     /// Compute hash of all outputs
     fn compute_outputs_hash(&self) -> Result<[u8; 32], String> {
-        let mut data = Vec::new();
-        
-        for (addr, amount) in &self.transparent_outputs {
-            // Write amount (8 bytes)
-            data.write_u64::<LittleEndian>(u64::from(*amount))
-                .map_err(|e| format!("Failed to write amount: {}", e))?;
-            
-            // Write script pubkey
-            let script = addr.script();
-            write_compact_size(&mut data, script.0.len() as u64)?;
-            data.write_all(&script.0)
-                .map_err(|e| format!("Failed to write script: {}", e))?;
+        hash_transparent_outputs(&self.transparent_outputs)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoinz_overwinter_builder::{SIGHASH_ANYONECANPAY, SIGHASH_NONE};
+    use crate::bitcoinz_transaction::fee_for_size;
+    use crate::{BitcoinZMainNetwork, BITCOINZ_MAINNET};
+    use rand::thread_rng;
+    use secp256k1::{Message, PublicKey as SecpPublicKey, Secp256k1};
+    use zcash_primitives::transaction::components::amount::DEFAULT_FEE;
+
+    // Expected sighashes were computed independently from the ZIP-243 spec
+    // (BitcoinZ version group ID 0x892f2085, branch ID 0x76b809bb, zero lock time,
+    // expiry and value balance unless a test says otherwise, no shielded components),
+    // with a separate implementation of the preimage hashed by Python's hashlib.
+
+    fn fixture_builder() -> BitcoinZShieldedBuilder<BitcoinZMainNetwork> {
+        fixture_builder_at(BITCOINZ_MAINNET, 500_000)
+    }
+
+    fn fixture_builder_at<P: Parameters>(params: P, height: u32) -> BitcoinZShieldedBuilder<P> {
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(params, BlockHeight::from(height));
+        builder
+            .add_transparent_input(
+                transparent::OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                sk,
+            )
+            .unwrap();
+        builder
+            .add_transparent_input(
+                transparent::OutPoint::new([0x33u8; 32], 1),
+                TxOut {
+                    value: Amount::from_u64(250_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x44u8; 20]).script(),
+                },
+                sk,
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(150_000).unwrap(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                TransparentAddress::Script([0x66u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            )
+            .unwrap();
+        builder
+    }
+
+    fn sighash_hex<P: Parameters>(
+        builder: &BitcoinZShieldedBuilder<P>,
+        sighash_type: u32,
+        input_index: usize,
+    ) -> Result<String, String> {
+        let (_, txout, _) = &builder.transparent_inputs[input_index];
+        let sighash = builder.compute_sapling_sighash(
+            &[],
+            input_index,
+            &txout.script_pubkey,
+            txout.value,
+            sighash_type,
+            &[],
+            &[],
+            0,
+        )?;
+        Ok(hex::encode(sighash))
+    }
+
+    #[test]
+    fn test_sapling_sighash_all() {
+        let builder = fixture_builder();
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_ALL, 0).unwrap(),
+            "f39da27049a575ce8d7b857863a878c3755aa9f415514dedfd6ab4decacd5312"
+        );
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_ALL, 1).unwrap(),
+            "f294e6e9c976ce7bed5cfc63807e78279a5cc226a989a9057111f88921b8424e"
+        );
+    }
+
+    #[test]
+    fn test_sighash_follows_branch_id_for_height() {
+        use crate::bitcoinz_branch::StagedUpgradesNetwork;
+
+        // Under Sapling the sighash matches mainnet's, and each later upgrade changes it
+        let sighash_at =
+            |height| sighash_hex(&fixture_builder_at(StagedUpgradesNetwork, height), SIGHASH_ALL, 0).unwrap();
+        let sapling = sighash_at(StagedUpgradesNetwork::BLOSSOM - 1);
+        assert_eq!(
+            sapling,
+            "f39da27049a575ce8d7b857863a878c3755aa9f415514dedfd6ab4decacd5312"
+        );
+        assert_eq!(sighash_at(StagedUpgradesNetwork::SAPLING), sapling);
+
+        let later = [
+            sighash_at(StagedUpgradesNetwork::BLOSSOM),
+            sighash_at(StagedUpgradesNetwork::HEARTWOOD),
+            sighash_at(StagedUpgradesNetwork::CANOPY),
+        ];
+        assert!(later.iter().all(|sighash| *sighash != sapling));
+        assert!(later[0] != later[1] && later[1] != later[2] && later[0] != later[2]);
+
+        // Mainnet never leaves Sapling
+        let mainnet = fixture_builder_at(BITCOINZ_MAINNET, 5_000_000);
+        assert_eq!(sighash_hex(&mainnet, SIGHASH_ALL, 0).unwrap(), sapling);
+    }
+
+    #[test]
+    fn test_consensus_branch_id_override() {
+        use crate::bitcoinz_branch::{bitcoinz_branch_id_to_u32, StagedUpgradesNetwork, BITCOINZ_SAPLING_BRANCH_ID};
+        use zcash_primitives::consensus::BranchId;
+
+        let sapling = "f39da27049a575ce8d7b857863a878c3755aa9f415514dedfd6ab4decacd5312";
+        let mut builder = fixture_builder();
+        builder.set_consensus_branch_id(Some(BITCOINZ_SAPLING_BRANCH_ID));
+        assert_eq!(sighash_hex(&builder, SIGHASH_ALL, 0).unwrap(), sapling);
+
+        // Identical inputs, another branch id: another transparent sighash
+        builder.set_consensus_branch_id(Some(bitcoinz_branch_id_to_u32(BranchId::Canopy)));
+        let canopy = sighash_hex(&builder, SIGHASH_ALL, 0).unwrap();
+        assert_ne!(canopy, sapling);
+
+        // The same as the height-driven sighash once Canopy is active
+        let staged = fixture_builder_at(StagedUpgradesNetwork, StagedUpgradesNetwork::CANOPY);
+        assert_eq!(sighash_hex(&staged, SIGHASH_ALL, 0).unwrap(), canopy);
+
+        // And another binding sighash
+        let binding_sighash = |id: Option<u32>| {
+            let mut builder = mixed_builder();
+            builder.set_consensus_branch_id(id);
+            builder.compute_binding_sig_sighash(&[], &[], 160_000).unwrap()
+        };
+        assert_eq!(binding_sighash(None), binding_sighash(Some(BITCOINZ_SAPLING_BRANCH_ID)));
+        assert_ne!(
+            binding_sighash(None),
+            binding_sighash(Some(bitcoinz_branch_id_to_u32(BranchId::Canopy)))
+        );
+    }
+
+    #[test]
+    fn test_sapling_sighash_none() {
+        let builder = fixture_builder();
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_NONE, 0).unwrap(),
+            "c1f2c2631c412ba8452a3484075219cbf8c474d1ccf1897f6e1211d588b91c94"
+        );
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_NONE, 1).unwrap(),
+            "d0b105231c8e08d427928136c93bd29c02a3ecb211e94a5e27f2db376e6a91c4"
+        );
+    }
+
+    #[test]
+    fn test_sapling_sighash_single() {
+        let builder = fixture_builder();
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_SINGLE, 0).unwrap(),
+            "84fe542457360e54e4b8d8f68812f41f63dd1dedefc14412a333e2f3eafe1c97"
+        );
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_SINGLE, 1).unwrap(),
+            "023196beb217697d42ad239f34fe14813d505f175572b2c6c5cd8b28d8b67ca5"
+        );
+    }
+
+    #[test]
+    fn test_sapling_sighash_anyonecanpay() {
+        let builder = fixture_builder();
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_ALL | SIGHASH_ANYONECANPAY, 0).unwrap(),
+            "8741ac6f52ca941dca689db691e50ec26a216b9dc425ebf46e0330f3c9720985"
+        );
+        assert_eq!(
+            sighash_hex(&builder, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, 0).unwrap(),
+            "df658c03b8fb9a42d7146cc41fa59f86a1a3957be6dc93acfc61745ec7d70533"
+        );
+    }
+
+    #[test]
+    fn test_sapling_sighash_single_without_matching_output() {
+        let mut builder = fixture_builder();
+        builder.transparent_outputs.truncate(1);
+        assert!(sighash_hex(&builder, SIGHASH_SINGLE, 1).is_err());
+        assert!(sighash_hex(&builder, SIGHASH_SINGLE, 0).is_ok());
+    }
+
+    #[test]
+    fn test_binding_sighash_vectors() {
+        // The sighash with no input being signed, over the fixture's transparent parts
+        let builder = fixture_builder();
+        assert_eq!(
+            hex::encode(builder.compute_binding_sig_sighash(&[], &[], 0).unwrap()),
+            "10c1a75aa6b95571013a27312426854a14bec60903bd2c5d5187893b33c58994"
+        );
+
+        // And with nothing transparent at all, so every transparent hash is zeros and
+        // only the value balance is left to sign
+        let builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        assert_eq!(
+            hex::encode(builder.compute_binding_sig_sighash(&[], &[], 160_000).unwrap()),
+            "e5868ea159ece7c49aa30a3a597006a149ea6129499b64aaa4d23340e293787c"
+        );
+    }
+
+    #[test]
+    fn test_transparent_sighash_against_transparent_builder() {
+        use crate::bitcoinz_transparent_builder::input_sighash;
+
+        // With nothing shielded, both builders sign the same digest
+        let builder = fixture_builder();
+        for sighash_type in [
+            SIGHASH_ALL,
+            SIGHASH_NONE,
+            SIGHASH_SINGLE,
+            SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+        ] {
+            let parts = TransparentParts {
+                sighash_type,
+                ..TransparentParts::new(builder.transparent_inputs.clone(), builder.transparent_outputs.clone())
+            };
+            for (index, (_, coin, _)) in builder.transparent_inputs.iter().enumerate() {
+                let expected = input_sighash(
+                    &BITCOINZ_MAINNET,
+                    BlockHeight::from(500_000),
+                    TransparentTxFormat::Sapling,
+                    &parts,
+                    index,
+                    &coin.script_pubkey,
+                    coin.value,
+                )
+                .unwrap();
+                assert_eq!(
+                    sighash_hex(&builder, sighash_type, index).unwrap(),
+                    hex::encode(expected)
+                );
+            }
         }
-        
-        let hash = Params::new()
-            .hash_length(32)
-            .personal(ZCASH_OUTPUTS_HASH_PERSONALIZATION)
-            .to_state()
-            .update(&data)
-            .finalize();
-        
-        let mut result = [0u8; 32];
-        result.copy_from_slice(hash.as_bytes());
-        
-        Ok(result)
     }
-}
\ No newline at end of file
+
+    // t-input + z-spend funding a t-output with z-change
+    fn mixed_builder() -> BitcoinZShieldedBuilder<BitcoinZMainNetwork> {
+        mixed_builder_with_rng(StdRng::from_entropy())
+    }
+
+    fn mixed_builder_with_rng<R: RngCore + CryptoRng>(rng: R) -> BitcoinZShieldedBuilder<BitcoinZMainNetwork, R> {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let mut builder = BitcoinZShieldedBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000), rng);
+        builder
+            .add_transparent_input(
+                transparent::OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+            )
+            .unwrap();
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let to = extfvk.default_address().1;
+        let note = to
+            .create_note(200_000, Rseed::BeforeZip212(jubjub::Fr::from(7u64)))
+            .unwrap();
+        builder
+            .add_sapling_spend(
+                extsk.expsk.clone(),
+                *to.diversifier(),
+                note,
+                vec![Node::new([0u8; 32]); 32],
+                0,
+            )
+            .unwrap();
+
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(250_000).unwrap(),
+            )
+            .unwrap();
+        builder
+            .add_sapling_output(
+                Some(extfvk.fvk.ovk),
+                to,
+                Amount::from_u64(40_000).unwrap(),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+
+        builder
+    }
+
+    #[test]
+    fn test_mixed_transaction() {
+        use crate::blaze::test_utils::FakeTxProver;
+
+        let builder = mixed_builder();
+
+        let fee = Amount::from_u64(10_000).unwrap();
+        assert_eq!(builder.calculate_value_balance(fee).unwrap(), 160_000);
+
+        let tx = builder.build(&FakeTxProver {}, fee).unwrap().raw;
+
+        // Header and version group ID
+        assert_eq!(&tx[0..4], &0x80000004u32.to_le_bytes());
+        assert_eq!(&tx[4..8], &BITCOINZ_VERSION_GROUP_ID.to_le_bytes());
+        let mut cursor = 8;
+
+        // One signed transparent input
+        assert_eq!(tx[cursor], 1);
+        cursor += 1 + 36;
+        let script_sig_len = tx[cursor] as usize;
+        assert!(script_sig_len > 0);
+        assert_eq!(tx[cursor + 1 + tx[cursor + 1] as usize] as u32, SIGHASH_ALL);
+        cursor += 1 + script_sig_len + 4;
+
+        // One transparent output
+        assert_eq!(tx[cursor], 1);
+        cursor += 1;
+        assert_eq!(&tx[cursor..cursor + 8], &250_000u64.to_le_bytes());
+        cursor += 8 + 1 + 25;
+
+        // Lock time, expiry, value balance
+        cursor += 8;
+        assert_eq!(&tx[cursor..cursor + 8], &160_000i64.to_le_bytes());
+        cursor += 8;
+
+        // One spend with a real spend authorization signature
+        assert_eq!(tx[cursor], 1);
+        cursor += 1;
+        assert_ne!(&tx[cursor + 320..cursor + 384], &[0u8; 64][..]);
+        cursor += 384;
+
+        // The change output, and a dummy output padding to two
+        assert_eq!(tx[cursor], 2);
+        cursor += 1 + 2 * 948;
+
+        // No JoinSplits, then the binding signature closes the transaction
+        assert_eq!(tx[cursor], 0);
+        cursor += 1;
+        assert_eq!(tx.len(), cursor + 64);
+    }
+
+    #[test]
+    fn test_estimate_matches_built_size() {
+        use crate::blaze::test_utils::FakeTxProver;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let to = extfvk.default_address().1;
+
+        // t→z with a transparent change output
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder
+            .add_transparent_input(
+                transparent::OutPoint::new([0x11u8; 32], 0),
+                TxOut {
+                    value: Amount::from_u64(100_000).unwrap(),
+                    script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                },
+                SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                TransparentAddress::Script([0x66u8; 20]),
+                Amount::from_u64(30_000).unwrap(),
+            )
+            .unwrap();
+        builder
+            .add_sapling_output(
+                Some(extfvk.fvk.ovk),
+                to,
+                Amount::from_u64(60_000).unwrap(),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+
+        // Past 1,000 bytes, so it pays for two
+        let estimate = builder.estimate();
+        assert_eq!(estimate.fee, fee_for_size(estimate.size));
+        assert_eq!(estimate.fee, (DEFAULT_FEE + DEFAULT_FEE).unwrap());
+
+        assert_eq!(builder.estimated_size(), estimate.size);
+
+        let built = builder
+            .build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap())
+            .unwrap();
+        assert_eq!(built.size, built.raw.len());
+        assert_eq!(built.weight(), built.size);
+
+        // Only the DER signature length can differ (71 or 72 bytes, rarely less)
+        assert!(estimate.size >= built.size);
+        assert!(
+            estimate.size - built.size <= 2,
+            "estimate {} vs actual {}",
+            estimate.size,
+            built.size
+        );
+    }
+
+    #[test]
+    fn test_estimate_tx_size_matches_built() {
+        use crate::bitcoinz_transaction::estimate_tx_size;
+        use crate::bitcoinz_transparent_builder::TransparentTxFormat;
+        use crate::blaze::test_utils::FakeTxProver;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let to = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[2u8; 32]))
+            .default_address()
+            .1;
+        let fee = Amount::from_u64(20_000).unwrap();
+
+        // z→t and z→z with two spends, and t→z with three transparent inputs
+        for (spends, t_inputs, t_outputs, z_outputs) in [(2usize, 0usize, 1usize, 2usize), (2, 0, 0, 2), (0, 3, 1, 1)] {
+            let mut builder = spends_builder(&vec![100_000; spends]);
+            for i in 0..t_inputs {
+                builder
+                    .add_transparent_input(
+                        transparent::OutPoint::new([0x11u8; 32], i as u32),
+                        TxOut {
+                            value: Amount::from_u64(100_000).unwrap(),
+                            script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                        },
+                        SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+                    )
+                    .unwrap();
+            }
+
+            let total = 100_000 * (spends + t_inputs) as u64 - 20_000;
+            let value = total / (t_outputs + z_outputs) as u64;
+            let mut paid = 0;
+            for i in 0..(t_outputs + z_outputs) {
+                // The last output takes whatever the division left over
+                let value = if i + 1 == t_outputs + z_outputs {
+                    total - paid
+                } else {
+                    value
+                };
+                paid += value;
+                if i < t_outputs {
+                    builder
+                        .add_transparent_output(
+                            TransparentAddress::PublicKey([0x55u8; 20]),
+                            Amount::from_u64(value).unwrap(),
+                        )
+                        .unwrap();
+                } else {
+                    builder
+                        .add_sapling_output(None, to.clone(), Amount::from_u64(value).unwrap(), MemoBytes::empty())
+                        .unwrap();
+                }
+            }
+
+            let estimate = estimate_tx_size(t_inputs, t_outputs, spends, z_outputs, TransparentTxFormat::Sapling);
+            // The builder's own estimate allows a byte more for each transparent signature
+            assert_eq!(builder.estimate().size, estimate + t_inputs);
+
+            let actual = builder.build(&FakeTxProver {}, fee).unwrap().raw.len();
+            assert!(
+                actual <= estimate && estimate - actual <= 2 * t_inputs,
+                "{} spends, {} transparent inputs: estimated {}, built {}",
+                spends,
+                t_inputs,
+                estimate,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_without_transparent_inputs() {
+        let mut builder = fixture_builder();
+        builder.transparent_inputs.clear();
+        builder.transparent_outputs.clear();
+
+        // header + 4 empty counts + locktime/expiry/value balance + joinsplit count + binding sig
+        assert_eq!(builder.estimate().size, 8 + 1 + 1 + 16 + 1 + 1 + 1 + 64);
+    }
+
+    #[test]
+    fn test_unbalanced_transaction_rejected() {
+        let mut builder = fixture_builder();
+        // 350_000 in, 340_000 out: only a 10_000 fee balances
+        assert!(builder
+            .calculate_value_balance(Amount::from_u64(10_000).unwrap())
+            .is_ok());
+        assert!(builder
+            .calculate_value_balance(Amount::from_u64(1_000).unwrap())
+            .is_err());
+        builder.transparent_outputs.pop();
+        assert!(builder
+            .calculate_value_balance(Amount::from_u64(10_000).unwrap())
+            .is_err());
+    }
+
+    /// A z→t builder spending notes of the given values
+    fn spends_builder(values: &[u64]) -> BitcoinZShieldedBuilder<BitcoinZMainNetwork> {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let to = ExtendedFullViewingKey::from(&extsk).default_address().1;
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        for value in values {
+            let note = to
+                .create_note(*value, Rseed::BeforeZip212(jubjub::Fr::from(7u64)))
+                .unwrap();
+            builder
+                .add_sapling_spend(
+                    extsk.expsk.clone(),
+                    *to.diversifier(),
+                    note,
+                    vec![Node::new([0u8; 32]); 32],
+                    0,
+                )
+                .unwrap();
+        }
+        builder
+    }
+
+    #[test]
+    fn test_invalid_diversifier() {
+        use crate::blaze::test_utils::FakeTxProver;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        // Roughly half of all diversifiers don't hash to a point
+        let bad = (0u8..=255)
+            .map(|i| Diversifier([i; 11]))
+            .find(|d| d.g_d().is_none())
+            .unwrap();
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let to = ExtendedFullViewingKey::from(&extsk).default_address().1;
+        let note = to
+            .create_note(200_000, Rseed::BeforeZip212(jubjub::Fr::from(7u64)))
+            .unwrap();
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder
+            .add_sapling_spend(extsk.expsk.clone(), bad, note, vec![Node::new([0u8; 32]); 32], 0)
+            .unwrap();
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            )
+            .unwrap();
+
+        let err = builder
+            .build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap())
+            .unwrap_err();
+        assert_eq!(err, String::from(BitcoinZTxError::InvalidDiversifier(bad.0)));
+
+        // The note's own diversifier is fine
+        let mut builder = spends_builder(&[200_000]);
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            )
+            .unwrap();
+        assert!(builder
+            .build(&FakeTxProver {}, Amount::from_u64(10_000).unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_dust_outputs() {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let taddr = TransparentAddress::PublicKey([0x55u8; 20]);
+        let zaddr = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[1u8; 32]))
+            .default_address()
+            .1;
+        let amount = |zats: u64| Amount::from_u64(zats).unwrap();
+        let dust = String::from(BitcoinZTxError::DustOutput {
+            amount: DUST_THRESHOLD - 1,
+        });
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        assert_eq!(
+            builder.add_transparent_output(taddr.clone(), amount(DUST_THRESHOLD - 1)),
+            Err(dust)
+        );
+        assert!(builder.transparent_outputs.is_empty());
+        builder
+            .add_transparent_output(taddr.clone(), amount(DUST_THRESHOLD))
+            .unwrap();
+
+        // Shielded outputs aren't dust, however small
+        builder
+            .add_sapling_output(None, zaddr.clone(), amount(DUST_THRESHOLD - 1), MemoBytes::empty())
+            .unwrap();
+        builder
+            .add_sapling_output(None, zaddr, amount(1), MemoBytes::empty())
+            .unwrap();
+        assert_eq!(builder.sapling_outputs.len(), 2);
+
+        // A lower threshold, as a testnet node might relay with
+        builder.set_dust_threshold(1);
+        builder.add_transparent_output(taddr.clone(), amount(1)).unwrap();
+        assert!(builder.add_transparent_output(taddr, amount(0)).is_err());
+    }
+
+    #[test]
+    fn test_value_balance_exact() {
+        let fee = Amount::from_u64(10_000).unwrap();
+
+        let mut builder = spends_builder(&[200_000]);
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(builder.calculate_value_balance(fee), Ok(200_000));
+
+        // The whole of MAX_MONEY, exactly
+        let max_money = Amount::from_u64(21_000_000 * 100_000_000).unwrap();
+        let mut builder = spends_builder(&[u64::from(max_money)]);
+        builder
+            .add_transparent_output(TransparentAddress::PublicKey([0x55u8; 20]), max_money)
+            .unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(Amount::zero()),
+            Ok(i64::from(max_money))
+        );
+    }
+
+    #[test]
+    fn test_value_balance_insufficient_funds() {
+        let mut builder = fixture_builder();
+        builder.transparent_outputs[0].1 = Amount::from_u64(160_000).unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(Amount::from_u64(10_000).unwrap()),
+            Err(ValueBalanceError::InsufficientFunds { missing: 10_000 })
+        );
+
+        // Nothing gets proved
+        let prover = crate::testing::MockTxProver::default();
+        let err = builder.build(&prover, Amount::from_u64(10_000).unwrap()).unwrap_err();
+        assert!(err.starts_with("Insufficient funds"), "{}", err);
+        assert_eq!(prover.log().contexts, 0);
+    }
+
+    #[test]
+    fn test_value_balance_negative_fee() {
+        let builder = fixture_builder();
+        let fee = Amount::from_i64(-10_000).unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(fee),
+            Err(ValueBalanceError::NegativeFee(-10_000))
+        );
+    }
+
+    #[test]
+    fn test_value_balance_overflow() {
+        let fee = Amount::from_u64(10_000).unwrap();
+
+        // Two notes that sum past i64::MAX
+        let builder = spends_builder(&[i64::MAX as u64, 1]);
+        assert_eq!(builder.calculate_value_balance(fee), Err(ValueBalanceError::Overflow));
+
+        // A note value that isn't an i64 at all
+        let builder = spends_builder(&[u64::MAX]);
+        assert_eq!(builder.calculate_value_balance(fee), Err(ValueBalanceError::Overflow));
+
+        // i64::MAX on its own fits in an i64, but not under MAX_MONEY
+        let builder = spends_builder(&[i64::MAX as u64]);
+        assert_eq!(builder.calculate_value_balance(fee), Err(ValueBalanceError::Overflow));
+    }
+
+    #[test]
+    fn test_value_balance_max_money() {
+        let max_money = MAX_MONEY as u64;
+        let to = TransparentAddress::PublicKey([0x55u8; 20]);
+
+        // One zatoshi past MAX_MONEY going in, even though it balances
+        let mut builder = spends_builder(&[max_money, 1]);
+        builder
+            .add_transparent_output(to.clone(), Amount::from_u64(max_money).unwrap())
+            .unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(Amount::from_u64(1).unwrap()),
+            Err(ValueBalanceError::Overflow)
+        );
+
+        // A note worth MAX_MONEY plus a fee going out
+        let mut builder = spends_builder(&[max_money - 10_000]);
+        builder
+            .add_transparent_output(to.clone(), Amount::from_u64(max_money - 10_000).unwrap())
+            .unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(Amount::from_u64(10_001).unwrap()),
+            Err(ValueBalanceError::Overflow)
+        );
+
+        // Up to MAX_MONEY on each side is fine
+        let mut builder = spends_builder(&[max_money - 10_000, 10_000]);
+        builder
+            .add_transparent_output(to, Amount::from_u64(max_money - 10_000).unwrap())
+            .unwrap();
+        assert_eq!(
+            builder.calculate_value_balance(Amount::from_u64(10_000).unwrap()),
+            Ok(MAX_MONEY)
+        );
+    }
+
+    #[test]
+    fn test_set_sighash_type() {
+        let mut builder = fixture_builder();
+        assert_eq!(builder.sighash_type, SIGHASH_ALL);
+        builder.set_sighash_type(SIGHASH_SINGLE).unwrap();
+        assert_eq!(builder.sighash_type, SIGHASH_SINGLE);
+        assert!(builder.set_sighash_type(0x05).is_err());
+        assert_eq!(builder.sighash_type, SIGHASH_SINGLE);
+    }
+
+    #[test]
+    fn test_set_sequence() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use secp256k1::ecdsa::Signature as EcdsaSignature;
+
+        // Opt-in RBF on the second input
+        let mut builder = fixture_builder();
+        builder.set_sequence(1, 0xfffffffd).unwrap();
+        assert!(builder.set_sequence(2, 0).is_err());
+        let tx = builder
+            .build(
+                &crate::testing::MockTxProver::default(),
+                Amount::from_u64(10_000).unwrap(),
+            )
+            .unwrap()
+            .raw;
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+        let sequences: Vec<u32> = parsed.inputs.iter().map(|i| i.sequence).collect();
+        assert_eq!(sequences, vec![DEFAULT_SEQUENCE, 0xfffffffd]);
+
+        // Each scriptSig verifies against the sighash of the sequences as serialized
+        let mut verifier = fixture_builder();
+        for (i, sequence) in sequences.iter().enumerate() {
+            verifier.set_sequence(i, *sequence).unwrap();
+        }
+        let unchanged = fixture_builder();
+        let secp = Secp256k1::verification_only();
+        for (i, input) in parsed.inputs.iter().enumerate() {
+            let script_sig = &input.script_sig;
+            let sig_len = script_sig[0] as usize;
+            let signature = EcdsaSignature::from_der(&script_sig[1..sig_len]).unwrap();
+            let pubkey = SecpPublicKey::from_slice(&script_sig[sig_len + 2..]).unwrap();
+
+            let sighash = |b: &BitcoinZShieldedBuilder<BitcoinZMainNetwork>| {
+                let (_, txout, _) = &b.transparent_inputs[i];
+                let hash = b
+                    .compute_sapling_sighash(&[], i, &txout.script_pubkey, txout.value, SIGHASH_ALL, &[], &[], 0)
+                    .unwrap();
+                Message::from_slice(&hash).unwrap()
+            };
+            assert!(secp.verify_ecdsa(&sighash(&verifier), &signature, &pubkey).is_ok());
+
+            // SIGHASH_ALL commits to every input's sequence, not just its own
+            assert!(secp.verify_ecdsa(&sighash(&unchanged), &signature, &pubkey).is_err());
+        }
+    }
+
+    #[test]
+    fn test_p2sh_input() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use crate::bitcoinz_script_sig::{hash160, multisig_redeem_script};
+        use secp256k1::ecdsa::Signature as EcdsaSignature;
+
+        // A 1-of-1 multisig behind a t3 address
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0x07u8; 32]).unwrap();
+        let pubkey = SecpPublicKey::from_secret_key(&secp, &sk);
+        let redeem_script = multisig_redeem_script(1, &[pubkey]).unwrap();
+        let coin = TxOut {
+            value: Amount::from_u64(100_000).unwrap(),
+            script_pubkey: TransparentAddress::Script(hash160(&redeem_script.0)).script(),
+        };
+        let p2sh_builder = || {
+            let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+            builder
+                .add_transparent_script_input(
+                    transparent::OutPoint::new([0x11u8; 32], 0),
+                    coin.clone(),
+                    redeem_script.clone(),
+                    vec![sk],
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    TransparentAddress::PublicKey([0x55u8; 20]),
+                    Amount::from_u64(90_000).unwrap(),
+                )
+                .unwrap();
+            builder
+        };
+
+        let builder = p2sh_builder();
+        let estimate = builder.estimate();
+        let tx = builder
+            .build(
+                &crate::testing::MockTxProver::default(),
+                Amount::from_u64(10_000).unwrap(),
+            )
+            .unwrap()
+            .raw;
+        assert!(estimate.size >= tx.len());
+
+        // OP_0, the signature at its largest and the 37-byte redeem script: six bytes
+        // more than a P2PKH input
+        assert_eq!(redeem_script.0.len(), 37);
+        assert_eq!(estimate.transparent_input_sizes, vec![36 + 1 + (1 + 74 + 1 + 37) + 4]);
+
+        // OP_0 <sig> <redeemScript>
+        let script_sig = &parse_bitcoinz_v4(&tx).unwrap().inputs[0].script_sig;
+        assert_eq!(script_sig[0], 0x00);
+        let sig_len = script_sig[1] as usize;
+        assert_eq!(script_sig[1 + sig_len] as u32, SIGHASH_ALL);
+        let signature = EcdsaSignature::from_der(&script_sig[2..1 + sig_len]).unwrap();
+        assert_eq!(&script_sig[3 + sig_len..], &redeem_script.0[..]);
+
+        // The signature commits to the redeem script as the script code
+        let verifier = p2sh_builder();
+        let sighash = |script_code: &Script| {
+            let hash = verifier
+                .compute_sapling_sighash(&[], 0, script_code, coin.value, SIGHASH_ALL, &[], &[], 0)
+                .unwrap();
+            Message::from_slice(&hash).unwrap()
+        };
+        assert!(secp.verify_ecdsa(&sighash(&redeem_script), &signature, &pubkey).is_ok());
+        assert!(secp
+            .verify_ecdsa(&sighash(&coin.script_pubkey), &signature, &pubkey)
+            .is_err());
+
+        // A redeem script the prevout doesn't pay to is refused up front
+        let other = multisig_redeem_script(
+            1,
+            &[SecpPublicKey::from_secret_key(
+                &secp,
+                &SecretKey::from_slice(&[0x08u8; 32]).unwrap(),
+            )],
+        )
+        .unwrap();
+        let mut builder = fixture_builder();
+        assert!(builder
+            .add_transparent_script_input(
+                transparent::OutPoint::new([0x11u8; 32], 0),
+                coin.clone(),
+                other,
+                vec![sk]
+            )
+            .is_err());
+        assert_eq!(builder.transparent_inputs.len(), 2);
+    }
+
+    #[test]
+    fn test_set_expiry_height() {
+        use crate::blaze::test_utils::FakeTxProver;
+
+        // None signs the same zero expiry as the fixture hashes above
+        let mut builder = fixture_builder();
+        let no_expiry = sighash_hex(&builder, SIGHASH_ALL, 0).unwrap();
+        assert_eq!(
+            no_expiry,
+            "f39da27049a575ce8d7b857863a878c3755aa9f415514dedfd6ab4decacd5312"
+        );
+
+        builder.set_expiry_height(Some(500_020));
+        let expiring = sighash_hex(&builder, SIGHASH_ALL, 0).unwrap();
+        assert_ne!(expiring, no_expiry);
+        builder.set_expiry_height(Some(500_021));
+        assert_ne!(sighash_hex(&builder, SIGHASH_ALL, 0).unwrap(), expiring);
+        builder.set_expiry_height(None);
+        assert_eq!(sighash_hex(&builder, SIGHASH_ALL, 0).unwrap(), no_expiry);
+
+        // The serialized expiry follows the lock time after the single transparent output
+        let expiry_of = |tx: &[u8]| {
+            let script_sig_len = tx[8 + 1 + 36] as usize;
+            let cursor = 8 + 1 + 36 + 1 + script_sig_len + 4 + 1 + 8 + 1 + 25 + 4;
+            u32::from_le_bytes([tx[cursor], tx[cursor + 1], tx[cursor + 2], tx[cursor + 3]])
+        };
+        let fee = Amount::from_u64(10_000).unwrap();
+
+        let tx = mixed_builder().build(&FakeTxProver {}, fee).unwrap().raw;
+        assert_eq!(expiry_of(&tx), 0);
+
+        // build() checks the binding signature, which commits to the expiry too
+        let mut builder = mixed_builder();
+        builder.set_expiry_height(Some(500_020));
+        let tx = builder.build(&FakeTxProver {}, fee).unwrap().raw;
+        assert_eq!(expiry_of(&tx), 500_020);
+    }
+
+    #[test]
+    fn test_large_fee_allowed_for_shielded_size() {
+        use crate::bitcoinz_transaction::max_fee;
+        use crate::blaze::test_utils::FakeTxProver;
+
+        // 110,000 would be absurd for a transparent transaction, but this one carries
+        // a spend and an output
+        let mut builder = mixed_builder();
+        builder.transparent_outputs[0].1 = Amount::from_u64(150_000).unwrap();
+        let fee = Amount::from_u64(110_000).unwrap();
+        assert!(i64::from(max_fee(builder.estimate().size)) >= 110_000);
+        assert_eq!(builder.build(&FakeTxProver {}, fee).unwrap().fee, fee);
+    }
+
+    #[test]
+    fn test_oversized_transaction_refused() {
+        use crate::bitcoinz_transaction::MAX_STANDARD_TX_SIZE;
+        use crate::blaze::test_utils::FakeTxProver;
+
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        for i in 0..700 {
+            builder
+                .add_transparent_input(
+                    transparent::OutPoint::new([0x11u8; 32], i),
+                    TxOut {
+                        value: Amount::from_u64(10_000).unwrap(),
+                        script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                    },
+                    sk,
+                )
+                .unwrap();
+        }
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(6_000_000).unwrap(),
+            )
+            .unwrap();
+
+        assert!(builder.estimate().size > MAX_STANDARD_TX_SIZE);
+        let err = builder
+            .build(&FakeTxProver {}, Amount::from_u64(1_000_000).unwrap())
+            .unwrap_err();
+        assert!(err.contains("byte limit"), "{}", err);
+    }
+
+    #[test]
+    fn test_built_transaction() {
+        use crate::bitcoinz_transaction::{compute_txid, BitcoinZTxType};
+        use crate::blaze::test_utils::FakeTxProver;
+
+        let fee = Amount::from_u64(10_000).unwrap();
+        let mut builder = mixed_builder();
+        builder.set_expiry_height(Some(500_020));
+        let built = builder.build(&FakeTxProver {}, fee).unwrap();
+
+        assert_eq!(built.txid, compute_txid(&built.raw));
+        assert_eq!(built.size, built.raw.len());
+        assert_eq!(built.fee, fee);
+        assert_eq!(built.tx_type, BitcoinZTxType::Mixed);
+        assert_eq!(built.expiry_height, 500_020);
+
+        // Typed by the real outputs, not the padding that goes in with them
+        let mut builder = spends_builder(&[200_000]);
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(190_000).unwrap(),
+            )
+            .unwrap();
+        assert!(builder.padding_outputs() > 0);
+        let built = builder.build(&FakeTxProver {}, fee).unwrap();
+        assert_eq!(built.tx_type, BitcoinZTxType::ShieldedToTransparent);
+    }
+
+    #[test]
+    fn test_pinned_anchor() {
+        use zcash_primitives::merkle_tree::IncrementalWitness;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let to = ExtendedFullViewingKey::from(&extsk).default_address().1;
+        let note1 = to
+            .create_note(100_000, Rseed::BeforeZip212(jubjub::Fr::random(&mut thread_rng())))
+            .unwrap();
+        let note2 = to
+            .create_note(200_000, Rseed::BeforeZip212(jubjub::Fr::random(&mut thread_rng())))
+            .unwrap();
+
+        // Two notes in one tree, plus a witness for the first taken before the second was added
+        let mut tree = CommitmentTree::<Node>::empty();
+        tree.append(note_commitment_node(&note1)).unwrap();
+        let mut witness1 = IncrementalWitness::from_tree(&tree);
+        let stale_witness1 = witness1.clone();
+        tree.append(note_commitment_node(&note2)).unwrap();
+        witness1.append(note_commitment_node(&note2)).unwrap();
+        let witness2 = IncrementalWitness::from_tree(&tree);
+
+        let add_spend = |builder: &mut BitcoinZShieldedBuilder<BitcoinZMainNetwork>,
+                         note: &Note,
+                         witness: &IncrementalWitness<Node>| {
+            let path = witness.path().unwrap();
+            builder.add_sapling_spend(
+                extsk.expsk.clone(),
+                *to.diversifier(),
+                note.clone(),
+                path.auth_path.iter().map(|(node, _)| *node).collect(),
+                path.position,
+            )
+        };
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        assert!(builder.pin_anchor(stale_witness1.root(), tree.clone()).is_err());
+        builder.pin_anchor(tree.root(), tree.clone()).unwrap();
+        assert_eq!(builder.pinned_anchor(), Some(tree.root()));
+
+        add_spend(&mut builder, &note1, &witness1).unwrap();
+        add_spend(&mut builder, &note2, &witness2).unwrap();
+        assert!(add_spend(&mut builder, &note1, &stale_witness1).is_err());
+        assert_eq!(builder.sapling_spends.len(), 2);
+
+        // Both spends are proved against the pinned anchor, the one in their descriptions
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(290_000).unwrap(),
+            )
+            .unwrap();
+        let prover = crate::testing::MockTxProver::default();
+        let tx = builder.build(&prover, Amount::from_u64(10_000).unwrap()).unwrap().raw;
+        let tx = crate::bitcoinz_parse::parse_bitcoinz_v4(&tx).unwrap();
+        let anchors = prover.log().spend_anchors;
+        assert_eq!(anchors.len(), 2);
+        for (spend, anchor) in tx.shielded_spends.iter().zip(&anchors) {
+            assert_eq!(anchor.to_bytes(), node_bytes(&tree.root()));
+            assert_eq!(spend.anchor, anchor.to_bytes());
+        }
+
+        // Pinning after the fact checks the spends already added
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        add_spend(&mut builder, &note1, &stale_witness1).unwrap();
+        assert!(builder.pin_anchor(tree.root(), tree.clone()).is_err());
+        assert_eq!(builder.pinned_anchor(), None);
+    }
+
+    #[test]
+    fn test_spend_from_witness() {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let to = ExtendedFullViewingKey::from(&extsk).default_address().1;
+        let notes: Vec<Note> = (0..6u64)
+            .map(|i| {
+                to.create_note(10_000 * (i + 1), Rseed::BeforeZip212(jubjub::Fr::from(i + 1)))
+                    .unwrap()
+            })
+            .collect();
+
+        // A witness for every note in a tree of six, brought up to date
+        let mut tree = CommitmentTree::<Node>::empty();
+        let mut witnesses: Vec<IncrementalWitness<Node>> = vec![];
+        for note in &notes {
+            let node = note_commitment_node(note);
+            tree.append(node).unwrap();
+            for witness in witnesses.iter_mut() {
+                witness.append(node).unwrap();
+            }
+            witnesses.push(IncrementalWitness::from_tree(&tree));
+        }
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder.pin_anchor(tree.root(), tree.clone()).unwrap();
+
+        // Odd positions are right children at the leaf, 2 and 3 one level up, 4 and 5 two up
+        for position in [1usize, 2, 3, 4, 5] {
+            builder
+                .add_sapling_spend_from_witness(
+                    extsk.expsk.clone(),
+                    *to.diversifier(),
+                    notes[position].clone(),
+                    &witnesses[position],
+                )
+                .unwrap();
+
+            let spend = builder.sapling_spends.last().unwrap();
+            assert_eq!(spend.witness_position, position as u64);
+            let root = spend_merkle_path(&spend.merkle_path, spend.witness_position)
+                .unwrap()
+                .root(note_commitment_node(&notes[position]));
+            assert_eq!(root, witnesses[position].root());
+
+            // Every node on the left gives some other root
+            let all_left = MerklePath::from_path(
+                spend.merkle_path.iter().map(|node| (*node, false)).collect(),
+                position as u64,
+            );
+            assert_ne!(
+                all_left.root(note_commitment_node(&notes[position])),
+                witnesses[position].root()
+            );
+        }
+
+        // A witness for a different note is refused
+        assert!(builder
+            .add_sapling_spend_from_witness(extsk.expsk.clone(), *to.diversifier(), notes[0].clone(), &witnesses[1])
+            .is_err());
+        assert_eq!(builder.sapling_spends.len(), 5);
+
+        // As is a path whose sides disagree with its position
+        let mut path = witnesses[3].path().unwrap();
+        path.auth_path[0].1 = false;
+        assert!(witness_path_nodes(&path).is_err());
+    }
+
+    #[test]
+    fn test_nullifier_uses_witness_position() {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extsk = ExtendedSpendingKey::master(&[1u8; 32]);
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let to = extfvk.default_address().1;
+        let notes: Vec<Note> = (0..3u64)
+            .map(|i| {
+                to.create_note(100_000, Rseed::BeforeZip212(jubjub::Fr::from(i + 1)))
+                    .unwrap()
+            })
+            .collect();
+
+        let mut tree = CommitmentTree::<Node>::empty();
+        for note in &notes {
+            tree.append(note_commitment_node(note)).unwrap();
+        }
+        let witness = IncrementalWitness::from_tree(&tree);
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder
+            .add_sapling_spend_from_witness(extsk.expsk.clone(), *to.diversifier(), notes[2].clone(), &witness)
+            .unwrap();
+        builder
+            .add_transparent_output(
+                TransparentAddress::PublicKey([0x55u8; 20]),
+                Amount::from_u64(90_000).unwrap(),
+            )
+            .unwrap();
+        let tx = builder
+            .build(
+                &crate::testing::MockTxProver::default(),
+                Amount::from_u64(10_000).unwrap(),
+            )
+            .unwrap()
+            .raw;
+        let tx = crate::bitcoinz_parse::parse_bitcoinz_v4(&tx).unwrap();
+
+        let nk = extfvk.fvk.vk.nk;
+        assert_eq!(tx.shielded_spends[0].nullifier, notes[2].nf(&nk, 2).0);
+        assert_ne!(tx.shielded_spends[0].nullifier, notes[2].nf(&nk, 0).0);
+    }
+
+    #[test]
+    fn test_build_with_progress() {
+        use crate::blaze::test_utils::FakeTxProver;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // Rc isn't Send, so this also checks the callback doesn't need to be
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorder = events.clone();
+        let tx = mixed_builder()
+            .build_with_progress(&FakeTxProver {}, Amount::from_u64(10_000).unwrap(), move |p| {
+                recorder.borrow_mut().push(p)
+            })
+            .unwrap();
+        assert!(!tx.raw.is_empty());
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                BuildProgress::Selecting,
+                BuildProgress::ProvingSpend { current: 1, total: 1 },
+                BuildProgress::ProvingOutput { current: 1, total: 2 },
+                BuildProgress::ProvingOutput { current: 2, total: 2 },
+                BuildProgress::Signing,
+                BuildProgress::Serializing,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binding_signature_check() {
+        let mut rng = thread_rng();
+        let rcv_spend = jubjub::Fr::random(&mut rng);
+        let rcv_output = jubjub::Fr::random(&mut rng);
+        let cv_spend: jubjub::ExtendedPoint = ValueCommitment {
+            value: 100_000,
+            randomness: rcv_spend,
+        }
+        .commitment()
+        .into();
+        let cv_output: jubjub::ExtendedPoint = ValueCommitment {
+            value: 60_000,
+            randomness: rcv_output,
+        }
+        .commitment()
+        .into();
+
+        // bsk is the spend randomness minus the output randomness
+        let bsk = PrivateKey(rcv_spend - rcv_output);
+        let bvk = PublicKey::from_private(&bsk, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+        let derived = binding_verification_key(&[cv_spend], &[cv_output], 40_000);
+        assert_eq!(derived.0, bvk.0);
+
+        let sighash = [0x42u8; 32];
+        let mut bvk_bytes = [0u8; 32];
+        bvk.write(&mut bvk_bytes[..]).unwrap();
+        let sig = bsk.sign(
+            &compute_bitcoinz_binding_message(&bvk_bytes, &sighash),
+            &mut rng,
+            VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+        );
+        assert!(verify_binding_signature(&derived, &sighash, &sig).is_ok());
+        assert!(verify_bitcoinz_binding_signature(&derived, &sighash, &sig));
+
+        // Wrong value balance, so bvk doesn't follow from the commitments
+        let wrong_bvk = binding_verification_key(&[cv_spend], &[cv_output], 40_001);
+        assert!(verify_binding_signature(&wrong_bvk, &sighash, &sig).is_err());
+
+        // Signed over a different sighash
+        let mut corrupted = sighash;
+        corrupted[0] ^= 1;
+        assert!(verify_binding_signature(&derived, &corrupted, &sig).is_err());
+        assert!(!verify_bitcoinz_binding_signature(&derived, &corrupted, &sig));
+
+        // Over the sighash alone rather than bvk || sighash
+        let sig = bsk.sign(&sighash, &mut rng, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+        assert!(verify_binding_signature(&derived, &sighash, &sig).is_err());
+    }
+
+    #[test]
+    fn test_binding_sig_generator_is_consistent() {
+        let mut rng = thread_rng();
+        let rcv_spend = jubjub::Fr::random(&mut rng);
+        let rcv_output = jubjub::Fr::random(&mut rng);
+        let cv =
+            |value, randomness| -> jubjub::ExtendedPoint { ValueCommitment { value, randomness }.commitment().into() };
+
+        // Signed with bsk the way the prover signs, and checked against bvk derived the way a
+        // verifier derives it, from the commitments: both use the value commitment randomness base
+        let bsk = PrivateKey(rcv_spend - rcv_output);
+        let bvk = binding_verification_key(&[cv(100_000, rcv_spend)], &[cv(60_000, rcv_output)], 40_000);
+        let sighash = [0x42u8; 32];
+        let message = compute_bitcoinz_binding_message(&bvk.0.to_bytes(), &sighash);
+        let sig = bsk.sign(&message, &mut rng, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+        assert!(verify_bitcoinz_binding_signature(&bvk, &sighash, &sig));
+
+        // Under the subgroup's own generator, the key and the signature don't match
+        let other = jubjub::SubgroupPoint::generator();
+        assert_ne!(other, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+        assert!(!bvk.verify(&message, &sig, other));
+        let sig = bsk.sign(&message, &mut rng, other);
+        assert!(!verify_bitcoinz_binding_signature(&bvk, &sighash, &sig));
+    }
+
+    /// FakeTxProver, except that the binding signature is over the wrong sighash
+    struct CorruptBindingSigProver(crate::blaze::test_utils::FakeTxProver);
+
+    impl TxProver for CorruptBindingSigProver {
+        type SaplingProvingContext = crate::blaze::test_utils::FakeProvingContext;
+
+        fn new_sapling_proving_context(&self) -> Self::SaplingProvingContext {
+            self.0.new_sapling_proving_context()
+        }
+
+        fn spend_proof(
+            &self,
+            ctx: &mut Self::SaplingProvingContext,
+            proof_generation_key: ProofGenerationKey,
+            diversifier: Diversifier,
+            rseed: Rseed,
+            ar: jubjub::Fr,
+            value: u64,
+            anchor: bls12_381::Scalar,
+            merkle_path: MerklePath<Node>,
+        ) -> Result<([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint, PublicKey), ()> {
+            self.0.spend_proof(
+                ctx,
+                proof_generation_key,
+                diversifier,
+                rseed,
+                ar,
+                value,
+                anchor,
+                merkle_path,
+            )
+        }
+
+        fn output_proof(
+            &self,
+            ctx: &mut Self::SaplingProvingContext,
+            esk: jubjub::Fr,
+            payment_address: PaymentAddress,
+            rcm: jubjub::Fr,
+            value: u64,
+        ) -> ([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint) {
+            self.0.output_proof(ctx, esk, payment_address, rcm, value)
+        }
+
+        fn binding_sig(
+            &self,
+            ctx: &mut Self::SaplingProvingContext,
+            value_balance: Amount,
+            sighash: &[u8; 32],
+        ) -> Result<Signature, ()> {
+            let mut corrupted = *sighash;
+            corrupted[31] ^= 0xff;
+            self.0.binding_sig(ctx, value_balance, &corrupted)
+        }
+    }
+
+    #[test]
+    fn test_build_verifies_binding_signature() {
+        use crate::blaze::test_utils::FakeTxProver;
+
+        let fee = Amount::from_u64(10_000).unwrap();
+        assert!(mixed_builder().build(&FakeTxProver {}, fee).is_ok());
+
+        let err = mixed_builder()
+            .build(&CorruptBindingSigProver(FakeTxProver {}), fee)
+            .unwrap_err();
+        assert!(err.contains("Binding signature does not verify"), "{}", err);
+
+        // With the self-check off, the bad signature goes out unnoticed
+        let mut builder = mixed_builder();
+        builder.set_verify_on_build(false);
+        assert!(builder.build(&CorruptBindingSigProver(FakeTxProver {}), fee).is_ok());
+    }
+
+    #[test]
+    fn test_seeded_builds_are_reproducible() {
+        let fee = Amount::from_u64(10_000).unwrap();
+        let build = |seed: u64| {
+            mixed_builder_with_rng(StdRng::seed_from_u64(seed))
+                .build(&crate::testing::MockTxProver::new(42), fee)
+                .unwrap()
+        };
+
+        assert_eq!(build(1), build(1));
+
+        // The builder's own randomness (alpha, rseed, encryption) does change the result
+        assert_ne!(build(1), build(2));
+    }
+
+    #[test]
+    fn test_seeded_builds_match_field_by_field() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+
+        // The builder draws everything from its own RNG, and MockTxProver is seeded too,
+        // so nothing random is left to chance
+        let fee = Amount::from_u64(10_000).unwrap();
+        let build = |seed: u64| {
+            let raw = mixed_builder_with_rng(StdRng::seed_from_u64(seed))
+                .build(&crate::testing::MockTxProver::new(42), fee)
+                .unwrap()
+                .raw;
+            parse_bitcoinz_v4(&raw).unwrap()
+        };
+
+        let (first, second) = (build(9), build(9));
+        assert!(!first.shielded_outputs.is_empty());
+        for (a, b) in first.shielded_outputs.iter().zip(&second.shielded_outputs) {
+            assert_eq!(a.cv, b.cv);
+            // The note's rcm comes from the rseed, and the encryption from the RNG
+            assert_eq!(a.cmu, b.cmu);
+            assert_eq!(a.ephemeral_key, b.ephemeral_key);
+            assert_eq!(a.enc_ciphertext, b.enc_ciphertext);
+            assert_eq!(a.out_ciphertext, b.out_ciphertext);
+        }
+        assert_eq!(first.shielded_spends, second.shielded_spends);
+        assert!(first.binding_sig.is_some());
+        assert_eq!(first.binding_sig, second.binding_sig);
+
+        // Another seed gives another rseed, so other notes
+        let other = build(10);
+        assert_ne!(first.shielded_outputs[0].cmu, other.shielded_outputs[0].cmu);
+    }
+
+    #[test]
+    fn test_seeded_build_txid_is_stable() {
+        use crate::bitcoinz_txid::WireTxid;
+        use sha2::{Digest, Sha256};
+
+        let fee = Amount::from_u64(10_000).unwrap();
+        let build = |seed: u64| {
+            mixed_builder_with_rng(StdRng::seed_from_u64(seed))
+                .build(&crate::testing::MockTxProver::new(42), fee)
+                .unwrap()
+        };
+
+        let (first, second) = (build(9), build(9));
+        assert_eq!(first.raw, second.raw);
+        assert_eq!(first.txid, second.txid);
+        assert_ne!(first.txid, build(10).txid);
+
+        // The double SHA-256 of the whole transaction, shown byte-reversed
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(&Sha256::digest(&first.raw)));
+        let wire = WireTxid::from_bytes(hash);
+        let json = first.to_json();
+        assert_eq!(json["txid"], wire.to_display().to_string());
+        assert_eq!(json["hex"], hex::encode(&first.raw));
+        assert_eq!(json["size"], first.raw.len());
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let fee = Amount::from_u64(10_000).unwrap();
+        let build = |reversed: bool, canonical_order: bool| {
+            let mut builder = mixed_builder_with_rng(StdRng::seed_from_u64(9));
+            builder
+                .add_transparent_input(
+                    transparent::OutPoint::new([0x05u8; 32], 3),
+                    TxOut {
+                        value: Amount::from_u64(50_000).unwrap(),
+                        script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                    },
+                    SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    TransparentAddress::Script([0x66u8; 20]),
+                    Amount::from_u64(50_000).unwrap(),
+                )
+                .unwrap();
+            if reversed {
+                builder.transparent_inputs.reverse();
+                builder.transparent_sequences.reverse();
+                builder.transparent_outputs.reverse();
+            }
+            builder.set_canonical_order(canonical_order);
+            builder.build(&crate::testing::MockTxProver::new(42), fee).unwrap()
+        };
+
+        // Added in either order, the transparent parts are signed and written the same way
+        assert_eq!(build(false, true).raw, build(true, true).raw);
+        assert_ne!(build(false, false).raw, build(true, false).raw);
+    }
+
+    #[test]
+    fn test_seeded_rng_draws_alpha() {
+        let alphas = |seed: u64| {
+            mixed_builder_with_rng(StdRng::seed_from_u64(seed))
+                .sapling_spends
+                .iter()
+                .map(|spend| spend.alpha)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alphas(5), alphas(5));
+        assert_ne!(alphas(5), alphas(6));
+
+        // Nothing draws from the RNG before the first spend's alpha, so a caller holding
+        // the seed knows it. The rest (rseed, rcv, encryption) is pinned by the build above.
+        assert_eq!(alphas(5)[0], jubjub::Fr::random(&mut StdRng::seed_from_u64(5)));
+    }
+
+    #[test]
+    fn test_build_to_writer() {
+        let fee = Amount::from_u64(10_000).unwrap();
+        let built = mixed_builder_with_rng(StdRng::seed_from_u64(1))
+            .build(&crate::testing::MockTxProver::new(42), fee)
+            .unwrap();
+
+        let mut written = Vec::new();
+        let txid = mixed_builder_with_rng(StdRng::seed_from_u64(1))
+            .build_to_writer(&crate::testing::MockTxProver::new(42), fee, &mut written)
+            .unwrap();
+        assert_eq!(written, built.raw);
+        assert_eq!(txid, built.txid);
+
+        // A writer that runs out of room fails the build
+        let mut short = [0u8; 100];
+        assert!(mixed_builder()
+            .build_to_writer(&crate::testing::MockTxProver::new(42), fee, &mut &mut short[..])
+            .is_err());
+    }
+
+    #[test]
+    fn test_output_points_must_be_in_prime_subgroup() {
+        use zcash_note_encryption::EphemeralKeyBytes;
+
+        // (0, -1) has order 2
+        let small_order = jubjub::ExtendedPoint::from(jubjub::AffinePoint::from_raw_unchecked(
+            jubjub::Fq::zero(),
+            -jubjub::Fq::one(),
+        ));
+        assert!(bool::from(small_order.is_small_order()));
+        let generator = jubjub::ExtendedPoint::from(jubjub::SubgroupPoint::generator());
+        // Not small order, so the *8 check alone lets it through
+        let mixed_order = generator + small_order;
+        assert!(!bool::from(mixed_order.is_small_order()));
+
+        let output = |cv: jubjub::ExtendedPoint, epk: jubjub::ExtendedPoint| OutputDescription::<GrothProofBytes> {
+            cv,
+            cmu: bls12_381::Scalar::one(),
+            ephemeral_key: EphemeralKeyBytes(epk.to_bytes()),
+            enc_ciphertext: [0u8; 580],
+            out_ciphertext: [0u8; 80],
+            zkproof: [0u8; GROTH_PROOF_SIZE],
+        };
+        let builder = fixture_builder();
+        let write = |o| builder.write_output_description(&mut vec![], &o);
+
+        assert!(write(output(generator, generator)).is_ok());
+
+        for bad in [small_order, mixed_order] {
+            let err = write(output(bad, generator)).unwrap_err();
+            assert!(
+                err.starts_with("cv ") && err.contains("prime-order subgroup"),
+                "{}",
+                err
+            );
+
+            let err = write(output(generator, bad)).unwrap_err();
+            assert!(err.starts_with("ephemeral_key "), "{}", err);
+        }
+
+        // And on the bellman encoding directly
+        let mut encoded = vec![];
+        write_edwards_point_bellman(&small_order, &mut encoded).unwrap();
+        assert!(check_prime_subgroup_encoding("cv", &encoded).is_err());
+        assert!(check_prime_subgroup_encoding("cv", &encoded[..31]).is_err());
+    }
+
+    #[test]
+    fn test_output_padding() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use crate::testing::MockTxProver;
+
+        let fee = Amount::from_u64(10_000).unwrap();
+
+        // z→t: 1 spend, no outputs of its own
+        let z_to_t = || {
+            let mut builder = mixed_builder();
+            builder.transparent_inputs.clear();
+            builder.sapling_outputs.clear();
+            builder.transparent_outputs[0].1 = Amount::from_u64(190_000).unwrap();
+            builder
+        };
+
+        let builder = z_to_t();
+        assert_eq!(builder.padding_outputs(), 2);
+        let estimate = builder.estimate();
+        let prover = MockTxProver::default();
+        let tx = builder.build(&prover, fee).unwrap().raw;
+        let parsed = parse_bitcoinz_v4(&tx).unwrap();
+
+        // Two dummy outputs, worth nothing, so the value balance is just the spend
+        assert_eq!(parsed.shielded_outputs.len(), 2);
+        assert_eq!(i64::from(parsed.value_balance), 200_000);
+        let log = prover.log();
+        assert_eq!(log.output_recipients.len(), 2);
+        assert!(log.output_recipients.iter().all(|(_, value)| *value == 0));
+        assert_ne!(log.output_recipients[0].0, log.output_recipients[1].0);
+
+        // The binding signature still closes (the mock prover refuses otherwise), and
+        // the estimate (exact without transparent inputs) counted the padding
+        assert_eq!(log.binding_sigs.len(), 1);
+        assert_eq!(estimate.size, tx.len());
+
+        // z→z with a single recipient gets one dummy
+        let mut builder = z_to_t();
+        builder.transparent_outputs.clear();
+        builder
+            .add_sapling_output(
+                None,
+                log.output_recipients[0].0.clone(),
+                Amount::from_u64(190_000).unwrap(),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        assert_eq!(builder.padding_outputs(), 1);
+        let tx = builder.build(&MockTxProver::default(), fee).unwrap().raw;
+        assert_eq!(parse_bitcoinz_v4(&tx).unwrap().shielded_outputs.len(), 2);
+
+        // Transactions that don't spend notes aren't padded
+        let mut builder = mixed_builder();
+        builder.sapling_spends.clear();
+        builder.spending_keys.clear();
+        assert_eq!(builder.padding_outputs(), 0);
+
+        // A higher minimum, and padding turned off
+        let mut builder = z_to_t();
+        builder.set_min_sapling_outputs(3);
+        assert_eq!(builder.padding_outputs(), 3);
+        builder.set_output_padding(false);
+        assert_eq!(builder.padding_outputs(), 0);
+        let tx = builder.build(&MockTxProver::default(), fee).unwrap().raw;
+        assert!(parse_bitcoinz_v4(&tx).unwrap().shielded_outputs.is_empty());
+    }
+
+    #[test]
+    fn test_input_from_utxo() {
+        let txid = "6e1f1a4e6b3c4f1f3b0e8f9c2d7a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e0f01";
+        let wif = "KwFfNUhSDaASSAwtG7ssQM1uVX8RgX5GHWnnLfhfiQDigjioWXHH";
+        let sk = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+        let pubkey = SecpPublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let script = TransparentAddress::PublicKey(hash160(&pubkey.serialize())).script();
+
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+        builder
+            .add_transparent_input_from_utxo(txid, 3, &hex::encode(&script.0), 100_000, wif)
+            .unwrap();
+
+        // The txid is given as displayed, and the outpoint holds it the other way round
+        let (outpoint, coin, keys) = &builder.transparent_inputs[0];
+        let mut wire = hex::decode(txid).unwrap();
+        wire.reverse();
+        assert_eq!(&outpoint.hash()[..], &wire[..]);
+        assert_eq!(outpoint.n(), 3);
+        assert_eq!(coin.value, Amount::from_u64(100_000).unwrap());
+        assert_eq!(coin.script_pubkey, script);
+        assert!(matches!(keys, TransparentInputKeys::PubKeyHash(key) if *key == sk));
+
+        // A script paying another key, and bad hex, txids and keys
+        let other = TransparentAddress::PublicKey([0x22u8; 20]).script();
+        assert!(builder
+            .add_transparent_input_from_utxo(txid, 0, &hex::encode(&other.0), 100_000, wif)
+            .is_err());
+        assert!(builder
+            .add_transparent_input_from_utxo(txid, 0, "zz", 100_000, wif)
+            .is_err());
+        assert!(builder
+            .add_transparent_input_from_utxo(&txid[2..], 0, &hex::encode(&script.0), 100_000, wif)
+            .is_err());
+        assert!(builder
+            .add_transparent_input_from_utxo(txid, 0, &hex::encode(&script.0), 100_000, &wif[1..])
+            .is_err());
+        assert_eq!(builder.transparent_inputs.len(), 1);
+
+        // An uncompressed WIF spends what was paid to its uncompressed public key, and
+        // nothing paid to the compressed one
+        let uncompressed = "5HpHagT65TZzG1PH3CSu63k8DbpvD8s5ip4nEB3kEsreAnchuDf";
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let sk = SecretKey::from_slice(&one).unwrap();
+        let pubkey = SecpPublicKey::from_secret_key(&Secp256k1::new(), &sk);
+        let compressed_script = TransparentAddress::PublicKey(hash160(&pubkey.serialize())).script();
+        let uncompressed_script = TransparentAddress::PublicKey(hash160(&pubkey.serialize_uncompressed())).script();
+        assert!(builder
+            .add_transparent_input_from_utxo(txid, 0, &hex::encode(&compressed_script.0), 100_000, uncompressed)
+            .is_err());
+        builder
+            .add_transparent_input_from_utxo(txid, 0, &hex::encode(&uncompressed_script.0), 100_000, uncompressed)
+            .unwrap();
+        assert!(
+            matches!(&builder.transparent_inputs[1].2, TransparentInputKeys::UncompressedPubKeyHash(key) if *key == sk)
+        );
+    }
+
+    #[test]
+    fn test_output_with_text_memo() {
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let to = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[1u8; 32]))
+            .default_address()
+            .1;
+        let value = Amount::from_u64(90_000).unwrap();
+        let mut builder = BitcoinZShieldedBuilder::new_with_entropy(BITCOINZ_MAINNET, BlockHeight::from(500_000));
+
+        builder
+            .add_sapling_output_with_text(None, to.clone(), value, "")
+            .unwrap();
+        builder
+            .add_sapling_output_with_text(None, to.clone(), value, &"z".repeat(512))
+            .unwrap();
+        assert!(builder
+            .add_sapling_output_with_text(None, to, value, &"z".repeat(513))
+            .is_err());
+
+        assert_eq!(builder.sapling_outputs.len(), 2);
+        assert_eq!(builder.sapling_outputs[0].memo, MemoBytes::empty());
+        assert_eq!(builder.sapling_outputs[1].memo.as_slice(), "z".repeat(512).as_bytes());
+    }
+
+    #[test]
+    fn test_output_without_ovk() {
+        use crate::bitcoinz_parse::parse_bitcoinz_v4;
+        use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
+
+        let extfvk = ExtendedFullViewingKey::from(&ExtendedSpendingKey::master(&[1u8; 32]));
+        let build = |ovk: Option<OutgoingViewingKey>| {
+            let mut builder =
+                BitcoinZShieldedBuilder::new(BITCOINZ_MAINNET, BlockHeight::from(500_000), StdRng::seed_from_u64(3));
+            builder
+                .add_transparent_input(
+                    transparent::OutPoint::new([0x11u8; 32], 0),
+                    TxOut {
+                        value: Amount::from_u64(100_000).unwrap(),
+                        script_pubkey: TransparentAddress::PublicKey([0x22u8; 20]).script(),
+                    },
+                    SecretKey::from_slice(&[0x01u8; 32]).unwrap(),
+                )
+                .unwrap();
+            builder
+                .add_sapling_output(
+                    ovk,
+                    extfvk.default_address().1,
+                    Amount::from_u64(90_000).unwrap(),
+                    MemoBytes::empty(),
+                )
+                .unwrap();
+
+            let tx = builder
+                .build(
+                    &crate::testing::MockTxProver::default(),
+                    Amount::from_u64(10_000).unwrap(),
+                )
+                .unwrap()
+                .raw;
+            parse_bitcoinz_v4(&tx).unwrap().shielded_outputs.remove(0)
+        };
+
+        let with_ovk = build(Some(extfvk.fvk.ovk));
+        let without_ovk = build(None);
+
+        // Same note for the recipient...
+        assert_eq!(with_ovk.cmu, without_ovk.cmu);
+        assert_eq!(with_ovk.enc_ciphertext, without_ovk.enc_ciphertext);
+
+        // ...but the outgoing ciphertext isn't made with our ovk
+        assert_eq!(without_ovk.out_ciphertext.len(), with_ovk.out_ciphertext.len());
+        assert_ne!(with_ovk.out_ciphertext, without_ovk.out_ciphertext);
+    }
+}
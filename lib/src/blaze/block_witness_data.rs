@@ -1,3 +1,4 @@
+use crate::bitcoinz_v4_shielded::witness_path_nodes;
 use crate::compact_formats::vec_to_array;
 use crate::{
     compact_formats::{CompactBlock, CompactTx, TreeState},
@@ -736,6 +737,102 @@ impl BlockAndWitnessData {
     }
 }
 
+/// The Merkle path and position of the note at `position`, in the form
+/// `BitcoinZShieldedBuilder::add_sapling_spend` takes them. `tree` is the note commitment
+/// tree as it was before the first of `blocks`, which go up in height and have to include
+/// the note. The path leads to the root of the tree after the last block.
+pub fn sapling_merkle_path(
+    tree: &CommitmentTree<Node>,
+    blocks: &[CompactBlock],
+    position: u64,
+) -> Result<(Vec<Node>, u64), String> {
+    if position < tree.size() as u64 {
+        return Err(format!(
+            "Note at position {} comes before the {} commitments already in the tree",
+            position,
+            tree.size()
+        ));
+    }
+
+    let mut tree = tree.clone();
+    let mut witness: Option<IncrementalWitness<Node>> = None;
+
+    for cb in blocks {
+        for ctx in &cb.vtx {
+            for co in &ctx.outputs {
+                let cmu = co
+                    .cmu()
+                    .map_err(|_| format!("Invalid note commitment in block {}", cb.height))?;
+                let node = Node::new(cmu.into());
+
+                // Everything up to the note goes into the tree, and everything after it
+                // into the note's witness
+                let appended = match witness.as_mut() {
+                    Some(w) => w.append(node),
+                    None => tree.append(node),
+                };
+                appended.map_err(|_| "Note commitment tree is full".to_string())?;
+
+                if witness.is_none() && tree.size() as u64 == position + 1 {
+                    witness = Some(IncrementalWitness::from_tree(&tree));
+                }
+            }
+        }
+    }
+
+    let witness = witness.ok_or_else(|| format!("Note at position {} is not in the given blocks", position))?;
+    let path = witness.path().ok_or("Witness has no Merkle path")?;
+    witness_path_nodes(&path)
+}
+
+/// Check `anchor`, this wallet's Sapling root at `anchor_height`, where its block has
+/// hash `block_hash`, against `tree_state`, lightwalletd's GetTreeState at that height.
+/// lightwalletd has to have the same block there (a different one means ours was
+/// orphaned) with the same root, and the block has to be at least `min_depth` below the
+/// server's `tip`.
+pub fn check_anchor_tree_state(
+    anchor: &Node,
+    anchor_height: u64,
+    block_hash: &str,
+    tree_state: &TreeState,
+    tip: u64,
+    min_depth: u64,
+) -> Result<(), String> {
+    if tree_state.height != anchor_height {
+        return Err(format!(
+            "Asked the server for the tree at height {}, but got height {}",
+            anchor_height, tree_state.height
+        ));
+    }
+
+    if tree_state.hash != block_hash {
+        return Err(format!(
+            "Block {} at height {} is no longer on the server's main chain (it has {}). Please rescan.",
+            block_hash, anchor_height, tree_state.hash
+        ));
+    }
+
+    if tip < anchor_height + min_depth {
+        return Err(format!(
+            "The anchor at height {} needs to be {} blocks deep, but the server's tip is {}",
+            anchor_height, min_depth, tip
+        ));
+    }
+
+    let tree = hex::decode(&tree_state.tree)
+        .ok()
+        .and_then(|b| CommitmentTree::<Node>::read(&b[..]).ok())
+        .ok_or_else(|| format!("Server sent an invalid tree for height {}", anchor_height))?;
+    if tree.root() != *anchor {
+        return Err(format!(
+            "The server's note commitment tree at height {} doesn't match this wallet's. Please rescan.",
+            anchor_height
+        ));
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
@@ -755,7 +852,7 @@ mod test {
     use tokio::{sync::mpsc::unbounded_channel, task::JoinHandle};
     use zcash_primitives::block::BlockHash;
 
-    use super::BlockAndWitnessData;
+    use super::{sapling_merkle_path, BlockAndWitnessData};
 
     #[tokio::test]
     async fn setup_finish_simple() {
@@ -796,6 +893,76 @@ mod test {
         }
     }
 
+    #[test]
+    fn anchor_checked_against_tree_state() {
+        use super::check_anchor_tree_state;
+        use crate::blaze::test_utils::tree_to_string;
+        use crate::compact_formats::TreeState;
+        use zcash_primitives::{merkle_tree::CommitmentTree, sapling::Node};
+
+        let mut tree = CommitmentTree::<Node>::empty();
+        tree.append(Node::new([1u8; 32])).unwrap();
+        let anchor = tree.root();
+
+        let hash = BlockHash([7u8; 32]).to_string();
+        let mut tree_state = TreeState::default();
+        tree_state.height = 100;
+        tree_state.hash = hash.clone();
+        tree_state.tree = tree_to_string(&tree);
+
+        assert_eq!(check_anchor_tree_state(&anchor, 100, &hash, &tree_state, 105, 5), Ok(()));
+
+        // Not deep enough yet
+        assert!(check_anchor_tree_state(&anchor, 100, &hash, &tree_state, 104, 5).is_err());
+
+        // The server has another block at that height
+        let orphaned = BlockHash([8u8; 32]).to_string();
+        let err = check_anchor_tree_state(&anchor, 100, &orphaned, &tree_state, 105, 5).unwrap_err();
+        assert!(err.contains("no longer on the server's main chain"), "{}", err);
+
+        // Same block, different tree
+        tree.append(Node::new([2u8; 32])).unwrap();
+        assert!(check_anchor_tree_state(&tree.root(), 100, &hash, &tree_state, 105, 5).is_err());
+
+        // A tree state for some other height
+        assert!(check_anchor_tree_state(&anchor, 101, &hash, &tree_state, 105, 4).is_err());
+    }
+
+    #[test]
+    fn merkle_path_from_compact_blocks() {
+        use crate::bitcoinz_v4_shielded::spend_merkle_path;
+        use crate::blaze::test_utils::list_all_witness_nodes;
+        use zcash_primitives::{merkle_tree::CommitmentTree, sapling::Node};
+
+        let mut fcbl = FakeCompactBlockList::new(0);
+        fcbl.add_blocks(5);
+        let mut blocks = fcbl.into_compact_blocks();
+        blocks.reverse();
+
+        // Start from the tree after the first block
+        let mut start = CommitmentTree::empty();
+        for node in list_all_witness_nodes(&blocks[0]) {
+            start.append(node).unwrap();
+        }
+        let nodes = blocks[1..].iter().flat_map(list_all_witness_nodes).collect::<Vec<Node>>();
+        let mut tree = start.clone();
+        for node in &nodes {
+            tree.append(*node).unwrap();
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            let position = (start.size() + i) as u64;
+            let (path, witness_position) = sapling_merkle_path(&start, &blocks[1..], position).unwrap();
+            assert_eq!(witness_position, position);
+            assert_eq!(path.len(), 32);
+            assert_eq!(spend_merkle_path(&path, witness_position).unwrap().root(*node), tree.root());
+        }
+
+        // Notes before the starting tree or past the last block can't be found
+        assert!(sapling_merkle_path(&start, &blocks[1..], 0).is_err());
+        assert!(sapling_merkle_path(&start, &blocks[1..], tree.size() as u64).is_err());
+    }
+
     #[tokio::test]
     async fn from_sapling_genesis() {
         let mut config = LightClientConfig::create_unconnected(UnitTestNetwork, None);
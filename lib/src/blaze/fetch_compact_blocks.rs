@@ -1,9 +1,6 @@
-use std::{cmp::max, sync::Arc};
-
 use crate::{
     compact_formats::CompactBlock, grpc_connector::GrpcConnector, lightclient::lightclient_config::LightClientConfig,
 };
-use log::info;
 use tokio::sync::mpsc::{Sender, UnboundedReceiver};
 use zcash_primitives::consensus;
 
@@ -23,23 +20,9 @@ impl<P: consensus::Parameters> FetchCompactBlocks<P> {
         end_block: u64,
         spam_filter_threshold: i64,
     ) -> Result<(), String> {
-        let grpc_client = Arc::new(GrpcConnector::new(self.config.server.clone()));
-        const STEP: u64 = 1_000;
-
-        // We need the `rev()` here because rust ranges can only go up
-        for b in (end_block..(start_block + 1)).rev().step_by(STEP as usize) {
-            let start = b;
-            let end = max((b as i64) - (STEP as i64) + 1, end_block as i64) as u64;
-            if start < end {
-                return Err(format!("Wrong block order"));
-            }
-
-            info!("Fetching blocks {}-{}", start, end);
-
-            grpc_client
-                .get_block_range(start, end, spam_filter_threshold, receivers)
-                .await?;
-        }
+        GrpcConnector::new(self.config.server.clone())
+            .get_block_range_chunked(start_block, end_block, spam_filter_threshold, receivers)
+            .await?;
 
         Ok(())
     }
@@ -21,7 +21,7 @@ use zcash_note_encryption::{EphemeralKeyBytes, NoteEncryption};
 use zcash_primitives::{
     block::BlockHash,
     consensus::{self, BlockHeight, BranchId, TEST_NETWORK},
-    constants::SPENDING_KEY_GENERATOR,
+    constants::{SPENDING_KEY_GENERATOR, VALUE_COMMITMENT_RANDOMNESS_GENERATOR},
     keys::OutgoingViewingKey,
     legacy::{Script, TransparentAddress},
     memo::Memo,
@@ -39,7 +39,6 @@ use zcash_primitives::{
     },
     zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
 };
-use zcash_proofs::sapling::SaplingProvingContext;
 
 pub fn random_u8_32() -> [u8; 32] {
     let mut b = [0u8; 32];
@@ -520,16 +519,22 @@ impl FakeCompactBlockList {
 
 pub struct FakeTxProver {}
 
+/// Accumulates the value commitment randomness like the real proving context does,
+/// so the binding signature is valid even though the proofs are not
+pub struct FakeProvingContext {
+    bsk: jubjub::Fr,
+}
+
 impl TxProver for FakeTxProver {
-    type SaplingProvingContext = SaplingProvingContext;
+    type SaplingProvingContext = FakeProvingContext;
 
     fn new_sapling_proving_context(&self) -> Self::SaplingProvingContext {
-        SaplingProvingContext::new()
+        FakeProvingContext { bsk: jubjub::Fr::zero() }
     }
 
     fn spend_proof(
         &self,
-        _ctx: &mut Self::SaplingProvingContext,
+        ctx: &mut Self::SaplingProvingContext,
         proof_generation_key: ProofGenerationKey,
         _diversifier: Diversifier,
         _rseed: Rseed,
@@ -544,6 +549,7 @@ impl TxProver for FakeTxProver {
 
         // We create the randomness of the value commitment
         let rcv = jubjub::Fr::random(&mut rng);
+        ctx.bsk += rcv;
         let cv = ValueCommitment { value, randomness: rcv };
         // Compute value commitment
         let value_commitment: jubjub::ExtendedPoint = cv.commitment().into();
@@ -555,7 +561,7 @@ impl TxProver for FakeTxProver {
 
     fn output_proof(
         &self,
-        _ctx: &mut Self::SaplingProvingContext,
+        ctx: &mut Self::SaplingProvingContext,
         _esk: jubjub::Fr,
         _payment_address: PaymentAddress,
         _rcm: jubjub::Fr,
@@ -567,6 +573,7 @@ impl TxProver for FakeTxProver {
 
         // We create the randomness of the value commitment
         let rcv = jubjub::Fr::random(&mut rng);
+        ctx.bsk -= rcv;
 
         let cv = ValueCommitment { value, randomness: rcv };
         // Compute value commitment
@@ -576,11 +583,18 @@ impl TxProver for FakeTxProver {
 
     fn binding_sig(
         &self,
-        _ctx: &mut Self::SaplingProvingContext,
+        ctx: &mut Self::SaplingProvingContext,
         _value_balance: Amount,
-        _sighash: &[u8; 32],
+        sighash: &[u8; 32],
     ) -> Result<Signature, ()> {
-        let fake_bytes = vec![0u8; 64];
-        Signature::read(&fake_bytes[..]).map_err(|_e| ())
+        // Sign bvk || sighash, as the real prover does
+        let bsk = redjubjub::PrivateKey(ctx.bsk);
+        let bvk = redjubjub::PublicKey::from_private(&bsk, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+
+        let mut message = [0u8; 64];
+        message[..32].copy_from_slice(&bvk.0.to_bytes());
+        message[32..].copy_from_slice(sighash);
+
+        Ok(bsk.sign(&message, &mut OsRng, VALUE_COMMITMENT_RANDOMNESS_GENERATOR))
     }
 }
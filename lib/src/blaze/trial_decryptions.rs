@@ -1,5 +1,5 @@
 use crate::{
-    compact_formats::CompactBlock,
+    compact_formats::{CompactBlock, CompactTx},
     lightwallet::{data::WalletTx, keys::Keys, wallet_txns::WalletTxns, MemoDownloadOption},
 };
 use futures::{stream::FuturesUnordered, StreamExt};
@@ -19,7 +19,7 @@ use tokio::{
 };
 
 use zcash_primitives::{
-    consensus::{self, BlockHeight},
+    consensus::{self, BlockHeight, NetworkUpgrade},
     sapling::{self, note_encryption::SaplingDomain, SaplingIvk},
     transaction::{Transaction, TxId},
 };
@@ -206,8 +206,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> TrialDecryptions<P> {
                     }
                 }
 
-                // If the epk or ciphertext is missing, that means this was a spam Tx, so we can't decrypt it
-                if ctx.outputs.len() > 0 && ctx.outputs[0].epk.len() > 0 && ctx.outputs[0].ciphertext.len() > 0 {
+                if has_decryptable_sapling_outputs(&params, height, &ctx) {
                     // Sapling
                     let outputs_total = ctx.outputs.len();
                     // if outputs_total < 100 {
@@ -301,3 +300,51 @@ impl<P: consensus::Parameters + Send + Sync + 'static> TrialDecryptions<P> {
         Ok::<(), String>(())
     }
 }
+
+// Whether to trial-decrypt the Sapling outputs of `ctx`, mined at `height`. There is nothing
+// to find before Sapling activates (328500 on mainnet), and if the epk or ciphertext is
+// missing, that means this was a spam Tx, so we can't decrypt it. Transparent transactions
+// are picked up by the taddr fetcher either way.
+fn has_decryptable_sapling_outputs<P: consensus::Parameters>(params: &P, height: BlockHeight, ctx: &CompactTx) -> bool {
+    params.is_nu_active(NetworkUpgrade::Sapling, height)
+        && ctx.outputs.len() > 0
+        && ctx.outputs[0].epk.len() > 0
+        && ctx.outputs[0].ciphertext.len() > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_formats::CompactSaplingOutput;
+    use crate::BITCOINZ_MAINNET;
+
+    fn ctx_with_output(epk: Vec<u8>, ciphertext: Vec<u8>) -> CompactTx {
+        CompactTx {
+            outputs: vec![CompactSaplingOutput {
+                cmu: vec![0u8; 32],
+                epk,
+                ciphertext,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_no_sapling_decryption_before_activation() {
+        let ctx = ctx_with_output(vec![1u8; 32], vec![2u8; 52]);
+        let decrypts_at = |height: u32| has_decryptable_sapling_outputs(&BITCOINZ_MAINNET, BlockHeight::from(height), &ctx);
+
+        assert!(!decrypts_at(1));
+        assert!(!decrypts_at(328_499));
+        assert!(decrypts_at(328_500));
+        assert!(decrypts_at(1_000_000));
+    }
+
+    #[test]
+    fn test_spam_outputs_are_skipped() {
+        let at = BlockHeight::from(500_000);
+        assert!(!has_decryptable_sapling_outputs(&BITCOINZ_MAINNET, at, &ctx_with_output(vec![], vec![2u8; 52])));
+        assert!(!has_decryptable_sapling_outputs(&BITCOINZ_MAINNET, at, &ctx_with_output(vec![1u8; 32], vec![])));
+        assert!(!has_decryptable_sapling_outputs(&BITCOINZ_MAINNET, at, &CompactTx::default()));
+    }
+}
@@ -1,5 +1,8 @@
+use crate::bitcoinz_coinselect::CoinSelection;
+use crate::bitcoinz_parse::BitcoinZTransaction;
 use crate::lightwallet::keys::Keys;
-use crate::lightwallet::MemoDownloadOption;
+use crate::lightwallet::data::Compatibility;
+use crate::lightwallet::{MemoDownloadOption, SendOptions};
 use crate::{lightclient::LightClient, lightwallet::utils};
 use json::object;
 use lazy_static::lazy_static;
@@ -168,6 +171,34 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for RescanComm
     }
 }
 
+struct RebuildSpentIndexCommand {}
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for RebuildSpentIndexCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Rebuild which notes are spent from the transactions already in the wallet");
+        h.push("Usage:");
+        h.push("rebuildspentindex");
+        h.push("");
+        h.push("This command recomputes the nullifier of every note in the wallet and marks the notes spent whose");
+        h.push("nullifiers are spent by a mined transaction in the wallet. No blocks are downloaded, so it's much");
+        h.push("quicker than 'rescan' when only the spent notes are wrong.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Repair which notes are marked spent, without rescanning".to_string()
+    }
+    fn exec(&self, _args: &[&str], lightclient: &LightClient<P>) -> String {
+        RT.block_on(async move {
+            match lightclient.do_rebuild_spent_index().await {
+                Ok(j) => j.pretty(2),
+                Err(e) => e,
+            }
+        })
+    }
+}
+
 struct ClearCommand {}
 
 impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ClearCommand {
@@ -298,6 +329,28 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for LastTxIdCo
     }
 }
 
+struct WalletInfoCommand {}
+
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for WalletInfoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show information about the wallet file and how long it took to load");
+        h.push("Usage:");
+        h.push("walletinfo");
+        h.push("");
+        h.push("Note witnesses are loaded on first use (sync or a shielded send), so 'witness_load_ms' is null until then.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show wallet file information and load timings".to_string()
+    }
+    fn exec(&self, _args: &[&str], lightclient: &LightClient<P>) -> String {
+        RT.block_on(async move { format!("{}", lightclient.do_wallet_info().await.pretty(2)) })
+    }
+}
+
 struct BalanceCommand {}
 
 impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for BalanceCommand {
@@ -380,6 +433,46 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ExportComm
     }
 }
 
+struct DiagnosticsCommand {}
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for DiagnosticsCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Write a diagnostics bundle (a tar file) to attach to bug reports.");
+        h.push("Usage:");
+        h.push("diagnostics <path> [--include-raw]");
+        h.push("");
+        h.push("The bundle has the wallet info, recent logs, the server info and the structure of recently failed transactions.");
+        h.push("Addresses are shortened to their first and last 4 characters, and memos and keys are removed.");
+        h.push("Pass --include-raw to also include the raw bytes of failed transactions.");
+        h.push("The self test results and any sends still waiting to be mined are in it too. Sends the server refused");
+        h.push("are only kept in memory, so only the ones since the wallet was opened are included.");
+        h.push("");
+        h.push("Example:");
+        h.push("diagnostics /tmp/bitcoinz-diagnostics.tar");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Write a redacted diagnostics bundle for bug reports".to_string()
+    }
+    fn exec(&self, args: &[&str], lightclient: &LightClient<P>) -> String {
+        let include_raw = args.contains(&"--include-raw");
+        let paths = args.iter().filter(|a| **a != "--include-raw").collect::<Vec<_>>();
+        if paths.len() != 1 {
+            return Command::<P>::help(self);
+        }
+
+        RT.block_on(async move {
+            match lightclient.do_diagnostics(paths[0], include_raw).await {
+                Ok(j) => j,
+                Err(e) => object! { "error" => e },
+            }
+            .pretty(2)
+        })
+    }
+}
+
 struct EncryptCommand {}
 impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for EncryptCommand {
     fn help(&self) -> String {
@@ -556,7 +649,10 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ShieldComm
         let mut h = vec![];
         h.push("Shield all your transparent funds");
         h.push("Usage:");
-        h.push("shield [optional address]");
+        h.push("shield [optional address] [--force-experimental]");
+        h.push("");
+        h.push("Shielding is refused while BitcoinZ's branch IDs are unconfirmed, since the network will probably reject it.");
+        h.push("Add --force-experimental to try anyway.");
         h.push("");
         h.push("NOTE: The fee required to send this transaction (currently ZEC 0.0001) is additionally deducted from your balance.");
         h.push("Example:");
@@ -570,6 +666,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ShieldComm
         "Shield your transparent ZEC into a sapling address".to_string()
     }
     fn exec(&self, args: &[&str], lightclient: &LightClient<P>) -> String {
+        let force_experimental = args.last() == Some(&"--force-experimental");
+        let args = if force_experimental { &args[..args.len() - 1] } else { args };
+
         // Parse the address or amount
         let address = if args.len() > 0 {
             Some(args[0].to_string())
@@ -577,7 +676,54 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ShieldComm
             None
         };
         RT.block_on(async move {
-            match lightclient.do_shield(address).await {
+            match lightclient.do_shield(address, force_experimental).await {
+                Ok(txid) => {
+                    object! { "txid" => txid }
+                }
+                Err(e) => {
+                    object! { "error" => e }
+                }
+            }
+            .pretty(2)
+        })
+    }
+}
+
+struct SweepCommand {}
+
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SweepCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Send all your transparent funds to one address");
+        h.push("Usage:");
+        h.push("sweep <address> [--force-experimental]");
+        h.push("");
+        h.push("Every transparent UTXO is spent, and the address gets their total less the fee, with no change.");
+        h.push("The fee is worked out from the transaction's size, and the sweep is refused if what's left is dust.");
+        h.push("");
+        h.push("Sweeping into a z-address is refused while BitcoinZ's branch IDs are unconfirmed, since the network");
+        h.push("will probably reject it. Add --force-experimental to try anyway.");
+        h.push("");
+        h.push("Example:");
+        h.push("sweep t1eQ63fwkQ4n4Eo5uCrPGaAV8FWB2tmx7ui");
+        h.push("");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Send all your transparent funds to one address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient<P>) -> String {
+        let force_experimental = args.last() == Some(&"--force-experimental");
+        let args = if force_experimental { &args[..args.len() - 1] } else { args };
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        RT.block_on(async move {
+            match lightclient.do_sweep(args[0], force_experimental).await {
                 Ok(txid) => {
                     object! { "txid" => txid }
                 }
@@ -692,6 +838,39 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for DecryptMes
     }
 }
 
+struct DecodeRawTransactionCommand {}
+
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for DecodeRawTransactionCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Decode a raw v1, v3 or v4 BitcoinZ transaction and print it as JSON.");
+        h.push("Usage:");
+        h.push("decoderawtransaction \"hexstring\"");
+        h.push("");
+        h.push("Values are in zatoshis. Sapling proofs, signatures and ciphertexts aren't shown.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Decode a raw transaction and print it as JSON".to_string()
+    }
+    fn exec(&self, args: &[&str], _lightclient: &LightClient<P>) -> String {
+        if args.len() != 1 {
+            return Command::<P>::help(self);
+        }
+
+        let raw = match hex::decode(args[0].trim()) {
+            Ok(raw) => raw,
+            Err(e) => return object! { "error" => format!("Couldn't decode hex: {}", e) }.pretty(2),
+        };
+        match BitcoinZTransaction::read(&raw) {
+            Ok(tx) => tx.to_json().pretty(2),
+            Err(e) => object! { "error" => e.to_string() }.pretty(2),
+        }
+    }
+}
+
 struct SendCommand {}
 
 impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SendCommand {
@@ -707,6 +886,17 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SendComman
         h.push("Example:");
         h.push("send ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 200000 \"Hello from the command line\"");
         h.push("");
+        h.push("Add --no-broadcast at the end to get the signed transaction's hex and txid without sending it.");
+        h.push("The coins it spends are held as pending, so submit the hex yourself (eg. with sendrawtransaction).");
+        h.push("Add --expiry <blocks> to let the send expire if it isn't mined within that many blocks.");
+        h.push("The default, 0, never expires.");
+        h.push("");
+        h.push("Sends with shielded parts are refused while BitcoinZ's branch IDs are unconfirmed, since the network");
+        h.push("will probably reject them. Add --force-experimental to try anyway.");
+        h.push("");
+        h.push("A transparent payment too big to relay as one transaction is sent as several, each spending the");
+        h.push("one before, and every txid is listed.");
+        h.push("");
 
         h.join("\n")
     }
@@ -718,6 +908,32 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SendComman
         // Parse the args. There are two argument types.
         // 1 - A set of 2(+1 optional) arguments for a single address send representing address, value, memo?
         // 2 - A single argument in the form of a JSON string that is "[{address: address, value: value, memo: memo},...]"
+        // Either can be followed by --no-broadcast to get the signed transaction back instead of sending it,
+        // --expiry <blocks> to have it expire if it isn't mined in time, and --force-experimental
+        let mut args = args;
+        let mut build_only = false;
+        let mut options = SendOptions::default();
+        loop {
+            match args {
+                [rest @ .., "--no-broadcast"] => {
+                    build_only = true;
+                    args = rest;
+                }
+                [rest @ .., "--force-experimental"] => {
+                    options.force_experimental = true;
+                    args = rest;
+                }
+                [rest @ .., "--expiry", blocks] => match blocks.parse::<u32>() {
+                    Ok(b) => {
+                        options.expiry_offset = b;
+                        args = rest;
+                    }
+                    Err(e) => return format!("Couldn't parse expiry {}: {}", blocks, e),
+                },
+                _ => break,
+            }
+        }
+
         if args.len() < 1 || args.len() > 3 {
             return Command::<P>::help(self);
         }
@@ -811,15 +1027,16 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SendComman
                 .iter()
                 .map(|(a, v, m)| (a.as_str(), *v, m.clone()))
                 .collect::<Vec<_>>();
-            match lightclient.do_send(tos).await {
-                Ok(txid) => {
-                    object! { "txid" => txid }
-                }
-                Err(e) => {
-                    object! { "error" => e }
-                }
+            // A payment too big to relay as one transaction goes out as several
+            match lightclient.do_send_with_options(tos, build_only, options).await {
+                Ok(mut sent) if sent.len() == 1 => sent.remove(0).pretty(2),
+                Ok(sent) => format!(
+                    "Sending in {} transactions\n{}",
+                    sent.len(),
+                    json::JsonValue::from(sent).pretty(2)
+                ),
+                Err(e) => object! { "error" => e }.pretty(2),
             }
-            .pretty(2)
         })
     }
 }
@@ -896,9 +1113,11 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for Transactio
         let mut h = vec![];
         h.push("List all incoming and outgoing transactions from this wallet");
         h.push("Usage:");
-        h.push("list [allmemos]");
+        h.push("list [allmemos] [confirmed|experimental]");
         h.push("");
         h.push("If you include the 'allmemos' argument, all memos are returned in their raw hex format");
+        h.push("'confirmed' or 'experimental' lists only the transactions this wallet sent with that");
+        h.push("compatibility tag. Transparent sends are confirmed, sends with shielded parts are experimental.");
 
         h.join("\n")
     }
@@ -907,25 +1126,31 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for Transactio
         "List all transactions in the wallet".to_string()
     }
     fn exec(&self, args: &[&str], lightclient: &LightClient<P>) -> String {
-        if args.len() > 1 {
+        if args.len() > 2 {
             return format!("Didn't understand arguments\n{}", Command::<P>::help(self));
         }
 
-        let include_memo_hex = if args.len() == 1 {
-            if args[0] == "allmemos" || args[0] == "true" || args[0] == "yes" {
-                true
+        let mut include_memo_hex = false;
+        let mut compatibility = None;
+        for arg in args {
+            if *arg == "allmemos" || *arg == "true" || *arg == "yes" {
+                include_memo_hex = true;
+            } else if let Some(c) = Compatibility::from_name(arg) {
+                compatibility = Some(c);
             } else {
-                return format!(
-                    "Couldn't understand first argument '{}'\n{}",
-                    args[0],
-                    Command::<P>::help(self)
-                );
+                return format!("Couldn't understand argument '{}'\n{}", arg, Command::<P>::help(self));
             }
-        } else {
-            false
-        };
+        }
 
-        RT.block_on(async move { format!("{}", lightclient.do_list_transactions(include_memo_hex).await.pretty(2)) })
+        RT.block_on(async move {
+            format!(
+                "{}",
+                lightclient
+                    .do_list_transactions_filtered(include_memo_hex, compatibility)
+                    .await
+                    .pretty(2)
+            )
+        })
     }
 }
 
@@ -939,6 +1164,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SetOptionC
         h.push("setoption <optionname>=<optionvalue>");
         h.push("List of available options:");
         h.push("download_memos : none | wallet | all");
+        h.push("coin_selection : largest_first | smallest_first | exact");
 
         h.join("\n")
     }
@@ -978,6 +1204,10 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for SetOptionC
                     let threshold = option_value.parse::<i64>().unwrap();
                     lightclient.wallet.set_spam_filter_threshold(threshold).await
                 }
+                "coin_selection" => match option_value.parse::<CoinSelection>() {
+                    Ok(strategy) => lightclient.wallet.set_coin_selection(strategy).await,
+                    Err(e) => return format!("Error: {}", e),
+                },
                 _ => return format!("Error: Couldn't understand {}", option_name),
             }
 
@@ -1027,6 +1257,14 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for GetOptionC
                     .await
                     .spam_threshold
                     .to_string(),
+                "coin_selection" => lightclient
+                    .wallet
+                    .wallet_options
+                    .read()
+                    .await
+                    .coin_selection
+                    .name()
+                    .to_string(),
                 _ => return format!("Error: Couldn't understand {}", option_name),
             };
 
@@ -1123,7 +1361,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ImportComm
         };
 
         RT.block_on(async move {
-            let r = match lightclient.do_import_key(key, birthday).await {
+            let r = match lightclient.do_import_key(&key, birthday).await {
                 Ok(r) => r.pretty(2),
                 Err(e) => return format!("Error: {}", e),
             };
@@ -1140,6 +1378,39 @@ impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for ImportComm
     }
 }
 
+struct RemoveKeyCommand {}
+
+impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for RemoveKeyCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Remove an imported spending, viewing or transparent key from the wallet");
+        h.push("Usage:");
+        h.push("removekey <address>");
+        h.push("");
+        h.push("The notes and UTXOs that belong to the key, and the transactions that only involved it, are removed too, so its funds no longer count towards the balance.");
+        h.push("Keys derived from the wallet's seed can't be removed, and neither can the wallet's only key.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Remove an imported key and its funds from the wallet".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient<P>) -> String {
+        if args.len() != 1 {
+            return format!("Wrong number of arguments\n\n{}", Command::<P>::help(self));
+        }
+
+        RT.block_on(async move {
+            match lightclient.do_remove_key(args[0].to_string()).await {
+                Ok(r) => r.pretty(2),
+                Err(e) => format!("Error: {}", e),
+            }
+        })
+    }
+}
+
 struct HeightCommand {}
 
 impl<P: consensus::Parameters + Send + Sync + 'static> Command<P> for HeightCommand {
@@ -1303,6 +1574,7 @@ pub fn get_commands<P: consensus::Parameters + Send + Sync + 'static>() -> Box<H
     map.insert("encryptmessage".to_string(), Box::new(EncryptMessageCommand {}));
     map.insert("decryptmessage".to_string(), Box::new(DecryptMessageCommand {}));
     map.insert("rescan".to_string(), Box::new(RescanCommand {}));
+    map.insert("rebuildspentindex".to_string(), Box::new(RebuildSpentIndexCommand {}));
     map.insert("clear".to_string(), Box::new(ClearCommand {}));
     map.insert("help".to_string(), Box::new(HelpCommand {}));
     map.insert("lasttxid".to_string(), Box::new(LastTxIdCommand {}));
@@ -1313,11 +1585,16 @@ pub fn get_commands<P: consensus::Parameters + Send + Sync + 'static>() -> Box<H
     map.insert("setoption".to_string(), Box::new(SetOptionCommand {}));
     map.insert("getoption".to_string(), Box::new(GetOptionCommand {}));
     map.insert("import".to_string(), Box::new(ImportCommand {}));
+    map.insert("removekey".to_string(), Box::new(RemoveKeyCommand {}));
     map.insert("export".to_string(), Box::new(ExportCommand {}));
+    map.insert("diagnostics".to_string(), Box::new(DiagnosticsCommand {}));
     map.insert("info".to_string(), Box::new(InfoCommand {}));
+    map.insert("walletinfo".to_string(), Box::new(WalletInfoCommand {}));
     map.insert("zecprice".to_string(), Box::new(ZecPriceCommand {}));
     map.insert("send".to_string(), Box::new(SendCommand {}));
+    map.insert("decoderawtransaction".to_string(), Box::new(DecodeRawTransactionCommand {}));
     map.insert("shield".to_string(), Box::new(ShieldCommand {}));
+    map.insert("sweep".to_string(), Box::new(SweepCommand {}));
     map.insert("save".to_string(), Box::new(SaveCommand {}));
     map.insert("quit".to_string(), Box::new(QuitCommand {}));
     map.insert("list".to_string(), Box::new(TransactionsCommand {}));
@@ -5,13 +5,13 @@ use std::any::TypeId;
 
 use crate::compact_formats::compact_tx_streamer_client::CompactTxStreamerClient;
 use crate::compact_formats::{
-    BlockId, BlockRange, ChainSpec, CompactBlock, Empty, LightdInfo, PriceRequest, PriceResponse, RawTransaction,
-    TransparentAddressBlockFilter, TreeState, TxFilter,
+    BlockId, BlockRange, ChainSpec, CompactBlock, Empty, GetAddressUtxosArg, GetAddressUtxosReply, LightdInfo,
+    PriceRequest, PriceResponse, RawTransaction, SendResponse, TransparentAddressBlockFilter, TreeState, TxFilter,
 };
 use crate::ServerCert;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
-use log::warn;
+use log::{info, warn};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::mpsc::{Sender, UnboundedReceiver};
 use tokio::sync::oneshot;
@@ -23,17 +23,31 @@ use tonic::{
     Request,
 };
 use zcash_primitives::consensus::{self, BlockHeight, BranchId};
-use zcash_primitives::transaction::{Transaction, TxId};
+use zcash_primitives::transaction::{components::OutPoint, Transaction, TxId};
 use crate::{BitcoinZMainNetwork, BITCOINZ_MAINNET};
+use crate::bitcoinz_txid::DisplayTxid;
+
+/// How many blocks a single GetBlockRange call asks for by default
+pub const DEFAULT_BLOCK_CHUNK_SIZE: u64 = 1_000;
 
 #[derive(Clone)]
 pub struct GrpcConnector {
     uri: http::Uri,
+    block_chunk_size: u64,
 }
 
 impl GrpcConnector {
     pub fn new(uri: http::Uri) -> Self {
-        Self { uri }
+        Self {
+            uri,
+            block_chunk_size: DEFAULT_BLOCK_CHUNK_SIZE,
+        }
+    }
+
+    /// Limit how many blocks `get_block_range_chunked` asks for in one call
+    pub fn with_block_chunk_size(mut self, block_chunk_size: u64) -> Self {
+        self.block_chunk_size = cmp::max(1, block_chunk_size);
+        self
     }
 
     async fn get_client(&self) -> Result<CompactTxStreamerClient<Channel>, Error> {
@@ -220,6 +234,25 @@ impl GrpcConnector {
         Ok(())
     }
 
+    /// Fetch the blocks from `start_height` down to `end_height`, at most `block_chunk_size`
+    /// blocks per GetBlockRange call, so a long initial sync doesn't time out or hold the
+    /// whole range in memory. The blocks reach the receivers in the same order as one
+    /// call over the full range would send them.
+    pub async fn get_block_range_chunked(
+        &self,
+        start_height: u64,
+        end_height: u64,
+        spam_filter_threshold: i64,
+        receivers: &[Sender<CompactBlock>; 2],
+    ) -> Result<(), String> {
+        for (start, end) in block_range_chunks(start_height, end_height, self.block_chunk_size)? {
+            info!("Fetching blocks {}-{}", start, end);
+            self.get_block_range(start, end, spam_filter_threshold, receivers).await?;
+        }
+
+        Ok(())
+    }
+
     async fn get_full_tx<P: consensus::Parameters + Send + Sync + 'static>(
         uri: http::Uri,
         txid: &TxId,
@@ -467,16 +500,209 @@ impl GrpcConnector {
             .await
             .map_err(|e| format!("Send Error: {}", e))?;
 
-        let sendresponse = response.into_inner();
-        if sendresponse.error_code == 0 {
-            let mut txid = sendresponse.error_message;
-            if txid.starts_with("\"") && txid.ends_with("\"") {
-                txid = txid[1..txid.len() - 1].to_string();
-            }
+        txid_from_send_response(response.into_inner())
+    }
 
-            Ok(txid)
-        } else {
-            Err(format!("Error: {:?}", sendresponse))
+    // get_address_utxos GRPC call. Returns the unspent outputs the server knows for `addresses`
+    pub async fn get_address_utxos(
+        uri: http::Uri,
+        addresses: Vec<String>,
+        start_height: u64,
+    ) -> Result<Vec<GetAddressUtxosReply>, String> {
+        let client = Arc::new(GrpcConnector::new(uri));
+        let mut client = client
+            .get_client()
+            .await
+            .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+        let request = Request::new(GetAddressUtxosArg {
+            addresses,
+            start_height,
+            max_entries: 0,
+        });
+
+        let response = client
+            .get_address_utxos(request)
+            .await
+            .map_err(|e| format!("Error with response: {:?}", e))?;
+
+        Ok(response.into_inner().address_utxos)
+    }
+
+    /// Ask the server whether `outpoint`, paying to the transparent `address`, has been spent.
+    /// The UTXO endpoint only lists unspent outputs, so an outpoint missing from the list is
+    /// spent. Meant for dropping stale coins before coin selection, and for catching an input
+    /// that was spent elsewhere before a transaction is broadcast.
+    pub async fn is_outpoint_spent(uri: http::Uri, address: String, outpoint: &OutPoint) -> Result<bool, String> {
+        let utxos = Self::get_address_utxos(uri, vec![address], 0).await?;
+        Ok(!outpoint_in_utxos(&utxos, outpoint))
+    }
+}
+
+// lightwalletd sends the txid in the same byte order as an OutPoint's hash
+fn outpoint_in_utxos(utxos: &[GetAddressUtxosReply], outpoint: &OutPoint) -> bool {
+    utxos
+        .iter()
+        .any(|utxo| utxo.txid.as_slice() == &outpoint.hash()[..] && utxo.index == outpoint.n() as i32)
+}
+
+// Split the range from `start_height` down to `end_height` (inclusive) into sub-ranges of at
+// most `chunk_size` blocks, highest first, the way the sync asks for blocks
+fn block_range_chunks(start_height: u64, end_height: u64, chunk_size: u64) -> Result<Vec<(u64, u64)>, String> {
+    if start_height < end_height {
+        return Err(format!("Wrong block order"));
+    }
+
+    let chunk_size = cmp::max(1, chunk_size);
+    let mut chunks = vec![];
+    let mut start = start_height;
+    loop {
+        let end = cmp::max(start.saturating_sub(chunk_size - 1), end_height);
+        chunks.push((start, end));
+        if end == end_height {
+            break;
+        }
+        start = end - 1;
+    }
+
+    Ok(chunks)
+}
+
+// lightwalletd reports the txid in error_message when error_code is 0, sometimes quoted
+fn txid_from_send_response(sendresponse: SendResponse) -> Result<String, String> {
+    if sendresponse.error_code == 0 {
+        let mut txid = sendresponse.error_message;
+        if txid.starts_with("\"") && txid.ends_with("\"") {
+            txid = txid[1..txid.len() - 1].to_string();
+        }
+
+        Ok(txid)
+    } else {
+        Err(format!("Error: {:?}", sendresponse))
+    }
+}
+
+/// Submit a raw transaction to the lightwalletd at `server` and return the txid it
+/// reports. The request carries the server's current block height, which lightwalletd
+/// uses to pick the consensus branch.
+pub async fn broadcast_raw_tx(server: &str, raw_tx: &[u8]) -> Result<DisplayTxid, String> {
+    let uri: http::Uri = server
+        .parse()
+        .map_err(|e| format!("Invalid server URI {}: {}", server, e))?;
+
+    let latest = GrpcConnector::get_latest_block(uri.clone()).await?;
+
+    let mut client = GrpcConnector::new(uri)
+        .get_client()
+        .await
+        .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+    let request = Request::new(RawTransaction {
+        data: raw_tx.to_vec(),
+        height: latest.height,
+    });
+
+    let response = client
+        .send_transaction(request)
+        .await
+        .map_err(|e| format!("Send Error: {}", e))?;
+
+    let txid = txid_from_send_response(response.into_inner())?;
+    DisplayTxid::from_hex(&txid).map_err(|_| format!("Server returned an invalid txid: {}", txid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txid_from_send_response() {
+        let txid = "ab".repeat(32);
+
+        let quoted = SendResponse {
+            error_code: 0,
+            error_message: format!("\"{}\"", txid),
+        };
+        assert_eq!(txid_from_send_response(quoted), Ok(txid.clone()));
+
+        let plain = SendResponse {
+            error_code: 0,
+            error_message: txid.clone(),
+        };
+        assert_eq!(txid_from_send_response(plain), Ok(txid));
+
+        let rejected = SendResponse {
+            error_code: -26,
+            error_message: "bad-txns-inputs-spent".to_string(),
+        };
+        assert!(txid_from_send_response(rejected).unwrap_err().contains("bad-txns-inputs-spent"));
+    }
+
+    #[test]
+    fn test_block_range_chunks() {
+        let chunks = block_range_chunks(10_000, 1, 1_000).unwrap();
+        assert_eq!(chunks.len(), 10);
+        assert_eq!(chunks.first(), Some(&(10_000, 9_001)));
+        assert_eq!(chunks.last(), Some(&(1_000, 1)));
+
+        // Every chunk is at most 1,000 blocks and starts right below the one before it
+        for (start, end) in &chunks {
+            assert!(start >= end && start - end + 1 <= 1_000);
+        }
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[1].0 + 1, pair[0].1);
         }
+        let total: u64 = chunks.iter().map(|(start, end)| start - end + 1).sum();
+        assert_eq!(total, 10_000);
+
+        // A range shorter than a chunk, a single block and a partial last chunk
+        assert_eq!(block_range_chunks(500, 100, 1_000).unwrap(), vec![(500, 100)]);
+        assert_eq!(block_range_chunks(7, 7, 1_000).unwrap(), vec![(7, 7)]);
+        assert_eq!(block_range_chunks(2_500, 1, 1_000).unwrap(), vec![(2_500, 1_501), (1_500, 501), (500, 1)]);
+        assert_eq!(block_range_chunks(2, 0, 1).unwrap(), vec![(2, 2), (1, 1), (0, 0)]);
+
+        assert!(block_range_chunks(1, 2, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_outpoint_in_utxos() {
+        let utxo = |txid: [u8; 32], index| GetAddressUtxosReply {
+            txid: txid.to_vec(),
+            index,
+            ..Default::default()
+        };
+        let utxos = vec![utxo([1u8; 32], 0), utxo([2u8; 32], 3)];
+
+        assert!(outpoint_in_utxos(&utxos, &OutPoint::new([1u8; 32], 0)));
+        assert!(outpoint_in_utxos(&utxos, &OutPoint::new([2u8; 32], 3)));
+
+        // Same txid with a different index, and an unknown txid
+        assert!(!outpoint_in_utxos(&utxos, &OutPoint::new([1u8; 32], 3)));
+        assert!(!outpoint_in_utxos(&utxos, &OutPoint::new([3u8; 32], 0)));
+        assert!(!outpoint_in_utxos(&[], &OutPoint::new([1u8; 32], 0)));
+    }
+
+    /// Broadcasts a signed transaction to a live lightwalletd:
+    ///
+    ///   BTCZ_LIGHTWALLETD=https://lightd.btcz.rocks:9067 BTCZ_RAW_TX=<hex> \
+    ///     cargo test -p bitcoinzwalletlib broadcast_live -- --ignored
+    ///
+    /// broadcast_raw_tx asks the server for its latest block, sends the transaction
+    /// with that height, and returns the txid the node accepted it under.
+    #[tokio::test]
+    #[ignore]
+    async fn test_broadcast_live() {
+        use crate::bitcoinz_transaction::compute_txid;
+        use crate::bitcoinz_txid::WireTxid;
+
+        let server = std::env::var("BTCZ_LIGHTWALLETD").expect("BTCZ_LIGHTWALLETD not set");
+        let raw_tx = hex::decode(std::env::var("BTCZ_RAW_TX").expect("BTCZ_RAW_TX not set")).unwrap();
+
+        let txid = broadcast_raw_tx(&server, &raw_tx).await.unwrap();
+        info!("Broadcast {}", txid);
+        assert_eq!(txid.to_wire(), WireTxid::from(compute_txid(&raw_tx)));
+
+        // A second broadcast of the same transaction is refused by the node
+        assert!(broadcast_raw_tx(&server, &raw_tx).await.is_err());
     }
 }
@@ -1,28 +1,47 @@
 #[macro_use]
 extern crate rust_embed;
 
+pub mod bitcoinz_address;
 pub mod bitcoinz_branch;
 pub mod bitcoinz_transaction;
+pub mod bitcoinz_encoding;
+pub mod bitcoinz_base58;
+pub mod bitcoinz_txid;
+pub mod bitcoinz_memo;
+pub mod bitcoinz_script_sig;
+pub mod bitcoinz_sighash;
 pub mod bitcoinz_binding_sig;
 pub mod bitcoinz_binding_sig_fix;
 pub mod bitcoinz_binding_sig_integration;
 pub mod bitcoinz_overwinter;
 pub mod bitcoinz_overwinter_builder;
 pub mod bitcoinz_legacy_builder;
+pub mod bitcoinz_transparent_builder;
 pub mod bitcoinz_rpc_builder;
+#[cfg(feature = "rpc-crosscheck")]
+pub mod bitcoinz_rpc_crosscheck;
+#[cfg(feature = "js-bridge")]
 pub mod bitcoinz_js_bridge;
 pub mod bitcoinz_v4_no_sig;
 pub mod bitcoinz_patch;
 pub mod bitcoinz_binding_fix;
+pub mod bitcoinz_parse;
+pub mod bitcoinz_coinselect;
+pub mod bitcoinz_output_plan;
+pub mod bitcoinz_builders;
 pub mod bitcoinz_v4_shielded; // Complex API issues with v0.7
-// pub mod bitcoinz_shielded_tx;
+pub mod bitcoinz_shielded_tx;
+pub mod bitcoinz_prover_cache;
+pub mod bitcoinz_send;
 // pub mod bitcoinz_shielded_sighash;
 pub mod bitcoinz_shielded_builder_simple;
 pub mod bitcoinz_shielded_simplified;
 pub mod bitcoinz_shielded_patch;
 
-// #[cfg(test)]
-// mod bitcoinz_shielded_tests;
+#[cfg(test)]
+mod bitcoinz_shielded_tests;
+#[cfg(test)]
+mod bitcoinz_sighash_vectors;
 pub mod blaze;
 pub mod commands;
 pub mod compact_formats;
@@ -35,6 +54,8 @@ pub mod bitcoinz_edwards_bellman;
 
 #[cfg(test)]
 mod test_edwards_serialization;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 
 #[cfg(feature = "embed_params")]
 #[derive(RustEmbed)]
@@ -99,6 +120,57 @@ impl Parameters for BitcoinZMainNetwork {
 
 pub const BITCOINZ_MAINNET: BitcoinZMainNetwork = BitcoinZMainNetwork;
 
+// BitcoinZ Testnet Implementation
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct BitcoinZTestNetwork;
+
+impl Parameters for BitcoinZTestNetwork {
+    fn activation_height(&self, nu: NetworkUpgrade) -> Option<BlockHeight> {
+        // BitcoinZ testnet activates Overwinter and Sapling together, like mainnet
+        // These need to be confirmed against bitcoinzd's testnet chainparams
+        match nu {
+            NetworkUpgrade::Overwinter => Some(BlockHeight::from(1)),
+            NetworkUpgrade::Sapling => Some(BlockHeight::from(1)),
+            NetworkUpgrade::Blossom => None, // BitcoinZ doesn't have Blossom
+            NetworkUpgrade::Heartwood => None, // BitcoinZ doesn't have Heartwood
+            NetworkUpgrade::Canopy => None, // BitcoinZ doesn't have Canopy
+            NetworkUpgrade::Nu5 => None, // BitcoinZ doesn't have Nu5
+            #[cfg(feature = "zfuture")]
+            NetworkUpgrade::ZFuture => None,
+        }
+    }
+
+    fn coin_type(&self) -> u32 {
+        1 // Testnet coin type (SLIP-44)
+    }
+
+    fn hrp_sapling_extended_spending_key(&self) -> &str {
+        "secret-extended-key-test"
+    }
+
+    fn hrp_sapling_extended_full_viewing_key(&self) -> &str {
+        "zxviewtestsapling"
+    }
+
+    fn hrp_sapling_payment_address(&self) -> &str {
+        "ztestsapling"
+    }
+
+    fn b58_pubkey_address_prefix(&self) -> [u8; 2] {
+        [0x1d, 0x25] // BitcoinZ testnet pubkey hash prefix ("tm")
+    }
+
+    fn b58_script_address_prefix(&self) -> [u8; 2] {
+        [0x1c, 0xba] // BitcoinZ testnet script hash prefix ("t2")
+    }
+
+    fn address_network(&self) -> Option<zcash_address::Network> {
+        Some(zcash_address::Network::Test)
+    }
+}
+
+pub const BITCOINZ_TESTNET: BitcoinZTestNetwork = BitcoinZTestNetwork;
+
 // pub mod blaze;
 // pub mod compact_formats;
 // pub mod grpc_connector;
@@ -112,3 +184,35 @@ pub const BITCOINZ_MAINNET: BitcoinZMainNetwork = BitcoinZMainNetwork;
 //     let lc = LightClient::new(Some(seed)).unwrap();
 //     lc.start_sync();
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zcash_primitives::legacy::TransparentAddress;
+    use zcash_client_backend::encoding::AddressCodec;
+
+    #[test]
+    fn test_testnet_params() {
+        assert_eq!(BITCOINZ_TESTNET.coin_type(), 1);
+        assert_eq!(BITCOINZ_TESTNET.hrp_sapling_payment_address(), "ztestsapling");
+        assert_eq!(BITCOINZ_TESTNET.hrp_sapling_extended_spending_key(), "secret-extended-key-test");
+        assert_eq!(BITCOINZ_TESTNET.hrp_sapling_extended_full_viewing_key(), "zxviewtestsapling");
+        assert_eq!(BITCOINZ_TESTNET.address_network(), Some(zcash_address::Network::Test));
+
+        // Testnet and mainnet must not share address encodings
+        assert_ne!(BITCOINZ_TESTNET.coin_type(), BITCOINZ_MAINNET.coin_type());
+        assert_ne!(
+            BITCOINZ_TESTNET.b58_pubkey_address_prefix(),
+            BITCOINZ_MAINNET.b58_pubkey_address_prefix()
+        );
+    }
+
+    #[test]
+    fn test_testnet_taddr_round_trip() {
+        let addr = TransparentAddress::PublicKey([0x42u8; 20]);
+        let encoded = addr.encode(&BITCOINZ_TESTNET);
+        assert!(encoded.starts_with("tm"));
+        assert_eq!(TransparentAddress::decode(&BITCOINZ_TESTNET, &encoded).unwrap(), addr);
+        assert!(TransparentAddress::decode(&BITCOINZ_MAINNET, &encoded).is_err());
+    }
+}
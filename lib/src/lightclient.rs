@@ -1,14 +1,22 @@
-use self::lightclient_config::LightClientConfig;
+use self::diagnostics::{
+    describe_failed_send, describe_pending_tx, write_tar, Redactor, DIAGNOSTICS_LOG_SEGMENTS, NOT_INCLUDED,
+};
+use self::lightclient_config::{LightClientConfig, LOG_WINDOW_SIZE, ROLLED_LOGFILE_PREFIX};
+use self::seed_source::SeedSource;
+use self::selftest::run_selftest;
 use crate::{
+    bitcoinz_address::decode_shielded_address,
+    bitcoinz_prover_cache::ProverCache,
     blaze::{
-        block_witness_data::BlockAndWitnessData, fetch_compact_blocks::FetchCompactBlocks,
+        block_witness_data::{check_anchor_tree_state, BlockAndWitnessData},
+        fetch_compact_blocks::FetchCompactBlocks,
         fetch_full_tx::FetchFullTxns, fetch_taddr_txns::FetchTaddrTxns, sync_status::SyncStatus,
         syncdata::BlazeSyncData, trial_decryptions::TrialDecryptions, update_notes::UpdateNotes,
     },
     compact_formats::RawTransaction,
     grpc_connector::GrpcConnector,
     lightclient::lightclient_config::MAX_REORG,
-    lightwallet::{self, data::WalletTx, message::Message, now, LightWallet, MAX_CHECKPOINTS, MERKLE_DEPTH},
+    lightwallet::{self, data::{Compatibility, WalletTx}, message::Message, now, LightWallet, SendOptions, MAX_CHECKPOINTS, MERKLE_DEPTH},
     BitcoinZMainNetwork, BITCOINZ_MAINNET,
 };
 use futures::{stream::FuturesUnordered, StreamExt};
@@ -33,7 +41,7 @@ use tokio::{
     task::yield_now,
     time::sleep,
 };
-use zcash_client_backend::encoding::{decode_payment_address, encode_payment_address};
+use zcash_client_backend::encoding::encode_payment_address;
 use zcash_primitives::{
     block::BlockHash,
     consensus::{self, BlockHeight, BranchId},
@@ -41,10 +49,12 @@ use zcash_primitives::{
     merkle_tree::CommitmentTree,
     transaction::{components::amount::DEFAULT_FEE, Transaction, TxId},
 };
-use zcash_proofs::prover::LocalTxProver;
 
 pub(crate) mod checkpoints;
+pub mod diagnostics;
 pub mod lightclient_config;
+pub mod seed_source;
+pub mod selftest;
 
 #[derive(Clone, Debug)]
 pub struct WalletStatus {
@@ -71,6 +81,9 @@ pub struct LightClient<P> {
 
     sync_lock: Mutex<()>,
 
+    // Sapling parameters, loaded the first time a transaction is built
+    prover_cache: ProverCache,
+
     bsync_data: Arc<RwLock<BlazeSyncData>>,
 }
 
@@ -86,11 +99,12 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         }
 
         let l = LightClient {
-            wallet: LightWallet::new(config.clone(), seed_phrase, height, 1, 1)?,
+            wallet: LightWallet::new(config.clone(), seed_phrase.as_deref(), height, 1, 1)?,
             config: config.clone(),
             mempool_monitor: std::sync::RwLock::new(None),
             bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
             sync_lock: Mutex::new(()),
+            prover_cache: ProverCache::new(),
         };
 
         l.set_wallet_initial_state(height).await;
@@ -226,6 +240,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                 config: config.clone(),
                 mempool_monitor: std::sync::RwLock::new(None),
                 sync_lock: Mutex::new(()),
+                prover_cache: ProverCache::new(),
                 bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
             };
 
@@ -259,8 +274,22 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         Self::new_wallet(config, latest_block, 1, 1)
     }
 
+    /// Create a wallet from a seed phrase (or key) supplied without a prompt, for
+    /// headless use. See `SeedSource` for where it can come from.
+    pub fn from_seed_source(
+        source: SeedSource,
+        config: &LightClientConfig<P>,
+        birthday: u64,
+        overwrite: bool,
+    ) -> io::Result<Self> {
+        let seed_phrase = source.read()?;
+        Self::new_from_phrase(&seed_phrase, config, birthday, overwrite)
+    }
+
+    /// Create a wallet from a seed phrase, or from a spending or viewing key. The phrase
+    /// is borrowed so that no copy of it outlives the caller's.
     pub fn new_from_phrase(
-        seed_phrase: String,
+        seed_phrase: &str,
         config: &LightClientConfig<P>,
         birthday: u64,
         overwrite: bool,
@@ -295,6 +324,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                     config: config.clone(),
                     mempool_monitor: std::sync::RwLock::new(None),
                     sync_lock: Mutex::new(()),
+                    prover_cache: ProverCache::new(),
                     bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
                 };
 
@@ -323,6 +353,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                 config: config.clone(),
                 mempool_monitor: std::sync::RwLock::new(None),
                 sync_lock: Mutex::new(()),
+                prover_cache: ProverCache::new(),
                 bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
             };
 
@@ -355,6 +386,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                 config: config.clone(),
                 mempool_monitor: std::sync::RwLock::new(None),
                 sync_lock: Mutex::new(()),
+                prover_cache: ProverCache::new(),
                 bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
             };
 
@@ -371,6 +403,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         // Configure logging first.
         let log_config = self.config.get_log_config()?;
         log4rs::init_config(log_config).map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
+        crate::bitcoinz_branch::warn_if_branch_ids_unconfirmed();
 
         Ok(())
     }
@@ -446,12 +479,220 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         }
     }
 
+    /// Every address in the wallet: UAs, then z addresses, then t addresses
+    pub async fn get_all_addresses(&self) -> Vec<String> {
+        let keys = self.wallet.keys();
+        let keys = keys.read().await;
+
+        let mut addresses = keys.get_all_uaddresses();
+        addresses.extend(keys.get_all_zaddresses());
+        addresses.extend(keys.get_all_taddrs());
+        addresses
+    }
+
+    pub fn get_all_addresses_sync(&self) -> Vec<String> {
+        Runtime::new()
+            .unwrap()
+            .block_on(async move { self.get_all_addresses().await })
+    }
+
+    /// The txids of the wallet's `limit` most recent transactions, newest first
+    pub async fn get_recent_txids(&self, limit: usize) -> Vec<TxId> {
+        let txns = self.wallet.txns.read().await;
+
+        let mut recent = txns
+            .current
+            .values()
+            .map(|wtx| (wtx.block, wtx.txid))
+            .collect::<Vec<_>>();
+        recent.sort_by(|a, b| b.0.cmp(&a.0));
+        recent.into_iter().take(limit).map(|(_, txid)| txid).collect()
+    }
+
+    pub fn get_recent_txids_sync(&self, limit: usize) -> Vec<TxId> {
+        Runtime::new()
+            .unwrap()
+            .block_on(async move { self.get_recent_txids(limit).await })
+    }
+
     pub async fn do_last_txid(&self) -> JsonValue {
         object! {
             "last_txid" => self.wallet.txns().read().await.get_last_txid().map(|t| t.to_string())
         }
     }
 
+    pub async fn do_wallet_info(&self) -> JsonValue {
+        let stats = self.wallet.load_stats().await;
+
+        object! {
+            "wallet_version" => LightWallet::<P>::serialized_version(),
+            "birthday" => self.wallet.get_birthday().await,
+            "last_scanned_height" => self.wallet.last_scanned_height().await,
+            "witnesses_loaded" => self.wallet.witnesses_loaded(),
+            "eager_load_ms" => stats.eager_load.map(|d| d.as_millis() as u64),
+            "witness_load_ms" => stats.witness_load.map(|d| d.as_millis() as u64),
+        }
+    }
+
+    /// Write a bundle with everything we need to look into a bug report to `path`. Addresses
+    /// are shortened and memos and keys removed from every file in it.
+    pub async fn do_diagnostics(&self, path: &str, include_raw: bool) -> Result<JsonValue, String> {
+        let redactor = self.diagnostics_redactor().await;
+        let mut files: Vec<(String, Vec<u8>)> = vec![];
+        let mut unavailable: Vec<String> = vec![];
+
+        let add_json = |files: &mut Vec<(String, Vec<u8>)>, name: &str, v: JsonValue| {
+            files.push((name.to_string(), redactor.redact_json(&v).pretty(2).into_bytes()));
+        };
+
+        add_json(
+            &mut files,
+            "version.json",
+            object! {
+                "version" => env!("CARGO_PKG_VERSION"),
+                "os" => std::env::consts::OS,
+                "arch" => std::env::consts::ARCH,
+                "debug_build" => cfg!(debug_assertions),
+                "chain_name" => self.config.chain_name.clone(),
+                "server_uri" => self.get_server_uri().to_string(),
+            },
+        );
+
+        let mut walletinfo = self.do_wallet_info().await;
+        walletinfo["balance"] = self.do_balance().await;
+        let status = self.do_sync_status().await;
+        walletinfo["sync_status"] = object! {
+            "in_progress" => status.in_progress,
+            "last_error" => status.last_error,
+            "start_block" => status.start_block,
+            "end_block" => status.end_block,
+        };
+        add_json(&mut files, "walletinfo.json", walletinfo);
+
+        // do_info() returns either the server's info as JSON, or the error
+        let info = self.do_info().await;
+        let probe = json::parse(&info).unwrap_or_else(|_| object! { "error" => info });
+        add_json(&mut files, "server.json", probe);
+
+        let mut send = self.do_send_progress().await?;
+        send["failed_transactions"] = self
+            .wallet
+            .failed_sends()
+            .await
+            .iter()
+            .rev()
+            .map(|f| describe_failed_send(f, include_raw))
+            .collect::<Vec<_>>()
+            .into();
+
+        // Our sends still waiting to be mined, including any the network dropped, from the
+        // wallet's transactions rather than memory
+        let last_scanned_height = self.wallet.last_scanned_height().await;
+        send["pending_transactions"] = self
+            .wallet
+            .txns()
+            .read()
+            .await
+            .current
+            .values()
+            .filter(|wtx| wtx.unconfirmed && wtx.total_transparent_value_spent + wtx.total_sapling_value_spent > 0)
+            .map(|wtx| describe_pending_tx(wtx, last_scanned_height))
+            .collect::<Vec<_>>()
+            .into();
+        add_json(&mut files, "send.json", send);
+
+        let target_height = BlockHeight::from_u32(last_scanned_height as u32 + 1);
+        add_json(
+            &mut files,
+            "selftest.json",
+            run_selftest(&self.config.get_params(), target_height),
+        );
+
+        // The live log first, then the most recently rolled over ones
+        let live_log = self.config.get_log_path();
+        let mut log_paths = vec![live_log.to_path_buf()];
+        for i in 0..LOG_WINDOW_SIZE {
+            let rolled = format!("{}{}", ROLLED_LOGFILE_PREFIX, i);
+            log_paths.push(match live_log.parent() {
+                Some(dir) if dir.join(&rolled).exists() => dir.join(&rolled),
+                _ => Path::new(&rolled).to_path_buf(),
+            });
+        }
+        for log_path in log_paths.iter().filter(|p| p.exists()).take(DIAGNOSTICS_LOG_SEGMENTS) {
+            match std::fs::read(log_path) {
+                Ok(contents) => {
+                    let name = log_path.file_name().unwrap().to_string_lossy();
+                    let redacted = redactor.redact_text(&String::from_utf8_lossy(&contents));
+                    files.push((format!("logs/{}", name), redacted.into_bytes()));
+                }
+                Err(e) => unavailable.push(format!("{}: {}", log_path.display(), e)),
+            }
+        }
+
+        let names = files.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        add_json(
+            &mut files,
+            "manifest.json",
+            object! {
+                "created" => now(),
+                "files" => names.clone(),
+                "unavailable" => unavailable.clone(),
+                "not_included" => NOT_INCLUDED.to_vec(),
+                "include_raw" => include_raw,
+            },
+        );
+
+        let file = File::create(path).map_err(|e| format!("Couldn't create {}: {}", path, e))?;
+        write_tar(io::BufWriter::new(file), &files, now()).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+        info!("Wrote diagnostics bundle to {}", path);
+
+        Ok(object! {
+            "path" => path,
+            "files" => names,
+            "unavailable" => unavailable,
+            "not_included" => NOT_INCLUDED.to_vec(),
+        })
+    }
+
+    /// Redactor that knows this wallet's seed, keys, addresses and memos
+    async fn diagnostics_redactor(&self) -> Redactor {
+        let mut redactor = Redactor::new(&self.config);
+
+        {
+            let keys = self.wallet.keys();
+            let keys = keys.read().await;
+            redactor.add_key(keys.get_seed_phrase());
+            for (address, private_key, viewing_key) in keys.get_z_private_keys() {
+                redactor.add_address(address);
+                redactor.add_key(private_key);
+                redactor.add_key(viewing_key);
+            }
+            for (address, secret_key) in keys.get_t_secret_keys() {
+                redactor.add_address(address);
+                redactor.add_key(secret_key);
+            }
+            keys.get_all_uaddresses().into_iter().for_each(|ua| redactor.add_address(ua));
+        }
+
+        let txns = self.wallet.txns();
+        let txns = txns.read().await;
+        for wtx in txns.current.values() {
+            let memos = wtx
+                .s_notes
+                .iter()
+                .map(|nd| nd.memo.clone())
+                .chain(wtx.o_notes.iter().map(|nd| nd.memo.clone()))
+                .chain(wtx.outgoing_metadata.iter().map(|om| Some(om.memo.clone())));
+            for memo in memos {
+                if let Some(m) = LightWallet::<P>::memo_str(memo) {
+                    redactor.add_memo(m);
+                }
+            }
+        }
+
+        redactor
+    }
+
     pub async fn do_balance(&self) -> JsonValue {
         // Collect UA addresses
         let mut ua_addresses = vec![];
@@ -819,10 +1060,10 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     pub fn do_encrypt_message(&self, to_address_str: String, memo: Memo) -> JsonValue {
-        let to = match decode_payment_address(self.config.hrp_sapling_address(), &to_address_str) {
-            Ok(Some(to)) => to,
-            _ => {
-                return object! {"error" => format!("Couldn't parse {} as a z-address", to_address_str) };
+        let to = match decode_shielded_address(&to_address_str, &self.config.get_params()) {
+            Ok(to) => to,
+            Err(e) => {
+                return object! {"error" => format!("Couldn't parse {} as a z-address: {}", to_address_str, e) };
             }
         };
 
@@ -863,6 +1104,16 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     pub async fn do_list_transactions(&self, include_memo_hex: bool) -> JsonValue {
+        self.do_list_transactions_filtered(include_memo_hex, None).await
+    }
+
+    /// Like `do_list_transactions`, but if `compatibility` is given, only the transactions this
+    /// wallet sent with that tag are listed
+    pub async fn do_list_transactions_filtered(
+        &self,
+        include_memo_hex: bool,
+        compatibility: Option<Compatibility>,
+    ) -> JsonValue {
         // Create a list of TransactionItems from wallet txns
         let mut tx_list = self
             .wallet
@@ -871,6 +1122,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
             .await
             .current
             .iter()
+            .filter(|(_k, v)| compatibility.is_none() || v.compatibility == compatibility)
             .flat_map(|(_k, v)| {
                 let mut txns: Vec<JsonValue> = vec![];
 
@@ -919,6 +1171,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                         "zec_price"    => v.zec_price.map(|p| (p * 100.0).round() / 100.0),
                         "amount"       => total_change as i64 - v.total_funds_spent() as i64,
                         "outgoing_metadata" => outgoing_json,
+                        "compatibility" => v.compatibility.map(|c| c.as_str()),
                     });
                 }
 
@@ -1052,7 +1305,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     /// Convinence function to determine what type of key this is and import it
-    pub async fn do_import_key(&self, key: String, birthday: u64) -> Result<JsonValue, String> {
+    pub async fn do_import_key(&self, key: &str, birthday: u64) -> Result<JsonValue, String> {
         if key.starts_with(self.config.hrp_sapling_private_key()) {
             self.do_import_sk(key, birthday).await
         } else if key.starts_with(self.config.hrp_sapling_viewing_key()) {
@@ -1068,7 +1321,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     /// Import a new transparent private key
-    pub async fn do_import_tk(&self, sk: String) -> Result<JsonValue, String> {
+    pub async fn do_import_tk(&self, sk: &str) -> Result<JsonValue, String> {
         if !self.wallet.is_unlocked_for_spending().await {
             error!("Wallet is locked");
             return Err("Wallet is locked".to_string());
@@ -1086,7 +1339,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     /// Import a new z-address private key
-    pub async fn do_import_sk(&self, sk: String, birthday: u64) -> Result<JsonValue, String> {
+    pub async fn do_import_sk(&self, sk: &str, birthday: u64) -> Result<JsonValue, String> {
         if !self.wallet.is_unlocked_for_spending().await {
             error!("Wallet is locked");
             return Err("Wallet is locked".to_string());
@@ -1109,7 +1362,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
     }
 
     /// Import a new viewing key
-    pub async fn do_import_vk(&self, vk: String, birthday: u64) -> Result<JsonValue, String> {
+    pub async fn do_import_vk(&self, vk: &str, birthday: u64) -> Result<JsonValue, String> {
         if !self.wallet.is_unlocked_for_spending().await {
             error!("Wallet is locked");
             return Err("Wallet is locked".to_string());
@@ -1131,6 +1384,25 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         Ok(array![new_address])
     }
 
+    /// Remove an imported key and everything the wallet knows about its funds
+    pub async fn do_remove_key(&self, address: String) -> Result<JsonValue, String> {
+        if !self.wallet.is_unlocked_for_spending().await {
+            error!("Wallet is locked");
+            return Err("Wallet is locked".to_string());
+        }
+
+        if let Err(e) = self.wallet.remove_key(address.clone()).await {
+            error!("{}", e);
+            return Err(e);
+        }
+
+        self.do_save(true).await?;
+        Ok(object! {
+            "result" => "success",
+            "removed" => address,
+        })
+    }
+
     pub async fn clear_state(&self) {
         // First, clear the state from the wallet
         self.wallet.clear_all().await;
@@ -1162,6 +1434,23 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         response
     }
 
+    /// Repair which notes are marked spent from the transactions already in the wallet,
+    /// for when the spent index is out of step and a full rescan would be overkill
+    pub async fn do_rebuild_spent_index(&self) -> Result<JsonValue, String> {
+        let changed = {
+            // Don't race a sync that's marking notes spent
+            let _lock = self.sync_lock.lock().await;
+            self.wallet.txns.write().await.rebuild_spent_index()
+        };
+
+        self.do_save(true).await?;
+
+        Ok(object! {
+            "result" => "success",
+            "notes_changed" => changed,
+        })
+    }
+
     async fn update_current_price(&self) {
         // Get the zec price from the server
         match GrpcConnector::get_current_zec_price(self.get_server_uri()).await {
@@ -1390,6 +1679,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
             }
         }
 
+        // The first batch updates note witnesses, so it has to wait for them to be parsed
+        self.wallet.ensure_witnesses_loaded().await;
+
         // Re-read the last scanned height
         let last_scanned_height = self.wallet.last_scanned_height().await;
 
@@ -1660,7 +1952,8 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         })
     }
 
-    pub async fn do_shield(&self, address: Option<String>) -> Result<String, String> {
+    /// Shielding is a t→z send, so it needs `force_experimental` while the branch IDs are unconfirmed
+    pub async fn do_shield(&self, address: Option<String>, force_experimental: bool) -> Result<String, String> {
         let fee = u64::from(DEFAULT_FEE);
         let tbal = self.wallet.tbalance(None).await;
 
@@ -1683,14 +1976,38 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
                 .map(|s| s.clone()))
             .unwrap();
 
+        let options = SendOptions {
+            force_experimental,
+            ..SendOptions::default()
+        };
         let result = {
             let _lock = self.sync_lock.lock().await;
-            let (sapling_output, sapling_spend) = self.read_sapling_params()?;
+            let prover = self.prover_cache.get_or_load(|| self.read_sapling_params())?;
+
+            self.wallet
+                .send_to_address(prover, true, vec![(&addr, tbal - fee, None)], options, |txbytes| {
+                    GrpcConnector::send_transaction(self.get_server_uri(), txbytes)
+                })
+                .await
+        };
+
+        result.map(|sent| sent.last().unwrap().0.clone())
+    }
 
-            let prover = LocalTxProver::from_bytes(&sapling_spend, &sapling_output);
+    /// Send every transparent coin to `address` in one transaction, less its fee. Sweeping
+    /// into a z-address is a t→z send, so it needs `force_experimental` while the branch
+    /// IDs are unconfirmed.
+    pub async fn do_sweep(&self, address: &str, force_experimental: bool) -> Result<String, String> {
+        let options = SendOptions {
+            force_experimental,
+            ..SendOptions::default()
+        };
+        let result = {
+            let _lock = self.sync_lock.lock().await;
+            let prover = self.prover_cache.get_or_load(|| self.read_sapling_params())?;
 
             self.wallet
-                .send_to_address(prover, true, vec![(&addr, tbal - fee, None)], |txbytes| {
+                .sweep_to_address(prover, address, options, |txbytes| {
                     GrpcConnector::send_transaction(self.get_server_uri(), txbytes)
                 })
                 .await
@@ -1699,29 +2016,104 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
         result.map(|(txid, _)| txid)
     }
 
+    /// Make sure lightwalletd will take the anchor a Sapling spend would be made against:
+    /// the server has to have the same block at the anchor height, with the same tree,
+    /// and deep enough. Catches a wallet left on an orphaned chain before any proof is made.
+    pub async fn verify_spend_anchor(&self) -> Result<(), String> {
+        let (height, hash, anchor) = match self.wallet.sapling_spend_anchor().await {
+            Some(anchor) => anchor,
+            None => return Ok(()),
+        };
+
+        let tree_state = GrpcConnector::get_merkle_tree(self.get_server_uri(), height).await?;
+        let tip = GrpcConnector::get_latest_block(self.get_server_uri()).await?.height;
+
+        check_anchor_tree_state(&anchor, height, &hash, &tree_state, tip, self.config.anchor_offset as u64)
+    }
+
     pub async fn do_send(&self, addrs: Vec<(&str, u64, Option<String>)>) -> Result<String, String> {
         info!("Creating transaction");
+        self.verify_spend_anchor().await?;
 
         // println!("BranchID {:x}", branch_id);
 
         let result = {
             let _lock = self.sync_lock.lock().await;
-            let (sapling_output, sapling_spend) = self.read_sapling_params()?;
-
-            let prover = LocalTxProver::from_bytes(&sapling_spend, &sapling_output);
+            let prover = self.prover_cache.get_or_load(|| self.read_sapling_params())?;
 
             self.wallet
-                .send_to_address(prover, false, addrs, |txbytes| {
+                .send_to_address(prover, false, addrs, SendOptions::default(), |txbytes| {
                     GrpcConnector::send_transaction(self.get_server_uri(), txbytes)
                 })
                 .await
         };
 
-        result.map(|(txid, _)| txid)
+        result.map(|sent| sent.last().unwrap().0.clone())
     }
 
+    /// Create and sign a payment like `do_send`, returning the txid of each transaction it
+    /// took, in the order they were sent. With `build_only`, nothing is broadcast: each
+    /// transaction's hex comes back with its txid so it can be submitted some other way. The
+    /// notes and utxos it spends are still marked as pending either way, so they aren't reused.
+    /// `options` set the expiry, and whether a send with shielded parts is built while the
+    /// branch IDs are unconfirmed.
+    pub async fn do_send_with_options(
+        &self,
+        addrs: Vec<(&str, u64, Option<String>)>,
+        build_only: bool,
+        options: SendOptions,
+    ) -> Result<Vec<JsonValue>, String> {
+        info!("Creating transaction");
+        self.verify_spend_anchor().await?;
+
+        let result = {
+            let _lock = self.sync_lock.lock().await;
+            let prover = self.prover_cache.get_or_load(|| self.read_sapling_params())?;
+
+            if build_only {
+                self.wallet
+                    .send_to_address(prover, false, addrs, options, skip_broadcast)
+                    .await
+            } else {
+                self.wallet
+                    .send_to_address(prover, false, addrs, options, |txbytes| {
+                        GrpcConnector::send_transaction(self.get_server_uri(), txbytes)
+                    })
+                    .await
+            }
+        };
+
+        result.map(|sent| {
+            sent.into_iter()
+                .map(|(txid, raw)| {
+                    if build_only {
+                        object! { "txid" => txid, "hex" => hex::encode(raw) }
+                    } else {
+                        object! { "txid" => txid }
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Tests build shielded transactions whether or not the branch IDs are confirmed
     #[cfg(test)]
     pub async fn test_do_send(&self, addrs: Vec<(&str, u64, Option<String>)>) -> Result<String, String> {
+        let options = SendOptions {
+            force_experimental: true,
+            ..SendOptions::default()
+        };
+        self.test_do_send_with(addrs, options)
+            .await
+            .map(|txids| txids.last().unwrap().clone())
+    }
+
+    #[cfg(test)]
+    pub async fn test_do_send_with(
+        &self,
+        addrs: Vec<(&str, u64, Option<String>)>,
+        options: SendOptions,
+    ) -> Result<Vec<String>, String> {
         info!("Creating transaction");
 
         let result = {
@@ -1729,16 +2121,41 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightClient<P> {
             let prover = crate::blaze::test_utils::FakeTxProver {};
 
             self.wallet
-                .send_to_address(prover, false, addrs, |txbytes| {
+                .send_to_address(prover, false, addrs, options, |txbytes| {
                     GrpcConnector::send_transaction(self.get_server_uri(), txbytes)
                 })
                 .await
         };
 
-        result.map(|(txid, _)| txid)
+        result.map(|sent| sent.into_iter().map(|(txid, _)| txid).collect())
+    }
+
+    #[cfg(test)]
+    pub async fn test_do_send_build_only(&self, addrs: Vec<(&str, u64, Option<String>)>) -> Result<JsonValue, String> {
+        let result = {
+            let _lock = self.sync_lock.lock().await;
+            let prover = crate::blaze::test_utils::FakeTxProver {};
+
+            let options = SendOptions {
+                force_experimental: true,
+                ..SendOptions::default()
+            };
+            self.wallet.send_to_address(prover, false, addrs, options, skip_broadcast).await
+        };
+
+        result.map(|mut sent| {
+            let (txid, raw) = sent.remove(0);
+            object! { "txid" => txid, "hex" => hex::encode(raw) }
+        })
     }
 }
 
+/// Stands in for the broadcaster when a transaction is only being built, returning the txid the
+/// server would have
+async fn skip_broadcast(txbytes: Box<[u8]>) -> Result<String, String> {
+    Ok(crate::bitcoinz_transaction::compute_txid(&txbytes).to_string())
+}
+
 #[cfg(test)]
 pub mod tests;
 
@@ -1747,3 +2164,6 @@ pub(crate) mod test_server;
 
 #[cfg(test)]
 pub(crate) mod faketx;
+
+#[cfg(all(test, feature = "regtest-e2e"))]
+mod regtest_e2e;
@@ -0,0 +1,339 @@
+/// Diagnostics bundle for bug reports
+///
+/// Collects what we usually have to ask for when a transaction is rejected into a
+/// single tar file. Everything goes through a `Redactor` first: addresses are cut
+/// down to their first and last 4 characters, and memos and key material are removed.
+///
+/// The self test is run and its results included. Sends still waiting to be mined come
+/// from the wallet's own transactions, so they survive a restart; sends the server
+/// refused are only kept in memory, so the ones from before the wallet was opened are
+/// missing, and the manifest says so (see `NOT_INCLUDED`). Failed transactions of every
+/// version we build are decoded, transparent v1 sends included.
+
+use base58::FromBase58;
+use json::{object, JsonValue};
+use std::io::{self, Write};
+use zcash_primitives::consensus;
+
+use crate::bitcoinz_parse::BitcoinZTransaction;
+use crate::lightwallet::data::WalletTx;
+use crate::lightwallet::FailedSend;
+
+use super::lightclient_config::LightClientConfig;
+
+/// How many log files (the live one first, then the rolled over ones) go in the bundle
+pub const DIAGNOSTICS_LOG_SEGMENTS: usize = 2;
+
+/// What the bundle leaves out, listed in its manifest
+pub const NOT_INCLUDED: &[&str] = &["failed_transactions: only sends that failed since the wallet was opened are kept"];
+
+const REDACTED_KEY: &str = "[redacted key]";
+const REDACTED_MEMO: &str = "[redacted memo]";
+
+pub struct Redactor {
+    // Exact strings to remove wherever they appear (seed phrase, keys, memos)
+    secrets: Vec<(String, &'static str)>,
+    // Exact strings to shorten (our own addresses)
+    addresses: Vec<String>,
+    address_prefixes: Vec<String>,
+    key_prefixes: Vec<String>,
+    base58_address_prefixes: Vec<[u8; 2]>,
+    base58_secretkey_prefix: [u8; 1],
+}
+
+impl Redactor {
+    pub fn new<P: consensus::Parameters>(config: &LightClientConfig<P>) -> Self {
+        Self {
+            secrets: vec![],
+            addresses: vec![],
+            address_prefixes: vec![format!("{}1", config.hrp_sapling_address()), "u1".to_string()],
+            key_prefixes: vec![
+                format!("{}1", config.hrp_sapling_private_key()),
+                format!("{}1", config.hrp_sapling_viewing_key()),
+                "uview1".to_string(),
+            ],
+            base58_address_prefixes: vec![config.base58_pubkey_address(), config.base58_script_address()],
+            base58_secretkey_prefix: config.base58_secretkey_prefix(),
+        }
+    }
+
+    /// Seed phrase or key that must not appear anywhere in the bundle
+    pub fn add_key(&mut self, key: String) {
+        if !key.is_empty() {
+            self.secrets.push((key, REDACTED_KEY));
+        }
+    }
+
+    /// Memo text that must not appear anywhere in the bundle
+    pub fn add_memo(&mut self, memo: String) {
+        if !memo.trim().is_empty() {
+            self.secrets.push((memo, REDACTED_MEMO));
+        }
+    }
+
+    /// One of our addresses, which might not match the generic address patterns
+    pub fn add_address(&mut self, address: String) {
+        if !address.is_empty() {
+            self.addresses.push(address);
+        }
+    }
+
+    pub fn redact_text(&self, text: &str) -> String {
+        // Longest first, so a secret that contains another is removed whole
+        let mut secrets = self.secrets.iter().collect::<Vec<_>>();
+        secrets.sort_by_key(|(s, _)| std::cmp::Reverse(s.len()));
+
+        let mut text = text.to_string();
+        for (secret, replacement) in secrets {
+            text = text.replace(secret.as_str(), replacement);
+        }
+        for address in &self.addresses {
+            text = text.replace(address.as_str(), &shorten(address));
+        }
+
+        // Then look at every word that could be an encoded address or key
+        let mut out = String::with_capacity(text.len());
+        let mut token = String::new();
+        for c in text.chars() {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                token.push(c);
+            } else {
+                out.push_str(&self.redact_token(&token));
+                token.clear();
+                out.push(c);
+            }
+        }
+        out.push_str(&self.redact_token(&token));
+
+        out
+    }
+
+    pub fn redact_json(&self, value: &JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Object(o) => {
+                let mut redacted = JsonValue::new_object();
+                for (k, v) in o.iter() {
+                    let k_lower = k.to_lowercase();
+                    redacted[k] = if v.is_null() {
+                        JsonValue::Null
+                    } else if k_lower.contains("memo") {
+                        REDACTED_MEMO.into()
+                    } else if k_lower.contains("private_key") || k_lower.contains("viewing_key") || k_lower.contains("seed") {
+                        REDACTED_KEY.into()
+                    } else {
+                        self.redact_json(v)
+                    };
+                }
+                redacted
+            }
+            JsonValue::Array(a) => JsonValue::Array(a.iter().map(|v| self.redact_json(v)).collect()),
+            JsonValue::String(_) | JsonValue::Short(_) => self.redact_text(value.as_str().unwrap()).into(),
+            _ => value.clone(),
+        }
+    }
+
+    fn redact_token(&self, token: &str) -> String {
+        if token.len() < 26 {
+            return token.to_string();
+        }
+
+        if self.key_prefixes.iter().any(|p| token.starts_with(p.as_str())) {
+            return REDACTED_KEY.to_string();
+        }
+        if self.address_prefixes.iter().any(|p| token.starts_with(p.as_str())) {
+            return shorten(token);
+        }
+
+        // Base58 transparent addresses and WIF keys
+        if let Ok(bytes) = token.from_base58() {
+            if bytes.len() == 26 && self.base58_address_prefixes.iter().any(|p| bytes[..2] == p[..]) {
+                return shorten(token);
+            }
+            if (bytes.len() == 37 || bytes.len() == 38) && bytes[0] == self.base58_secretkey_prefix[0] {
+                return REDACTED_KEY.to_string();
+            }
+        }
+
+        token.to_string()
+    }
+}
+
+/// Keep only the first and last 4 characters
+pub fn shorten(s: &str) -> String {
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() <= 8 {
+        return s.to_string();
+    }
+
+    format!(
+        "{}...{}",
+        chars[..4].iter().collect::<String>(),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// Structure of a transaction the server refused. The raw bytes are only included on request.
+pub fn describe_failed_send(failed: &FailedSend, include_raw: bool) -> JsonValue {
+    let mut o = object! {
+        "time" => failed.time,
+        "error" => failed.error.clone(),
+        "size" => failed.raw_tx.len(),
+    };
+
+    match BitcoinZTransaction::read(&failed.raw_tx) {
+        Ok(tx) => {
+            o["decoded"] = object! {
+                "header" => format!("{:#010x}", tx.header),
+                "version_group_id" => format!("{:#010x}", tx.version_group_id),
+                "inputs" => tx.inputs.iter().map(|i| {
+                    object! {
+                        "prevout" => format!("{}:{}", i.prevout_hash.to_display(), i.prevout_index),
+                        "script_sig_len" => i.script_sig.len(),
+                        "sequence" => i.sequence,
+                    }
+                }).collect::<Vec<_>>(),
+                "outputs" => tx.outputs.iter().map(|o| object! {
+                    "value" => u64::from(o.value),
+                    "script_len" => o.script_pubkey.0.len(),
+                }).collect::<Vec<_>>(),
+                "lock_time" => tx.lock_time,
+                "expiry_height" => tx.expiry_height,
+                "value_balance" => i64::from(tx.value_balance),
+                "shielded_spends" => tx.shielded_spends.len(),
+                "shielded_outputs" => tx.shielded_outputs.len(),
+                "joinsplits" => tx.joinsplit_count,
+                "binding_sig" => format!("{:?}", tx.binding_sig_status()),
+            };
+        }
+        Err(e) => {
+            o["decode_error"] = e.to_string().into();
+            if failed.raw_tx.len() >= 4 {
+                let header = u32::from_le_bytes([failed.raw_tx[0], failed.raw_tx[1], failed.raw_tx[2], failed.raw_tx[3]]);
+                o["header"] = format!("{:#010x}", header).into();
+            }
+        }
+    }
+
+    if include_raw {
+        o["raw"] = hex::encode(&failed.raw_tx).into();
+    }
+
+    o
+}
+
+/// A send of ours still waiting to be mined, as the wallet keeps it. Its bytes aren't kept,
+/// so there's no structure to decode, only what it spent and how long it's been waiting.
+pub fn describe_pending_tx(wtx: &WalletTx, last_scanned_height: u64) -> JsonValue {
+    object! {
+        "txid" => wtx.txid.to_string(),
+        "time" => wtx.datetime,
+        "target_height" => u32::from(wtx.block),
+        "blocks_waiting" => (last_scanned_height + 1).saturating_sub(u32::from(wtx.block) as u64),
+        "compatibility" => wtx.compatibility.map(|c| c.as_str()),
+        "transparent_spent" => wtx.total_transparent_value_spent,
+        "sapling_spent" => wtx.total_sapling_value_spent,
+        "outgoing_outputs" => wtx.outgoing_metadata.len(),
+    }
+}
+
+/// Write the files as an uncompressed (ustar) tar archive
+pub fn write_tar<W: Write>(mut writer: W, files: &[(String, Vec<u8>)], mtime: u64) -> io::Result<()> {
+    for (name, contents) in files {
+        if name.len() >= 100 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("File name too long: {}", name)));
+        }
+
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // The checksum is computed with its own field set to spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(contents)?;
+        let padding = (512 - contents.len() % 512) % 512;
+        writer.write_all(&vec![0u8; padding])?;
+    }
+
+    // End of archive
+    writer.write_all(&[0u8; 1024])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lightclient::lightclient_config::UnitTestNetwork;
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!(shorten("t1abcdefghijklmnop"), "t1ab...mnop");
+        assert_eq!(shorten("short"), "short");
+    }
+
+    #[test]
+    fn test_redact_json() {
+        let config = LightClientConfig::create_unconnected(UnitTestNetwork, None);
+        let mut redactor = Redactor::new(&config);
+        redactor.add_memo("meet me at noon".to_string());
+
+        let v = object! {
+            "memo" => "meet me at noon",
+            "private_key" => "anything",
+            "note" => "as discussed, meet me at noon",
+            "amount" => 5,
+            "spent" => JsonValue::Null,
+        };
+        let r = redactor.redact_json(&v);
+        assert_eq!(r["memo"], REDACTED_MEMO);
+        assert_eq!(r["private_key"], REDACTED_KEY);
+        assert_eq!(r["note"], format!("as discussed, {}", REDACTED_MEMO));
+        assert_eq!(r["amount"], 5);
+        assert!(r["spent"].is_null());
+    }
+
+    #[test]
+    fn test_describe_failed_v1_send() {
+        // A transparent send: v1 header, no inputs, no outputs, lock time 0
+        let failed = FailedSend {
+            time: 1,
+            raw_tx: vec![0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            error: "rejected".to_string(),
+        };
+
+        let described = describe_failed_send(&failed, false);
+        assert!(described["decode_error"].is_null());
+        assert_eq!(described["decoded"]["header"], "0x00000001");
+        assert_eq!(described["decoded"]["shielded_outputs"], 0);
+        assert!(described["raw"].is_null());
+    }
+
+    #[test]
+    fn test_tar_layout() {
+        let files = vec![("a.txt".to_string(), b"hello".to_vec())];
+        let mut tar = vec![];
+        write_tar(&mut tar, &files, 0).unwrap();
+
+        // One header, one padded data block, two end blocks
+        assert_eq!(tar.len(), 512 * 4);
+        assert_eq!(&tar[..5], b"a.txt");
+        assert_eq!(&tar[124..136], b"00000000005\0");
+        assert_eq!(&tar[512..517], b"hello");
+
+        let stored = std::str::from_utf8(&tar[148..154]).unwrap();
+        let mut header = tar[..512].to_vec();
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|b| *b as u32).sum();
+        assert_eq!(u32::from_str_radix(stored, 8).unwrap(), sum);
+    }
+}
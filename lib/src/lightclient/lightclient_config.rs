@@ -26,6 +26,10 @@ use crate::{grpc_connector::GrpcConnector, lightclient::checkpoints};
 pub const DEFAULT_SERVER: &str = "https://lightd.btcz.rocks:9067";
 pub const WALLET_NAME: &str = "bitcoinz-wallet.dat";
 pub const LOGFILE_NAME: &str = "bitcoinz-wallet.debug.log";
+pub const HISTORY_NAME: &str = "bitcoinz-cli-history.txt";
+// Rolled over log files are named ROLLED_LOGFILE_PREFIX{0..LOG_WINDOW_SIZE}
+pub const ROLLED_LOGFILE_PREFIX: &str = "bitcoinz-wallet-log";
+pub const LOG_WINDOW_SIZE: u32 = 3;
 pub const DEFAULT_ANCHOR_OFFSET: u32 = 1;
 pub const MAX_REORG: usize = 100;
 pub const GAP_RULE_UNUSED_ADDRESSES: usize = if cfg!(any(target_os = "ios", target_os = "android")) {
@@ -167,9 +171,8 @@ impl<P: consensus::Parameters> LightClientConfig<P> {
 
     /// Build the Logging config
     pub fn get_log_config(&self) -> io::Result<Config> {
-        let window_size = 3; // log0, log1, log2
         let fixed_window_roller = FixedWindowRoller::builder()
-            .build("bitcoinz-wallet-log{}", window_size)
+            .build(&format!("{}{{}}", ROLLED_LOGFILE_PREFIX), LOG_WINDOW_SIZE)
             .unwrap();
         let size_limit = 5 * 1024 * 1024; // 5MB as max log file size to roll
         let size_trigger = SizeTrigger::new(size_limit);
@@ -302,6 +305,14 @@ impl<P: consensus::Parameters> LightClientConfig<P> {
         log_path.into_boxed_path()
     }
 
+    /// Where the interactive shell keeps its command history
+    pub fn get_history_path(&self) -> Box<Path> {
+        let mut history_path = self.get_zcash_data_path().into_path_buf();
+        history_path.push(HISTORY_NAME);
+
+        history_path.into_boxed_path()
+    }
+
     pub async fn get_initial_state(&self, height: u64) -> Option<(u64, String, String)> {
         if height <= self.sapling_activation_height {
             return None;
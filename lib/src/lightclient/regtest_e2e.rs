@@ -0,0 +1,342 @@
+/// End-to-end regtest harness
+///
+/// Drives a real bitcoinzd + lightwalletd pair (see docker/regtest) through a full
+/// t→z→t round trip using `BitcoinZShieldedBuilder`. Only compiled with the
+/// `regtest-e2e` feature, and each test is a no-op unless the environment points
+/// at a running node:
+///
+///   BTCZ_REGTEST_RPC_URL           e.g. http://127.0.0.1:18232
+///   BTCZ_REGTEST_RPC_USER          rpcuser from bitcoinz.conf
+///   BTCZ_REGTEST_RPC_PASSWORD      rpcpassword from bitcoinz.conf
+///   BTCZ_REGTEST_LIGHTWALLETD_URL  e.g. http://127.0.0.1:9067
+///
+/// Run with `make regtest-e2e`.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tempdir::TempDir;
+use tokio::time::sleep;
+use zcash_client_backend::encoding::{decode_payment_address, AddressCodec};
+use zcash_primitives::{
+    consensus::{BlockHeight, NetworkUpgrade, Parameters},
+    legacy::{Script, TransparentAddress},
+    memo::MemoBytes,
+    transaction::components::{amount::DEFAULT_FEE, Amount, TxOut},
+};
+use zcash_proofs::prover::LocalTxProver;
+
+use crate::bitcoinz_v4_shielded::BitcoinZShieldedBuilder;
+use crate::lightclient::{lightclient_config::LightClientConfig, LightClient};
+
+/// Blocks mined after every broadcast, so the wallet's anchor offset is satisfied
+const CONFIRMATIONS: u32 = 2;
+
+/// Amount the node sends to the wallet's t-address to start the round trip
+const FUNDING_ZATS: u64 = 10_0000_0000;
+
+// Marker struct for a bitcoinzd started with -regtest and the -nuparams in docker/regtest/bitcoinz.conf
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct RegtestNetwork;
+
+impl Parameters for RegtestNetwork {
+    fn activation_height(&self, nu: NetworkUpgrade) -> Option<BlockHeight> {
+        match nu {
+            NetworkUpgrade::Overwinter => Some(BlockHeight::from(1)),
+            NetworkUpgrade::Sapling => Some(BlockHeight::from(1)),
+            NetworkUpgrade::Blossom => None,
+            NetworkUpgrade::Heartwood => None,
+            NetworkUpgrade::Canopy => None,
+            NetworkUpgrade::Nu5 => None,
+            #[cfg(feature = "zfuture")]
+            NetworkUpgrade::ZFuture => None,
+        }
+    }
+
+    fn coin_type(&self) -> u32 {
+        1
+    }
+
+    fn hrp_sapling_extended_spending_key(&self) -> &str {
+        "secret-extended-key-regtest"
+    }
+
+    fn hrp_sapling_extended_full_viewing_key(&self) -> &str {
+        "zxviewregtestsapling"
+    }
+
+    fn hrp_sapling_payment_address(&self) -> &str {
+        "zregtestsapling"
+    }
+
+    fn b58_pubkey_address_prefix(&self) -> [u8; 2] {
+        [0x1d, 0x25]
+    }
+
+    fn b58_script_address_prefix(&self) -> [u8; 2] {
+        [0x1c, 0xba]
+    }
+
+    fn address_network(&self) -> Option<zcash_address::Network> {
+        Some(zcash_address::Network::Regtest)
+    }
+}
+
+/// Which leg of the round trip a transaction belongs to, so a rejection says exactly what failed
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Shield,
+    Unshield,
+}
+
+impl Stage {
+    fn describe(&self) -> &'static str {
+        match self {
+            Stage::Shield => "t→z (shield)",
+            Stage::Unshield => "z→t (unshield)",
+        }
+    }
+}
+
+/// Minimal JSON-RPC client for bitcoinzd
+struct NodeRpc {
+    host: String,
+    auth: String,
+}
+
+impl NodeRpc {
+    fn new(url: &str, user: &str, password: &str) -> Self {
+        let uri: http::Uri = url.parse().expect("BTCZ_REGTEST_RPC_URL is not a valid URL");
+        let host = format!("{}:{}", uri.host().unwrap(), uri.port_u16().unwrap_or(18232));
+
+        NodeRpc {
+            host,
+            auth: base64::encode(format!("{}:{}", user, password)),
+        }
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "regtest-e2e",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let mut stream = TcpStream::connect(&self.host).map_err(|e| format!("Couldn't connect to bitcoinzd: {}", e))?;
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.host,
+            self.auth,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+
+        let json_start = response
+            .find("\r\n\r\n")
+            .ok_or(format!("Malformed HTTP response from bitcoinzd: {}", response))?;
+        let reply: Value = serde_json::from_str(&response[json_start + 4..])
+            .map_err(|e| format!("Couldn't parse bitcoinzd reply to {}: {}", method, e))?;
+
+        if !reply["error"].is_null() {
+            return Err(format!("{} failed: {}", method, reply["error"]));
+        }
+
+        Ok(reply["result"].clone())
+    }
+
+    fn generate(&self, blocks: u32) {
+        self.call("generate", json!([blocks])).unwrap();
+    }
+
+    fn block_count(&self) -> u64 {
+        self.call("getblockcount", json!([])).unwrap().as_u64().unwrap()
+    }
+
+    /// Submit a raw transaction and panic with the node's reject reason, naming the stage that failed
+    fn send_raw(&self, stage: Stage, raw_tx: &[u8]) -> String {
+        match self.call("sendrawtransaction", json!([hex::encode(raw_tx)])) {
+            Ok(txid) => txid.as_str().unwrap().to_string(),
+            Err(e) => panic!(
+                "bitcoinzd rejected the {} transaction: {}\nraw tx: {}",
+                stage.describe(),
+                e,
+                hex::encode(raw_tx)
+            ),
+        }
+    }
+
+    /// Assert that `txid` made it into a block
+    fn assert_confirmed(&self, stage: Stage, txid: &str) {
+        let tx = self
+            .call("getrawtransaction", json!([txid, 1]))
+            .unwrap_or_else(|e| panic!("{} transaction {} is unknown to bitcoinzd: {}", stage.describe(), txid, e));
+        let confirmations = tx["confirmations"].as_u64().unwrap_or(0);
+        assert!(
+            confirmations > 0,
+            "{} transaction {} was accepted to the mempool but never mined",
+            stage.describe(),
+            txid
+        );
+    }
+}
+
+struct Harness {
+    node: NodeRpc,
+    lightwalletd: http::Uri,
+}
+
+impl Harness {
+    /// Returns None (and the test is skipped) unless every env var is set
+    fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        let (rpc_url, user, password, lwd) = match (
+            var("BTCZ_REGTEST_RPC_URL"),
+            var("BTCZ_REGTEST_RPC_USER"),
+            var("BTCZ_REGTEST_RPC_PASSWORD"),
+            var("BTCZ_REGTEST_LIGHTWALLETD_URL"),
+        ) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => {
+                println!("Skipping regtest e2e test: BTCZ_REGTEST_* environment variables are not set");
+                return None;
+            }
+        };
+
+        Some(Harness {
+            node: NodeRpc::new(&rpc_url, &user, &password),
+            lightwalletd: lwd.parse().expect("BTCZ_REGTEST_LIGHTWALLETD_URL is not a valid URL"),
+        })
+    }
+
+    /// Mine `blocks`, then wait for lightwalletd to ingest them and sync the wallet
+    async fn mine_and_sync(&self, lc: &LightClient<RegtestNetwork>, blocks: u32) {
+        self.node.generate(blocks);
+        let target = self.node.block_count();
+
+        for _ in 0..60 {
+            let latest = crate::grpc_connector::GrpcConnector::get_latest_block(self.lightwalletd.clone())
+                .await
+                .unwrap();
+            if latest.height >= target {
+                break;
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        lc.do_sync(false).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn t_to_z_to_t_round_trip() {
+    let h = match Harness::from_env() {
+        Some(h) => h,
+        None => return,
+    };
+
+    // Make sure the node wallet has mature coinbase to fund us with
+    if h.node.block_count() < 101 {
+        h.node.generate(101);
+    }
+
+    let dir = TempDir::new("regtest-e2e").unwrap();
+    let mut config = LightClientConfig::create_unconnected(RegtestNetwork, Some(dir.path().to_str().unwrap().to_string()));
+    config.server = h.lightwalletd.clone();
+
+    let lc = LightClient::test_new(&config, None, h.node.block_count()).await.unwrap();
+    let params = lc.config.get_params();
+
+    let taddr = lc.wallet.keys().read().await.get_all_taddrs()[0].clone();
+    let zaddr = lc.wallet.keys().read().await.get_all_zaddresses()[0].clone();
+    let ovk = lc.wallet.keys().read().await.get_all_extfvks()[0].fvk.ovk;
+
+    let (sapling_output, sapling_spend) = lc.read_sapling_params().unwrap();
+    let prover = LocalTxProver::from_bytes(&sapling_spend, &sapling_output);
+
+    // Step 1: fund the wallet t-address
+    let funding = format!("{:.8}", FUNDING_ZATS as f64 / 1e8);
+    h.node.call("sendtoaddress", json!([taddr, funding])).unwrap();
+    h.mine_and_sync(&lc, CONFIRMATIONS).await;
+    assert_eq!(lc.wallet.tbalance(None).await, FUNDING_ZATS);
+
+    // Step 2: shield everything to our own z-address
+    let shield_value = FUNDING_ZATS - u64::from(DEFAULT_FEE);
+    let shield_height = BlockHeight::from_u32(h.node.block_count() as u32 + 1);
+    let mut builder = BitcoinZShieldedBuilder::new_with_entropy(params, shield_height);
+
+    let sks = lc.wallet.keys().read().await.get_taddr_to_sk_map();
+    for utxo in lc.wallet.get_utxos().await {
+        let coin = TxOut {
+            value: Amount::from_u64(utxo.value).unwrap(),
+            script_pubkey: Script(utxo.script.clone()),
+        };
+        builder
+            .add_transparent_input(utxo.to_outpoint(), coin, *sks.get(&utxo.address).unwrap())
+            .unwrap();
+    }
+
+    let to = decode_payment_address(params.hrp_sapling_payment_address(), &zaddr)
+        .unwrap()
+        .unwrap();
+    builder
+        .add_sapling_output(Some(ovk), to, Amount::from_u64(shield_value).unwrap(), MemoBytes::empty())
+        .unwrap();
+
+    let raw_tx = builder
+        .build(&prover, DEFAULT_FEE)
+        .unwrap_or_else(|e| panic!("Couldn't build the {} transaction: {}", Stage::Shield.describe(), e))
+        .raw;
+    let shield_txid = h.node.send_raw(Stage::Shield, &raw_tx);
+    h.mine_and_sync(&lc, CONFIRMATIONS).await;
+    h.node.assert_confirmed(Stage::Shield, &shield_txid);
+
+    assert_eq!(lc.wallet.tbalance(None).await, 0);
+    assert_eq!(lc.wallet.verified_zbalance(None).await, shield_value);
+
+    // Step 3: unshield back to the t-address
+    let unshield_value = shield_value - u64::from(DEFAULT_FEE);
+    let unshield_height = BlockHeight::from_u32(h.node.block_count() as u32 + 1);
+    let mut builder = BitcoinZShieldedBuilder::new_with_entropy(params, unshield_height);
+
+    let notes = lc
+        .wallet
+        .select_sapling_notes(Amount::from_u64(shield_value).unwrap())
+        .await;
+    assert_eq!(notes.len(), 1, "Expected exactly the shielded note to be spendable");
+    for note in notes {
+        let path = note.witness.path().unwrap();
+        builder
+            .add_sapling_spend(
+                note.extsk.expsk.clone(),
+                note.diversifier,
+                note.note.clone(),
+                path.auth_path.iter().map(|(node, _)| *node).collect(),
+                path.position,
+            )
+            .unwrap();
+    }
+
+    let taddr_decoded = TransparentAddress::decode(&params, &taddr).unwrap();
+    builder
+        .add_transparent_output(taddr_decoded, Amount::from_u64(unshield_value).unwrap())
+        .unwrap();
+
+    let raw_tx = builder
+        .build(&prover, DEFAULT_FEE)
+        .unwrap_or_else(|e| panic!("Couldn't build the {} transaction: {}", Stage::Unshield.describe(), e))
+        .raw;
+    let unshield_txid = h.node.send_raw(Stage::Unshield, &raw_tx);
+    h.mine_and_sync(&lc, CONFIRMATIONS).await;
+    h.node.assert_confirmed(Stage::Unshield, &unshield_txid);
+
+    assert_eq!(lc.wallet.zbalance(None).await, 0);
+    assert_eq!(lc.wallet.tbalance(None).await, unshield_value);
+}
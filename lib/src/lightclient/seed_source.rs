@@ -0,0 +1,109 @@
+/// Non-interactive seed input
+///
+/// Headless deployments can't type the seed phrase at a prompt, so it can come
+/// from the command line, an environment variable or a file instead. Our copies
+/// of the phrase are wiped from memory when they're dropped.
+use log::warn;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+pub enum SeedSource {
+    /// The phrase itself
+    Literal(Zeroizing<String>),
+    /// Name of an environment variable holding the phrase
+    EnvVar(String),
+    /// A file holding the phrase. It must not be readable by other users.
+    File(PathBuf),
+}
+
+impl SeedSource {
+    /// Read the seed phrase, with surrounding whitespace removed
+    pub fn read(&self) -> io::Result<Zeroizing<String>> {
+        let phrase = match self {
+            SeedSource::Literal(phrase) => phrase.clone(),
+            SeedSource::EnvVar(name) => Zeroizing::new(std::env::var(name).map_err(|e| {
+                io::Error::new(ErrorKind::NotFound, format!("Couldn't read seed from ${}: {}", name, e))
+            })?),
+            SeedSource::File(path) => {
+                let mut file = File::open(path)?;
+                check_permissions(&file, path)?;
+
+                // Size the buffer up front, so no partial copies are left behind when it grows
+                let len = file.metadata()?.len() as usize;
+                let mut bytes = Zeroizing::new(Vec::with_capacity(len + 1));
+                file.read_to_end(&mut bytes)?;
+
+                let phrase = std::str::from_utf8(&bytes)
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("Seed file {:?} is not UTF-8", path)))?;
+                Zeroizing::new(phrase.to_string())
+            }
+        };
+
+        if phrase.trim().is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Seed phrase is empty"));
+        }
+
+        Ok(Zeroizing::new(phrase.trim().to_string()))
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(file: &File, path: &PathBuf) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = file.metadata()?.permissions().mode();
+    if mode & 0o004 != 0 {
+        let e = format!(
+            "Seed file {:?} is readable by all users (mode {:o}). Restrict it with 'chmod 600' and try again.",
+            path,
+            mode & 0o777
+        );
+        warn!("{}", e);
+        return Err(io::Error::new(ErrorKind::PermissionDenied, e));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_file: &File, _path: &PathBuf) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_world_readable_file_rejected() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("seed-source").unwrap();
+        let path = dir.path().join("seed.txt");
+        File::create(&path).unwrap().write_all(b"some seed words\n").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let e = SeedSource::File(path.clone()).read().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(SeedSource::File(path).read().unwrap().as_str(), "some seed words");
+    }
+
+    #[test]
+    fn test_env_var() {
+        std::env::set_var("BTCZ_TEST_SEED_SOURCE", "  words from env ");
+        assert_eq!(
+            SeedSource::EnvVar("BTCZ_TEST_SEED_SOURCE".to_string()).read().unwrap().as_str(),
+            "words from env"
+        );
+
+        let e = SeedSource::EnvVar("BTCZ_TEST_SEED_SOURCE_UNSET".to_string()).read().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+    }
+}
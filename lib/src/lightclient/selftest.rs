@@ -0,0 +1,126 @@
+/// Self test
+///
+/// Quick offline checks that the builders still sign what they should, for the
+/// diagnostics bundle. A transparent transaction is built at the wallet's height in the
+/// format a send would use, read back and its signature checked against the sighash, and
+/// a binding signature is made and checked the way the shielded builder does. Neither
+/// needs the server or the Sapling parameters.
+
+use ff::Field;
+use group::GroupEncoding;
+use json::{object, JsonValue};
+use rand::rngs::OsRng;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use zcash_primitives::{
+    consensus::{BlockHeight, Parameters},
+    constants::VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+    legacy::TransparentAddress,
+    sapling::{redjubjub::PrivateKey, ValueCommitment},
+    transaction::components::{Amount, OutPoint, TxOut},
+};
+
+use crate::bitcoinz_binding_sig_fix::{binding_verification_key, compute_bitcoinz_binding_message};
+use crate::bitcoinz_branch::{bitcoinz_consensus_branch_id, BRANCH_IDS_CONFIRMED};
+use crate::bitcoinz_parse::BitcoinZTransaction;
+use crate::bitcoinz_script_sig::{hash160, pubkey_hash_inputs};
+use crate::bitcoinz_transaction::compute_txid;
+use crate::bitcoinz_transparent_builder::{
+    build_transparent, input_sighash, select_format, FormatPolicy, TransparentParts,
+};
+use crate::bitcoinz_v4_shielded::verify_bitcoinz_binding_signature;
+
+/// Run every check for a transaction mined at `height`. `passed` is whether they all did.
+pub fn run_selftest<P: Parameters>(params: &P, height: BlockHeight) -> JsonValue {
+    let results = vec![
+        ("transparent_signature", check_transparent_signature(params, height)),
+        ("binding_signature", check_binding_signature()),
+    ];
+
+    let passed = results.iter().all(|(_, result)| result.is_ok());
+    let checks = results
+        .into_iter()
+        .map(|(name, result)| match result {
+            Ok(detail) => object! { "name" => name, "ok" => true, "detail" => detail },
+            Err(e) => object! { "name" => name, "ok" => false, "error" => e },
+        })
+        .collect::<Vec<_>>();
+
+    object! {
+        "height" => u32::from(height),
+        "branch_id" => format!("{:#010x}", bitcoinz_consensus_branch_id(params, height)),
+        "branch_ids_confirmed" => BRANCH_IDS_CONFIRMED,
+        "passed" => passed,
+        "checks" => checks,
+    }
+}
+
+/// Build a one input transparent transaction the way a send would at `height`, read it
+/// back, and check its signature against the input's sighash
+fn check_transparent_signature<P: Parameters>(params: &P, height: BlockHeight) -> Result<String, String> {
+    let secp = Secp256k1::new();
+    let key = SecretKey::from_slice(&[0x01u8; 32]).unwrap();
+    let pubkey = PublicKey::from_secret_key(&secp, &key);
+    let address = TransparentAddress::PublicKey(hash160(&pubkey.serialize()));
+    let coin = TxOut {
+        value: Amount::from_u64(100_000).unwrap(),
+        script_pubkey: address.script(),
+    };
+    let inputs = pubkey_hash_inputs(&[(OutPoint::new([0x11u8; 32], 0), coin.clone(), key)]);
+    let parts = TransparentParts::new(inputs, vec![(address, Amount::from_u64(90_000).unwrap())]);
+
+    let format = select_format(params, height, FormatPolicy::Current);
+    let built = build_transparent(params, height, format, &parts)?;
+    if compute_txid(&built.raw) != built.txid {
+        return Err("The txid doesn't hash the serialized transaction".to_string());
+    }
+
+    // The scriptSig is <signature || hash type> <public key>
+    let tx = BitcoinZTransaction::read(&built.raw).map_err(|e| format!("Couldn't read it back: {}", e))?;
+    let script_sig = &tx.inputs[0].script_sig;
+    let signature = script_sig
+        .get(1..*script_sig.first().unwrap_or(&0) as usize)
+        .and_then(|der| Signature::from_der(der).ok())
+        .ok_or("The scriptSig doesn't start with a signature")?;
+
+    let sighash = input_sighash(params, height, format, &parts, 0, &coin.script_pubkey, coin.value)?;
+    secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, &pubkey)
+        .map_err(|e| format!("The signature doesn't verify against the sighash: {}", e))?;
+
+    Ok(format!("{:?}, {} bytes", format, built.size))
+}
+
+/// Sign over bvk || sighash with bsk, as the prover does, and check it against bvk
+/// derived from the value commitments, as a node does
+fn check_binding_signature() -> Result<String, String> {
+    let mut rng = OsRng;
+    let rcv_spend = jubjub::Fr::random(&mut rng);
+    let rcv_output = jubjub::Fr::random(&mut rng);
+    let cv = |value, randomness| -> jubjub::ExtendedPoint { ValueCommitment { value, randomness }.commitment().into() };
+
+    let bsk = PrivateKey(rcv_spend - rcv_output);
+    let bvk = binding_verification_key(&[cv(100_000, rcv_spend)], &[cv(60_000, rcv_output)], 40_000);
+    let sighash = [0x42u8; 32];
+    let message = compute_bitcoinz_binding_message(&bvk.0.to_bytes(), &sighash);
+    let signature = bsk.sign(&message, &mut rng, VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+    if !verify_bitcoinz_binding_signature(&bvk, &sighash, &signature) {
+        return Err("A binding signature doesn't verify against bvk from the value commitments".to_string());
+    }
+
+    Ok("bvk from the value commitments verifies bsk's signature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BITCOINZ_MAINNET;
+
+    #[test]
+    fn test_selftest_passes() {
+        // Before Overwinter and after Sapling, so both the v1 and v4 formats are signed
+        for height in [100, 500_000] {
+            let result = run_selftest(&BITCOINZ_MAINNET, BlockHeight::from_u32(height));
+            assert_eq!(result["passed"], true, "{}", result.pretty(2));
+            assert_eq!(result["checks"].len(), 2);
+        }
+    }
+}
@@ -146,6 +146,7 @@ pub struct TestServerData<P> {
     pub config: LightClientConfig<P>,
     pub zec_price: f64,
     pub tree_states: Vec<(u64, String, String)>,
+    pub utxos: Vec<GetAddressUtxosReply>,
 }
 
 impl<P: consensus::Parameters> TestServerData<P> {
@@ -157,6 +158,7 @@ impl<P: consensus::Parameters> TestServerData<P> {
             config,
             zec_price: 140.5,
             tree_states: vec![],
+            utxos: vec![],
         };
 
         data
@@ -462,9 +464,26 @@ impl<P: consensus::Parameters + Send + Sync + 'static> CompactTxStreamer for Tes
 
     async fn get_address_utxos(
         &self,
-        _request: Request<GetAddressUtxosArg>,
+        request: Request<GetAddressUtxosArg>,
     ) -> Result<Response<GetAddressUtxosReplyList>, Status> {
-        todo!()
+        Self::wait_random().await;
+
+        let arg = request.into_inner();
+        let mut address_utxos = self
+            .data
+            .read()
+            .await
+            .utxos
+            .iter()
+            .filter(|utxo| arg.addresses.contains(&utxo.address) && utxo.height >= arg.start_height)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if arg.max_entries > 0 {
+            address_utxos.truncate(arg.max_entries as usize);
+        }
+
+        Ok(Response::new(GetAddressUtxosReplyList { address_utxos }))
     }
 
     type GetAddressUtxosStreamStream = Pin<Box<dyn Stream<Item = Result<GetAddressUtxosReply, Status>> + Send + Sync>>;
@@ -1,5 +1,7 @@
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use ff::{Field, PrimeField};
 use group::GroupEncoding;
@@ -26,19 +28,26 @@ use zcash_primitives::transaction::components::{sapling, Amount};
 use zcash_primitives::sapling::Node;
 use zcash_primitives::sapling::{Note, Rseed, ValueCommitment};
 use zcash_primitives::transaction::components::amount::DEFAULT_FEE;
-use zcash_primitives::transaction::components::{OutputDescription, GROTH_PROOF_SIZE};
+use zcash_primitives::transaction::components::{OutPoint, OutputDescription, GROTH_PROOF_SIZE};
 use zcash_primitives::transaction::{Transaction, TransactionData};
 use zcash_primitives::zip32::{ExtendedFullViewingKey, ExtendedSpendingKey};
 
 use crate::blaze::fetch_full_tx::FetchFullTxns;
+use crate::bitcoinz_parse::BitcoinZTransaction;
+use crate::bitcoinz_txid::WireTxid;
 use crate::blaze::test_utils::{FakeCompactBlockList, FakeTransaction};
+use crate::grpc_connector::GrpcConnector;
 use crate::compact_formats::compact_tx_streamer_client::CompactTxStreamerClient;
 
-use crate::compact_formats::{CompactSaplingOutput, CompactTx, Empty};
+use crate::compact_formats::{CompactSaplingOutput, CompactTx, Empty, GetAddressUtxosReply};
+use crate::lightclient::diagnostics::shorten;
 use crate::lightclient::faketx::new_transactiondata;
+use crate::lightclient::seed_source::SeedSource;
 use crate::lightclient::test_server::{create_test_server, mine_pending_blocks, mine_random_blocks};
 use crate::lightclient::LightClient;
-use crate::lightwallet::data::WalletTx;
+use crate::lightwallet::data::{Compatibility, WalletTx};
+use crate::lightwallet::wallettkey::WalletTKey;
+use crate::lightwallet::{LightWallet, SendOptions};
 
 use super::checkpoints;
 use super::lightclient_config::{LightClientConfig, UnitTestNetwork};
@@ -55,7 +64,7 @@ fn new_wallet_from_phrase() {
         .to_string();
 
     let config = LightClientConfig::create_unconnected(UnitTestNetwork, Some(data_dir));
-    let lc = LightClient::new_from_phrase(TEST_SEED.to_string(), &config, 0, false).unwrap();
+    let lc = LightClient::new_from_phrase(TEST_SEED, &config, 0, false).unwrap();
 
     // The first t address and z address should be derived
     Runtime::new().unwrap().block_on(async move {
@@ -73,6 +82,37 @@ fn new_wallet_from_phrase() {
     });
 }
 
+#[cfg(unix)]
+#[test]
+fn new_wallet_from_seed_file() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new("test").unwrap();
+    let seed_path = temp_dir.path().join("seed.txt");
+    let mut seed_file = fs::File::create(&seed_path).unwrap();
+    seed_file.write_all(format!("{}\n", TEST_SEED).as_bytes()).unwrap();
+    fs::set_permissions(&seed_path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let data_dir = temp_dir.path().join("wallet");
+    fs::create_dir(&data_dir).unwrap();
+    let config = LightClientConfig::create_unconnected(UnitTestNetwork, Some(data_dir.to_str().unwrap().to_string()));
+    let lc = LightClient::from_seed_source(SeedSource::File(seed_path), &config, 0, false).unwrap();
+
+    Runtime::new().unwrap().block_on(async move {
+        let addresses = lc.do_address().await;
+
+        assert_eq!(
+            "zs1q6xk3q783t5k92kjqt2rkuuww8pdw2euzy5rk6jytw97enx8fhpazdv3th4xe7vsk6e9sfpawfg".to_string(),
+            addresses["z_addresses"][0]
+        );
+        assert_eq!(
+            "t1eQ63fwkQ4n4Eo5uCrPGaAV8FWB2tmx7ui".to_string(),
+            addresses["t_addresses"][0]
+        );
+    });
+}
+
 #[test]
 fn new_wallet_from_sk() {
     let temp_dir = TempDir::new("test").unwrap();
@@ -86,7 +126,7 @@ fn new_wallet_from_sk() {
 
     let config = LightClientConfig::create_unconnected(UnitTestNetwork, Some(data_dir));
     let sk = "secret-extended-key-main1qvpa0qr8qqqqpqxn4l054nzxpxzp3a8r2djc7sekdek5upce8mc2j2z0arzps4zv940qeg706hd0wq6g5snzvhp332y6vhwyukdn8dhekmmsk7fzvzkqm6ypc99uy63tpesqwxhpre78v06cx8k5xpp9mrhtgqs5dvp68cqx2yrvthflmm2ynl8c0506dekul0f6jkcdmh0292lpphrksyc5z3pxwws97zd5els3l2mjt2s7hntap27mlmt6w0drtfmz36vz8pgu7ec0twfrq";
-    let lc = LightClient::new_from_phrase(sk.to_string(), &config, 0, false).unwrap();
+    let lc = LightClient::new_from_phrase(sk, &config, 0, false).unwrap();
     Runtime::new().unwrap().block_on(async move {
         let addresses = lc.do_address().await;
         assert_eq!(addresses["z_addresses"].len(), 1);
@@ -121,7 +161,7 @@ fn new_wallet_from_vk() {
 
     let config = LightClientConfig::create_unconnected(UnitTestNetwork, Some(data_dir));
     let vk = "zxviews1qvpa0qr8qqqqpqxn4l054nzxpxzp3a8r2djc7sekdek5upce8mc2j2z0arzps4zv9kdvg28gjzvxd47ant6jn4svln5psw3htx93cq93ahw4e7lptrtlq7he5r6p6rcm3s0z6l24ype84sgqfrmghu449htrjspfv6qg2zfx2yrvthflmm2ynl8c0506dekul0f6jkcdmh0292lpphrksyc5z3pxwws97zd5els3l2mjt2s7hntap27mlmt6w0drtfmz36vz8pgu7ecrxzsls";
-    let lc = LightClient::new_from_phrase(vk.to_string(), &config, 0, false).unwrap();
+    let lc = LightClient::new_from_phrase(vk, &config, 0, false).unwrap();
 
     Runtime::new().unwrap().block_on(async move {
         let addresses = lc.do_address().await;
@@ -626,7 +666,7 @@ async fn z_incoming_viewkey() {
     let iaddr = encode_payment_address(config.hrp_sapling_address(), &iextfvk.default_address().1);
     let addrs = lc
         .do_import_vk(
-            encode_extended_full_viewing_key(config.hrp_sapling_viewing_key(), &iextfvk),
+            &encode_extended_full_viewing_key(config.hrp_sapling_viewing_key(), &iextfvk),
             1,
         )
         .await
@@ -661,7 +701,7 @@ async fn z_incoming_viewkey() {
     // 5. Import the corresponding spending key.
     let sk_addr = lc
         .do_import_sk(
-            encode_extended_spending_key(config.hrp_sapling_private_key(), &iextsk),
+            &encode_extended_spending_key(config.hrp_sapling_private_key(), &iextsk),
             1,
         )
         .await
@@ -803,6 +843,153 @@ async fn t_incoming_t_outgoing() {
     h1.await.unwrap();
 }
 
+#[tokio::test]
+async fn sent_txns_are_tagged_with_compatibility() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks, and get some transparent funds
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let sk = lc.wallet.keys().read().await.tkeys[0].clone();
+    let pk = sk.pubkey().unwrap();
+    let mut ftx = FakeTransaction::new();
+    ftx.add_t_output(&pk, sk.address, 100_000);
+    fcbl.add_ftx(ftx);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+
+    // 2. A transparent send is confirmed compatible
+    let t_txid = lc.test_do_send(vec![(EXT_TADDR, 20_000, None)]).await.unwrap();
+    fcbl.add_pending_sends(&data).await;
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+
+    // 3. Get some shielded funds, and wait for them to be spendable
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    fcbl.add_tx_paying(&extfvk1, 100_000);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 5).await;
+
+    // 4. A shielded send is still experimental
+    let z_txid = lc.test_do_send(vec![(EXT_ZADDR, 2_000, None)]).await.unwrap();
+
+    {
+        let txns = lc.wallet.txns.read().await;
+        let compatibility = |txid: &str| {
+            txns.current
+                .values()
+                .find(|wtx| wtx.txid.to_string() == txid)
+                .unwrap()
+                .compatibility
+        };
+        assert_eq!(compatibility(&t_txid), Some(Compatibility::Confirmed));
+        assert_eq!(compatibility(&z_txid), Some(Compatibility::Experimental));
+
+        // Received txns aren't tagged
+        assert_eq!(txns.current.values().filter(|wtx| wtx.compatibility.is_some()).count(), 2);
+    }
+
+    // 5. History can be filtered on the tag
+    let list = lc.do_list_transactions_filtered(false, Some(Compatibility::Confirmed)).await;
+    assert!(list.len() > 0);
+    assert!(list.members().all(|jv| jv["txid"] == t_txid));
+    assert!(list.members().any(|jv| jv["compatibility"] == "confirmed"));
+
+    let list = lc.do_list_transactions_filtered(false, Some(Compatibility::Experimental)).await;
+    assert!(list.len() > 0);
+    assert!(list.members().all(|jv| jv["txid"] == z_txid));
+    assert!(list.members().any(|jv| jv["compatibility"] == "experimental"));
+
+    assert!(lc.do_list_transactions(false).await.len() > 2);
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn remove_imported_keys() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    // 2. Import a spending key and a transparent key
+    let iextsk = ExtendedSpendingKey::master(&[1u8; 32]);
+    let iextfvk = ExtendedFullViewingKey::from(&iextsk);
+    let iaddr = lc
+        .do_import_sk(&encode_extended_spending_key(config.hrp_sapling_private_key(), &iextsk), 1)
+        .await
+        .unwrap()[0]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let isk = secp256k1::SecretKey::from_slice(&[2u8; 32]).unwrap();
+    let ipk = secp256k1::PublicKey::from_secret_key(&secp256k1::Secp256k1::new(), &isk);
+    let itaddr = WalletTKey::address_from_prefix_sk(&config.base58_pubkey_address(), &isk);
+    let wif = WalletTKey::from_raw(&isk, &itaddr, 0).sk_as_string(&config).unwrap();
+    assert_eq!(lc.do_import_tk(&wif).await.unwrap()[0], itaddr);
+
+    // 3. Fund both imported keys, and the wallet's own taddr
+    let zvalue = 100_000;
+    fcbl.add_tx_paying(&iextfvk, zvalue);
+
+    let tvalue = 40_000;
+    let mut ftx = FakeTransaction::new();
+    ftx.add_t_output(&ipk, itaddr.clone(), tvalue);
+    fcbl.add_ftx(ftx);
+
+    let hd_tkey = lc.wallet.keys().read().await.tkeys[0].clone();
+    let hd_value = 25_000;
+    let mut ftx = FakeTransaction::new();
+    ftx.add_t_output(&hd_tkey.pubkey().unwrap(), hd_tkey.address.clone(), hd_value);
+    fcbl.add_ftx(ftx);
+
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+
+    assert_eq!(lc.do_balance().await["zbalance"].as_u64().unwrap(), zvalue);
+    assert_eq!(lc.do_balance().await["tbalance"].as_u64().unwrap(), tvalue + hd_value);
+    assert_eq!(lc.do_list_transactions(false).await.len(), 3);
+
+    // 4. Seed-derived keys can't be removed
+    assert!(lc.do_remove_key(hd_tkey.address.clone()).await.is_err());
+    assert!(lc.do_remove_key(EXT_TADDR.to_string()).await.is_err());
+
+    // 5. Remove the imported keys. Their funds and txns are gone, the wallet's own aren't.
+    lc.do_remove_key(iaddr.clone()).await.unwrap();
+    assert_eq!(lc.do_balance().await["zbalance"].as_u64().unwrap(), 0);
+    assert_eq!(lc.do_list_notes(true).await["unspent_notes"].len(), 0);
+
+    lc.do_remove_key(itaddr.clone()).await.unwrap();
+    assert_eq!(lc.do_balance().await["tbalance"].as_u64().unwrap(), hd_value);
+
+    let utxos = lc.do_list_notes(true).await["utxos"].clone();
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0]["address"], hd_tkey.address);
+
+    let list = lc.do_list_transactions(false).await;
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0]["address"], hd_tkey.address);
+
+    // 6. The keys themselves are gone too
+    let addresses = lc.do_address().await;
+    assert!(!addresses["z_addresses"].members().any(|a| *a == iaddr));
+    assert!(!addresses["t_addresses"].members().any(|a| *a == itaddr));
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
 #[tokio::test]
 async fn mixed_txn() {
     let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
@@ -1075,6 +1262,201 @@ async fn no_change() {
     h1.await.unwrap();
 }
 
+#[tokio::test]
+async fn build_only_send() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks, then receive a note and let it confirm
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    let value = 100_000;
+    let (tx, _height, _) = fcbl.add_tx_paying(&extfvk1, value);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 5).await;
+
+    // 2. Build the send without broadcasting it
+    let sent_value = 2000;
+    let result = lc
+        .test_do_send_build_only(vec![(EXT_ZADDR, sent_value, None)])
+        .await
+        .unwrap();
+    let sent_txid = result["txid"].as_str().unwrap().to_string();
+
+    // The hex is the signed transaction, and hashes to the txid
+    let raw = hex::decode(result["hex"].as_str().unwrap()).unwrap();
+    assert_eq!(crate::bitcoinz_transaction::compute_txid(&raw).to_string(), sent_txid);
+
+    // 3. Nothing reached the server
+    assert_eq!(data.read().await.sent_txns.len(), 0);
+
+    // 4. But the note is held by the pending spend, so it can't be spent again
+    let notes = lc.do_list_notes(true).await;
+    assert_eq!(notes["pending_notes"].len(), 1);
+    assert_eq!(notes["pending_notes"][0]["created_in_txid"], tx.txid().to_string());
+    assert_eq!(notes["pending_notes"][0]["unconfirmed_spent"], sent_txid);
+
+    assert!(lc.test_do_send(vec![(EXT_ZADDR, sent_value, None)]).await.is_err());
+    assert_eq!(data.read().await.sent_txns.len(), 0);
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn shielded_send_needs_force() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks, then receive a note and let it confirm
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    fcbl.add_tx_paying(&extfvk1, 100_000);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 5).await;
+
+    // 2. Without forcing it, a shielded send isn't built while the branch IDs are unconfirmed
+    let tos = vec![(EXT_ZADDR, 2_000, None)];
+    if !crate::bitcoinz_branch::BRANCH_IDS_CONFIRMED {
+        let err = lc.test_do_send_with(tos.clone(), SendOptions::default()).await.unwrap_err();
+        assert!(err.contains("--force-experimental"), "{}", err);
+        assert_eq!(data.read().await.sent_txns.len(), 0);
+        assert_eq!(lc.do_list_notes(true).await["pending_notes"].len(), 0);
+    }
+
+    // 3. Forced, it's built and sent, expiring like a transparent send would
+    let options = SendOptions {
+        force_experimental: true,
+        expiry_offset: 20,
+    };
+    lc.test_do_send_with(tos, options).await.unwrap();
+    let sent = data.read().await.sent_txns.clone();
+    assert_eq!(sent.len(), 1);
+    let target_height = lc.wallet.last_scanned_height().await as u32 + 1;
+    assert_eq!(
+        crate::bitcoinz_parse::parse_bitcoinz_v4(&sent[0].data).unwrap().expiry_height,
+        target_height + 20
+    );
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn oversized_send_is_split() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks, and get a thousand small transparent coins
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let sk = lc.wallet.keys().read().await.tkeys[0].clone();
+    let pk = sk.pubkey().unwrap();
+    let mut ftx = FakeTransaction::new();
+    for _ in 0..1_000 {
+        ftx.add_t_output(&pk, sk.address.clone(), 10_000);
+    }
+    fcbl.add_ftx(ftx);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+
+    // 2. Paying with hundreds of them is too big for one transaction, so it's sent as two
+    let txids = lc
+        .test_do_send_with(vec![(EXT_TADDR, 8_000_000, None)], SendOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(txids.len(), 2);
+    let sent = data.read().await.sent_txns.clone();
+    assert_eq!(sent.len(), 2);
+    assert!(sent
+        .iter()
+        .all(|tx| tx.data.len() <= crate::bitcoinz_transaction::MAX_STANDARD_TX_SIZE));
+
+    // 3. The first sweeps coins into one the second spends
+    let swept = WireTxid::from(crate::bitcoinz_transaction::compute_txid(&sent[0].data));
+    let first = BitcoinZTransaction::read(&sent[0].data).unwrap();
+    let second = BitcoinZTransaction::read(&sent[1].data).unwrap();
+    assert_eq!(first.outputs.len(), 1);
+    assert!(second
+        .inputs
+        .iter()
+        .any(|input| (input.prevout_hash, input.prevout_index) == (swept, 0)));
+
+    // 4. Each coin is held by the transaction that spends it
+    {
+        let txns = lc.wallet.txns.read().await;
+        let held_by = |txid: &str| {
+            txns.current
+                .values()
+                .flat_map(|wtx| wtx.utxos.iter())
+                .filter(|utxo| matches!(utxo.unconfirmed_spent, Some((spent, _)) if spent.to_string() == txid))
+                .count()
+        };
+        assert_eq!(held_by(&txids[0]), first.inputs.len());
+        assert!(held_by(&txids[1]) >= second.inputs.len() - 1);
+    }
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn spend_anchor_checked_against_server() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // Nothing to spend, so nothing to check
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+    assert!(lc.wallet.sapling_spend_anchor().await.is_none());
+    lc.verify_spend_anchor().await.unwrap();
+
+    // 1. Receive a note and let it confirm
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    fcbl.add_tx_paying(&extfvk1, 100_000);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 5).await;
+
+    // 2. The anchor is on the server's main chain
+    let (height, _, _) = lc.wallet.sapling_spend_anchor().await.unwrap();
+    lc.verify_spend_anchor().await.unwrap();
+
+    // 3. The server reorgs the anchor's block away
+    data.write()
+        .await
+        .blocks
+        .iter_mut()
+        .find(|b| b.height == height)
+        .unwrap()
+        .hash = [0xaau8; 32].to_vec();
+
+    let err = lc.verify_spend_anchor().await.unwrap_err();
+    assert!(err.contains("no longer on the server's main chain"), "{}", err);
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
 #[tokio::test]
 async fn recover_at_checkpoint() {
     // 1. Wait for test server to start
@@ -1268,6 +1650,148 @@ async fn witness_clearing() {
     h1.await.unwrap();
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn deferred_witness_load() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks, then a spendable incoming note
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    let value = 100_000;
+    let (tx, _height, _) = fcbl.add_tx_paying(&extfvk1, value);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 5).await;
+
+    let (expected_len, expected_root) = {
+        let txns = lc.wallet.txns().read().await;
+        let witnesses = &txns.current.get(&tx.txid()).unwrap().s_notes[0].witnesses;
+        (witnesses.len(), witnesses.last().unwrap().root())
+    };
+    assert!(expected_len > 1);
+
+    // 2. Save and re-open the wallet, with witness parsing made slow
+    let mut buf = vec![];
+    lc.wallet.write(&mut buf).await.unwrap();
+
+    let wallet = Arc::new(LightWallet::read(&buf[..], &config).await.unwrap());
+    assert!(!wallet.witnesses_loaded());
+    assert!(wallet.load_stats().await.eager_load.is_some());
+    assert!(wallet.load_stats().await.witness_load.is_none());
+
+    let w = wallet.clone();
+    let loader = tokio::spawn(async move {
+        w.load_witnesses(|| std::thread::sleep(Duration::from_millis(2_000))).await
+    });
+
+    // 3. Balances answer while the witnesses are still being parsed
+    let zbalance = tokio::time::timeout(Duration::from_millis(500), wallet.zbalance(None))
+        .await
+        .expect("zbalance waited on the witness load");
+    let spendable = tokio::time::timeout(Duration::from_millis(500), wallet.spendable_zbalance(None))
+        .await
+        .expect("spendable_zbalance waited on the witness load");
+    assert_eq!(zbalance, value);
+    assert_eq!(spendable, value);
+    assert!(!wallet.witnesses_loaded());
+
+    loader.await.unwrap();
+    assert!(wallet.witnesses_loaded());
+    assert!(wallet.load_stats().await.witness_load.unwrap() >= Duration::from_millis(2_000));
+
+    // 4. Shielded spends see the full witness set
+    {
+        let txns = wallet.txns().read().await;
+        assert_eq!(txns.current.get(&tx.txid()).unwrap().s_notes[0].witnesses.len(), expected_len);
+    }
+    let notes = wallet.select_sapling_notes(Amount::from_u64(value).unwrap()).await;
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].witness.root(), expected_root);
+
+    // 5. A wallet whose witnesses were never parsed writes back the same bytes
+    let reopened = LightWallet::read(&buf[..], &config).await.unwrap();
+    let mut buf2 = vec![];
+    reopened.write(&mut buf2).await.unwrap();
+    assert!(!reopened.witnesses_loaded());
+    assert_eq!(buf, buf2);
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn diagnostics_bundle_is_redacted() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, Some(TEST_SEED.to_string()), 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    // 1. Everything in the fixture wallet that must not leak
+    let zaddr = lc.wallet.keys().read().await.get_all_zaddresses()[0].clone();
+    let taddr = lc.wallet.keys().read().await.get_all_taddrs()[0].clone();
+    let mut secrets = vec![TEST_SEED.to_string(), zaddr.clone(), taddr.clone()];
+    for k in lc.do_export(None).await.unwrap().members() {
+        secrets.push(k["private_key"].as_str().unwrap().to_string());
+        if let Some(vk) = k["viewing_key"].as_str() {
+            secrets.push(vk.to_string());
+        }
+    }
+    assert!(secrets.len() >= 5);
+
+    // 2. A log and a failed send that mention them
+    fs::write(
+        config.get_log_path(),
+        format!(
+            "INFO::Sending from {} to {}\nINFO::Keys {} {}\nINFO::Seed {}\n",
+            taddr, zaddr, secrets[3], secrets[4], TEST_SEED
+        ),
+    )
+    .unwrap();
+    lc.wallet
+        .record_failed_send(&[0x04, 0x00, 0x00, 0x80], &format!("Rejected spend from {}", taddr))
+        .await;
+
+    // 3. And a send that's still waiting to be mined
+    let sk = lc.wallet.keys().read().await.tkeys[0].clone();
+    let mut ftx = FakeTransaction::new();
+    ftx.add_t_output(&sk.pubkey().unwrap(), sk.address.clone(), 100_000);
+    fcbl.add_ftx(ftx);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    let pending_txid = lc.test_do_send(vec![(EXT_TADDR, 20_000, None)]).await.unwrap();
+
+    // 4. Write the bundle and scan it
+    let path = Path::new(config.data_dir.as_ref().unwrap()).join("diagnostics.tar");
+    let result = lc.do_diagnostics(path.to_str().unwrap(), false).await.unwrap();
+    assert!(result["files"].members().any(|f| *f == "logs/bitcoinz-wallet.debug.log"));
+    assert!(result["files"].members().any(|f| *f == "send.json"));
+    assert!(result["files"].members().any(|f| *f == "selftest.json"));
+    assert_eq!(result["not_included"].len(), 1);
+
+    let bundle = String::from_utf8_lossy(&fs::read(&path).unwrap()).to_string();
+    for secret in &secrets {
+        assert!(!bundle.contains(secret.as_str()), "Bundle contains {}", secret);
+    }
+    assert!(bundle.contains(&shorten(&zaddr)));
+    assert!(bundle.contains(&shorten(&taddr)));
+    assert!(bundle.contains("Rejected spend from"));
+    assert!(!bundle.contains("\"raw\""));
+    assert!(bundle.contains("\"pending_transactions\""));
+    assert!(bundle.contains(&pending_txid));
+    assert!(bundle.contains("\"passed\": true"));
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
 #[tokio::test]
 async fn mempool_clearing() {
     let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
@@ -1435,6 +1959,103 @@ async fn mempool_and_balance() {
     h1.await.unwrap();
 }
 
+#[tokio::test]
+async fn rebuild_spent_index() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+
+    ready_rx.await.unwrap();
+
+    let lc = LightClient::test_new(&config, None, 0).await.unwrap();
+    let mut fcbl = FakeCompactBlockList::new(0);
+
+    // 1. Mine 10 blocks
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    // 2. Receive a note, and spend part of it once it's verified
+    let extfvk1 = lc.wallet.keys().read().await.get_all_extfvks()[0].clone();
+    let value = 100_000;
+    let (_tx, _height, _) = fcbl.add_tx_paying(&extfvk1, value);
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let sent_value = 2000;
+    let sent_txid = lc.test_do_send(vec![(EXT_ZADDR, sent_value, None)]).await.unwrap();
+    fcbl.add_pending_sends(&data).await;
+    mine_pending_blocks(&mut fcbl, &data, &lc).await;
+    mine_random_blocks(&mut fcbl, &data, &lc, 10).await;
+
+    let change = value - (sent_value + u64::from(DEFAULT_FEE));
+    let bal = lc.do_balance().await;
+    assert_eq!(bal["zbalance"].as_u64().unwrap(), change);
+    assert_eq!(bal["spendable_zbalance"].as_u64().unwrap(), change);
+
+    // 3. Corrupt the spent index: the spent note looks unspent, and the change looks spent
+    {
+        let mut txns = lc.wallet.txns.write().await;
+        for nd in txns.current.values_mut().flat_map(|wtx| wtx.s_notes.iter_mut()) {
+            nd.spent = if nd.note.value == value {
+                None
+            } else {
+                Some((zcash_primitives::transaction::TxId::from_bytes([9u8; 32]), 5))
+            };
+        }
+    }
+    let bal = lc.do_balance().await;
+    assert_eq!(bal["zbalance"].as_u64().unwrap(), value);
+
+    // 4. Rebuilding puts it back, without any new blocks
+    let result = lc.do_rebuild_spent_index().await.unwrap();
+    assert_eq!(result["notes_changed"].as_usize().unwrap(), 2);
+
+    let bal = lc.do_balance().await;
+    assert_eq!(bal["zbalance"].as_u64().unwrap(), change);
+    assert_eq!(bal["spendable_zbalance"].as_u64().unwrap(), change);
+
+    let notes = lc.do_list_notes(true).await;
+    assert_eq!(notes["spent_notes"].len(), 1);
+    assert_eq!(notes["spent_notes"][0]["spent"], sent_txid);
+
+    // A second rebuild has nothing left to fix
+    let result = lc.do_rebuild_spent_index().await.unwrap();
+    assert_eq!(result["notes_changed"].as_usize().unwrap(), 0);
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
+#[tokio::test]
+async fn outpoint_spent_from_address_utxos() {
+    let (data, config, ready_rx, stop_tx, h1) = create_test_server(UnitTestNetwork).await;
+    ready_rx.await.unwrap();
+
+    // The server only knows about output 0 of the first transaction as unspent
+    data.write().await.utxos.push(GetAddressUtxosReply {
+        address: EXT_TADDR.to_string(),
+        txid: vec![1u8; 32],
+        index: 0,
+        script: vec![],
+        value_zat: 100_000,
+        height: 5,
+    });
+
+    let is_spent = |outpoint: OutPoint| GrpcConnector::is_outpoint_spent(config.server.clone(), EXT_TADDR.to_string(), &outpoint);
+
+    assert_eq!(is_spent(OutPoint::new([1u8; 32], 0)).await, Ok(false));
+    assert_eq!(is_spent(OutPoint::new([1u8; 32], 1)).await, Ok(true));
+    assert_eq!(is_spent(OutPoint::new([2u8; 32], 0)).await, Ok(true));
+
+    // Unspent outputs of another address don't count
+    assert_eq!(
+        GrpcConnector::is_outpoint_spent(config.server.clone(), "t1other".to_string(), &OutPoint::new([1u8; 32], 0)).await,
+        Ok(true)
+    );
+
+    // Shutdown everything cleanly
+    stop_tx.send(true).unwrap();
+    h1.await.unwrap();
+}
+
 pub const EXT_TADDR: &str = "t1NoS6ZgaUTpmjkge2cVpXGcySasdYDrXqh";
 pub const EXT_ZADDR: &str = "zs1va5902apnzlhdu0pw9r9q7ca8s4vnsrp2alr6xndt69jnepn2v2qrj9vg3wfcnjyks5pg65g9dc";
 pub const EXT_ZADDR2: &str = "zs1fxgluwznkzm52ux7jkf4st5znwzqay8zyz4cydnyegt2rh9uhr9458z0nk62fdsssx0cqhy6lyv";
@@ -1,6 +1,6 @@
 use crate::compact_formats::TreeState;
 use crate::lightwallet::data::WalletTx;
-use crate::lightwallet::wallettkey::WalletTKey;
+use crate::lightwallet::wallettkey::{WalletTKey, WalletTKeyType};
 use crate::{
     blaze::fetch_full_tx::FetchFullTxns,
     lightclient::lightclient_config::LightClientConfig,
@@ -33,15 +33,14 @@ use incrementalmerkletree::Hashable;
 use log::{error, info, warn};
 
 use orchard::Anchor;
-use std::sync::mpsc;
 use std::{
     cmp,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io::{Error, ErrorKind, Read, Write},
     sync::{atomic::AtomicU64, Arc},
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::sync::RwLock;
+use tokio::sync::{OnceCell, RwLock};
 use zcash_address::unified::Receiver;
 use zcash_address::unified::{Address as UnifiedAddress, Encoding};
 use zcash_client_backend::{
@@ -50,16 +49,15 @@ use zcash_client_backend::{
 };
 
 use zcash_primitives::consensus::{self, BranchId};
-use zcash_primitives::memo::MemoBytes;
 use zcash_primitives::merkle_tree::incremental::{read_bridge, read_leu64_usize, write_bridge, write_usize_leu64};
 use zcash_primitives::merkle_tree::HashSer;
 use zcash_primitives::sapling::prover::TxProver;
 use zcash_primitives::{
     legacy::{Script, TransparentAddress},
     memo::Memo,
+    sapling::Node,
     transaction::{
-        builder::Builder,
-        components::{amount::DEFAULT_FEE, OutPoint, TxOut},
+        components::{amount::DEFAULT_FEE, TxOut},
         Transaction,
     },
     zip32::ExtendedFullViewingKey,
@@ -67,18 +65,20 @@ use zcash_primitives::{
 
 use self::data::SpendableOrchardNote;
 use self::{
-    data::{BlockData, SaplingNoteData, Utxo, WalletZecPriceInfo},
+    data::{BlockData, Compatibility, SaplingNoteData, Utxo, WalletZecPriceInfo},
     keys::Keys,
     message::Message,
     wallet_txns::WalletTxns,
 };
-use crate::bitcoinz_transaction::{detect_tx_type, BitcoinZTxType};
+use crate::bitcoinz_address::{classify_address, decode_transparent_address, AddressKind};
+use crate::bitcoinz_coinselect::{select_coins_with, CoinSelection};
+use crate::bitcoinz_transaction::{detect_tx_type, BitcoinZTxType, BuiltTransaction, FeePolicy};
 use crate::bitcoinz_binding_sig_fix::{needs_bitcoinz_binding_sig_fix, compute_bitcoinz_binding_message, verify_bitcoinz_binding_signature};
-use crate::bitcoinz_branch::bitcoinz_branch_id_for_height;
-use crate::bitcoinz_overwinter_builder::{build_overwinter_tx, should_use_overwinter};
-use crate::bitcoinz_legacy_builder::build_legacy_tx;
-use crate::bitcoinz_js_bridge::build_bitcoinz_js_tx;
-use crate::bitcoinz_v4_no_sig::build_bitcoinz_v4_no_sig;
+use crate::bitcoinz_branch::{bitcoinz_branch_id_for_height, check_shielded_send_allowed, BRANCH_IDS_CONFIRMED};
+use crate::bitcoinz_parse::BitcoinZTransaction;
+use crate::bitcoinz_send::{build_transactions, SendInput};
+use crate::bitcoinz_txid::WireTxid;
+use crate::bitcoinz_transparent_builder::{build_sweep_tx, expiry_for_offset, select_format, FormatPolicy};
 use crate::bitcoinz_shielded_patch::{
     patch_shielded_binding_signature, 
     compute_bitcoinz_shielded_sighash,
@@ -99,6 +99,9 @@ mod walletzkey;
 pub const MERKLE_DEPTH: u8 = 32;
 pub const MAX_CHECKPOINTS: usize = 100;
 
+// How many transactions the server refused to broadcast we remember
+pub const MAX_FAILED_SENDS: usize = 5;
+
 pub fn now() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -129,6 +132,14 @@ impl SendProgress {
     }
 }
 
+/// A transaction the server refused to broadcast. Only kept in memory, for diagnostics.
+#[derive(Debug, Clone)]
+pub struct FailedSend {
+    pub time: u64,
+    pub raw_tx: Vec<u8>,
+    pub error: String,
+}
+
 // Enum to refer to the first or last position of the Node
 pub enum NodePosition {
     Oldest,
@@ -146,6 +157,7 @@ pub enum MemoDownloadOption {
 pub struct WalletOptions {
     pub(crate) download_memos: MemoDownloadOption,
     pub(crate) spam_threshold: i64,
+    pub(crate) coin_selection: CoinSelection,
 }
 
 impl Default for WalletOptions {
@@ -153,13 +165,14 @@ impl Default for WalletOptions {
         WalletOptions {
             download_memos: MemoDownloadOption::WalletMemos,
             spam_threshold: -1,
+            coin_selection: CoinSelection::default(),
         }
     }
 }
 
 impl WalletOptions {
     pub fn serialized_version() -> u64 {
-        return 2;
+        return 3;
     }
 
     pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
@@ -183,9 +196,18 @@ impl WalletOptions {
             reader.read_i64::<LittleEndian>()?
         };
 
+        let coin_selection = if version <= 2 {
+            CoinSelection::default()
+        } else {
+            let v = reader.read_u8()?;
+            CoinSelection::from_u8(v)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Bad coin selection {}", v)))?
+        };
+
         Ok(Self {
             download_memos,
             spam_threshold,
+            coin_selection,
         })
     }
 
@@ -195,10 +217,29 @@ impl WalletOptions {
 
         writer.write_u8(self.download_memos as u8)?;
 
-        writer.write_i64::<LittleEndian>(self.spam_threshold)
+        writer.write_i64::<LittleEndian>(self.spam_threshold)?;
+
+        writer.write_u8(self.coin_selection as u8)
     }
 }
 
+/// Choices made for a single send, on top of who gets paid what
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SendOptions {
+    /// A send expires this many blocks after the target height; 0 never expires.
+    pub expiry_offset: u32,
+    /// Build transactions with shielded parts even though the branch IDs are unconfirmed
+    pub force_experimental: bool,
+}
+
+/// How long opening the wallet took, split into the part done when the file is read
+/// and the deferred witness parsing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalletLoadStats {
+    pub eager_load: Option<Duration>,
+    pub witness_load: Option<Duration>,
+}
+
 pub struct LightWallet<P> {
     // All the keys in the wallet
     keys: Arc<RwLock<Keys<P>>>,
@@ -230,6 +271,15 @@ pub struct LightWallet<P> {
 
     // The current price of ZEC. (time_fetched, price in USD)
     pub price: Arc<RwLock<WalletZecPriceInfo>>,
+
+    // Set once every note's witnesses have been parsed (they are read lazily)
+    witnesses_loaded: Arc<OnceCell<()>>,
+
+    // Timings of the wallet load
+    load_stats: Arc<RwLock<WalletLoadStats>>,
+
+    // Most recent failed broadcasts, newest last
+    failed_sends: Arc<RwLock<VecDeque<FailedSend>>>,
 }
 
 impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
@@ -239,7 +289,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
 
     pub fn new(
         config: LightClientConfig<P>,
-        seed_phrase: Option<String>,
+        seed_phrase: Option<&str>,
         height: u64,
         num_zaddrs: u32,
         num_oaddrs: u32,
@@ -258,6 +308,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             verified_tree: Arc::new(RwLock::new(None)),
             send_progress: Arc::new(RwLock::new(SendProgress::new(0))),
             price: Arc::new(RwLock::new(WalletZecPriceInfo::new())),
+            witnesses_loaded: Arc::new(OnceCell::new()),
+            load_stats: Arc::new(RwLock::new(WalletLoadStats::default())),
+            failed_sends: Arc::new(RwLock::new(VecDeque::new())),
         })
     }
 
@@ -328,6 +381,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
     }
 
     pub async fn read<R: Read>(mut reader: R, config: &LightClientConfig<P>) -> io::Result<Self> {
+        let start = Instant::now();
         let version = reader.read_u64::<LittleEndian>()?;
         if version > Self::serialized_version() {
             let e = format!(
@@ -430,6 +484,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             verified_tree: Arc::new(RwLock::new(verified_tree)),
             send_progress: Arc::new(RwLock::new(SendProgress::new(0))),
             price: Arc::new(RwLock::new(price)),
+            witnesses_loaded: Arc::new(OnceCell::new()),
+            load_stats: Arc::new(RwLock::new(WalletLoadStats::default())),
+            failed_sends: Arc::new(RwLock::new(VecDeque::new())),
         };
 
         // For old wallets, remove unused addresses
@@ -447,9 +504,53 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             lw.keys().write().await.add_oaddr();
         }
 
+        // Note witnesses are parsed later, see ensure_witnesses_loaded()
+        let eager_load = start.elapsed();
+        info!("Read wallet in {:?} (witnesses deferred)", eager_load);
+        lw.load_stats.write().await.eager_load = Some(eager_load);
+
         Ok(lw)
     }
 
+    /// Parse all the note witnesses that were deferred when the wallet was read. Concurrent
+    /// callers wait for the same load; once done this returns immediately. Balances and
+    /// history don't need this, only syncing and building shielded spends do.
+    pub async fn ensure_witnesses_loaded(&self) {
+        self.load_witnesses(|| {}).await
+    }
+
+    // `before_parse` runs once, with the transactions locked, before any witnesses are
+    // parsed. Tests use it to slow the load down and check what does and doesn't wait on it.
+    pub(crate) async fn load_witnesses<F: FnOnce()>(&self, before_parse: F) {
+        self.witnesses_loaded
+            .get_or_init(|| async {
+                let start = Instant::now();
+                {
+                    // A read lock is enough: each note parses its own witnesses on first use
+                    let txns = self.txns.read().await;
+                    before_parse();
+                    txns.current.values().for_each(|wtx| {
+                        wtx.s_notes.iter().for_each(|nd| {
+                            nd.witnesses.load();
+                        })
+                    });
+                }
+
+                let witness_load = start.elapsed();
+                info!("Loaded note witnesses in {:?}", witness_load);
+                self.load_stats.write().await.witness_load = Some(witness_load);
+            })
+            .await;
+    }
+
+    pub fn witnesses_loaded(&self) -> bool {
+        self.witnesses_loaded.initialized()
+    }
+
+    pub async fn load_stats(&self) -> WalletLoadStats {
+        *self.load_stats.read().await
+    }
+
     pub async fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
         if self.keys.read().await.encrypted && self.keys.read().await.unlocked {
             return Err(Error::new(
@@ -545,6 +646,10 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         self.wallet_options.write().await.spam_threshold = value;
     }
 
+    pub async fn set_coin_selection(&self, value: CoinSelection) {
+        self.wallet_options.write().await.coin_selection = value;
+    }
+
     pub async fn get_birthday(&self) -> u64 {
         let birthday = self.birthday.load(std::sync::atomic::Ordering::SeqCst);
         if birthday == 0 {
@@ -569,6 +674,23 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         self.send_progress.read().await.clone()
     }
 
+    // Remember a transaction the server wouldn't take
+    pub(crate) async fn record_failed_send(&self, raw_tx: &[u8], error: &str) {
+        let mut failed = self.failed_sends.write().await;
+        if failed.len() >= MAX_FAILED_SENDS {
+            failed.pop_front();
+        }
+        failed.push_back(FailedSend {
+            time: now(),
+            raw_tx: raw_tx.to_vec(),
+            error: error.to_string(),
+        });
+    }
+
+    pub async fn failed_sends(&self) -> Vec<FailedSend> {
+        self.failed_sends.read().await.iter().cloned().collect()
+    }
+
     // Set the previous send's status as an error
     async fn set_send_error(&self, e: String) {
         let mut p = self.send_progress.write().await;
@@ -630,7 +752,7 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         }
     }
 
-    pub async fn add_imported_tk(&self, sk: String) -> String {
+    pub async fn add_imported_tk(&self, sk: &str) -> String {
         if self.keys.read().await.encrypted {
             return "Error: Can't import transparent address key while wallet is encrypted".to_string();
         }
@@ -660,13 +782,13 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
 
     // Add a new imported spending key to the wallet
     /// NOTE: This will not rescan the wallet
-    pub async fn add_imported_sk(&self, sk: String, birthday: u64) -> String {
+    pub async fn add_imported_sk(&self, sk: &str, birthday: u64) -> String {
         if self.keys.read().await.encrypted {
             return "Error: Can't import spending key while wallet is encrypted".to_string();
         }
 
         // First, try to interpret the key
-        let extsk = match decode_extended_spending_key(self.config.hrp_sapling_private_key(), &sk) {
+        let extsk = match decode_extended_spending_key(self.config.hrp_sapling_private_key(), sk) {
             Ok(Some(k)) => k,
             Ok(None) => return format!("Error: Couldn't decode spending key"),
             Err(e) => return format!("Error importing spending key: {}", e),
@@ -711,13 +833,13 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
 
     // Add a new imported viewing key to the wallet
     /// NOTE: This will not rescan the wallet
-    pub async fn add_imported_vk(&self, vk: String, birthday: u64) -> String {
+    pub async fn add_imported_vk(&self, vk: &str, birthday: u64) -> String {
         if !self.keys().read().await.unlocked {
             return "Error: Can't add key while wallet is locked".to_string();
         }
 
         // First, try to interpret the key
-        let extfvk = match decode_extended_full_viewing_key(self.config.hrp_sapling_viewing_key(), &vk) {
+        let extfvk = match decode_extended_full_viewing_key(self.config.hrp_sapling_viewing_key(), vk) {
             Ok(Some(k)) => k,
             Ok(None) => return format!("Error: Couldn't decode viewing key"),
             Err(e) => return format!("Error importing viewing key: {}", e),
@@ -745,6 +867,47 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         encode_payment_address(self.config.hrp_sapling_address(), &newkey.zaddress)
     }
 
+    /// Remove an imported key, given its z or t address, along with its notes, utxos and the
+    /// txns that only involved it. The balance is computed from the remaining txns.
+    pub async fn remove_key(&self, identifier: String) -> Result<(), String> {
+        let mut keys = self.keys.write().await;
+
+        if keys.zkeys.len() + keys.tkeys.len() + keys.okeys.len() <= 1 {
+            return Err("Can't remove the only key in the wallet".to_string());
+        }
+
+        if let Some(pos) = keys
+            .zkeys
+            .iter()
+            .position(|zk| encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress) == identifier)
+        {
+            if keys.zkeys[pos].keytype == WalletZKeyType::HdKey {
+                return Err("Only imported keys can be removed".to_string());
+            }
+
+            let removed = keys.zkeys.remove(pos);
+            self.txns
+                .write()
+                .await
+                .remove_key_data(|nd| nd.extfvk == removed.extfvk, |_| false);
+        } else if let Some(pos) = keys.tkeys.iter().position(|tk| tk.address == identifier) {
+            if keys.tkeys[pos].keytype == WalletTKeyType::HdKey {
+                return Err("Only imported keys can be removed".to_string());
+            }
+
+            let removed = keys.tkeys.remove(pos);
+            self.txns
+                .write()
+                .await
+                .remove_key_data(|_| false, |utxo| utxo.address == removed.address);
+        } else {
+            return Err(format!("No key for address {}", identifier));
+        }
+
+        info!("Removed key for {}", identifier);
+        Ok(())
+    }
+
     /// Clears all the downloaded blocks and resets the state back to the initial block.
     /// After this, the wallet's initial state will need to be set
     /// and the wallet will need to be rescanned
@@ -819,6 +982,17 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         }
     }
 
+    /// The anchor a Sapling spend would be made against now: its height, the hash of the
+    /// block at that height and the note commitment tree's root there. None if there's no
+    /// note that can be spent.
+    pub async fn sapling_spend_anchor(&self) -> Option<(u64, String, Node)> {
+        let note = self.select_sapling_notes(Amount::from_u64(1).unwrap()).await.into_iter().next()?;
+        let height = self.get_anchor_height().await as u64;
+        let hash = self.blocks.read().await.iter().find(|b| b.height == height)?.hash();
+
+        Some((height, hash, note.witness.root()))
+    }
+
     pub fn memo_str(memo: Option<Memo>) -> Option<String> {
         match memo {
             Some(Memo::Text(m)) => Some(m.to_string()),
@@ -1202,7 +1376,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         o_notes
     }
 
-    async fn select_sapling_notes(&self, target_amount: Amount) -> Vec<SpendableSaplingNote> {
+    pub(crate) async fn select_sapling_notes(&self, target_amount: Amount) -> Vec<SpendableSaplingNote> {
+        self.ensure_witnesses_loaded().await;
+
         let keys = self.keys.read().await;
         let mut candidate_notes = self
             .txns
@@ -1331,13 +1507,18 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         return (o_notes, s_notes, utxos, total_value_selected);
     }
 
+    /// `options` set when the send expires, and whether a send with shielded parts is built
+    /// while the branch IDs are unconfirmed. A payment too big to relay as one transaction
+    /// is sent as several; returns each one's txid and raw bytes, in the order they were
+    /// broadcast.
     pub async fn send_to_address<F, Fut, PR: TxProver>(
         &self,
         prover: PR,
         transparent_only: bool,
         tos: Vec<(&str, u64, Option<String>)>,
+        options: SendOptions,
         broadcast_fn: F,
-    ) -> Result<(String, Vec<u8>), String>
+    ) -> Result<Vec<(String, Vec<u8>)>, String>
     where
         F: Fn(Box<[u8]>) -> Fut,
         Fut: Future<Output = Result<String, String>>,
@@ -1347,12 +1528,12 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
 
         // Call the internal function
         match self
-            .send_to_address_internal(prover, transparent_only, tos, broadcast_fn)
+            .send_to_address_internal(prover, transparent_only, tos, options, broadcast_fn)
             .await
         {
-            Ok((txid, rawtx)) => {
-                self.set_send_success(txid.clone()).await;
-                Ok((txid, rawtx))
+            Ok(sent) => {
+                self.set_send_success(sent.last().unwrap().0.clone()).await;
+                Ok(sent)
             }
             Err(e) => {
                 self.set_send_error(format!("{}", e)).await;
@@ -1366,8 +1547,9 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         prover: PR,
         transparent_only: bool,
         tos: Vec<(&str, u64, Option<String>)>,
+        options: SendOptions,
         broadcast_fn: F,
-    ) -> Result<(String, Vec<u8>), String>
+    ) -> Result<Vec<(String, Vec<u8>)>, String>
     where
         F: Fn(Box<[u8]>) -> Fut,
         Fut: Future<Output = Result<String, String>>,
@@ -1392,7 +1574,12 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
         let recepients = tos
             .iter()
             .map(|to| {
-                let ra = match address::RecipientAddress::decode(&self.config.get_params(), to.0) {
+                // t1 and t3 addresses are decoded here, so a script hash gets a P2SH output
+                let ra = match decode_transparent_address(to.0, &self.config.get_params()) {
+                    Ok(taddr) => Some(address::RecipientAddress::Transparent(taddr)),
+                    Err(_) => address::RecipientAddress::decode(&self.config.get_params(), to.0),
+                };
+                let ra = match ra {
                     Some(to) => to,
                     None => {
                         let e = format!("Invalid recipient address: '{}'", to.0);
@@ -1407,16 +1594,39 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             })
             .collect::<Result<Vec<(address::RecipientAddress, Amount, Option<String>)>, String>>()?;
 
-        // Calculate how much we're sending to each type of address
-        let (_t_out, s_out, _o_out) = recepients
+        // BitcoinZ has no Orchard, so a unified address is paid through its Sapling or
+        // transparent receiver
+        let mut recipients = vec![];
+        for (to, value, memo) in recepients {
+            let to = match to {
+                address::RecipientAddress::Transparent(to) => AddressKind::Transparent(to),
+                address::RecipientAddress::Shielded(to) => AddressKind::Sapling(to),
+                address::RecipientAddress::Unified(to) => match (to.sapling(), to.transparent()) {
+                    (Some(to), _) => AddressKind::Sapling(to.clone()),
+                    (None, Some(to)) => AddressKind::Transparent(to.clone()),
+                    (None, None) => return Err("Unified address has no supported receivers for BitcoinZ".to_string()),
+                },
+            };
+
+            // If the string starts with an "0x", and contains only hex chars ([a-f0-9]+) then
+            // interpret it as a hex
+            let memo = match memo {
+                None => None,
+                Some(s) => match utils::interpret_memo_string(s) {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(e);
+                    }
+                },
+            };
+            recipients.push((to, value, memo));
+        }
+        let t_out = recipients
             .iter()
-            .map(|(to, value, _)| match to {
-                address::RecipientAddress::Unified(_) => (0, 0, value.into()),
-                address::RecipientAddress::Shielded(_) => (0, value.into(), 0),
-                address::RecipientAddress::Transparent(_) => (value.into(), 0, 0),
-            })
-            .reduce(|(t, s, o), (t2, s2, o2)| (t + t2, s + s2, o + o2))
-            .unwrap_or((0, 0, 0));
+            .filter(|(to, _, _)| matches!(to, AddressKind::Transparent(_)))
+            .count();
+        let s_out = recipients.len() - t_out;
 
         // Select notes to cover the target value
         println!("{}: Selecting notes", now() - start_time);
@@ -1427,17 +1637,6 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             None => return Err("No blocks in wallet to target, please sync first".to_string()),
         };
 
-        let (progress_notifier, progress_notifier_rx) = mpsc::channel();
-
-        // BitcoinZ doesn't use Orchard, so no orchard_anchor needed
-
-        
-        // For BitcoinZ, we need special handling for transparent-only transactions
-        // Check if all recipients are transparent addresses
-        let all_transparent_recipients = recepients.iter().all(|(addr, _, _)| {
-            matches!(addr, address::RecipientAddress::Transparent(_))
-        });
-        
         // Create a map from address -> sk for all taddrs, so we can spend from the
         // right address
         let address_to_sk = self.keys.read().await.get_taddr_to_sk_map();
@@ -1457,553 +1656,181 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             return Err(e);
         }
 
-        // Create the transaction
-        println!(
-            "{}: Adding {} o_notes {} s_notes and {} utxos",
-            now() - start_time,
-            o_notes.len(),
-            s_notes.len(),
-            utxos.len()
-        );
-        
-        // Detect BitcoinZ transaction type
-        let tx_type = detect_tx_type(
-            utxos.len(),  // transparent inputs
-            s_notes.len() + o_notes.len(),  // shielded inputs
-            recepients.iter().filter(|(addr, _, _)| matches!(addr, address::RecipientAddress::Transparent(_))).count(),  // transparent outputs
-            recepients.iter().filter(|(addr, _, _)| !matches!(addr, address::RecipientAddress::Transparent(_))).count()   // shielded outputs
-        );
-        
-        
-        let is_transparent_only = tx_type == BitcoinZTxType::TransparentToTransparent;
-        
-        // BitcoinZ Fix: Use same approach as BitcoinZ Blue - always use standard builder
-        if tx_type != BitcoinZTxType::TransparentToTransparent {
-            println!("BitcoinZ: Detected shielded transaction type: {:?}", tx_type);
-            println!("BitcoinZ: Using standard zcash_primitives Builder (same as BitcoinZ Blue)");
-        }
-        
-        // BitcoinZ Fix: For transparent-only transactions, use JavaScript bridge
-        if is_transparent_only && should_use_overwinter(
-            utxos.len(),
-            s_notes.len() + o_notes.len(), 
-            recepients.iter().filter(|(addr, _, _)| matches!(addr, address::RecipientAddress::Transparent(_))).count(),
-            recepients.iter().filter(|(addr, _, _)| !matches!(addr, address::RecipientAddress::Transparent(_))).count()
-        ) {
-            
-            // Prepare inputs and outputs for legacy builder
-            let mut legacy_inputs = Vec::new();
-            for utxo in &utxos {
-                let outpoint = utxo.to_outpoint();
-                let coin = TxOut {
-                    value: Amount::from_u64(utxo.value).unwrap(),
-                    script_pubkey: Script { 0: utxo.script.clone() },
-                };
-                
-                match address_to_sk.get(&utxo.address) {
-                    Some(sk) => {
-                        legacy_inputs.push((outpoint, coin, *sk));
-                    }
-                    None => {
-                        return Err(format!("Couldn't find the secret key for taddr {}", utxo.address));
-                    }
-                }
-            }
-            
-            let mut legacy_outputs = Vec::new();
-            for (addr, value, _) in &recepients {
-                if let address::RecipientAddress::Transparent(taddr) = addr {
-                    legacy_outputs.push((taddr.clone(), *value));
-                } else {
-                    return Err("Legacy builder only supports transparent outputs".to_string());
-                }
-            }
-            
-            // Add change output if needed
-            let total_in = utxos.iter().map(|u| u.value).sum::<u64>();
-            let total_out = recepients.iter().map(|(_, v, _)| u64::from(*v)).sum::<u64>();
-            let fee = u64::from(DEFAULT_FEE);
-            
-            if total_in > total_out + fee {
-                // Send change to first transparent address
-                let change_amount = total_in - total_out - fee;
-                if let Ok(change_addr) = TransparentAddress::decode(&self.config.get_params(), &self.keys.read().await.tkeys[0].address) {
-                    legacy_outputs.push((change_addr, Amount::from_u64(change_amount).unwrap()));
-                } else {
-                    return Err("Failed to decode change address".to_string());
-                }
-            }
-            
-            // Build the transaction using v4 no-binding-sig builder
-            match build_bitcoinz_v4_no_sig(&self.config.get_params(), legacy_inputs, legacy_outputs, target_height) {
-                Ok(raw_tx) => {
-                    
-                    // Compute txid manually for our custom Overwinter transaction
-                    use sha2::{Sha256, Digest as Sha2Digest};
-                    let mut hasher = Sha256::new();
-                    hasher.update(&raw_tx);
-                    let hash1 = hasher.finalize();
-                    let mut hasher2 = Sha256::new();
-                    hasher2.update(&hash1);
-                    let hash2 = hasher2.finalize();
-                    let mut txid_bytes = [0u8; 32];
-                    txid_bytes.copy_from_slice(&hash2);
-                    txid_bytes.reverse(); // Convert to big-endian for display
-                    let txid = hex::encode(&txid_bytes);
-                    
-                    // Broadcast the transaction
-                    let broadcast_result = broadcast_fn(raw_tx.clone().into_boxed_slice()).await?;
-                    
-                    // Mark UTXOs as spent
-                    {
-                        let mut txs = self.txns.write().await;
-                        for utxo in utxos {
-                            let spent_utxo = txs
-                                .current
-                                .get_mut(&utxo.txid)
-                                .unwrap()
-                                .utxos
-                                .iter_mut()
-                                .find(|u| utxo.txid == u.txid && utxo.output_index == u.output_index)
-                                .unwrap();
-                            use zcash_primitives::transaction::TxId;
-                            spent_utxo.unconfirmed_spent = Some((TxId::from_bytes(txid_bytes), u32::from(target_height)));
-                        }
-                    }
-                    
-                    // For Overwinter transactions, we skip the mempool update since we don't have a parsed Transaction object
-                    
-                    self.send_progress.write().await.is_send_in_progress = false;
-                    return Ok((broadcast_result, raw_tx));
-                }
-                Err(e) => {
-                    // Continue with standard builder below
-                }
-            }
-        }
-        
-        // BitcoinZ Fix: DISABLED custom builder - use standard Builder for all transactions (same as BitcoinZ Blue)
-        /*
-        if tx_type != BitcoinZTxType::TransparentToTransparent {
-            println!("BitcoinZ: Shielded transaction detected - type: {:?}", tx_type);
-            println!("BitcoinZ: Using custom shielded transaction builder");
-            
-            // Import the shielded builder
-            use crate::bitcoinz_v4_shielded::BitcoinZShieldedBuilder;
-            
-            // Create a new shielded builder
-            let mut shielded_builder = BitcoinZShieldedBuilder::new(
-                self.config.get_params().clone(),
-                target_height,
-            );
-            
-            // Add transparent inputs from utxos
-            for utxo in &utxos {
-                let outpoint = utxo.to_outpoint();
-                let coin = TxOut {
-                    value: Amount::from_u64(utxo.value).unwrap(),
-                    script_pubkey: Script { 0: utxo.script.clone() },
-                };
-                
-                match address_to_sk.get(&utxo.address) {
-                    Some(sk) => {
-                        shielded_builder.add_transparent_input(outpoint, coin, *sk)
-                            .map_err(|e| format!("Failed to add transparent input: {}", e))?;
-                    }
-                    None => {
-                        return Err(format!("Couldn't find the secret key for taddr {}", utxo.address));
-                    }
-                }
-            }
-            
-            // Add transparent outputs from recipients
-            for (addr, value, _) in &recepients {
-                if let address::RecipientAddress::Transparent(taddr) = addr {
-                    shielded_builder.add_transparent_output(taddr.clone(), *value)
-                        .map_err(|e| format!("Failed to add transparent output: {}", e))?;
-                }
-            }
-            
-            // Add shielded spends from wallet notes
-            for s_note in &s_notes {
-                // Extract merkle path from witness
-                let witness_path = s_note.witness.path()
-                    .ok_or("Failed to get merkle path from witness")?;
-                
-                let merkle_path = witness_path.auth_path
-                    .iter()
-                    .map(|(node, _)| *node)
-                    .collect::<Vec<_>>();
-                
-                // Get witness position from the path
-                let witness_position = witness_path.position as u64;
-                
-                shielded_builder.add_sapling_spend(
-                    s_note.extsk.expsk.clone(),
-                    s_note.diversifier,
-                    s_note.note.clone(),
-                    merkle_path,
-                    witness_position,
-                ).map_err(|e| format!("Failed to add sapling spend: {}", e))?;
-            }
-            
-            // Add shielded outputs from recipients
-            for (addr, value, memo) in &recepients {
-                if let address::RecipientAddress::Shielded(to) = addr {
-                    // Use the first ovk for encryption
-                    let ovk = self.keys.read().await.zkeys[0].extfvk.fvk.ovk;
-                    
-                    // Parse memo if provided
-                    let memo_bytes = match memo {
-                        Some(s) => utils::interpret_memo_string(s.clone())?,
-                        None => MemoBytes::empty(),
-                    };
-                    
-                    shielded_builder.add_sapling_output(
-                        ovk,
-                        to.clone(),
-                        *value,
-                        memo_bytes,
-                    ).map_err(|e| format!("Failed to add sapling output: {}", e))?;
-                }
-            }
-            
-            // Calculate change and add change output if needed
-            let total_in = utxos.iter().map(|u| u.value).sum::<u64>() +
-                          s_notes.iter().map(|n| n.note.value).sum::<u64>();
-            let total_out = recepients.iter().map(|(_, v, _)| u64::from(*v)).sum::<u64>() +
-                           u64::from(DEFAULT_FEE);
-            
-            if total_in > total_out {
-                let change = total_in - total_out;
-                
-                // Send change to first sapling address
-                let change_addr = self.keys.read().await.zkeys[0].zaddress.clone();
-                let ovk = self.keys.read().await.zkeys[0].extfvk.fvk.ovk;
-                
-                shielded_builder.add_sapling_output(
-                    ovk,
-                    change_addr,
-                    Amount::from_u64(change).unwrap(),
-                    MemoBytes::empty(),
-                ).map_err(|e| format!("Failed to add change output: {}", e))?;
-            }
-            
-            // Add orchard notes if any (future enhancement)
-            // For now, BitcoinZ focuses on Sapling transactions
-            if o_notes.len() > 0 {
-                return Err("BitcoinZ orchard transactions are not yet supported. Please use sapling addresses.".to_string());
-            }
-            
-            // Build the transaction
-            println!("BitcoinZ: Building shielded transaction with {} inputs and {} outputs", 
-                     utxos.len() + s_notes.len(), recepients.len());
-            match shielded_builder.build(&prover, DEFAULT_FEE) {
-                Ok(raw_tx) => {
-                    println!("BitcoinZ: Successfully built shielded transaction, size: {} bytes", raw_tx.len());
-                    // Compute txid
-                    use sha2::{Sha256, Digest as Sha2Digest};
-                    let mut hasher = Sha256::new();
-                    hasher.update(&raw_tx);
-                    let hash1 = hasher.finalize();
-                    let mut hasher2 = Sha256::new();
-                    hasher2.update(&hash1);
-                    let hash2 = hasher2.finalize();
-                    let mut txid_bytes = [0u8; 32];
-                    txid_bytes.copy_from_slice(&hash2);
-                    txid_bytes.reverse();
-                    
-                    // Broadcast the transaction
-                    let broadcast_result = broadcast_fn(raw_tx.clone().into_boxed_slice()).await?;
-                    
-                    // Mark UTXOs as spent
-                    {
-                        let mut txs = self.txns.write().await;
-                        for utxo in utxos {
-                            let spent_utxo = txs
-                                .current
-                                .get_mut(&utxo.txid)
-                                .unwrap()
-                                .utxos
-                                .iter_mut()
-                                .find(|u| utxo.txid == u.txid && utxo.output_index == u.output_index)
-                                .unwrap();
-                            use zcash_primitives::transaction::TxId;
-                            spent_utxo.unconfirmed_spent = Some((TxId::from_bytes(txid_bytes), u32::from(target_height)));
-                        }
-                    }
-                    
-                    self.send_progress.write().await.is_send_in_progress = false;
-                    return Ok((broadcast_result, raw_tx));
-                }
-                Err(e) => {
-                    println!("BitcoinZ: Failed to build shielded transaction: {}", e);
-                    return Err(format!("Failed to build BitcoinZ shielded transaction: {}", e));
-                }
-            }
-        }
-        */
-        
-        // Standard builder - use same approach as BitcoinZ Blue (no Orchard support)
-        let builder_height = target_height;
-        let mut builder = Builder::new(self.config.get_params().clone(), builder_height);
-        builder.with_progress_notifier(progress_notifier);
-
-        let mut change = 0u64;
-
-        // Add all tinputs
-        utxos
-            .iter()
-            .map(|utxo| {
-                let outpoint: OutPoint = utxo.to_outpoint();
-
-                let coin = TxOut {
-                    value: Amount::from_u64(utxo.value).unwrap(),
-                    script_pubkey: Script { 0: utxo.script.clone() },
-                };
-
-                match address_to_sk.get(&utxo.address) {
-                    Some(sk) => {
-                        change += u64::from(coin.value);
-                        builder.add_transparent_input(*sk, outpoint.clone(), coin.clone())
-                    }
-                    None => {
-                        // Something is very wrong
-                        let e = format!("Couldn't find the secreykey for taddr {}", utxo.address);
-                        error!("{}", e);
-
-                        Err(zcash_primitives::transaction::builder::Error::InvalidAmount)
-                    }
-                }
-            })
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| format!("{:?}", e))?;
-
         // Skip Orchard notes for BitcoinZ (same as BitcoinZ Blue)
         if o_notes.len() > 0 {
-            warn!("Skipping {} Orchard notes as BitcoinZ doesn't support Orchard", o_notes.len());
+            warn!(
+                "Skipping {} Orchard notes as BitcoinZ doesn't support Orchard",
+                o_notes.len()
+            );
         }
 
-        // Add Sapling notes
-        for selected in s_notes.iter() {
-            if let Err(e) = builder.add_sapling_spend(
-                selected.extsk.clone(),
-                selected.diversifier,
-                selected.note.clone(),
-                selected.witness.path().unwrap(),
-            ) {
-                let e = format!("Error adding sapling note: {:?}", e);
+        let tx_type = detect_tx_type(utxos.len(), s_notes.len(), t_out, s_out);
+        if tx_type != BitcoinZTxType::TransparentToTransparent {
+            if let Err(e) = check_shielded_send_allowed(BRANCH_IDS_CONFIRMED, options.force_experimental) {
                 error!("{}", e);
                 return Err(e);
-            } else {
-                change += selected.note.value;
             }
         }
 
-        // We'll use the first ovk to encrypt outgoing Txns (same as BitcoinZ Blue)
-        let s_ovk = self.keys.read().await.zkeys[0].extfvk.fvk.ovk;
-        // BitcoinZ doesn't use Orchard OVK
-
-        let mut total_z_recepients = 0u32;
-        let mut total_o_recepients = 0u32;
-        for (to, value, memo) in recepients {
-            // Compute memo if it exists
-            let encoded_memo = match memo {
-                None => MemoBytes::empty(),
-                Some(s) => {
-                    // If the string starts with an "0x", and contains only hex chars ([a-f0-9]+) then
-                    // interpret it as a hex
-                    match utils::interpret_memo_string(s) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            error!("{}", e);
-                            return Err(e);
-                        }
-                    }
+        // A transparent-only send picks its coins by the wallet's strategy and pays the fee for
+        // its size, with change back to the first taddr. Anything shielded spends what was
+        // selected above, pays the default fee and sends change to the first zaddr.
+        let total_out = Amount::from_u64(total_value).unwrap();
+        let (utxos, change, policy) = if tx_type == BitcoinZTxType::TransparentToTransparent {
+            // The format decides the size, and so the fee the coins have to cover
+            let format = select_format(&self.config.get_params(), target_height, FormatPolicy::Current);
+            let strategy = self.wallet_options.read().await.coin_selection;
+            let utxos = match select_coins_with(&utxos, total_out, t_out, strategy, &FeePolicy::default(), format) {
+                Ok((utxos, _)) => utxos,
+                Err(e) => {
+                    let e = format!(
+                        "{}. NOTE: funds need at least {} confirmations before they can be spent.",
+                        e,
+                        self.config.anchor_offset + 1
+                    );
+                    error!("{}", e);
+                    return Err(e);
                 }
             };
-
-            println!("{}: Adding output", now() - start_time);
-
-            if let Err(e) = match to {
-                address::RecipientAddress::Unified(to) => {
-                    // BitcoinZ doesn't support Orchard, so use Sapling component if available (same as BitcoinZ Blue)
-                    if let Some(sapling_addr) = to.sapling() {
-                        total_z_recepients += 1;
-                        change -= u64::from(value);
-                        builder.add_sapling_output(Some(s_ovk), sapling_addr.clone(), value, encoded_memo)
-                    } else if let Some(t_addr) = to.transparent() {
-                        change -= u64::from(value);
-                        builder.add_transparent_output(&t_addr, value)
-                    } else {
-                        return Err("Unified address has no supported receivers for BitcoinZ".to_string());
-                    }
-                }
-                address::RecipientAddress::Shielded(to) => {
-                    total_z_recepients += 1;
-                    change -= u64::from(value);
-                    builder.add_sapling_output(Some(s_ovk), to.clone(), value, encoded_memo)
-                }
-                address::RecipientAddress::Transparent(to) => {
-                    change -= u64::from(value);
-                    builder.add_transparent_output(&to, value)
-                }
-            } {
-                let e = format!("Error adding output: {:?}", e);
-                error!("{}", e);
-                return Err(e);
-            }
-        }
-
-        // Change
-        // BitcoinZ doesn't support Orchard, so always send change to Sapling (same as BitcoinZ Blue)
-        change -= u64::from(DEFAULT_FEE);
-        if change > 0 {
-            // Always send to sapling address for BitcoinZ
-            builder.send_change_to(
-                self.keys.read().await.zkeys[0].extfvk.fvk.ovk,
-                self.keys.read().await.zkeys[0].zaddress.clone(),
+            info!(
+                "{}: Selected {} utxos ({})",
+                now() - start_time,
+                utxos.len(),
+                strategy.name()
             );
-        }
-
-        // Set up a channel to recieve updates on the progress of building the transaction.
-        let progress = self.send_progress.clone();
 
-        // Use a separate thread to handle sending from std::mpsc to tokio::sync::mpsc
-        let (tx2, mut rx2) = tokio::sync::mpsc::unbounded_channel();
-        std::thread::spawn(move || {
-            while let Ok(r) = progress_notifier_rx.recv() {
-                tx2.send(r.cur()).unwrap();
-            }
-        });
+            let change_addr = self.keys.read().await.tkeys[0].address.clone();
+            let change = match TransparentAddress::decode(&self.config.get_params(), &change_addr) {
+                Ok(change_addr) => AddressKind::Transparent(change_addr),
+                Err(_) => return Err("Failed to decode change address".to_string()),
+            };
+            (utxos, change, FeePolicy::default())
+        } else {
+            let change = AddressKind::Sapling(self.keys.read().await.zkeys[0].zaddress.clone());
+            let flat = FeePolicy {
+                per_kb: Amount::zero(),
+                min: DEFAULT_FEE,
+            };
+            (utxos, change, flat)
+        };
 
-        let progress_handle = tokio::spawn(async move {
-            while let Some(r) = rx2.recv().await {
-                println!("Progress: {}", r);
-                progress.write().await.progress = r;
+        println!(
+            "{}: Adding {} s_notes and {} utxos",
+            now() - start_time,
+            s_notes.len(),
+            utxos.len()
+        );
+        let mut inputs = vec![];
+        for utxo in &utxos {
+            let coin = TxOut {
+                value: Amount::from_u64(utxo.value).unwrap(),
+                script_pubkey: Script { 0: utxo.script.clone() },
+            };
+            match address_to_sk.get(&utxo.address) {
+                Some(sk) => inputs.push(SendInput::Transparent {
+                    outpoint: utxo.to_outpoint(),
+                    coin,
+                    key: *sk,
+                }),
+                None => return Err(format!("Couldn't find the secret key for taddr {}", utxo.address)),
             }
-
-            progress.write().await.is_send_in_progress = false;
-        });
+        }
+        for selected in &s_notes {
+            let path = match selected.witness.path() {
+                Some(path) => path,
+                None => return Err("Couldn't get the Merkle path of a note to spend".to_string()),
+            };
+            inputs.push(SendInput::Sapling {
+                extsk: selected.extsk.expsk.clone(),
+                diversifier: selected.diversifier,
+                note: selected.note.clone(),
+                merkle_path: path.auth_path.iter().map(|(node, _)| *node).collect(),
+                position: path.position,
+            });
+        }
 
         {
-            // TODO(orchard): Orchard building progress
             let mut p = self.send_progress.write().await;
             p.is_send_in_progress = true;
             p.progress = 0;
-            p.total = s_notes.len() as u32 + total_z_recepients + total_o_recepients;
+            p.total = s_notes.len() as u32 + s_out as u32;
         }
 
+        // We'll use the first ovk to encrypt outgoing Txns (same as BitcoinZ Blue)
+        let ovk = self.keys.read().await.zkeys.get(0).map(|zk| zk.extfvk.fvk.ovk);
         println!("{}: Building transaction", now() - start_time);
-        let (tx, _) = match builder.build(&prover) {
-            Ok(res) => res,
+        let built = build_transactions(
+            self.config.get_params(),
+            target_height,
+            inputs,
+            recipients,
+            change,
+            &policy,
+            expiry_for_offset(target_height, options.expiry_offset),
+            ovk,
+            &prover,
+        );
+        self.send_progress.write().await.is_send_in_progress = false;
+        let built = match built {
+            Ok(built) => built,
             Err(e) => {
-                let e = format!("Error creating transaction: {:?}", e);
+                let e = format!("Error creating transaction: {}", e);
                 error!("{}", e);
-                self.send_progress.write().await.is_send_in_progress = false;
                 return Err(e);
             }
         };
-
-        // Wait for all the progress to be updated
-        progress_handle.await.unwrap();
-
-        println!("{}: Transaction created", now() - start_time);
-        println!("Transaction ID: {}", tx.txid());
-        
-        // Debug: Print transaction details for BitcoinZ debugging
-        println!("Transaction version: {:?}", tx.version());
-        // Note: We'll add more debugging after checking transaction structure
-
-        {
-            self.send_progress.write().await.is_send_in_progress = false;
-        }
-
-        // Create the TX bytes
-        let mut raw_tx = vec![];
-        tx.write(&mut raw_tx).unwrap();
-        
-        // Debug: Print raw transaction info for BitcoinZ
-        println!("Raw TX size: {} bytes", raw_tx.len());
-        println!("First 4 bytes (version): {:02x} {:02x} {:02x} {:02x}", 
-                 raw_tx.get(0).unwrap_or(&0),
-                 raw_tx.get(1).unwrap_or(&0), 
-                 raw_tx.get(2).unwrap_or(&0),
-                 raw_tx.get(3).unwrap_or(&0));
-        
-        
-        // BitcoinZ Fix: For transparent-only transactions, analyze the binding signature
-        if is_transparent_only && raw_tx.len() >= 64 {
-            
-            // The binding signature is the last 64 bytes
-            let sig_start = raw_tx.len() - 64;
-            
-            // Print the current binding signature for debugging
-            print!("  ");
-            for i in sig_start..(sig_start + 16).min(raw_tx.len()) {
-                print!("{:02x} ", raw_tx[i]);
-            }
-            println!();
-            
-            // Check if it's all zeros
-            let mut all_zeros = true;
-            for i in sig_start..raw_tx.len() {
-                if raw_tx[i] != 0 {
-                    all_zeros = false;
-                    break;
-                }
-            }
-            
-            if all_zeros {
-            } else {
-            }
-            
-            // Log transaction details
-        }
-        
-        // For BitcoinZ, check if we need to fix the binding signature
-        let has_sapling_spends = s_notes.len() > 0;
-        let has_sapling_outputs = total_z_recepients > 0 || total_o_recepients > 0;
-        
-        if is_transparent_only {
-            // Check if we actually created an Overwinter transaction
-            let tx_version = tx.version();
-            match tx_version {
-                zcash_primitives::transaction::TxVersion::Overwinter => {
-                }
-                zcash_primitives::transaction::TxVersion::Sapling => {
-                }
-                _ => {
+        println!("{}: Created {} transactions", now() - start_time, built.len());
+
+        // A payment too big for one transaction is a chain, each spending the one before, so
+        // they go out in order and stop at the first the server refuses
+        let mut sent = vec![];
+        for built in built {
+            println!("Transaction ID: {}", built.txid);
+            let txid = match broadcast_fn(built.raw.clone().into_boxed_slice()).await {
+                Ok(txid) => txid,
+                Err(e) => {
+                    self.record_failed_send(&built.raw, &e).await;
+                    if sent.is_empty() {
+                        return Err(e);
+                    }
+                    return Err(format!(
+                        "{} ({} transactions of the payment were already sent)",
+                        e,
+                        sent.len()
+                    ));
                 }
-            }
-        } else if needs_bitcoinz_binding_sig_fix(has_sapling_spends, has_sapling_outputs) {
-            
+            };
+            self.add_sent_tx(&built, &utxos, &s_notes, target_height).await;
+            sent.push((txid, built.raw));
         }
 
-        let txid = broadcast_fn(raw_tx.clone().into_boxed_slice()).await?;
+        Ok(sent)
+    }
+
+    /// Record a transaction `send_to_address` broadcast: mark the selected utxos and notes it
+    /// spends as spent by it, add it to the mempool, and note whether its shielded parts
+    /// depend on the BitcoinZ binding signature handling.
+    async fn add_sent_tx(
+        &self,
+        built: &BuiltTransaction,
+        utxos: &[Utxo],
+        s_notes: &[SpendableSaplingNote],
+        target_height: BlockHeight,
+    ) {
+        // Our own output, so it parses
+        let parsed = BitcoinZTransaction::read(&built.raw).unwrap();
 
         // Mark notes as spent.
         {
-            // Mark sapling and orchard notes as unconfirmed spent
             let mut txs = self.txns.write().await;
-            for selected in o_notes {
-                let mut spent_note = txs
-                    .current
-                    .get_mut(&selected.txid)
-                    .unwrap()
-                    .o_notes
-                    .iter_mut()
-                    .find(|nd| {
-                        nd.note.nullifier(&nd.fvk)
-                            == selected
-                                .note
-                                .nullifier(&orchard::keys::FullViewingKey::from(&selected.sk))
-                    })
-                    .unwrap();
-                spent_note.unconfirmed_spent = Some((tx.txid(), u32::from(target_height)));
-            }
-
             for selected in s_notes {
+                if !parsed
+                    .shielded_spends
+                    .iter()
+                    .any(|spend| spend.nullifier == selected.nullifier.0)
+                {
+                    continue;
+                }
                 let mut spent_note = txs
                     .current
                     .get_mut(&selected.txid)
@@ -2012,11 +1839,19 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
                     .iter_mut()
                     .find(|nd| nd.nullifier == selected.nullifier)
                     .unwrap();
-                spent_note.unconfirmed_spent = Some((tx.txid(), u32::from(target_height)));
+                spent_note.unconfirmed_spent = Some((built.txid, u32::from(target_height)));
             }
 
             // Mark this utxo as unconfirmed spent
             for utxo in utxos {
+                let outpoint = (WireTxid::from(utxo.txid), utxo.output_index as u32);
+                if !parsed
+                    .inputs
+                    .iter()
+                    .any(|input| (input.prevout_hash, input.prevout_index) == outpoint)
+                {
+                    continue;
+                }
                 let spent_utxo = txs
                     .current
                     .get_mut(&utxo.txid)
@@ -2025,12 +1860,14 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
                     .iter_mut()
                     .find(|u| utxo.txid == u.txid && utxo.output_index == u.output_index)
                     .unwrap();
-                spent_utxo.unconfirmed_spent = Some((tx.txid(), u32::from(target_height)));
+                spent_utxo.unconfirmed_spent = Some((built.txid, u32::from(target_height)));
             }
         }
 
-        // Add this Tx to the mempool structure
-        {
+        // Add this Tx to the mempool structure. A transaction in a format zcash_primitives
+        // doesn't read (v1) is filled in once it's mined.
+        let branch_id = BranchId::for_height(&self.config.get_params(), target_height);
+        if let Ok(tx) = Transaction::read(&built.raw[..], branch_id) {
             let price = self.price.read().await.clone();
 
             FetchFullTxns::<P>::scan_full_tx(
@@ -2046,7 +1883,150 @@ impl<P: consensus::Parameters + Send + Sync + 'static> LightWallet<P> {
             .await;
         }
 
-        Ok((txid, raw_tx))
+        // Sapling parts still rely on the BitcoinZ binding signature handling
+        let compatibility = if needs_bitcoinz_binding_sig_fix(
+            !parsed.shielded_spends.is_empty(),
+            !parsed.shielded_outputs.is_empty(),
+        ) {
+            Compatibility::Experimental
+        } else {
+            Compatibility::Confirmed
+        };
+        self.txns
+            .write()
+            .await
+            .set_compatibility(&built.txid, target_height, now(), compatibility);
+    }
+
+    /// Spend every verified utxo into one output to `to`, paying the fee for the
+    /// transaction's size out of the total and leaving no change. Sweeping into a
+    /// z-address needs `options.force_experimental` while the branch IDs are unconfirmed.
+    pub async fn sweep_to_address<F, Fut, PR: TxProver>(
+        &self,
+        prover: PR,
+        to: &str,
+        options: SendOptions,
+        broadcast_fn: F,
+    ) -> Result<(String, Vec<u8>), String>
+    where
+        F: Fn(Box<[u8]>) -> Fut,
+        Fut: Future<Output = Result<String, String>>,
+    {
+        self.reset_send_progress().await;
+
+        match self.sweep_to_address_internal(prover, to, options, broadcast_fn).await {
+            Ok((txid, rawtx)) => {
+                self.set_send_success(txid.clone()).await;
+                Ok((txid, rawtx))
+            }
+            Err(e) => {
+                self.set_send_error(format!("{}", e)).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn sweep_to_address_internal<F, Fut, PR: TxProver>(
+        &self,
+        prover: PR,
+        to: &str,
+        options: SendOptions,
+        broadcast_fn: F,
+    ) -> Result<(String, Vec<u8>), String>
+    where
+        F: Fn(Box<[u8]>) -> Fut,
+        Fut: Future<Output = Result<String, String>>,
+    {
+        if !self.keys.read().await.unlocked {
+            return Err("Cannot spend while wallet is locked".to_string());
+        }
+
+        let destination = classify_address(to, &self.config.get_params())
+            .map_err(|e| format!("Invalid recipient address: '{}': {}", to, e))?;
+        let is_shielded = matches!(destination, AddressKind::Sapling(_));
+        if is_shielded {
+            check_shielded_send_allowed(BRANCH_IDS_CONFIRMED, options.force_experimental)?;
+        }
+
+        let target_height = match self.get_target_height().await {
+            Some(h) => BlockHeight::from_u32(h),
+            None => return Err("No blocks in wallet to target, please sync first".to_string()),
+        };
+
+        let utxos = self
+            .get_utxos()
+            .await
+            .into_iter()
+            .filter(|utxo| utxo.unconfirmed_spent.is_none())
+            .collect::<Vec<_>>();
+        if utxos.is_empty() {
+            return Err("No transparent funds to sweep".to_string());
+        }
+
+        let address_to_sk = self.keys.read().await.get_taddr_to_sk_map();
+        let mut inputs = vec![];
+        for utxo in &utxos {
+            let coin = TxOut {
+                value: Amount::from_u64(utxo.value).unwrap(),
+                script_pubkey: Script { 0: utxo.script.clone() },
+            };
+            match address_to_sk.get(&utxo.address) {
+                Some(sk) => inputs.push((utxo.to_outpoint(), coin, *sk)),
+                None => return Err(format!("Couldn't find the secret key for taddr {}", utxo.address)),
+            }
+        }
+
+        // The first ovk, so a sweep into a z-address can be read back like any other send
+        let ovk = self.keys.read().await.zkeys.get(0).map(|zk| zk.extfvk.fvk.ovk);
+        let built = build_sweep_tx(
+            self.config.get_params(),
+            inputs,
+            destination,
+            &FeePolicy::default(),
+            target_height,
+            ovk,
+            &prover,
+        )?;
+        info!(
+            "Sweeping {} utxos: {} zats, of which {} zats is the fee",
+            utxos.len(),
+            utxos.iter().map(|utxo| utxo.value).sum::<u64>(),
+            u64::from(built.fee)
+        );
+
+        let raw_tx = built.raw;
+        let broadcast_result = match broadcast_fn(raw_tx.clone().into_boxed_slice()).await {
+            Ok(r) => r,
+            Err(e) => {
+                self.record_failed_send(&raw_tx, &e).await;
+                return Err(e);
+            }
+        };
+
+        {
+            let mut txs = self.txns.write().await;
+            for utxo in utxos {
+                let spent_utxo = txs
+                    .current
+                    .get_mut(&utxo.txid)
+                    .unwrap()
+                    .utxos
+                    .iter_mut()
+                    .find(|u| utxo.txid == u.txid && utxo.output_index == u.output_index)
+                    .unwrap();
+                spent_utxo.unconfirmed_spent = Some((built.txid, u32::from(target_height)));
+            }
+
+            // A t→z sweep relies on the BitcoinZ binding signature handling
+            let compatibility = if is_shielded {
+                Compatibility::Experimental
+            } else {
+                Compatibility::Confirmed
+            };
+            txs.set_compatibility(&built.txid, target_height, now(), compatibility);
+        }
+
+        Ok((broadcast_result, raw_tx))
     }
 
     pub async fn encrypt(&self, passwd: String) -> io::Result<()> {
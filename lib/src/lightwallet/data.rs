@@ -8,8 +8,9 @@ use orchard::Address;
 use prost::Message;
 use std::convert::TryFrom;
 use std::io::{self, Read, Write};
+use std::sync::OnceLock;
 use std::usize;
-use zcash_encoding::{Optional, Vector};
+use zcash_encoding::{CompactSize, Optional, Vector};
 use zcash_primitives::memo::MemoBytes;
 use zcash_primitives::sapling;
 
@@ -117,55 +118,122 @@ impl BlockData {
 
 #[derive(Clone)]
 pub(crate) struct WitnessCache {
-    witnesses: Vec<IncrementalWitness<Node>>,
+    // Witnesses as read from the wallet file (including the length prefix). They are
+    // only parsed into `witnesses` on first use, so opening a large wallet doesn't
+    // pay for deserializing every witness up front.
+    raw: Option<(usize, Vec<u8>)>,
+    witnesses: OnceLock<Vec<IncrementalWitness<Node>>>,
     pub(crate) top_height: u64,
 }
 
 impl WitnessCache {
     pub fn new(witnesses: Vec<IncrementalWitness<Node>>, top_height: u64) -> Self {
-        Self { witnesses, top_height }
+        Self {
+            raw: None,
+            witnesses: OnceLock::from(witnesses),
+            top_height,
+        }
     }
 
     pub fn empty() -> Self {
+        Self::new(vec![], 0)
+    }
+
+    /// Read the serialized witnesses without parsing them. The bytes are walked to find
+    /// where they end (and to count them), but no witness is built until it is needed.
+    pub fn read_deferred<R: Read>(mut reader: R) -> io::Result<(usize, Vec<u8>)> {
+        let mut raw = vec![];
+        let count = CompactSize::read(&mut reader)?;
+        CompactSize::write(&mut raw, count as usize)?;
+
+        let mut reader = CopyingReader {
+            inner: reader,
+            copy: &mut raw,
+        };
+        for _ in 0..count {
+            skip_witness(&mut reader)?;
+        }
+
+        Ok((count as usize, raw))
+    }
+
+    pub fn new_deferred(count: usize, raw: Vec<u8>, top_height: u64) -> Self {
         Self {
-            witnesses: vec![],
-            top_height: 0,
+            raw: Some((count, raw)),
+            witnesses: OnceLock::new(),
+            top_height,
         }
     }
 
+    /// Whether the witnesses have been parsed yet
+    pub fn is_loaded(&self) -> bool {
+        self.witnesses.get().is_some()
+    }
+
+    /// Parse the witnesses if that hasn't happened yet
+    pub fn load(&self) -> &Vec<IncrementalWitness<Node>> {
+        self.witnesses.get_or_init(|| match &self.raw {
+            Some((_, raw)) => parse_witnesses(&raw[..])
+                .expect("Witness bytes were already validated when the wallet was read"),
+            None => vec![],
+        })
+    }
+
+    fn load_mut(&mut self) -> &mut Vec<IncrementalWitness<Node>> {
+        self.load();
+        // Once mutated, the raw bytes no longer match
+        self.raw = None;
+        self.witnesses.get_mut().unwrap()
+    }
+
     pub fn len(&self) -> usize {
-        self.witnesses.len()
+        match (self.witnesses.get(), &self.raw) {
+            (Some(w), _) => w.len(),
+            (None, Some((count, _))) => *count,
+            (None, None) => 0,
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.witnesses.is_empty()
+        self.len() == 0
     }
 
     pub fn clear(&mut self) {
-        self.witnesses.clear();
+        self.load_mut().clear();
     }
 
     pub fn get(&self, i: usize) -> Option<&IncrementalWitness<Node>> {
-        self.witnesses.get(i)
+        self.load().get(i)
     }
 
     #[cfg(test)]
     pub fn get_from_last(&self, i: usize) -> Option<&IncrementalWitness<Node>> {
-        self.witnesses.get(self.len() - i - 1)
+        self.load().get(self.len() - i - 1)
     }
 
     pub fn last(&self) -> Option<&IncrementalWitness<Node>> {
-        self.witnesses.last()
+        self.load().last()
     }
 
-    pub fn into_fsb(self, fsb: &mut FixedSizeBuffer<IncrementalWitness<Node>>) {
-        self.witnesses.into_iter().for_each(|w| fsb.push(w));
+    pub fn into_fsb(mut self, fsb: &mut FixedSizeBuffer<IncrementalWitness<Node>>) {
+        std::mem::take(self.load_mut()).into_iter().for_each(|w| fsb.push(w));
     }
 
     pub fn pop(&mut self, at_height: u64) {
-        while !self.witnesses.is_empty() && self.top_height >= at_height {
-            self.witnesses.pop();
-            self.top_height -= 1;
+        let mut top_height = self.top_height;
+        let witnesses = self.load_mut();
+        while !witnesses.is_empty() && top_height >= at_height {
+            witnesses.pop();
+            top_height -= 1;
+        }
+        self.top_height = top_height;
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        match (self.witnesses.get(), &self.raw) {
+            // Never parsed, so write back exactly what was read
+            (None, Some((_, raw))) => writer.write_all(raw),
+            _ => Vector::write(&mut writer, self.load(), |wr, wi| wi.write(wr)),
         }
     }
 
@@ -180,6 +248,59 @@ impl WitnessCache {
     // }
 }
 
+fn parse_witnesses(raw: &[u8]) -> io::Result<Vec<IncrementalWitness<Node>>> {
+    Vector::read(raw, |r| IncrementalWitness::<Node>::read(r))
+}
+
+/// Reader that keeps a copy of everything read through it
+struct CopyingReader<'a, R: Read> {
+    inner: R,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<'a, R: Read> Read for CopyingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Walk over a serialized IncrementalWitness (see IncrementalWitness::write) without building it
+fn skip_witness<R: Read>(mut reader: R) -> io::Result<()> {
+    skip_commitment_tree(&mut reader)?;
+    let filled = CompactSize::read(&mut reader)?;
+    for _ in 0..filled {
+        skip_node(&mut reader)?;
+    }
+    if reader.read_u8()? == 1 {
+        skip_commitment_tree(&mut reader)?;
+    }
+    Ok(())
+}
+
+fn skip_commitment_tree<R: Read>(mut reader: R) -> io::Result<()> {
+    // left, right
+    for _ in 0..2 {
+        if reader.read_u8()? == 1 {
+            skip_node(&mut reader)?;
+        }
+    }
+    // parents
+    let parents = CompactSize::read(&mut reader)?;
+    for _ in 0..parents {
+        if reader.read_u8()? == 1 {
+            skip_node(&mut reader)?;
+        }
+    }
+    Ok(())
+}
+
+fn skip_node<R: Read>(mut reader: R) -> io::Result<()> {
+    let mut node = [0u8; 32];
+    reader.read_exact(&mut node)
+}
+
 pub struct OrchardNoteData {
     pub(super) fvk: FullViewingKey,
 
@@ -431,13 +552,13 @@ impl SaplingNoteData {
             )),
         }?;
 
-        let witnesses_vec = Vector::read(&mut reader, |r| IncrementalWitness::<Node>::read(r))?;
+        let (witness_count, witness_bytes) = WitnessCache::read_deferred(&mut reader)?;
         let top_height = if version < 20 {
             0
         } else {
             reader.read_u64::<LittleEndian>()?
         };
-        let witnesses = WitnessCache::new(witnesses_vec, top_height);
+        let witnesses = WitnessCache::new_deferred(witness_count, witness_bytes, top_height);
 
         let mut nullifier = [0u8; 32];
         reader.read_exact(&mut nullifier)?;
@@ -538,7 +659,7 @@ impl SaplingNoteData {
 
         write_rseed(&mut writer, &self.note.rseed)?;
 
-        Vector::write(&mut writer, &self.witnesses.witnesses, |wr, wi| wi.write(wr))?;
+        self.witnesses.write(&mut writer)?;
         writer.write_u64::<LittleEndian>(self.witnesses.top_height)?;
 
         writer.write_all(&self.nullifier.0)?;
@@ -723,6 +844,50 @@ impl OutgoingTxMetadata {
     }
 }
 
+/// Whether a transaction we sent was built along a path bitcoinzd is known to accept.
+/// Transparent-only transactions are; anything with Sapling parts still depends on the
+/// BitcoinZ binding signature handling, which hasn't been confirmed on mainnet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Confirmed,
+    Experimental,
+}
+
+impl Compatibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compatibility::Confirmed => "confirmed",
+            Compatibility::Experimental => "experimental",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "confirmed" => Some(Compatibility::Confirmed),
+            "experimental" => Some(Compatibility::Experimental),
+            _ => None,
+        }
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        match reader.read_u8()? {
+            0 => Ok(Compatibility::Confirmed),
+            1 => Ok(Compatibility::Experimental),
+            n => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown compatibility tag {}", n),
+            )),
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u8(match self {
+            Compatibility::Confirmed => 0,
+            Compatibility::Experimental => 1,
+        })
+    }
+}
+
 pub struct WalletTx {
     // Block in which this tx was included
     pub block: BlockHeight,
@@ -769,11 +934,14 @@ pub struct WalletTx {
 
     // Price of Zec when this Tx was created
     pub zec_price: Option<f64>,
+
+    // For Txs this wallet sent, how the builder that made it is known to fare with bitcoinzd. Added in v24
+    pub compatibility: Option<Compatibility>,
 }
 
 impl WalletTx {
     pub fn serialized_version() -> u64 {
-        return 23;
+        return 24;
     }
 
     pub fn new_txid(txid: &Vec<u8>) -> TxId {
@@ -814,6 +982,7 @@ impl WalletTx {
             outgoing_metadata: vec![],
             full_tx_scanned: false,
             zec_price: None,
+            compatibility: None,
         }
     }
 
@@ -883,6 +1052,12 @@ impl WalletTx {
             })?
         };
 
+        let compatibility = if version <= 23 {
+            None
+        } else {
+            Optional::read(&mut reader, |r| Compatibility::read(r))?
+        };
+
         Ok(Self {
             block,
             unconfirmed,
@@ -899,6 +1074,7 @@ impl WalletTx {
             outgoing_metadata,
             full_tx_scanned,
             zec_price,
+            compatibility,
         })
     }
 
@@ -934,6 +1110,8 @@ impl WalletTx {
 
         Vector::write(&mut writer, &self.o_spent_nullifiers, |w, n| w.write_all(&n.to_bytes()))?;
 
+        Optional::write(&mut writer, self.compatibility, |w, c| c.write(w))?;
+
         Ok(())
     }
 
@@ -3,7 +3,6 @@ use std::{
     io::{self, Error, ErrorKind, Read, Write},
 };
 
-use base58::{FromBase58, ToBase58};
 use bip39::{Language, Mnemonic, Seed};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use orchard::keys::{FullViewingKey, IncomingViewingKey, Scope};
@@ -25,6 +24,7 @@ use zcash_primitives::{
 };
 
 use crate::{
+    bitcoinz_base58::{decode_check, encode_check},
     lightclient::lightclient_config::{LightClientConfig, GAP_RULE_UNUSED_ADDRESSES},
     lightwallet::utils,
 };
@@ -57,10 +57,7 @@ impl ToBase58Check for [u8] {
         payload.extend_from_slice(version);
         payload.extend_from_slice(self);
         payload.extend_from_slice(suffix);
-
-        let checksum = double_sha256(&payload);
-        payload.append(&mut checksum[..4].to_vec());
-        payload.to_base58()
+        encode_check(&payload)
     }
 }
 
@@ -72,22 +69,9 @@ pub trait FromBase58Check {
 
 impl FromBase58Check for str {
     fn from_base58check(&self) -> io::Result<(u8, Vec<u8>)> {
-        let mut payload: Vec<u8> = match self.from_base58() {
-            Ok(payload) => payload,
-            Err(error) => return Err(io::Error::new(ErrorKind::InvalidData, format!("{:?}", error))),
-        };
-        if payload.len() < 5 {
-            return Err(io::Error::new(
-                ErrorKind::InvalidData,
-                format!("Invalid Checksum length"),
-            ));
-        }
-
-        let checksum_index = payload.len() - 4;
-        let provided_checksum = payload.split_off(checksum_index);
-        let checksum = double_sha256(&payload)[..4].to_vec();
-        if checksum != provided_checksum {
-            return Err(io::Error::new(ErrorKind::InvalidData, format!("Invalid Checksum")));
+        let payload = decode_check(self).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        if payload.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("No version byte")));
         }
         Ok((payload[0], payload[1..].to_vec()))
     }
@@ -148,7 +132,7 @@ impl<P: consensus::Parameters> Keys<P> {
 
     pub fn new(
         config: &LightClientConfig<P>,
-        seed_phrase: Option<String>,
+        seed_phrase: Option<&str>,
         num_zaddrs: u32,
         num_oaddrs: u32,
     ) -> Result<Self, String> {
@@ -159,7 +143,7 @@ impl<P: consensus::Parameters> Keys<P> {
             let mut system_rng = OsRng;
             system_rng.fill(&mut seed_bytes);
         } else {
-            let phrase = match Mnemonic::from_phrase(seed_phrase.unwrap().as_str(), Language::English) {
+            let phrase = match Mnemonic::from_phrase(seed_phrase.unwrap(), Language::English) {
                 Ok(p) => p,
                 Err(e) => {
                     let e = format!("Error parsing phrase: {}", e);
@@ -20,7 +20,7 @@ use zcash_primitives::{
 
 use crate::lightclient::lightclient_config::MAX_REORG;
 
-use super::data::{OrchardNoteData, OutgoingTxMetadata, SaplingNoteData, Utxo, WalletTx, WitnessCache};
+use super::data::{Compatibility, OrchardNoteData, OutgoingTxMetadata, SaplingNoteData, Utxo, WalletTx, WitnessCache};
 
 /// List of all transactions in a wallet.
 /// Note that the parent is expected to hold a RwLock, so we will assume that all accesses to
@@ -172,6 +172,73 @@ impl WalletTxns {
         });
     }
 
+    // When a key is removed from the wallet, forget the notes and utxos that belong to it, and the spends
+    // of them. Txns that no longer involve any of the remaining keys are removed from the history.
+    pub fn remove_key_data<F, G>(&mut self, is_removed_note: F, is_removed_utxo: G)
+    where
+        F: Fn(&SaplingNoteData) -> bool,
+        G: Fn(&Utxo) -> bool,
+    {
+        // (spending txid, nullifier, value) of every removed note that was spent
+        let mut note_spends = vec![];
+        // (spending txid, value) of every removed utxo that was spent
+        let mut utxo_spends = vec![];
+
+        for wtx in self.current.values_mut() {
+            wtx.s_notes.retain(|nd| {
+                if !is_removed_note(nd) {
+                    return true;
+                }
+
+                if let Some(spent_txid) = nd.spent.or(nd.unconfirmed_spent).map(|(txid, _)| txid) {
+                    note_spends.push((spent_txid, nd.nullifier, nd.note.value));
+                }
+                false
+            });
+
+            wtx.utxos.retain(|utxo| {
+                if !is_removed_utxo(utxo) {
+                    return true;
+                }
+
+                if let Some(spent_txid) = utxo.spent.or(utxo.unconfirmed_spent.map(|(txid, _)| txid)) {
+                    utxo_spends.push((spent_txid, utxo.value));
+                }
+                false
+            });
+        }
+
+        for (txid, nullifier, value) in note_spends {
+            if let Some(wtx) = self.current.get_mut(&txid) {
+                wtx.s_spent_nullifiers.retain(|nf| *nf != nullifier);
+                wtx.total_sapling_value_spent = wtx.total_sapling_value_spent.saturating_sub(value);
+            }
+        }
+
+        for (txid, value) in utxo_spends {
+            if let Some(wtx) = self.current.get_mut(&txid) {
+                wtx.total_transparent_value_spent = wtx.total_transparent_value_spent.saturating_sub(value);
+            }
+        }
+
+        let txids_to_remove = self
+            .current
+            .values()
+            .filter(|wtx| {
+                wtx.s_notes.is_empty()
+                    && wtx.o_notes.is_empty()
+                    && wtx.utxos.is_empty()
+                    && wtx.total_sapling_value_spent == 0
+                    && wtx.total_orchard_value_spent == 0
+                    && wtx.total_transparent_value_spent == 0
+            })
+            .map(|wtx| wtx.txid.clone())
+            .collect::<Vec<_>>();
+
+        info!("Removing {} txns that only involved the removed key", txids_to_remove.len());
+        self.remove_txids(txids_to_remove);
+    }
+
     // During reorgs, we need to remove all txns at a given height, and all spends that refer to any removed txns.
     pub fn remove_txns_at_height(&mut self, reorg_height: u64) {
         let reorg_height = BlockHeight::from_u32(reorg_height as u32);
@@ -385,6 +452,68 @@ impl WalletTxns {
         }
     }
 
+    // Rebuild which notes are spent from the stored transactions alone, without fetching any blocks. Each sapling
+    // note's nullifier is recomputed from its key and witness position (notes whose witnesses were already cleared
+    // keep the nullifier they have), and a note is spent if a mined transaction in the wallet spends its nullifier.
+    // Returns how many notes changed.
+    pub fn rebuild_spent_index(&mut self) -> usize {
+        let s_spends = self
+            .current
+            .values()
+            .filter(|wtx| !wtx.unconfirmed)
+            .flat_map(|wtx| {
+                wtx.s_spent_nullifiers
+                    .iter()
+                    .map(move |nf| (nf.0, (wtx.txid.clone(), u32::from(wtx.block))))
+            })
+            .collect::<HashMap<_, _>>();
+        let o_spends = self
+            .current
+            .values()
+            .filter(|wtx| !wtx.unconfirmed)
+            .flat_map(|wtx| {
+                wtx.o_spent_nullifiers
+                    .iter()
+                    .map(move |nf| (nf.to_bytes(), (wtx.txid.clone(), u32::from(wtx.block))))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut changed = 0;
+        for wtx in self.current.values_mut() {
+            for nd in wtx.s_notes.iter_mut() {
+                let nullifier = match nd.witnesses.last() {
+                    Some(witness) => nd.note.nf(&nd.extfvk.fvk.vk.nk, witness.position() as u64),
+                    None => nd.nullifier,
+                };
+                let spent = s_spends.get(&nullifier.0).cloned();
+
+                if nullifier != nd.nullifier || spent != nd.spent {
+                    changed += 1;
+                }
+                nd.nullifier = nullifier;
+                nd.spent = spent;
+                if nd.spent.is_some() {
+                    nd.unconfirmed_spent = None;
+                }
+            }
+
+            for nd in wtx.o_notes.iter_mut() {
+                let spent = o_spends.get(&nd.note.nullifier(&nd.fvk).to_bytes()).cloned();
+
+                if spent != nd.spent {
+                    changed += 1;
+                }
+                nd.spent = spent;
+                if nd.spent.is_some() {
+                    nd.unconfirmed_spent = None;
+                }
+            }
+        }
+
+        info!("Rebuilt the spent note index, {} notes changed", changed);
+        changed
+    }
+
     fn get_or_create_tx(
         &mut self,
         txid: &TxId,
@@ -411,6 +540,20 @@ impl WalletTxns {
         wtx
     }
 
+    // Tag a Tx this wallet just sent. The Tx might not be in the wallet yet, in which case it's added as unconfirmed
+    pub fn set_compatibility(
+        &mut self,
+        txid: &TxId,
+        height: BlockHeight,
+        datetime: u64,
+        compatibility: Compatibility,
+    ) {
+        match self.current.get_mut(txid) {
+            Some(wtx) => wtx.compatibility = Some(compatibility),
+            None => self.get_or_create_tx(txid, height, true, datetime).compatibility = Some(compatibility),
+        }
+    }
+
     pub fn set_price(&mut self, txid: &TxId, price: Option<f64>) {
         price.map(|p| self.current.get_mut(txid).map(|tx| tx.zec_price = Some(p)));
     }
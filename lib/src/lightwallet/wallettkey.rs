@@ -85,8 +85,8 @@ impl WalletTKey {
         }
     }
 
-    pub fn from_sk_string<P: consensus::Parameters>(config: &LightClientConfig<P>, sks: String) -> io::Result<Self> {
-        let (_v, mut bytes) = sks.as_str().from_base58check()?;
+    pub fn from_sk_string<P: consensus::Parameters>(config: &LightClientConfig<P>, sks: &str) -> io::Result<Self> {
+        let (_v, mut bytes) = sks.from_base58check()?;
         let suffix = bytes.split_off(32);
 
         // Assert the suffix
@@ -373,7 +373,7 @@ mod test {
             // println!("key:{}", sks);
 
             // Import it back
-            let wtk2 = WalletTKey::from_sk_string(&config, sks).unwrap();
+            let wtk2 = WalletTKey::from_sk_string(&config, &sks).unwrap();
 
             // Make sure they're the same
             assert_eq!(wtk.address, wtk2.address);
@@ -0,0 +1,265 @@
+/// Test helpers for the transaction builders
+///
+/// `MockTxProver` stands in for the Sapling prover, so `build()` can run end to end
+/// without loading zcash-params. Its proofs are all zeros: the right size, but not
+/// valid. Everything else is computed the way the real prover does it, so the value
+/// commitments, rk and binding signature are all well formed.
+///
+/// The value commitment randomness is derived from the prover's seed and the order in
+/// which descriptions are proved (see `MockTxProver::rcv`), so tests can recompute every
+/// commitment and the binding signing key. The prover also keeps a log of what it made.
+///
+/// Compiled for this crate's tests, and for other crates with the `test-utils` feature.
+use std::sync::Mutex;
+
+use blake2b_simd::Params;
+use ff::Field;
+use group::{Group, GroupEncoding};
+use rand::{rngs::StdRng, SeedableRng};
+use secp256k1::{ecdsa, Message, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use zcash_primitives::{
+    constants::{SPENDING_KEY_GENERATOR, VALUE_COMMITMENT_RANDOMNESS_GENERATOR, VALUE_COMMITMENT_VALUE_GENERATOR},
+    legacy::TransparentAddress,
+    merkle_tree::MerklePath,
+    sapling::{
+        prover::TxProver,
+        redjubjub::{PrivateKey, PublicKey, Signature},
+        Diversifier, Node, PaymentAddress, ProofGenerationKey, Rseed, ValueCommitment,
+    },
+    transaction::components::{Amount, OutPoint, TxOut, GROTH_PROOF_SIZE},
+};
+
+use crate::bitcoinz_binding_sig_fix::compute_bitcoinz_binding_message;
+use crate::bitcoinz_overwinter_builder::SIGHASH_ALL;
+use crate::bitcoinz_parse::BitcoinZTransaction;
+use crate::bitcoinz_script_sig::hash160;
+
+const MOCK_RCV_PERSONALIZATION: &[u8; 16] = b"BTCZ_MockRcv____";
+
+/// A binding signature made by the mock prover
+#[derive(Clone)]
+pub struct MockBindingSig {
+    pub bvk: jubjub::ExtendedPoint,
+    pub sighash: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+/// Everything the mock prover has made, in order
+#[derive(Clone, Default)]
+pub struct MockProverLog {
+    /// How many proving contexts were created, one per transaction built
+    pub contexts: usize,
+    pub spend_cvs: Vec<jubjub::ExtendedPoint>,
+    /// The anchor each spend was proved against
+    pub spend_anchors: Vec<bls12_381::Scalar>,
+    pub output_cvs: Vec<jubjub::ExtendedPoint>,
+    /// Who each output pays, and how much
+    pub output_recipients: Vec<(PaymentAddress, u64)>,
+    /// The esk each output was proved with
+    pub output_esks: Vec<jubjub::Fr>,
+    pub binding_sigs: Vec<MockBindingSig>,
+}
+
+pub struct MockTxProver {
+    seed: u64,
+    log: Mutex<MockProverLog>,
+}
+
+pub struct MockProvingContext {
+    // Number of descriptions proved so far, which picks the next rcv
+    proved: u64,
+    bsk: jubjub::Fr,
+    cv_sum: jubjub::ExtendedPoint,
+}
+
+impl MockTxProver {
+    pub fn new(seed: u64) -> Self {
+        MockTxProver {
+            seed,
+            log: Mutex::new(MockProverLog::default()),
+        }
+    }
+
+    /// The value commitment randomness for the `index`th description proved in a
+    /// transaction, counting spends first and then outputs, as the builder proves them
+    pub fn rcv(&self, index: u64) -> jubjub::Fr {
+        let hash = Params::new()
+            .hash_length(64)
+            .personal(MOCK_RCV_PERSONALIZATION)
+            .to_state()
+            .update(&self.seed.to_le_bytes())
+            .update(&index.to_le_bytes())
+            .finalize();
+
+        let mut wide = [0u8; 64];
+        wide.copy_from_slice(hash.as_bytes());
+        jubjub::Fr::from_bytes_wide(&wide)
+    }
+
+    pub fn log(&self) -> MockProverLog {
+        self.log.lock().unwrap().clone()
+    }
+
+    fn next_rcv(&self, ctx: &mut MockProvingContext) -> jubjub::Fr {
+        let rcv = self.rcv(ctx.proved);
+        ctx.proved += 1;
+        rcv
+    }
+}
+
+impl Default for MockTxProver {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TxProver for MockTxProver {
+    type SaplingProvingContext = MockProvingContext;
+
+    fn new_sapling_proving_context(&self) -> Self::SaplingProvingContext {
+        self.log.lock().unwrap().contexts += 1;
+        MockProvingContext {
+            proved: 0,
+            bsk: jubjub::Fr::zero(),
+            cv_sum: jubjub::ExtendedPoint::identity(),
+        }
+    }
+
+    fn spend_proof(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        proof_generation_key: ProofGenerationKey,
+        _diversifier: Diversifier,
+        _rseed: Rseed,
+        ar: jubjub::Fr,
+        value: u64,
+        anchor: bls12_381::Scalar,
+        _merkle_path: MerklePath<Node>,
+    ) -> Result<([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint, PublicKey), ()> {
+        let rcv = self.next_rcv(ctx);
+        ctx.bsk += rcv;
+
+        let cv: jubjub::ExtendedPoint = ValueCommitment { value, randomness: rcv }.commitment().into();
+        ctx.cv_sum += cv;
+
+        let rk = PublicKey(proof_generation_key.ak.into()).randomize(ar, SPENDING_KEY_GENERATOR);
+
+        let mut log = self.log.lock().unwrap();
+        log.spend_cvs.push(cv);
+        log.spend_anchors.push(anchor);
+
+        Ok(([0u8; GROTH_PROOF_SIZE], cv, rk))
+    }
+
+    fn output_proof(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        esk: jubjub::Fr,
+        payment_address: PaymentAddress,
+        _rcm: jubjub::Fr,
+        value: u64,
+    ) -> ([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint) {
+        let rcv = self.next_rcv(ctx);
+        ctx.bsk -= rcv;
+
+        let cv: jubjub::ExtendedPoint = ValueCommitment { value, randomness: rcv }.commitment().into();
+        ctx.cv_sum -= cv;
+
+        let mut log = self.log.lock().unwrap();
+        log.output_cvs.push(cv);
+        log.output_recipients.push((payment_address, value));
+        log.output_esks.push(esk);
+
+        ([0u8; GROTH_PROOF_SIZE], cv)
+    }
+
+    fn binding_sig(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        value_balance: Amount,
+        sighash: &[u8; 32],
+    ) -> Result<Signature, ()> {
+        // Like the real prover, refuse if the commitments don't add up to the value balance
+        let value_balance = i64::from(value_balance);
+        let balance = jubjub::ExtendedPoint::from(
+            VALUE_COMMITMENT_VALUE_GENERATOR * jubjub::Fr::from(value_balance.unsigned_abs()),
+        );
+        let bvk = if value_balance < 0 { ctx.cv_sum + balance } else { ctx.cv_sum - balance };
+
+        let bsk = PrivateKey(ctx.bsk);
+        if PublicKey::from_private(&bsk, VALUE_COMMITMENT_RANDOMNESS_GENERATOR).0 != bvk {
+            return Err(());
+        }
+
+        let message = compute_bitcoinz_binding_message(&bvk.to_bytes(), sighash);
+        let signature = bsk.sign(&message, &mut StdRng::seed_from_u64(self.seed), VALUE_COMMITMENT_RANDOMNESS_GENERATOR);
+
+        let mut signature_bytes = [0u8; 64];
+        signature.write(&mut signature_bytes[..]).map_err(|_| ())?;
+        self.log.lock().unwrap().binding_sigs.push(MockBindingSig {
+            bvk,
+            sighash: *sighash,
+            signature: signature_bytes,
+        });
+
+        Ok(signature)
+    }
+}
+
+/// Three pay-to-pubkey-hash coins of 100,000 zatoshis each. The second one pays to an
+/// uncompressed key, so the scriptSigs spending them differ in length.
+pub fn three_pubkey_hash_coins() -> Vec<(OutPoint, TxOut, SecretKey)> {
+    let secp = Secp256k1::signing_only();
+    [[0x01u8; 32], [0x02u8; 32], [0x03u8; 32]]
+        .iter()
+        .enumerate()
+        .map(|(i, k)| {
+            let sk = SecretKey::from_slice(k).unwrap();
+            let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &sk);
+            let hash = if i == 1 {
+                hash160(&pubkey.serialize_uncompressed())
+            } else {
+                hash160(&pubkey.serialize())
+            };
+            let coin = TxOut {
+                value: Amount::from_u64(100_000).unwrap(),
+                script_pubkey: TransparentAddress::PublicKey(hash).script(),
+            };
+            (OutPoint::new([0x11u8 * (i as u8 + 1); 32], 2 - i as u32), coin, sk)
+        })
+        .collect()
+}
+
+/// Check that every input of a v1 transaction is `<sig || hash type> <pubkey>`, where
+/// the pubkey is the one its coin pays to and the signature covers the legacy sighash
+pub fn assert_v1_signatures(tx: &BitcoinZTransaction, coins: &[(OutPoint, TxOut, SecretKey)]) {
+    let secp = Secp256k1::verification_only();
+    assert_eq!(tx.inputs.len(), coins.len());
+    for (index, (_, coin, _)) in coins.iter().enumerate() {
+        let script_sig = &tx.inputs[index].script_sig;
+        let sig_len = script_sig[0] as usize;
+        assert_eq!(script_sig[sig_len], SIGHASH_ALL as u8);
+        let signature = ecdsa::Signature::from_der(&script_sig[1..sig_len]).unwrap();
+        let pubkey = &script_sig[sig_len + 2..];
+        assert_eq!(script_sig[sig_len + 1] as usize, pubkey.len());
+        assert_eq!(TransparentAddress::PublicKey(hash160(pubkey)).script(), coin.script_pubkey);
+
+        // This input's scriptSig replaced by the prevout's script, every other one
+        // emptied, and the hash type on the end
+        let mut unsigned = tx.clone();
+        for (i, input) in unsigned.inputs.iter_mut().enumerate() {
+            input.script_sig = if i == index { coin.script_pubkey.0.clone() } else { vec![] };
+        }
+        let mut preimage = unsigned.write();
+        preimage.extend_from_slice(&SIGHASH_ALL.to_le_bytes());
+        let sighash = Sha256::digest(&Sha256::digest(&preimage));
+
+        let pubkey = secp256k1::PublicKey::from_slice(pubkey).unwrap();
+        assert!(
+            secp.verify_ecdsa(&Message::from_slice(&sighash).unwrap(), &signature, &pubkey).is_ok(),
+            "input {}",
+            index
+        );
+    }
+}